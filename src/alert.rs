@@ -0,0 +1,377 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use anyhow::bail;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::metrics::GAUGES;
+use crate::script::{self, Script, Timeframes};
+use crate::PerformanceIndicators;
+
+///
+/// A fired alert
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub rule: String,
+    pub symbol: String,
+    pub timestamp: DateTime<Utc>,
+    pub message: String,
+}
+
+///
+/// How a threshold rule compares the indicator with its value
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Below,
+    AtMost,
+    Above,
+    AtLeast,
+}
+
+impl Comparison {
+    const ALL: [(&'static str, Comparison); 4] = [
+        ("<", Comparison::Below),
+        ("<=", Comparison::AtMost),
+        (">", Comparison::Above),
+        (">=", Comparison::AtLeast),
+    ];
+
+    fn symbol(self) -> &'static str {
+        Self::ALL.iter().find(|(_, c)| *c == self).unwrap().0
+    }
+
+    fn holds(self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Below => left < right,
+            Comparison::AtMost => left <= right,
+            Comparison::Above => left > right,
+            Comparison::AtLeast => left >= right,
+        }
+    }
+}
+
+///
+/// A rule that compares one indicator with a value, e.g. `AAPL pct_change < -0.05`, or
+/// `price > 200` for every symbol
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdRule {
+    /// The symbol the rule is about, `None` for all symbols
+    pub symbol: Option<String>,
+    /// One of the gauges of the metrics, e.g. `price`, `pct_change`, or `rsi`
+    pub indicator: String,
+    pub comparison: Comparison,
+    pub value: f64,
+}
+
+impl ThresholdRule {
+    ///
+    /// Whether the rule is about the symbol and its indicator crosses the value. A missing
+    /// indicator never does.
+    ///
+    pub fn matches(&self, data: &PerformanceIndicators) -> Option<bool> {
+        if self.symbol.as_ref().is_some_and(|s| *s != data.symbol) {
+            return None;
+        }
+        let (_, gauge) = GAUGES.iter().find(|(name, _)| *name == self.indicator)?;
+        Some(gauge(data).is_some_and(|v| self.comparison.holds(v, self.value)))
+    }
+
+    ///
+    /// The same condition as a script, for the commands that work on the scripts
+    ///
+    pub fn script(&self) -> String {
+        let condition = format!(
+            "{} {} {:?}",
+            self.indicator,
+            self.comparison.symbol(),
+            self.value
+        );
+        match &self.symbol {
+            Some(symbol) => format!("symbol == {:?} && {}", symbol, condition),
+            None => condition,
+        }
+    }
+}
+
+impl std::str::FromStr for ThresholdRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let (symbol, indicator, comparison, value) = match parts[..] {
+            [indicator, comparison, value] => (None, indicator, comparison, value),
+            [symbol, indicator, comparison, value] => {
+                (Some(symbol.to_string()), indicator, comparison, value)
+            }
+            _ => bail!(
+                "Invalid rule '{}', expected e.g. 'AAPL pct_change < -0.05' or 'price > 200'",
+                s.trim()
+            ),
+        };
+        if !GAUGES.iter().any(|(name, _)| *name == indicator) {
+            let names: Vec<&str> = GAUGES.iter().map(|(name, _)| *name).collect();
+            bail!(
+                "Unknown indicator '{}', expected one of {}",
+                indicator,
+                names.join(", ")
+            );
+        }
+        let comparison = match Comparison::ALL.iter().find(|(op, _)| *op == comparison) {
+            Some((_, c)) => *c,
+            None => bail!(
+                "Unknown comparison '{}', expected <, <=, >, or >=",
+                comparison
+            ),
+        };
+        let value: f64 = match value.parse() {
+            Ok(value) if f64::is_finite(value) => value,
+            _ => bail!("'{}' is not a number", value),
+        };
+        Ok(ThresholdRule {
+            symbol,
+            indicator: indicator.to_string(),
+            comparison,
+            value,
+        })
+    }
+}
+
+impl fmt::Display for ThresholdRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(symbol) = &self.symbol {
+            write!(f, "{} ", symbol)?;
+        }
+        write!(
+            f,
+            "{} {} {}",
+            self.indicator,
+            self.comparison.symbol(),
+            self.value
+        )
+    }
+}
+
+impl Serialize for ThresholdRule {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ThresholdRule {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+///
+/// Request the (rule, symbol) pairs whose condition is currently true, for a snapshot
+///
+#[message(result = "BTreeSet<(String, String)>")]
+pub struct AlertStateRequest;
+
+///
+/// Put previously snapshotted alert states back, so their rules don't fire again
+///
+#[message]
+pub struct AlertRestore {
+    pub active: BTreeSet<(String, String)>,
+}
+
+///
+/// A threshold rule with the name its alerts carry
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct NamedRule {
+    pub name: String,
+    pub rule: ThresholdRule,
+}
+
+///
+/// The threshold rules, shared by the engine and the REST API that edits them. They outlive
+/// a restart of the engine.
+///
+pub type ThresholdRules = Arc<RwLock<Vec<NamedRule>>>;
+
+///
+/// Actor that evaluates alert rules against incoming indicators and publishes `Alert`s.
+/// A rule fires once when its condition becomes true for a symbol and is re-armed when the
+/// condition turns false again.
+///
+pub struct AlertEngine {
+    engine: rhai::Engine,
+    rules: Vec<Script>,
+    threshold_rules: ThresholdRules,
+    /// (rule, symbol) pairs whose condition is currently true
+    active: HashSet<(String, String)>,
+    default_thresholds: BTreeMap<String, f64>,
+    /// Thresholds of the symbols that override the defaults
+    thresholds: HashMap<String, BTreeMap<String, f64>>,
+    /// The latest indicators of every symbol (and watchlist) per resolution
+    timeframes: HashMap<(String, Option<String>), Timeframes>,
+}
+
+impl AlertEngine {
+    pub fn new(
+        rules: Vec<Script>,
+        threshold_rules: ThresholdRules,
+        default_thresholds: BTreeMap<String, f64>,
+        thresholds: HashMap<String, BTreeMap<String, f64>>,
+    ) -> Self {
+        AlertEngine {
+            engine: script::engine(),
+            rules,
+            threshold_rules,
+            active: HashSet::new(),
+            default_thresholds,
+            thresholds,
+            timeframes: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for AlertEngine {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for AlertEngine {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        // rules are about the current prices, not the ones of a backfill
+        if msg.historical {
+            return;
+        }
+        let timeframes = self
+            .timeframes
+            .entry((msg.symbol.clone(), msg.watchlist.clone()))
+            .or_default();
+        timeframes.update(&msg);
+        let thresholds = self
+            .thresholds
+            .get(&msg.symbol)
+            .unwrap_or(&self.default_thresholds);
+        let mut results = vec![];
+        for rule in &self.rules {
+            match rule.condition(&self.engine, &msg, timeframes, thresholds) {
+                Ok(holds) => results.push((rule.name.clone(), holds)),
+                Err(e) => tracing::error!(
+                    "Alert rule '{}' failed for {}: {}",
+                    rule.name,
+                    msg.symbol,
+                    e
+                ),
+            }
+        }
+        let threshold_rules = self.threshold_rules.read().unwrap().clone();
+        for rule in &threshold_rules {
+            if let Some(holds) = rule.rule.matches(&msg) {
+                results.push((rule.name.clone(), holds));
+            }
+        }
+        // a removed rule fires again once it's added back
+        let scripts = &self.rules;
+        self.active.retain(|(name, _)| {
+            scripts.iter().any(|r| r.name == *name)
+                || threshold_rules.iter().any(|r| r.name == *name)
+        });
+        for (name, holds) in results {
+            let key = (name, msg.symbol.clone());
+            if !holds {
+                self.active.remove(&key);
+            } else if self.active.insert(key.clone()) {
+                let alert = Alert {
+                    message: format!(
+                        "'{}' triggered for {} at {}",
+                        key.0,
+                        msg.symbol,
+                        msg.money(msg.price)
+                    ),
+                    rule: key.0,
+                    symbol: msg.symbol.clone(),
+                    timestamp: msg.timestamp,
+                };
+                tracing::info!("ALERT {}", alert.message);
+                if let Err(e) = Broker::from_registry().await.unwrap().publish(alert) {
+                    tracing::error!("{}", e);
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<AlertStateRequest> for AlertEngine {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: AlertStateRequest,
+    ) -> BTreeSet<(String, String)> {
+        self.active.iter().cloned().collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<AlertRestore> for AlertEngine {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: AlertRestore) {
+        self.active.extend(msg.active);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold_rule() {
+        let rule: ThresholdRule = "AAPL  pct_change < -0.05".parse().unwrap();
+        assert_eq!(rule.symbol.as_deref(), Some("AAPL"));
+        assert_eq!(rule.comparison, Comparison::Below);
+        assert_eq!(rule.to_string(), "AAPL pct_change < -0.05");
+        assert_eq!(rule.script(), "symbol == \"AAPL\" && pct_change < -0.05");
+
+        let mut data = PerformanceIndicators {
+            symbol: "AAPL".to_string(),
+            pct_change: -0.08,
+            ..Default::default()
+        };
+        assert_eq!(rule.matches(&data), Some(true));
+        data.pct_change = 0.01;
+        assert_eq!(rule.matches(&data), Some(false));
+        data.symbol = "MSFT".to_string();
+        assert_eq!(rule.matches(&data), None);
+
+        // a rule without a symbol is about all of them, a missing indicator never crosses
+        let rule: ThresholdRule = "rsi >= 70".parse().unwrap();
+        assert_eq!(rule.script(), "rsi >= 70.0");
+        assert_eq!(rule.matches(&data), Some(false));
+        data.rsi = Some(75.0);
+        assert_eq!(rule.matches(&data), Some(true));
+
+        for invalid in ["price >", "AAPL prize > 200", "price = 200", "price > lots"] {
+            assert!(invalid.parse::<ThresholdRule>().is_err(), "{}", invalid);
+        }
+        let named: NamedRule =
+            serde_json::from_str(r#"{"name": "high", "rule": "price > 200"}"#).unwrap();
+        assert_eq!(
+            serde_json::to_string(&named).unwrap(),
+            r#"{"name":"high","rule":"price > 200"}"#
+        );
+    }
+}
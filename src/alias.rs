@@ -0,0 +1,118 @@
+//!
+//! Aliases of symbols, e.g. `apple=AAPL`. An alias is accepted wherever a symbol is: in
+//! `--symbols`, the config file, and the paths of the API. Everything past that only sees the
+//! ticker.
+//!
+use std::collections::BTreeMap;
+
+use anyhow::bail;
+
+///
+/// Aliases by their lower-case name, and the ticker each stands for
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Aliases {
+    tickers: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    ///
+    /// Aliases from `alias → ticker` pairs. An alias can only stand for one ticker, and not
+    /// for another alias.
+    ///
+    pub fn new<I>(aliases: I) -> anyhow::Result<Self>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut tickers: BTreeMap<String, String> = BTreeMap::new();
+        for (alias, ticker) in aliases {
+            let (alias, ticker) = (alias.trim(), ticker.trim());
+            if alias.is_empty() || ticker.is_empty() {
+                bail!("Invalid alias '{}={}'", alias, ticker);
+            }
+            match tickers.insert(alias.to_lowercase(), ticker.to_string()) {
+                Some(other) if other != ticker => {
+                    bail!("'{}' is an alias of both {} and {}", alias, other, ticker)
+                }
+                _ => {}
+            }
+        }
+        for (alias, ticker) in &tickers {
+            let chained = tickers.get(&ticker.to_lowercase());
+            if chained.is_some_and(|t| t != ticker) {
+                bail!("'{}' is an alias of the alias {}", alias, ticker);
+            }
+        }
+        Ok(Aliases { tickers })
+    }
+
+    ///
+    /// Splits a command line alias, e.g. `apple=AAPL`
+    ///
+    pub fn parse_arg(arg: &str) -> anyhow::Result<(String, String)> {
+        match arg.split_once('=') {
+            Some((alias, ticker)) => Ok((alias.to_string(), ticker.to_string())),
+            None => bail!("Invalid alias '{}', expected e.g. apple=AAPL", arg),
+        }
+    }
+
+    ///
+    /// The ticker of an alias (regardless of case), anything else as it is
+    ///
+    pub fn resolve(&self, symbol: &str) -> String {
+        self.tickers
+            .get(&symbol.trim().to_lowercase())
+            .cloned()
+            .unwrap_or_else(|| symbol.to_string())
+    }
+
+    ///
+    /// The aliases of a ticker
+    ///
+    pub fn of(&self, ticker: &str) -> Vec<String> {
+        self.tickers
+            .iter()
+            .filter(|(_, t)| *t == ticker)
+            .map(|(alias, _)| alias.clone())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tickers.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(pairs: &[(&str, &str)]) -> anyhow::Result<Aliases> {
+        Aliases::new(
+            pairs
+                .iter()
+                .map(|(alias, ticker)| (alias.to_string(), ticker.to_string())),
+        )
+    }
+
+    #[test]
+    fn test_aliases() {
+        let aliases =
+            aliases(&[("apple", "AAPL"), ("Alphabet", "GOOG"), ("google", "GOOG")]).unwrap();
+        assert_eq!(aliases.resolve("apple"), "AAPL");
+        assert_eq!(aliases.resolve("APPLE"), "AAPL");
+        assert_eq!(aliases.resolve("alphabet"), "GOOG");
+        assert_eq!(aliases.resolve("MSFT"), "MSFT");
+        assert_eq!(aliases.of("GOOG"), vec!["alphabet", "google"]);
+        assert!(aliases.of("MSFT").is_empty());
+
+        assert_eq!(
+            Aliases::parse_arg("apple=AAPL").unwrap(),
+            ("apple".to_string(), "AAPL".to_string())
+        );
+        assert!(Aliases::parse_arg("apple").is_err());
+        assert!(self::aliases(&[("apple", "AAPL"), ("Apple", "APLE")]).is_err());
+        assert!(self::aliases(&[("fruit", "apple"), ("apple", "AAPL")]).is_err());
+        assert!(self::aliases(&[("", "AAPL")]).is_err());
+        assert!(self::aliases(&[("apple", "AAPL"), ("aapl", "AAPL")]).is_ok());
+    }
+}
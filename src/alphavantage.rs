@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use chrono::prelude::*;
+use serde::Deserialize;
+
+use crate::provider::DataProvider;
+use crate::signal::{DataSourceError, TickerQuote};
+
+const URL: &str = "https://www.alphavantage.co/query";
+
+///
+/// The compact response covers the last 100 trading days, older periods need the full one
+///
+const COMPACT_DAYS: i64 = 140;
+
+#[derive(Deserialize)]
+struct DailyBar {
+    #[serde(rename = "1. open")]
+    open: String,
+    #[serde(rename = "2. high")]
+    high: String,
+    #[serde(rename = "3. low")]
+    low: String,
+    #[serde(rename = "4. close")]
+    close: String,
+    #[serde(rename = "5. volume")]
+    volume: String,
+}
+
+#[derive(Deserialize)]
+struct DailyResponse {
+    #[serde(rename = "Time Series (Daily)")]
+    series: Option<BTreeMap<String, DailyBar>>,
+    /// Unknown symbols and invalid calls
+    #[serde(rename = "Error Message")]
+    error: Option<String>,
+    /// The rate limit was hit
+    #[serde(rename = "Note")]
+    note: Option<String>,
+    #[serde(rename = "Information")]
+    information: Option<String>,
+}
+
+///
+/// The daily bars of a `TIME_SERIES_DAILY` response, oldest first. Errors are reported like
+/// HTTP errors, so they are classified like Yahoo's.
+///
+fn parse_daily(body: &str) -> std::result::Result<Vec<TickerQuote>, DataSourceError> {
+    let response: DailyResponse = serde_json::from_str(body)?;
+    if let Some(error) = response.error {
+        return Err(DataSourceError::FetchFailed(format!("404 {}", error)));
+    }
+    if let Some(limit) = response.note.or(response.information) {
+        return Err(DataSourceError::FetchFailed(format!("429 {}", limit)));
+    }
+    let series = response.series.ok_or(DataSourceError::InvalidJson)?;
+    let number = |s: &str| s.parse::<f64>().map_err(|_| DataSourceError::InvalidJson);
+    // the keys are ISO dates, so the map is sorted by time
+    series
+        .iter()
+        .map(|(date, bar)| {
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| DataSourceError::InvalidJson)?;
+            let close = number(&bar.close)?;
+            Ok(TickerQuote {
+                timestamp: date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() as u64,
+                open: number(&bar.open)?,
+                high: number(&bar.high)?,
+                low: number(&bar.low)?,
+                volume: bar
+                    .volume
+                    .parse()
+                    .map_err(|_| DataSourceError::InvalidJson)?,
+                close,
+                adjclose: close,
+            })
+        })
+        .collect()
+}
+
+///
+/// Daily quotes from Alpha Vantage (`TIME_SERIES_DAILY`), which needs an API key
+///
+pub struct AlphaVantageProvider {
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl AlphaVantageProvider {
+    pub fn new(api_key: String) -> Self {
+        AlphaVantageProvider {
+            api_key,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl DataProvider for AlphaVantageProvider {
+    fn name(&self) -> &str {
+        "alphavantage"
+    }
+
+    async fn fetch_quotes(
+        &mut self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> std::result::Result<Vec<TickerQuote>, DataSourceError> {
+        let size = if Utc::now() - from > chrono::Duration::days(COMPACT_DAYS) {
+            "full"
+        } else {
+            "compact"
+        };
+        let response = self
+            .client
+            .get(URL)
+            .query(&[
+                ("function", "TIME_SERIES_DAILY"),
+                ("symbol", symbol),
+                ("outputsize", size),
+                ("apikey", &self.api_key),
+            ])
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(DataSourceError::FetchFailed(response.status().to_string()));
+        }
+        let quotes = parse_daily(&response.text().await?)?;
+        let (from, to) = (from.timestamp() as u64, to.timestamp() as u64);
+        Ok(quotes
+            .into_iter()
+            .filter(|q| (from..=to).contains(&q.timestamp))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::FetchStatus;
+
+    #[test]
+    fn test_parse_daily() {
+        let body = r#"{
+            "Meta Data": {"2. Symbol": "IBM"},
+            "Time Series (Daily)": {
+                "2024-01-05": {"1. open": "160.00", "2. high": "161.50", "3. low": "159.10",
+                               "4. close": "160.90", "5. volume": "3950000"},
+                "2024-01-04": {"1. open": "158.50", "2. high": "160.40", "3. low": "158.00",
+                               "4. close": "160.10", "5. volume": "4100000"}
+            }
+        }"#;
+        let quotes = parse_daily(body).unwrap();
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(quotes[0].timestamp, 1704326400);
+        assert_eq!(quotes[0].close, 160.10);
+        assert_eq!(quotes[1].high, 161.50);
+        assert_eq!(quotes[1].volume, 3950000);
+
+        let status = |body: &str| FetchStatus::from_result(&parse_daily(body));
+        assert_eq!(
+            status(r#"{"Error Message": "Invalid API call."}"#),
+            FetchStatus::NotFound
+        );
+        assert_eq!(
+            status(r#"{"Information": "Our standard API rate limit is 25 requests per day."}"#),
+            FetchStatus::RateLimited
+        );
+        assert!(matches!(status("{}"), FetchStatus::NetworkError(_)));
+    }
+}
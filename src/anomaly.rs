@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::signal::{AsyncStockSignal, ZScore};
+
+///
+/// Published when the latest return of a symbol is unusually large
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub symbol: String,
+    pub watchlist: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub zscore: f64,
+}
+
+///
+/// Flags a symbol when the Z-score of its latest return reaches the threshold
+///
+#[derive(Debug, Clone)]
+pub struct AnomalyDetector {
+    /// Number of returns before the latest one that make up the baseline
+    pub window: usize,
+    pub threshold: f64,
+}
+
+impl AnomalyDetector {
+    ///
+    /// The Z-score of the last return in `closes`, if there is enough data
+    ///
+    pub async fn zscore(&self, closes: &[f64]) -> Option<f64> {
+        let returns: Vec<f64> = closes
+            .windows(2)
+            .filter(|w| w[0] != 0.0)
+            .map(|w| w[1] / w[0] - 1.0)
+            .collect();
+        ZScore {
+            window_size: self.window,
+        }
+        .calculate(&returns)
+        .await
+    }
+
+    pub fn is_anomaly(&self, zscore: f64) -> bool {
+        zscore.abs() >= self.threshold
+    }
+}
+
+///
+/// How the scheduler reacts to an anomaly: the symbol is fetched every `interval` until
+/// `period` has passed without another anomaly.
+///
+#[derive(Debug, Clone, Copy)]
+pub struct Boost {
+    pub interval: Duration,
+    pub period: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_detector() {
+        let detector = AnomalyDetector {
+            window: 5,
+            threshold: 3.0,
+        };
+        let calm = [100.0, 101.0, 100.0, 101.0, 100.0, 101.0, 100.5];
+        let z = detector.zscore(&calm).await.unwrap();
+        assert!(!detector.is_anomaly(z));
+        let jump = [100.0, 101.0, 100.0, 101.0, 100.0, 101.0, 110.0];
+        let z = detector.zscore(&jump).await.unwrap();
+        assert!(detector.is_anomaly(z), "{}", z);
+        assert_eq!(detector.zscore(&jump[..4]).await, None);
+    }
+}
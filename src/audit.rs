@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use tide::{Middleware, Next, Request};
+use xactor::*;
+
+///
+/// How many audit entries are kept in memory for `/audit`
+///
+const RECENT_ENTRIES: usize = 1000;
+
+///
+/// A single audited API call
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    /// The peer address (or forwarded address) of the caller
+    pub remote: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+}
+
+///
+/// Request the `n` most recent audit entries (newest last)
+///
+#[message(result = "Vec<AuditEntry>")]
+pub struct AuditRequest {
+    pub n: usize,
+}
+
+///
+/// Only calls that change state or touch administrative endpoints are audited
+///
+pub fn is_audited(method: &str, path: &str) -> bool {
+    !matches!(method, "GET" | "HEAD" | "OPTIONS")
+        || path.starts_with("/admin")
+        || path.starts_with("/audit")
+}
+
+///
+/// tide middleware that publishes an `AuditEntry` for every audited request
+///
+#[derive(Default, Debug)]
+pub struct AuditMiddleware;
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for AuditMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let method = req.method().to_string();
+        let path = req.url().path().to_string();
+        let remote = req.remote().map(|r| r.to_string());
+        let response = next.run(req).await;
+
+        if is_audited(&method, &path) {
+            let entry = AuditEntry {
+                timestamp: Utc::now(),
+                remote,
+                method,
+                path,
+                status: response.status().into(),
+            };
+            if let Err(e) = Broker::from_registry().await?.publish(entry) {
+                tracing::error!("Could not publish audit entry: {}", e);
+            }
+        }
+        Ok(response)
+    }
+}
+
+///
+/// Actor that keeps the most recent audit entries in memory. `AuditFileSink` persists them
+/// to `filename` as JSON lines, this reads the entries of earlier runs from there.
+///
+#[derive(Default, Debug)]
+pub struct AuditLog {
+    pub filename: String,
+    recent: VecDeque<AuditEntry>,
+}
+
+impl AuditLog {
+    pub fn new(filename: String) -> Self {
+        AuditLog {
+            filename,
+            recent: VecDeque::with_capacity(RECENT_ENTRIES),
+        }
+    }
+
+    fn remember(&mut self, entry: AuditEntry) {
+        if self.recent.len() == RECENT_ENTRIES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(entry);
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for AuditLog {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        // continue where the last run left off
+        if let Ok(file) = File::open(&self.filename) {
+            for line in BufReader::new(file).lines().map_while(|l| l.ok()) {
+                if let Ok(entry) = serde_json::from_str(&line) {
+                    self.remember(entry);
+                }
+            }
+        }
+        ctx.subscribe::<AuditEntry>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<AuditEntry> for AuditLog {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: AuditEntry) {
+        self.remember(msg);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<AuditRequest> for AuditLog {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: AuditRequest) -> Vec<AuditEntry> {
+        let skip = self.recent.len().saturating_sub(msg.n);
+        self.recent.iter().skip(skip).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_audited() {
+        assert!(!is_audited("GET", "/tail/10"));
+        assert!(!is_audited("HEAD", "/metrics"));
+        assert!(is_audited("GET", "/audit"));
+        assert!(is_audited("GET", "/admin/provider"));
+        assert!(is_audited("POST", "/symbols"));
+        assert!(is_audited("DELETE", "/symbols/AAPL"));
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+use std::io::Write;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::checkpoint::Checkpoints;
+use crate::clock::SharedClock;
+use crate::fetch::FetchOutcome;
+use crate::QuoteRequest;
+
+///
+/// Width of the console progress bar in characters
+///
+const BAR_WIDTH: usize = 30;
+
+///
+/// How far the backfill (the first fetch of every symbol since `--from`) has come
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ProgressEvent {
+    pub symbols_total: usize,
+    pub symbols_completed: usize,
+    pub bars_fetched: usize,
+    pub elapsed_secs: f64,
+    /// Estimated seconds until all symbols are fetched, unknown before the first one is
+    pub eta_secs: Option<f64>,
+    pub complete: bool,
+}
+
+impl ProgressEvent {
+    ///
+    /// A single console line like `[#######-------] 12/40 symbols, 5120 bars, ETA 1m 20s`
+    ///
+    pub fn render(&self) -> String {
+        let filled = match self.symbols_total {
+            0 => 0,
+            total => BAR_WIDTH * self.symbols_completed / total,
+        };
+        let eta = match (self.complete, self.eta_secs) {
+            (true, _) => format!("done in {}", duration(self.elapsed_secs)),
+            (false, Some(eta)) => format!("ETA {}", duration(eta)),
+            (false, None) => "ETA ?".to_string(),
+        };
+        format!(
+            "[{}{}] {}/{} symbols, {} bars, {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            self.symbols_completed,
+            self.symbols_total,
+            self.bars_fetched,
+            eta
+        )
+    }
+}
+
+fn duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, m, _) => format!("{}h {}m", h, m),
+    }
+}
+
+///
+/// Tracks the first request of every symbol (and watchlist) until its response arrives
+///
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    started: Option<DateTime<Utc>>,
+    /// When the last pending symbol was completed
+    finished: Option<DateTime<Utc>>,
+    requested: HashSet<String>,
+    pending: HashSet<String>,
+    bars: usize,
+}
+
+impl Progress {
+    ///
+    /// Registers a request, only the first one of a symbol is part of the backfill
+    ///
+    pub fn request(&mut self, request: &QuoteRequest, now: DateTime<Utc>) {
+        let key = Checkpoints::key(&request.symbol, request.watchlist.as_deref());
+        if self.requested.insert(key.clone()) {
+            self.started.get_or_insert(now);
+            self.finished = None;
+            self.pending.insert(key);
+        }
+    }
+
+    ///
+    /// Registers the outcome of a request, returns whether it completed a symbol. Failed
+    /// requests complete a symbol as well, there is nothing more to wait for.
+    ///
+    pub fn response(&mut self, outcome: &FetchOutcome, now: DateTime<Utc>) -> bool {
+        let key = Checkpoints::key(&outcome.symbol, outcome.watchlist.as_deref());
+        let completed = self.pending.remove(&key);
+        if completed {
+            self.bars += outcome.status.bars();
+            if self.pending.is_empty() {
+                self.finished = Some(now);
+            }
+        }
+        completed
+    }
+
+    pub fn event(&self, now: DateTime<Utc>) -> ProgressEvent {
+        let total = self.requested.len();
+        let completed = total - self.pending.len();
+        let elapsed = self
+            .started
+            .map(|started| {
+                (self.finished.unwrap_or(now) - started)
+                    .num_milliseconds()
+                    .max(0) as f64
+                    / 1000.0
+            })
+            .unwrap_or_default();
+        ProgressEvent {
+            symbols_total: total,
+            symbols_completed: completed,
+            bars_fetched: self.bars,
+            elapsed_secs: elapsed,
+            eta_secs: (completed > 0)
+                .then(|| elapsed / completed as f64 * (total - completed) as f64),
+            complete: total > 0 && completed == total,
+        }
+    }
+}
+
+///
+/// Asks the `BackfillTracker` for the current progress
+///
+#[message(result = "ProgressEvent")]
+pub struct BackfillStatusRequest;
+
+///
+/// Actor that follows the backfill and publishes a `ProgressEvent` whenever a symbol is done
+///
+pub struct BackfillTracker {
+    progress: Progress,
+    clock: SharedClock,
+}
+
+impl BackfillTracker {
+    pub fn new(clock: SharedClock) -> Self {
+        BackfillTracker {
+            progress: Progress::default(),
+            clock,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for BackfillTracker {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<QuoteRequest>().await?;
+        ctx.subscribe::<FetchOutcome>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<QuoteRequest> for BackfillTracker {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: QuoteRequest) {
+        self.progress.request(&msg, self.clock.now());
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<FetchOutcome> for BackfillTracker {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: FetchOutcome) {
+        if self.progress.response(&msg, self.clock.now()) {
+            let event = self.progress.event(self.clock.now());
+            if let Err(e) = Broker::from_registry().await.unwrap().publish(event) {
+                tracing::error!("{}", e);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<BackfillStatusRequest> for BackfillTracker {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: BackfillStatusRequest,
+    ) -> ProgressEvent {
+        self.progress.event(self.clock.now())
+    }
+}
+
+///
+/// Actor that draws the backfill progress on the console and stops when it is complete
+///
+pub struct ProgressBar;
+
+#[async_trait::async_trait]
+impl Actor for ProgressBar {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<ProgressEvent>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<ProgressEvent> for ProgressBar {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: ProgressEvent) {
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r{}", msg.render());
+        let _ = stderr.flush();
+        if msg.complete {
+            eprintln!();
+            ctx.stop(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::FetchStatus;
+
+    #[test]
+    fn test_progress() {
+        let start = Utc.with_ymd_and_hms(2022, 12, 2, 15, 0, 0).unwrap();
+        let request = |symbol: &str| QuoteRequest {
+            symbol: symbol.to_string(),
+            from: start,
+            to: start,
+            watchlist: None,
+        };
+        let response = |symbol: &str, bars: usize| FetchOutcome {
+            symbol: symbol.to_string(),
+            watchlist: None,
+            provider: "yahoo".to_string(),
+            status: match bars {
+                0 => FetchStatus::NotFound,
+                bars => FetchStatus::Fetched { bars },
+            },
+        };
+        let mut progress = Progress::default();
+        assert!(!progress.event(start).complete);
+        for symbol in ["AAPL", "MSFT", "GOOG", "UBER"] {
+            progress.request(&request(symbol), start);
+        }
+        let later = |secs| start + chrono::Duration::seconds(secs);
+        assert!(progress.response(&response("AAPL", 100), later(10)));
+        assert!(!progress.response(&response("AAPL", 100), later(10)));
+        let event = progress.event(later(10));
+        assert_eq!(event.symbols_total, 4);
+        assert_eq!(event.symbols_completed, 1);
+        assert_eq!(event.bars_fetched, 100);
+        assert_eq!(event.eta_secs, Some(30.0));
+        assert_eq!(
+            event.render(),
+            "[#######-----------------------] 1/4 symbols, 100 bars, ETA 30s"
+        );
+
+        // later fetches of a symbol are not part of the backfill
+        progress.request(&request("AAPL"), start);
+        for symbol in ["MSFT", "GOOG"] {
+            assert!(progress.response(&response(symbol, 50), later(75)));
+        }
+        assert!(progress.response(&response("UBER", 0), later(75)));
+        // the elapsed time stops with the last symbol
+        let event = progress.event(later(300));
+        assert!(event.complete);
+        assert_eq!(event.bars_fetched, 200);
+        assert_eq!(event.eta_secs, Some(0.0));
+        assert!(event
+            .render()
+            .ends_with("4/4 symbols, 200 bars, done in 1m 15s"));
+    }
+}
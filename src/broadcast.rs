@@ -0,0 +1,133 @@
+//!
+//! Live updates for dashboards: `/stream` sends every new row of indicators as a Server-Sent
+//! Event, optionally only those of one symbol (`/stream?symbol=AAPL`).
+//!
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use futures::{Stream, StreamExt};
+use xactor::*;
+
+use crate::PerformanceIndicators;
+
+///
+/// Rows a client can fall behind before it misses some
+///
+const CLIENT_BACKLOG: usize = 1024;
+
+///
+/// Connect a client, it receives every row published from now on
+///
+#[message(result = "Receiver<PerformanceIndicators>")]
+pub struct Connect {
+    /// Only rows of this symbol, if set
+    pub symbol: Option<String>,
+}
+
+struct Client {
+    symbol: Option<String>,
+    sender: Sender<PerformanceIndicators>,
+}
+
+///
+/// Actor that forwards the published indicators to the connected clients. Disconnected clients
+/// are dropped with the next row.
+///
+#[derive(Default)]
+pub struct Broadcaster {
+    clients: Vec<Client>,
+}
+
+#[async_trait::async_trait]
+impl Actor for Broadcaster {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Connect> for Broadcaster {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: Connect,
+    ) -> Receiver<PerformanceIndicators> {
+        let (sender, receiver) = channel::bounded(CLIENT_BACKLOG);
+        self.clients.push(Client {
+            symbol: msg.symbol,
+            sender,
+        });
+        receiver
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for Broadcaster {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        self.clients.retain(|client| {
+            if client.symbol.as_ref().is_some_and(|s| s != &msg.symbol) {
+                return !client.sender.is_closed();
+            }
+            // a client that is too slow misses the row, but never holds up the others
+            !matches!(
+                client.sender.try_send(msg.clone()),
+                Err(TrySendError::Closed(_))
+            )
+        });
+    }
+}
+
+///
+/// Formats the rows as Server-Sent Events
+///
+pub fn events<F>(
+    rows: Receiver<PerformanceIndicators>,
+    mut format: F,
+) -> impl Stream<Item = std::io::Result<String>>
+where
+    F: FnMut(PerformanceIndicators) -> serde_json::Result<String>,
+{
+    rows.map(move |row| {
+        let json = format(row)?;
+        Ok(format!("event: indicators\ndata: {}\n\n", json))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(symbol: &str) -> PerformanceIndicators {
+        PerformanceIndicators {
+            symbol: symbol.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[async_std::test]
+    async fn test_broadcaster() {
+        let broadcaster = Broadcaster::default().start().await.unwrap();
+        let all = broadcaster.call(Connect { symbol: None }).await.unwrap();
+        let aapl = broadcaster
+            .call(Connect {
+                symbol: Some("AAPL".to_string()),
+            })
+            .await
+            .unwrap();
+        let gone = broadcaster.call(Connect { symbol: None }).await.unwrap();
+        drop(gone);
+        broadcaster.send(row("MSFT")).unwrap();
+        broadcaster.send(row("AAPL")).unwrap();
+
+        let mut events = Box::pin(events(all, |r| Ok(r.symbol)));
+        assert_eq!(
+            events.next().await.unwrap().unwrap(),
+            "event: indicators\ndata: MSFT\n\n"
+        );
+        assert_eq!(
+            events.next().await.unwrap().unwrap(),
+            "event: indicators\ndata: AAPL\n\n"
+        );
+        assert_eq!(aapl.recv().await.unwrap().symbol, "AAPL");
+        assert!(aapl.is_empty());
+    }
+}
@@ -1,23 +1,240 @@
 use std::cmp::min;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 
+use chrono::prelude::*;
+use clap::ArgEnum;
 use xactor::*;
 
 use crate::PerformanceIndicators;
 
+///
+/// What a full buffer does with new indicators
+///
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// The oldest indicators make room
+    #[default]
+    DropOldest,
+    /// New indicators are dropped until the buffer is drained
+    DropNewest,
+    /// The oldest indicators are folded into the next ones of the same symbol
+    AggregateOldest,
+}
+
+///
+/// What a full buffer did to make room
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Shed {
+    DroppedOldest,
+    DroppedNewest,
+    Aggregated,
+}
+
+impl Shed {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Shed::DroppedOldest => "dropped_oldest",
+            Shed::DroppedNewest => "dropped_newest",
+            Shed::Aggregated => "aggregated",
+        }
+    }
+}
+
+///
+/// Published for every row a full buffer sheds
+///
+#[message]
+#[derive(Debug, Clone)]
+pub struct BufferOverflow {
+    pub watchlist: Option<String>,
+    pub action: Shed,
+}
+
+///
+/// Published whenever the number of rows in a buffer changes
+///
+#[message]
+#[derive(Debug, Clone)]
+pub struct BufferLevel {
+    pub watchlist: Option<String>,
+    pub rows: usize,
+}
+
 pub struct BufferSink {
     pub data_sink: VecDeque<PerformanceIndicators>,
+    /// Only indicators of this watchlist are stored, `None` for the default pipeline
+    pub watchlist: Option<String>,
+    /// The latest indicators of every symbol (at the fetched resolution), kept when the
+    /// buffer is drained
+    pub latest: HashMap<String, PerformanceIndicators>,
+    /// Rows the buffer holds at most (0 for no limit)
+    pub capacity: usize,
+    pub overflow: Overflow,
 }
 
-#[message(result="Vec<PerformanceIndicators>")]
-pub struct BufferDataRequest {
-    pub n: usize,
+impl BufferSink {
+    pub fn new(watchlist: Option<String>, capacity: usize, overflow: Overflow) -> Self {
+        BufferSink {
+            data_sink: VecDeque::new(),
+            watchlist,
+            latest: HashMap::new(),
+            capacity,
+            overflow,
+        }
+    }
+
+    ///
+    /// Buffers the indicators, returns what was shed if the buffer was full
+    ///
+    fn push(&mut self, indicators: PerformanceIndicators) -> Option<Shed> {
+        self.index(&indicators);
+        if self.capacity == 0 || self.data_sink.len() < self.capacity {
+            self.data_sink.push_back(indicators);
+            return None;
+        }
+        if self.overflow == Overflow::DropNewest {
+            return Some(Shed::DroppedNewest);
+        }
+        let oldest = self.data_sink.pop_front()?;
+        self.data_sink.push_back(indicators);
+        if self.overflow == Overflow::DropOldest {
+            return Some(Shed::DroppedOldest);
+        }
+        let newer = self
+            .data_sink
+            .iter_mut()
+            .find(|r| r.symbol == oldest.symbol && r.resolution == oldest.resolution);
+        match newer {
+            Some(newer) => {
+                roll_up(newer, &oldest);
+                Some(Shed::Aggregated)
+            }
+            None => Some(Shed::DroppedOldest),
+        }
+    }
+
+    ///
+    /// The buffered indicators of a symbol newer than `since`, oldest first
+    ///
+    fn changes(&self, symbol: &str, since: Option<DateTime<Utc>>) -> Vec<PerformanceIndicators> {
+        self.data_sink
+            .iter()
+            .filter(|r| r.symbol == symbol && since.is_none_or(|since| r.timestamp > since))
+            .cloned()
+            .collect()
+    }
+
+    ///
+    /// Reports the number of buffered rows to the metrics
+    ///
+    async fn report_level(&self) {
+        let level = BufferLevel {
+            watchlist: self.watchlist.clone(),
+            rows: self.data_sink.len(),
+        };
+        if let Ok(mut broker) = Broker::from_registry().await {
+            let _ = broker.publish(level);
+        }
+    }
+
+    fn index(&mut self, indicators: &PerformanceIndicators) {
+        if indicators.resolution.is_some() {
+            return;
+        }
+        match self.latest.get(&indicators.symbol) {
+            Some(latest) if latest.timestamp > indicators.timestamp => {}
+            _ => {
+                self.latest
+                    .insert(indicators.symbol.clone(), indicators.clone());
+            }
+        }
+    }
+}
+
+///
+/// Request up to `n` buffered indicators, oldest first
+///
+#[message(result = "Vec<PerformanceIndicators>")]
+pub enum BufferDataRequest {
+    /// The latest `n`, leaving them in the buffer for other clients
+    Peek { n: usize },
+    /// The oldest `n`, removing them from the buffer
+    Drain { n: usize },
+}
+
+///
+/// Request a copy of everything in the buffer without removing it
+///
+#[message(result = "Vec<PerformanceIndicators>")]
+pub struct BufferSnapshotRequest;
+
+///
+/// Request the latest indicators of a symbol
+///
+#[message(result = "Option<PerformanceIndicators>")]
+pub struct LatestRequest {
+    pub symbol: String,
+}
+
+///
+/// Request the buffered indicators of a symbol newer than `since` (all without it), leaving
+/// them in the buffer
+///
+#[message(result = "Vec<PerformanceIndicators>")]
+pub struct ChangesRequest {
+    pub symbol: String,
+    pub since: Option<DateTime<Utc>>,
+}
+
+///
+/// Request the indicators of bars before `before`, removing them from the buffer
+///
+#[message(result = "Vec<PerformanceIndicators>")]
+pub struct EvictRequest {
+    pub before: DateTime<Utc>,
+}
+
+///
+/// Request the number of buffered indicators
+///
+#[message(result = "usize")]
+pub struct BufferLenRequest;
+
+///
+/// Put previously snapshotted data back in front of the buffer
+///
+#[message]
+pub struct BufferRestore {
+    pub data: Vec<PerformanceIndicators>,
+}
+
+///
+/// Folds older indicators of a symbol into newer ones: the newer values stand, the period's
+/// range covers both
+///
+fn roll_up(newer: &mut PerformanceIndicators, older: &PerformanceIndicators) {
+    newer.period_min = newer.period_min.min(older.period_min);
+    newer.period_max = newer.period_max.max(older.period_max);
+    newer.rolled_up = Some(newer.rolled_up.unwrap_or(0) + older.rolled_up.unwrap_or(0) + 1);
 }
 
 #[async_trait::async_trait]
 impl Handler<PerformanceIndicators> for BufferSink {
     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
-        self.data_sink.push_back(msg)
+        if msg.watchlist != self.watchlist {
+            return;
+        }
+        if let Some(action) = self.push(msg) {
+            let overflow = BufferOverflow {
+                watchlist: self.watchlist.clone(),
+                action,
+            };
+            if let Err(e) = Broker::from_registry().await.unwrap().publish(overflow) {
+                tracing::error!("{}", e);
+            }
+        }
+        self.report_level().await;
     }
 }
 
@@ -28,22 +245,168 @@ impl Handler<BufferDataRequest> for BufferSink {
         _ctx: &mut Context<Self>,
         msg: BufferDataRequest,
     ) -> Vec<PerformanceIndicators> {
-        let mut resp: Vec<PerformanceIndicators> = vec![];
-        let max_amount = min(msg.n, self.data_sink.len());
-        for i in 0..max_amount {
-            if let Some(v) = self.data_sink.pop_front() {
-                resp.push(v)
-            } else {
-                break
+        match msg {
+            BufferDataRequest::Peek { n } => {
+                let skip = self.data_sink.len().saturating_sub(n);
+                self.data_sink.iter().skip(skip).cloned().collect()
+            }
+            BufferDataRequest::Drain { n } => {
+                let max_amount = min(n, self.data_sink.len());
+                let drained = self.data_sink.drain(..max_amount).collect();
+                self.report_level().await;
+                drained
             }
         }
-        resp
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<BufferSnapshotRequest> for BufferSink {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: BufferSnapshotRequest,
+    ) -> Vec<PerformanceIndicators> {
+        self.data_sink.iter().cloned().collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<LatestRequest> for BufferSink {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: LatestRequest,
+    ) -> Option<PerformanceIndicators> {
+        self.latest.get(&msg.symbol).cloned()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<ChangesRequest> for BufferSink {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: ChangesRequest,
+    ) -> Vec<PerformanceIndicators> {
+        self.changes(&msg.symbol, msg.since)
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<EvictRequest> for BufferSink {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: EvictRequest,
+    ) -> Vec<PerformanceIndicators> {
+        let (evicted, kept): (Vec<_>, Vec<_>) = self
+            .data_sink
+            .drain(..)
+            .partition(|r| r.timestamp < msg.before);
+        self.data_sink = kept.into();
+        if !evicted.is_empty() {
+            self.report_level().await;
+        }
+        evicted
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<BufferLenRequest> for BufferSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: BufferLenRequest) -> usize {
+        self.data_sink.len()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<BufferRestore> for BufferSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: BufferRestore) {
+        for item in msg.data.into_iter().rev() {
+            self.index(&item);
+            self.data_sink.push_front(item);
+        }
+        self.report_level().await;
     }
 }
 
 #[async_trait::async_trait]
 impl Actor for BufferSink {
     async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
         ctx.subscribe::<PerformanceIndicators>().await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(symbol: &str, price: f64) -> PerformanceIndicators {
+        PerformanceIndicators {
+            symbol: symbol.to_string(),
+            price,
+            period_min: price,
+            period_max: price,
+            ..Default::default()
+        }
+    }
+
+    fn prices(buffer: &BufferSink) -> Vec<f64> {
+        buffer.data_sink.iter().map(|r| r.price).collect()
+    }
+
+    #[test]
+    fn test_overflow() {
+        let mut buffer = BufferSink::new(None, 2, Overflow::DropOldest);
+        assert_eq!(buffer.push(row("A", 1.0)), None);
+        assert_eq!(buffer.push(row("A", 2.0)), None);
+        assert_eq!(buffer.push(row("A", 3.0)), Some(Shed::DroppedOldest));
+        assert_eq!(prices(&buffer), vec![2.0, 3.0]);
+
+        let mut buffer = BufferSink::new(None, 2, Overflow::DropNewest);
+        buffer.push(row("A", 1.0));
+        buffer.push(row("A", 2.0));
+        assert_eq!(buffer.push(row("A", 3.0)), Some(Shed::DroppedNewest));
+        assert_eq!(prices(&buffer), vec![1.0, 2.0]);
+        // the latest indicators are kept anyway
+        assert_eq!(buffer.latest["A"].price, 3.0);
+
+        let mut buffer = BufferSink::new(None, 3, Overflow::AggregateOldest);
+        buffer.push(row("A", 1.0));
+        buffer.push(row("B", 5.0));
+        buffer.push(row("A", 4.0));
+        assert_eq!(buffer.push(row("A", 3.0)), Some(Shed::Aggregated));
+        assert_eq!(prices(&buffer), vec![5.0, 4.0, 3.0]);
+        let rollup = &buffer.data_sink[1];
+        assert_eq!((rollup.period_min, rollup.period_max), (1.0, 4.0));
+        assert_eq!(rollup.rolled_up, Some(1));
+        // nothing newer of the symbol to fold into
+        assert_eq!(buffer.push(row("C", 2.0)), Some(Shed::DroppedOldest));
+        assert_eq!(prices(&buffer), vec![4.0, 3.0, 2.0]);
+
+        let mut buffer = BufferSink::new(None, 0, Overflow::DropNewest);
+        for i in 0..100 {
+            assert_eq!(buffer.push(row("A", i as f64)), None);
+        }
+    }
+
+    #[test]
+    fn test_changes() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 2, 14, 30, 0).unwrap();
+        let mut buffer = BufferSink::new(None, 0, Overflow::default());
+        for (minutes, symbol, price) in [(0, "A", 1.0), (1, "B", 2.0), (2, "A", 3.0)] {
+            buffer.push(PerformanceIndicators {
+                timestamp: start + chrono::Duration::minutes(minutes),
+                ..row(symbol, price)
+            });
+        }
+        let changes = |since| buffer.changes("A", since);
+        assert_eq!(changes(None).len(), 2);
+        let newer = changes(Some(start));
+        assert_eq!(newer.len(), 1);
+        assert_eq!(newer[0].price, 3.0);
+        assert!(changes(Some(start + chrono::Duration::minutes(2))).is_empty());
+        assert!(buffer.changes("C", None).is_empty());
+    }
+}
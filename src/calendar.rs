@@ -0,0 +1,202 @@
+//!
+//! Exchange holidays and early closes. The NYSE calendar is embedded; a TOML file can add or
+//! change days, or replace it with the calendar of another exchange:
+//!
+//! ```toml
+//! replace = false         # keep the embedded days (the default)
+//!
+//! [days]
+//! "2024-11-28" = "closed" # a holiday
+//! "2024-11-29" = "18:00"  # a half-day, closing at 18:00 UTC
+//! "2025-01-09" = "open"   # an embedded holiday that isn't one
+//! ```
+//!
+//! Dates and times are UTC, like the sessions of the gap detection.
+//!
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use anyhow::Context;
+use chrono::prelude::*;
+use serde::Deserialize;
+
+///
+/// NYSE (and Nasdaq) holidays and half-days
+///
+const NYSE: &str = include_str!("../holidays.nyse.toml");
+
+///
+/// How an exchange deviates from a regular trading day
+///
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum Closing {
+    /// A holiday
+    Closed,
+    /// A half-day that closes at this time (UTC)
+    Early(NaiveTime),
+    /// A regular trading day, to remove an embedded holiday
+    Open,
+}
+
+impl TryFrom<String> for Closing {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.trim() {
+            "closed" => Ok(Closing::Closed),
+            "open" => Ok(Closing::Open),
+            time => NaiveTime::parse_from_str(time, "%H:%M")
+                .map(Closing::Early)
+                .map_err(|_| format!("expected closed, open, or a time (HH:MM), not '{}'", s)),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct CalendarFile {
+    #[serde(default)]
+    replace: bool,
+    #[serde(default)]
+    days: BTreeMap<NaiveDate, Closing>,
+}
+
+///
+/// The holidays and half-days of an exchange. Weekends are closed in any case.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Calendar {
+    days: BTreeMap<NaiveDate, Closing>,
+}
+
+impl Calendar {
+    ///
+    /// The embedded NYSE calendar
+    ///
+    pub fn nyse() -> Self {
+        let file: CalendarFile = toml::from_str(NYSE).expect("Invalid embedded calendar");
+        Calendar { days: file.days }
+    }
+
+    ///
+    /// The NYSE calendar, with the days of the overriding file if there is one
+    ///
+    pub fn load(overrides: Option<&str>) -> anyhow::Result<Self> {
+        let mut calendar = Calendar::nyse();
+        if let Some(path) = overrides {
+            let text = std::fs::read_to_string(path)
+                .with_context(|| format!("Could not read holidays '{}'", path))?;
+            let file: CalendarFile =
+                toml::from_str(&text).with_context(|| format!("Invalid holidays '{}'", path))?;
+            calendar.apply(file);
+        }
+        Ok(calendar)
+    }
+
+    fn apply(&mut self, file: CalendarFile) {
+        if file.replace {
+            self.days.clear();
+        }
+        for (date, closing) in file.days {
+            match closing {
+                Closing::Open => self.days.remove(&date),
+                closing => self.days.insert(date, closing),
+            };
+        }
+    }
+
+    ///
+    /// Whether the exchange is open on a day (not on weekends and holidays)
+    ///
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        date.weekday().number_from_monday() <= 5 && self.days.get(&date) != Some(&Closing::Closed)
+    }
+
+    ///
+    /// The time a half-day closes at
+    ///
+    pub fn early_close(&self, date: NaiveDate) -> Option<NaiveTime> {
+        match self.days.get(&date) {
+            Some(Closing::Early(time)) => Some(*time),
+            _ => None,
+        }
+    }
+
+    ///
+    /// When the session of a day closes: at the early close of a half-day, at the end of the
+    /// (UTC) day otherwise
+    ///
+    pub fn session_close(&self, date: NaiveDate) -> DateTime<Utc> {
+        match self.early_close(date) {
+            Some(close) => date.and_time(close).and_utc(),
+            None => (date + chrono::Duration::days(1))
+                .and_time(NaiveTime::MIN)
+                .and_utc(),
+        }
+    }
+
+    ///
+    /// Whether a time is on a holiday or after the close of a half-day. Weekends are left to the
+    /// schedules, e.g. for symbols that trade around the clock.
+    ///
+    pub fn is_closed(&self, time: DateTime<Utc>) -> bool {
+        match self.days.get(&time.date_naive()) {
+            Some(Closing::Closed) => true,
+            Some(Closing::Early(close)) => time.time() >= *close,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn test_calendar() {
+        let nyse = Calendar::nyse();
+        // Thanksgiving, and the half-day after
+        assert!(!nyse.is_trading_day(date(2024, 11, 28)));
+        assert!(nyse.is_trading_day(date(2024, 11, 29)));
+        assert!(!nyse.is_trading_day(date(2024, 11, 30)));
+        assert_eq!(
+            nyse.early_close(date(2024, 11, 29)),
+            NaiveTime::from_hms_opt(18, 0, 0)
+        );
+        let at = |h| Utc.with_ymd_and_hms(2024, 11, 29, h, 0, 0).unwrap();
+        assert!(!nyse.is_closed(at(17)));
+        assert!(nyse.is_closed(at(18)));
+        assert!(nyse.is_closed(Utc.with_ymd_and_hms(2024, 11, 28, 15, 0, 0).unwrap()));
+        assert_eq!(nyse.session_close(date(2024, 11, 29)), at(18));
+        assert_eq!(
+            nyse.session_close(date(2024, 11, 27)),
+            Utc.with_ymd_and_hms(2024, 11, 28, 0, 0, 0).unwrap()
+        );
+
+        let mut calendar = nyse.clone();
+        calendar.apply(
+            toml::from_str(
+                r#"
+                [days]
+                "2025-01-09" = "open"
+                "2024-11-29" = "closed"
+                "#,
+            )
+            .unwrap(),
+        );
+        assert!(calendar.is_trading_day(date(2025, 1, 9)));
+        assert!(!calendar.is_trading_day(date(2024, 11, 29)));
+        assert!(!calendar.is_trading_day(date(2024, 11, 28)));
+
+        let mut calendar = nyse;
+        calendar.apply(toml::from_str("replace = true").unwrap());
+        assert_eq!(calendar, Calendar::default());
+
+        assert!(toml::from_str::<CalendarFile>("[days]\n\"2024-11-29\" = \"noon\"").is_err());
+    }
+}
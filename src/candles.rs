@@ -0,0 +1,271 @@
+//!
+//! Transformations of the fetched candles before the signals are calculated over them.
+//!
+//! Heikin-Ashi candles average each bar with the one before, which smooths out the noise that
+//! trips up trend-following rules:
+//!
+//! - close = (open + high + low + close) / 4
+//! - open = (previous open + previous close) / 2, of the Heikin-Ashi bars (the first bar takes
+//!   the middle of its own open and close)
+//! - high = the highest of the high, the open, and the close
+//! - low = the lowest of the low, the open, and the close
+//!
+//! Renko bricks ignore time and only follow the closes: a brick of a fixed size (a price or a
+//! percentage of the previous brick's close) is added whenever the close moved a full brick
+//! beyond the last one. Turning around takes a move of two bricks, since the next brick down
+//! starts at the bottom of the last brick up. The first bar is a brick without height at the
+//! first close, so the series is never empty.
+//!
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+use serde::Deserialize;
+
+use crate::signal::TickerQuote;
+
+///
+/// The candles the signals are calculated over
+///
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(try_from = "String")]
+pub enum Candles {
+    /// The bars as fetched
+    #[default]
+    Raw,
+    HeikinAshi,
+    /// Bricks of a size, e.g. `renko:2` or `renko:1%`
+    Renko(BrickSize),
+}
+
+///
+/// Height of a Renko brick
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrickSize {
+    Price(f64),
+    /// Percent of the close of the previous brick
+    Percent(f64),
+}
+
+impl BrickSize {
+    fn at(&self, price: f64) -> f64 {
+        match self {
+            BrickSize::Price(size) => *size,
+            BrickSize::Percent(percent) => price.abs() * percent / 100.0,
+        }
+    }
+}
+
+impl std::str::FromStr for Candles {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(size) = s.strip_prefix("renko:") {
+            let (number, percent) = match size.trim().strip_suffix('%') {
+                Some(number) => (number, true),
+                None => (size, false),
+            };
+            let size: f64 = match number.trim().parse() {
+                Ok(size) if size > 0.0 && f64::is_finite(size) => size,
+                _ => anyhow::bail!("Invalid brick size '{}', expected e.g. 2 or 1%", size),
+            };
+            return Ok(Candles::Renko(if percent {
+                BrickSize::Percent(size)
+            } else {
+                BrickSize::Price(size)
+            }));
+        }
+        match s {
+            "raw" => Ok(Candles::Raw),
+            "heikin-ashi" => Ok(Candles::HeikinAshi),
+            _ => anyhow::bail!(
+                "Unknown candles '{}', expected raw, heikin-ashi, or renko:<size>",
+                s
+            ),
+        }
+    }
+}
+
+impl TryFrom<String> for Candles {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Candles {
+    ///
+    /// Transforms quotes sorted by time
+    ///
+    pub fn apply<'a>(&self, quotes: &'a [TickerQuote]) -> Cow<'a, [TickerQuote]> {
+        match self {
+            Candles::Raw => Cow::Borrowed(quotes),
+            Candles::HeikinAshi => Cow::Owned(heikin_ashi(quotes)),
+            Candles::Renko(size) => Cow::Owned(renko(quotes, size)),
+        }
+    }
+}
+
+///
+/// Converts quotes (sorted by time) into Heikin-Ashi bars
+///
+pub fn heikin_ashi(quotes: &[TickerQuote]) -> Vec<TickerQuote> {
+    let mut bars: Vec<TickerQuote> = Vec::with_capacity(quotes.len());
+    for quote in quotes {
+        let close = (quote.open + quote.high + quote.low + quote.close) / 4.0;
+        let open = match bars.last() {
+            Some(previous) => (previous.open + previous.close) / 2.0,
+            None => (quote.open + quote.close) / 2.0,
+        };
+        bars.push(TickerQuote {
+            open,
+            high: quote.high.max(open).max(close),
+            low: quote.low.min(open).min(close),
+            close,
+            adjclose: close,
+            ..quote.clone()
+        });
+    }
+    bars
+}
+
+///
+/// Converts quotes (sorted by time) into Renko bricks. A brick has the timestamp of the quote
+/// that completed it and the volume traded since the previous brick.
+///
+pub fn renko(quotes: &[TickerQuote], size: &BrickSize) -> Vec<TickerQuote> {
+    let first = match quotes.first() {
+        Some(first) => first,
+        None => return vec![],
+    };
+    let brick = |quote: &TickerQuote, open: f64, close: f64, volume: u64| TickerQuote {
+        timestamp: quote.timestamp,
+        open,
+        high: open.max(close),
+        low: open.min(close),
+        volume,
+        close,
+        adjclose: close,
+    };
+    let mut bricks = vec![brick(first, first.close, first.close, first.volume)];
+    let mut volume = 0;
+    for quote in &quotes[1..] {
+        volume += quote.volume;
+        loop {
+            let last = bricks.last().unwrap();
+            let (low, high) = (last.low, last.high);
+            let height = size.at(last.close);
+            if !(height > 0.0 && height.is_finite()) {
+                break;
+            }
+            let next = if quote.close >= high + height {
+                brick(quote, high, high + height, volume)
+            } else if quote.close <= low - height {
+                brick(quote, low, low - height, volume)
+            } else {
+                break;
+            };
+            bricks.push(next);
+            volume = 0;
+        }
+    }
+    bricks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(timestamp: u64, open: f64, high: f64, low: f64, close: f64) -> TickerQuote {
+        TickerQuote {
+            timestamp,
+            open,
+            high,
+            low,
+            volume: 10,
+            close,
+            adjclose: close,
+        }
+    }
+
+    #[test]
+    fn test_heikin_ashi() {
+        let quotes = vec![
+            quote(1, 10.0, 12.0, 9.0, 11.0),
+            quote(2, 11.0, 14.0, 10.0, 13.0),
+            quote(3, 13.0, 13.5, 8.0, 9.0),
+        ];
+        let bars = heikin_ashi(&quotes);
+        assert_eq!(bars[0].open, 10.5);
+        assert_eq!(bars[0].close, 10.5);
+        assert_eq!(bars[1].open, 10.5);
+        assert_eq!(bars[1].close, 12.0);
+        assert_eq!((bars[1].high, bars[1].low), (14.0, 10.0));
+        assert_eq!(bars[2].open, 11.25);
+        assert_eq!(bars[2].close, 10.875);
+        assert_eq!((bars[2].high, bars[2].low), (13.5, 8.0));
+        assert_eq!(bars[2].volume, 10);
+        assert_eq!(heikin_ashi(&quotes[..2]), bars[..2]);
+
+        assert!(matches!(Candles::Raw.apply(&quotes), Cow::Borrowed(_)));
+        assert_eq!(
+            "heikin-ashi".parse::<Candles>().unwrap(),
+            Candles::HeikinAshi
+        );
+    }
+
+    #[test]
+    fn test_renko() {
+        let closes = [10.0, 10.5, 12.2, 13.9, 13.0, 12.1, 10.9, 9.5];
+        let quotes: Vec<TickerQuote> = closes
+            .iter()
+            .enumerate()
+            .map(|(i, close)| quote(i as u64, *close, *close, *close, *close))
+            .collect();
+        let bricks = renko(&quotes, &BrickSize::Price(1.0));
+        let series: Vec<(u64, f64, f64)> = bricks
+            .iter()
+            .map(|b| (b.timestamp, b.open, b.close))
+            .collect();
+        assert_eq!(
+            series,
+            vec![
+                (0, 10.0, 10.0),
+                (2, 10.0, 11.0),
+                (2, 11.0, 12.0),
+                (3, 12.0, 13.0),
+                // a turn needs two bricks, from 13 to 11
+                (6, 12.0, 11.0),
+                (7, 11.0, 10.0),
+            ]
+        );
+        // the volume since the previous brick goes to the first new one
+        assert_eq!(
+            (bricks[1].volume, bricks[2].volume, bricks[4].volume),
+            (20, 0, 30)
+        );
+
+        let bricks = renko(&quotes, &BrickSize::Percent(10.0));
+        let closes: Vec<f64> = bricks
+            .iter()
+            .map(|b| (b.close * 1000.0).round() / 1000.0)
+            .collect();
+        // every brick is 10% of the close before it
+        assert_eq!(closes, vec![10.0, 11.0, 12.1, 13.31, 10.769, 9.692]);
+        assert!(renko(&[], &BrickSize::Price(1.0)).is_empty());
+
+        assert_eq!(
+            "renko:2".parse::<Candles>().unwrap(),
+            Candles::Renko(BrickSize::Price(2.0))
+        );
+        assert_eq!(
+            "renko: 1.5%".parse::<Candles>().unwrap(),
+            Candles::Renko(BrickSize::Percent(1.5))
+        );
+        assert!("renko".parse::<Candles>().is_err());
+        assert!("renko:0".parse::<Candles>().is_err());
+        assert!("renko:-1%".parse::<Candles>().is_err());
+    }
+}
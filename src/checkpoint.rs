@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+
+use anyhow::Context as _;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::Quotes;
+
+///
+/// Timestamp of the last successfully fetched quote, per symbol (and watchlist)
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Checkpoints {
+    pub last_fetch: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl Checkpoints {
+    ///
+    /// The key of a symbol: watchlists are tracked separately so they don't take each other's
+    /// data away.
+    ///
+    pub fn key(symbol: &str, watchlist: Option<&str>) -> String {
+        match watchlist {
+            Some(watchlist) => format!("{}:{}", watchlist, symbol),
+            None => symbol.to_string(),
+        }
+    }
+
+    ///
+    /// Reads a checkpoint file. A missing file means there are no checkpoints yet.
+    ///
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        match File::open(path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("Invalid checkpoint file '{}'", path)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Checkpoints::default()),
+            Err(e) => Err(e).with_context(|| format!("Could not open '{}'", path)),
+        }
+    }
+
+    ///
+    /// Writes to a temporary file first and moves it over `path` afterwards.
+    ///
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let tmp = format!("{}.tmp", path);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    ///
+    /// Moves a checkpoint forward, returns `true` if it changed.
+    ///
+    pub fn advance(&mut self, key: String, timestamp: DateTime<Utc>) -> bool {
+        match self.last_fetch.get(&key) {
+            Some(last) if *last >= timestamp => false,
+            _ => {
+                self.last_fetch.insert(key, timestamp);
+                true
+            }
+        }
+    }
+
+    ///
+    /// Records the newest quote of a response, returns `true` if the checkpoint changed.
+    ///
+    pub fn update(&mut self, quotes: &Quotes) -> bool {
+        let last = quotes
+            .quotes
+            .iter()
+            .map(|q| q.timestamp)
+            .max()
+            .and_then(|ts| Utc.timestamp_opt(ts as i64, 0).single());
+        match last {
+            Some(ts) => self.advance(Self::key(&quotes.symbol, quotes.watchlist.as_deref()), ts),
+            None => false,
+        }
+    }
+
+    ///
+    /// Where the next fetch for a symbol starts: right after the last fetched quote, or at
+    /// `default` if nothing was fetched yet.
+    ///
+    pub fn next_from(&self, key: &str, default: DateTime<Utc>) -> DateTime<Utc> {
+        self.last_fetch
+            .get(key)
+            .map(|last| (*last + chrono::Duration::seconds(1)).max(default))
+            .unwrap_or(default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::TickerQuote;
+
+    fn quotes(symbol: &str, watchlist: Option<&str>, timestamps: &[u64]) -> Quotes {
+        Quotes {
+            symbol: symbol.to_string(),
+            quotes: timestamps
+                .iter()
+                .map(|ts| TickerQuote {
+                    timestamp: *ts,
+                    open: 1.0,
+                    high: 1.0,
+                    low: 1.0,
+                    volume: 1,
+                    close: 1.0,
+                    adjclose: 1.0,
+                })
+                .collect(),
+            watchlist: watchlist.map(|w| w.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_checkpoints_update() {
+        let from = Utc.timestamp_opt(0, 0).unwrap();
+        let mut checkpoints = Checkpoints::default();
+        assert_eq!(checkpoints.next_from("AAPL", from), from);
+        assert!(checkpoints.update(&quotes("AAPL", None, &[100, 300, 200])));
+        assert!(!checkpoints.update(&quotes("AAPL", None, &[200])));
+        assert!(!checkpoints.update(&quotes("AAPL", None, &[])));
+        assert!(checkpoints.update(&quotes("AAPL", Some("tech"), &[50])));
+        assert_eq!(
+            checkpoints.next_from("AAPL", from),
+            Utc.timestamp_opt(301, 0).unwrap()
+        );
+        assert_eq!(
+            checkpoints.next_from("tech:AAPL", from),
+            Utc.timestamp_opt(51, 0).unwrap()
+        );
+
+        let path = std::env::temp_dir().join("checkpoints_roundtrip.json");
+        let path = path.to_str().unwrap();
+        checkpoints.save(path).unwrap();
+        assert_eq!(Checkpoints::load(path).unwrap(), checkpoints);
+        fs::remove_file(path).unwrap();
+        assert_eq!(Checkpoints::load(path).unwrap(), Checkpoints::default());
+    }
+}
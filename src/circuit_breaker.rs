@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+///
+/// Published when a symbol isn't fetched for a while after failing over and over
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SymbolSuspended {
+    pub symbol: String,
+    pub provider: String,
+    /// Consecutive failed fetches
+    pub failures: u32,
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Breaker {
+    failures: u32,
+    open_until: Option<DateTime<Utc>>,
+}
+
+///
+/// Stops fetching a symbol for a backoff period after a number of consecutive failures. Once
+/// the period is over, the symbol is fetched again, and a single failure suspends it again.
+///
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    /// Consecutive failures that open the breaker, 0 to never open it
+    threshold: u32,
+    backoff: Duration,
+    symbols: HashMap<String, Breaker>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, backoff: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            backoff,
+            symbols: HashMap::new(),
+        }
+    }
+
+    ///
+    /// Whether the symbol may be fetched at `now`
+    ///
+    pub fn allows(&self, symbol: &str, now: DateTime<Utc>) -> bool {
+        self.symbols
+            .get(symbol)
+            .and_then(|b| b.open_until)
+            .is_none_or(|until| now >= until)
+    }
+
+    ///
+    /// Counts the outcome of a fetch. Returns the event if the symbol is suspended now.
+    ///
+    pub fn record(
+        &mut self,
+        symbol: &str,
+        provider: &str,
+        succeeded: bool,
+        now: DateTime<Utc>,
+    ) -> Option<SymbolSuspended> {
+        if succeeded {
+            self.symbols.remove(symbol);
+            return None;
+        }
+        if self.threshold == 0 {
+            return None;
+        }
+        let breaker = self.symbols.entry(symbol.to_string()).or_default();
+        breaker.failures += 1;
+        // after a suspension, the first fetch decides
+        let reopen = breaker.open_until.is_some();
+        if breaker.failures < self.threshold && !reopen {
+            return None;
+        }
+        let until = now + chrono::Duration::from_std(self.backoff).unwrap_or_default();
+        breaker.open_until = Some(until);
+        Some(SymbolSuspended {
+            symbol: symbol.to_string(),
+            provider: provider.to_string(),
+            failures: breaker.failures,
+            until,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let now = Utc.with_ymd_and_hms(2022, 12, 2, 15, 0, 0).unwrap();
+        let minute = chrono::Duration::minutes(1);
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(600));
+        assert!(breaker.record("BAD", "yahoo", false, now).is_none());
+        assert!(breaker.record("BAD", "yahoo", false, now).is_none());
+        // a success in between resets the count
+        assert!(breaker.record("OK", "yahoo", false, now).is_none());
+        assert!(breaker.record("OK", "yahoo", true, now).is_none());
+        let suspended = breaker.record("BAD", "yahoo", false, now).unwrap();
+        assert_eq!(suspended.failures, 3);
+        assert_eq!(suspended.until, now + minute * 10);
+        assert!(!breaker.allows("BAD", now + minute));
+        assert!(breaker.allows("OK", now));
+
+        // half open: one more failure suspends it again
+        let later = now + minute * 10;
+        assert!(breaker.allows("BAD", later));
+        let suspended = breaker.record("BAD", "yahoo", false, later).unwrap();
+        assert_eq!(suspended.until, later + minute * 10);
+        assert!(breaker.record("BAD", "yahoo", true, later).is_none());
+        assert!(breaker.allows("BAD", later));
+
+        let mut never = CircuitBreaker::new(0, Duration::from_secs(600));
+        for _ in 0..10 {
+            assert!(never.record("BAD", "yahoo", false, now).is_none());
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//!
+//! The command line: parses the flags (and the config file), then runs the pipeline or one
+//! of the commands. `main` only calls `run`.
+//!
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::BufWriter;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_executor::Executor;
+use chrono::prelude::*;
+use clap::Parser;
+use xactor::*;
+
+use crate::alert::ThresholdRules;
+use crate::alphavantage::AlphaVantageProvider;
+use crate::audit::AuditLog;
+use crate::backfill::BackfillTracker;
+use crate::broadcast::Broadcaster;
+use crate::buffer::BufferSink;
+use crate::concurrency_limit::ConcurrencyLimit;
+use crate::config::Config;
+use crate::envelope::{Freshness, FreshnessTracker, InFlight};
+use crate::file_sink::{CsvWriter, SinkFiles};
+use crate::group::GroupAggregator;
+use crate::leaderboard::Leaderboard;
+use crate::metadata::SymbolDirectory;
+use crate::metrics::Metrics;
+use crate::number_format::NumberFormat;
+use crate::options::{
+    config_dir, csv_schema, load_aliases, load_config, Command, Opts, RecomputeOpts,
+};
+use crate::pipeline::{serve, Pipeline};
+use crate::provider::{Assignments, ProviderRouter, YahooProvider, PROVIDERS};
+use crate::quality::DataQuality;
+use crate::quota::{QuotaLimit, QuotaTracker};
+use crate::registry::SymbolRegistry;
+use crate::replica::{ReplicaOpts, StoreReader};
+use crate::response_cache::{CacheInvalidator, ResponseCache};
+use crate::script::Script;
+use crate::tiering::Tiering;
+use crate::trailing_stop::TrailingStop;
+use crate::{
+    clock, crash, doctor, export, listen, logging, prometheus_rules, quote_log, script, shutdown,
+    ProcessorConfig, State,
+};
+
+///
+/// Runs the `recompute` command: replays a quote log through the signal calculation and
+/// writes the indicators like `RollingFileSink` would.
+///
+async fn recompute(opts: &Opts, args: &RecomputeOpts) -> anyhow::Result<()> {
+    if args.sma_window.is_some_and(|w| w < 2) {
+        anyhow::bail!("--sma-window needs to be at least 2");
+    }
+    let config = load_config(opts)?;
+    let mut processor = ProcessorConfig::load(opts, &config)?.processor();
+    let file = BufWriter::new(File::create(&args.output)?);
+    let mut out = CsvWriter::new(file, csv_schema(opts, &config)?, opts.duplicate_rows)?;
+    let mut count = 0;
+    for mut batch in quote_log::read(&args.input)? {
+        if batch.watchlist != args.watchlist || batch.quotes.is_empty() {
+            continue;
+        }
+        batch.quotes.sort_by_cached_key(|k| k.timestamp);
+        crate::drop_out_of_range(&batch.symbol, &mut batch.quotes);
+        if batch.quotes.is_empty() {
+            continue;
+        }
+        processor.history.append(&batch.symbol, &batch.quotes);
+        let history = processor.history.quotes(&batch.symbol);
+        let (mut signals, currency) = processor.settings(&batch.symbol, batch.watchlist.as_deref());
+        if let Some(sma_window) = args.sma_window {
+            signals.sma_window = sma_window;
+        }
+        if let Some(ema_period) = args.ema_period {
+            signals.ema_period = ema_period;
+        }
+        let mut data = processor
+            .indicators(&batch.symbol, &history, &signals)
+            .await;
+        data.watchlist = batch.watchlist;
+        data.currency = currency;
+        if let Some(range) = processor.history.year_range(&batch.symbol) {
+            data.set_year_range(range);
+        }
+        if out.write(&data)? {
+            count += 1;
+        }
+    }
+    out.flush()?;
+    tracing::info!("Recomputed {} records into '{}'", count, args.output);
+    Ok(())
+}
+
+///
+/// Runs the `replica` command: the HTTP API over the rows a collector writes to its SQLite
+/// database. Nothing is fetched, calculated from quotes, or written to the sinks.
+///
+async fn replica(opts: &Opts, args: &ReplicaOpts) -> anyhow::Result<()> {
+    let mut config = load_config(opts)?;
+    let aliases = load_aliases(opts, &config)?;
+    config.resolve_aliases(&aliases);
+    let clock = clock::system();
+
+    let (buffer_capacity, buffer_overflow) = (opts.buffer_capacity, opts.buffer_overflow);
+    let buffer =
+        Supervisor::start(move || BufferSink::new(None, buffer_capacity, buffer_overflow)).await?;
+    let mut watchlist_buffers = BTreeMap::new();
+    for name in config.watchlists.keys() {
+        let tag = Some(name.clone());
+        let buffer = Supervisor::start(move || {
+            BufferSink::new(tag.clone(), buffer_capacity, buffer_overflow)
+        })
+        .await?;
+        watchlist_buffers.insert(name.clone(), buffer);
+    }
+    let processor_config = ProcessorConfig::load(opts, &config)?;
+    let overrides = processor_config.overrides.clone();
+    let processor = Supervisor::start(move || processor_config.processor()).await?;
+    let tags: HashMap<String, BTreeSet<String>> = config
+        .symbols
+        .iter()
+        .map(|(symbol, c)| {
+            let groups = c.tags.iter().chain(c.sector.iter()).cloned().collect();
+            (symbol.clone(), groups)
+        })
+        .collect();
+    let groups = Supervisor::start(move || GroupAggregator::new(tags.clone(), None)).await?;
+    let metadata_cache = opts.metadata_cache.clone();
+    let directory_aliases = aliases.clone();
+    let symbols = Supervisor::start(move || {
+        SymbolDirectory::new(metadata_cache.clone(), overrides.clone())
+            .with_aliases(directory_aliases.clone())
+            .offline()
+    })
+    .await?;
+    let limits = opts
+        .quotas
+        .iter()
+        .map(|q| QuotaLimit::from_arg(q))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let quota_clock = clock.clone();
+    let quota = Supervisor::start(move || QuotaTracker::new(&limits, quota_clock.clone())).await?;
+    let backfill_clock = clock.clone();
+    let backfill = Supervisor::start(move || BackfillTracker::new(backfill_clock.clone())).await?;
+    // no providers to route to
+    let providers = Supervisor::start(|| {
+        ProviderRouter::new(
+            BTreeMap::new(),
+            Assignments {
+                default: String::new(),
+                symbols: BTreeMap::new(),
+            },
+        )
+    })
+    .await?;
+    let stops = config.trailing_stops.clone();
+    let watch_start = clock.now();
+    let trailing_stops = Supervisor::start(move || TrailingStop::new(&stops, watch_start)).await?;
+    let summary = Some(Duration::from_secs(opts.metrics_summary)).filter(|d| !d.is_zero());
+    let audit_log = opts.audit_log.clone();
+    let freshness = Freshness::new(clock.clone());
+    let tracked = freshness.clone();
+    let _freshness_tracker = Supervisor::start(move || FreshnessTracker {
+        freshness: tracked.clone(),
+    })
+    .await?;
+    let cache = ResponseCache::new(Duration::from_millis(opts.cache_ttl), freshness.clone())
+        .with_aliases(aliases.clone());
+    let invalidated = cache.clone();
+    let _cache_invalidator = Supervisor::start(move || CacheInvalidator {
+        cache: invalidated.clone(),
+    })
+    .await?;
+    // the collector moves the rows to the cold tier
+    let hot: Vec<_> = std::iter::once(buffer.clone())
+        .chain(watchlist_buffers.values().cloned())
+        .collect();
+    let tiering =
+        Supervisor::start(move || Tiering::new(None, Duration::ZERO, hot.clone(), None)).await?;
+    let state = State {
+        buffer,
+        metrics: Supervisor::start(move || Metrics::new(summary)).await?,
+        audit: Supervisor::start(move || AuditLog::new(audit_log.clone())).await?,
+        quality: Supervisor::start(DataQuality::default).await?,
+        quota,
+        leaderboard: Supervisor::start(Leaderboard::default).await?,
+        symbols,
+        groups,
+        backfill,
+        providers,
+        broadcaster: Supervisor::start(Broadcaster::default).await?,
+        executor: Arc::new(Executor::new()),
+        registry: Supervisor::start(SymbolRegistry::default).await?,
+        trailing_stops,
+        processor,
+        cache,
+        limit: ConcurrencyLimit::new(opts.max_concurrent_requests),
+        watchlists: Arc::new(watchlist_buffers),
+        // there is no CSV file to download from
+        csv_files: SinkFiles::default(),
+        // the alerts run on the primary
+        alert_rules: ThresholdRules::default(),
+        numbers: Arc::new(NumberFormat::from_config(&config.format)?),
+        freshness,
+        buffer_calls: InFlight::default(),
+        aliases: Arc::new(aliases),
+        tiering,
+    };
+
+    // started last, so every actor sees the rows read at the start
+    let (store, poll) = (args.store.clone(), args.poll);
+    let _reader = Supervisor::start(move || StoreReader::new(store.clone(), poll)).await?;
+    let http_runtime = serve(opts, &state)?;
+    tracing::info!("Serving a replica of '{}'", args.store);
+    shutdown::on_signal()?.recv().await.ok();
+    async_std::task::spawn_blocking(move || http_runtime.stop()).await;
+    Ok(())
+}
+
+///
+/// Runs the `doctor` command: checks what a run would need and prints what's wrong
+///
+async fn doctor(opts: &Opts, args: &doctor::DoctorOpts) -> anyhow::Result<()> {
+    let mut report = doctor::Report::default();
+
+    let config = match (&opts.config, load_config(opts)) {
+        (_, Err(e)) => {
+            let hint = "Fix the file, `--config` is optional";
+            report.fail("config", format!("{:#}", e), hint);
+            Config::default()
+        }
+        (Some(path), Ok(config)) => {
+            report.ok("config", format!("'{}' is valid", path));
+            config
+        }
+        (None, Ok(config)) => config,
+    };
+    let engine = script::engine();
+    for (kind, scripts) in [("indicator", &config.indicators), ("alert", &config.alerts)] {
+        for c in scripts {
+            let check = format!("{} '{}'", kind, c.name);
+            let result = Script::from_config(&engine, c, &config_dir(opts))
+                .map(|_| "compiles".to_string())
+                .map_err(|e| format!("{:#}", e));
+            report.check(&check, result, "Fix the script in the config file");
+        }
+    }
+    if let Some(from) = &opts.from {
+        let result = from
+            .parse::<DateTime<Utc>>()
+            .map(|from| format!("starts at {}", from))
+            .map_err(|e| format!("'{}' is not a timestamp: {}", from, e));
+        report.check(
+            "--from",
+            result,
+            "Use an RFC 3339 timestamp, e.g. 2024-01-02T00:00:00Z",
+        );
+    }
+
+    let default = match (&opts.provider, opts.synthetic) {
+        (Some(provider), _) => provider.clone(),
+        (None, Some(_)) => "synthetic".to_string(),
+        (None, None) => "yahoo".to_string(),
+    };
+    let providers: BTreeSet<String> = std::iter::once(default)
+        .chain(config.symbols.values().filter_map(|o| o.provider.clone()))
+        .collect();
+    let symbol = opts
+        .symbols
+        .split(',')
+        .map(str::trim)
+        .find(|s| !s.is_empty() && !s.contains(':'))
+        .unwrap_or("AAPL")
+        .to_string();
+    for provider in &providers {
+        let check = format!("provider {}", provider);
+        match provider.as_str() {
+            "yahoo" if args.offline => report.ok(&check, "not contacted (--offline)"),
+            "yahoo" => {
+                let hint = "Check the network connection and proxy settings, and whether Yahoo \
+                            blocks the address (see --max-requests-per-minute)";
+                let mut provider = YahooProvider::default();
+                doctor::reachable(&mut report, &mut provider, &symbol, hint).await;
+            }
+            "alphavantage" => match &opts.api_key {
+                None => report.fail(&check, "no API key", "Pass --api-key"),
+                Some(_) if args.offline => report.ok(&check, "API key set (--offline)"),
+                Some(key) => {
+                    let hint = "Check the API key and the network connection";
+                    let mut provider = AlphaVantageProvider::new(key.clone());
+                    doctor::reachable(&mut report, &mut provider, &symbol, hint).await;
+                }
+            },
+            "file" => match &opts.replay_dir {
+                None => report.fail(&check, "no directory", "Pass --replay-dir"),
+                Some(dir) if !dir.is_dir() => report.fail(
+                    &check,
+                    format!("'{}' is not a directory", dir.display()),
+                    "Pass the directory with the quote files to --replay-dir",
+                ),
+                Some(dir) => report.ok(&check, format!("reads '{}'", dir.display())),
+            },
+            "synthetic" => report.ok(&check, "made up quotes"),
+            other => report.fail(
+                &check,
+                format!("'{}' is unknown", other),
+                &format!("Use one of {}", PROVIDERS.join(", ")),
+            ),
+        }
+    }
+
+    let mut files = vec![];
+    if !opts.no_csv {
+        files.push(("CSV sink", Some(format!("{}.csv", Utc::now().timestamp()))));
+        files.push(("audit log", Some(opts.audit_log.clone())));
+    }
+    if opts.webhook_sink.is_some() {
+        files.push(("webhook WAL", Some(opts.webhook_wal.clone())));
+    }
+    if opts.influx_url.is_some() {
+        files.push(("InfluxDB WAL", Some(opts.influx_wal.clone())));
+    }
+    files.extend([
+        ("SQLite sink", opts.sqlite.clone()),
+        ("Parquet sink", opts.parquet.clone()),
+        ("quote log", opts.quote_log.clone()),
+        ("recording", opts.record.clone()),
+        ("dead letters", opts.dead_letters.clone()),
+        ("checkpoints", opts.checkpoints.clone()),
+        ("snapshot", opts.snapshot.clone()),
+        ("ticker cache", opts.ticker_cache.clone()),
+    ]);
+    for (name, path) in files {
+        if let Some(path) = path {
+            let hint = "Check the directory exists and is writable, or pick another path";
+            report.check(name, doctor::writable(std::path::Path::new(&path)), hint);
+        }
+    }
+
+    let hint = "Stop whatever listens there, or pass another address to --listen";
+    report.check(
+        "API",
+        doctor::available(&listen::Endpoint::parse(&opts.listen)),
+        hint,
+    );
+
+    report.print(&mut std::io::stdout().lock())?;
+    match report.failures() {
+        0 => Ok(()),
+        n => anyhow::bail!("{} of {} checks failed", n, report.findings.len()),
+    }
+}
+
+///
+/// Parses the command line and runs what it asks for
+///
+pub async fn run() -> Result<()> {
+    let mut opts: Opts = Opts::parse();
+    if opts.config.is_some() {
+        match load_config(&opts) {
+            Ok(config) => {
+                // the file's settings as flags, before those of the command line
+                let mut args = std::env::args_os();
+                let program = args.next();
+                let flags = config.flags().into_iter().map(Into::into);
+                opts = Opts::parse_from(program.into_iter().chain(flags).chain(args));
+            }
+            // the doctor reports it
+            Err(_) if matches!(opts.command, Some(Command::Doctor(_))) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    logging::init(&opts.log_level, opts.log_format)?;
+    crash::install_panic_hook(opts.panic_webhook.clone());
+    match &opts.command {
+        Some(Command::Export(export)) => return export::run(export),
+        Some(Command::Recompute(args)) => return recompute(&opts, args).await,
+        Some(Command::PrometheusRules(args)) => {
+            return prometheus_rules::run(args, &load_config(&opts)?, &config_dir(&opts))
+        }
+        Some(Command::Doctor(args)) => return doctor(&opts, args).await,
+        Some(Command::Replica(args)) => return replica(&opts, args).await,
+        None => {}
+    }
+    Pipeline::builder().options(opts).build().await?.run().await
+}
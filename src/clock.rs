@@ -0,0 +1,90 @@
+//!
+//! Where the actors get the current time from. Production code uses the system clock, tests
+//! and replays use a `TestClock` they move forward themselves.
+//!
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::prelude::*;
+
+pub trait Clock: Send + Sync + Debug {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+///
+/// A clock as it's shared between actors
+///
+pub type SharedClock = Arc<dyn Clock>;
+
+///
+/// The wall clock
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+pub fn system() -> SharedClock {
+    Arc::new(SystemClock)
+}
+
+///
+/// A clock that only moves when it's told to. Clones share the same time.
+///
+#[derive(Debug, Clone)]
+pub struct TestClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl TestClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        TestClock {
+            now: Arc::new(Mutex::new(start)),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += chrono::Duration::from_std(by).unwrap_or_default();
+    }
+
+    pub fn shared(&self) -> SharedClock {
+        Arc::new(self.clone())
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_is_shared() {
+        let start = Utc.with_ymd_and_hms(2022, 12, 2, 15, 0, 0).unwrap();
+        let clock = TestClock::new(start);
+        let shared = clock.shared();
+        assert_eq!(shared.now(), start);
+        clock.advance(Duration::from_secs(3 * 24 * 3600));
+        assert_eq!(
+            shared.now(),
+            Utc.with_ymd_and_hms(2022, 12, 5, 15, 0, 0).unwrap()
+        );
+        clock.set(start);
+        assert_eq!(shared.now(), start);
+        assert!(system().now() > start);
+    }
+}
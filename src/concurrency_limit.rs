@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tide::{Middleware, Next, Request, Response, StatusCode};
+
+///
+/// tide middleware that limits how many requests of the routes it wraps are handled at once.
+/// Requests beyond the limit are answered with `503 Service Unavailable` right away, so a burst
+/// of expensive queries can't starve the actors that share the executor.
+///
+#[derive(Debug, Clone)]
+pub struct ConcurrencyLimit {
+    /// 0 for no limit
+    max: usize,
+    in_flight: Arc<AtomicUsize>,
+}
+
+///
+/// A request being handled, until it's dropped
+///
+struct Permit(Arc<AtomicUsize>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl ConcurrencyLimit {
+    pub fn new(max: usize) -> Self {
+        ConcurrencyLimit {
+            max,
+            in_flight: Default::default(),
+        }
+    }
+
+    fn try_acquire(&self) -> Option<Permit> {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| Permit(self.in_flight.clone()))
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ConcurrencyLimit {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if self.max == 0 {
+            return Ok(next.run(req).await);
+        }
+        let _permit = match self.try_acquire() {
+            Some(permit) => permit,
+            None => {
+                let mut response = Response::new(StatusCode::ServiceUnavailable);
+                response.insert_header("Retry-After", "1");
+                response.set_body("Too many concurrent requests");
+                return Ok(response);
+            }
+        };
+        Ok(next.run(req).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrency_limit() {
+        let limit = ConcurrencyLimit::new(2);
+        let first = limit.try_acquire();
+        let second = limit.try_acquire();
+        assert!(first.is_some() && second.is_some());
+        assert!(limit.try_acquire().is_none());
+        drop(first);
+        assert!(limit.try_acquire().is_some());
+        // the permit of the last call is dropped already
+        assert_eq!(limit.in_flight.load(Ordering::Acquire), 1);
+    }
+}
@@ -0,0 +1,694 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::alert::ThresholdRule;
+use crate::alias::Aliases;
+use crate::candles::Candles;
+use crate::derived::Derived;
+use crate::number_format::{FormatConfig, NumberFormat};
+use crate::pairs::PairConfig;
+use crate::provider::PROVIDERS;
+use crate::report::{Position, ReportConfig};
+use crate::score::ScoreWeights;
+use crate::trailing_stop::TrailingStopConfig;
+
+///
+/// A script given either inline or as a path to a `.rhai` file
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ScriptConfig {
+    pub name: String,
+    #[serde(default)]
+    pub script: Option<String>,
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Alerts only: a ready-made condition, e.g. `new_52w_high`
+    #[serde(default)]
+    pub template: Option<String>,
+    /// Alerts only: a threshold, e.g. `AAPL pct_change < -0.05` or `price > 200`
+    #[serde(default)]
+    pub rule: Option<ThresholdRule>,
+    /// Alerts only: raise a desktop notification when the rule fires
+    #[serde(default)]
+    pub notify: bool,
+}
+
+impl ScriptConfig {
+    ///
+    /// The script's source code. Relative files are resolved against `base`.
+    ///
+    pub fn source(&self, base: &Path) -> anyhow::Result<String> {
+        match (&self.script, &self.file, &self.template, &self.rule) {
+            (Some(script), None, None, None) => Ok(script.clone()),
+            (None, Some(file), None, None) => {
+                let path = base.join(file);
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("Could not read script '{}'", path.display()))
+            }
+            (None, None, None, Some(rule)) => Ok(rule.script()),
+            (None, None, Some(template), None) => match crate::script::template(template) {
+                Some(source) => Ok(source.to_string()),
+                None => bail!("'{}' uses the unknown template '{}'", self.name, template),
+            },
+            _ => bail!(
+                "'{}' needs exactly one of 'script', 'file', 'template', or 'rule'",
+                self.name
+            ),
+        }
+    }
+}
+
+///
+/// A group of symbols fetched on its own schedule
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ScheduleConfig {
+    pub name: String,
+    pub symbols: Vec<String>,
+    /// Cron expression, e.g. `*/5 9-16 * * MON-FRI`
+    #[serde(default)]
+    pub cron: Option<String>,
+    /// Fixed interval in seconds (used if there is no cron expression, defaults to
+    /// `--interval`)
+    #[serde(default)]
+    pub interval: Option<u64>,
+}
+
+///
+/// An isolated sub-pipeline with its own symbols, schedule, signals, and sinks
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct WatchlistConfig {
+    pub symbols: Vec<String>,
+    /// Cron expression, e.g. `*/5 9-16 * * MON-FRI`
+    #[serde(default)]
+    pub cron: Option<String>,
+    /// Fixed interval in seconds (used if there is no cron expression, defaults to
+    /// `--interval`)
+    #[serde(default)]
+    pub interval: Option<u64>,
+    /// Window of the moving average (defaults to 30)
+    #[serde(default)]
+    pub sma_window: Option<usize>,
+    /// Period of the exponential moving average (defaults to `--ema-period`)
+    #[serde(default)]
+    pub ema_period: Option<usize>,
+    /// Number of price changes of the relative strength index (defaults to `--rsi-period`)
+    #[serde(default)]
+    pub rsi_period: Option<usize>,
+    /// Number of returns of the volatility (defaults to `--volatility-window`)
+    #[serde(default)]
+    pub volatility_window: Option<usize>,
+    /// Names of the custom indicators and plugins to calculate (defaults to all)
+    #[serde(default)]
+    pub signals: Option<Vec<String>>,
+    /// Bar sizes to calculate the indicators for, e.g. `["1h", "1d"]` (defaults to
+    /// `--resolutions`)
+    #[serde(default)]
+    pub resolutions: Option<Vec<String>>,
+    /// Candles to calculate the signals over (defaults to `--candles`)
+    #[serde(default)]
+    pub candles: Option<Candles>,
+    /// CSV file to write to (defaults to `<name>-<timestamp>.csv`)
+    #[serde(default)]
+    pub csv: Option<String>,
+}
+
+///
+/// Settings of a single symbol that take precedence over the global defaults
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SymbolConfig {
+    /// Window of the moving average
+    pub sma_window: Option<usize>,
+    /// Period of the exponential moving average
+    pub ema_period: Option<usize>,
+    /// Number of price changes of the relative strength index
+    pub rsi_period: Option<usize>,
+    /// Number of returns of the volatility
+    pub volatility_window: Option<usize>,
+    /// Candles to calculate the signals over: `raw`, `heikin-ashi`, or e.g. `renko:1%`
+    pub candles: Option<Candles>,
+    /// Data provider to fetch the symbol from (`yahoo`, `alphavantage`, `file`, or
+    /// `synthetic`)
+    pub provider: Option<String>,
+    /// Currency the prices are shown in, e.g. `EUR` (defaults to `$`)
+    pub currency: Option<String>,
+    /// Fixed fetch interval in seconds
+    pub interval: Option<u64>,
+    /// Values available to alert conditions as `thresholds.<name>`
+    pub thresholds: BTreeMap<String, f64>,
+    /// Company name, instead of the provider's
+    pub name: Option<String>,
+    /// Sector, the provider doesn't know it
+    pub sector: Option<String>,
+    /// Groups the symbol belongs to, served at `/groups/:name`
+    pub tags: Vec<String>,
+}
+
+///
+/// Layout of the CSV files
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CsvConfig {
+    /// Columns in order, e.g. `["timestamp", "symbol", "price", "rsi"]`. Names that aren't
+    /// indicator fields are custom indicators.
+    pub columns: Option<Vec<String>>,
+}
+
+///
+/// `[fetch]`: what is fetched, and when
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct FetchSettings {
+    pub symbols: Option<Vec<String>>,
+    /// e.g. `30s` or `1m`
+    pub interval: Option<String>,
+    /// A cron schedule instead of the interval
+    pub schedule: Option<String>,
+    pub provider: Option<String>,
+    pub max_concurrency: Option<usize>,
+    pub max_requests_per_minute: Option<u32>,
+    /// Retries of network errors, see `--retry-attempts`
+    pub retry_attempts: Option<u32>,
+    /// Milliseconds before the first retry
+    pub retry_delay: Option<u64>,
+    pub retry_jitter: Option<f64>,
+}
+
+///
+/// `[signals]`: parameters of the indicators
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SignalSettings {
+    pub ema_period: Option<usize>,
+    pub ema_smoothing: Option<f64>,
+    pub rsi_period: Option<usize>,
+    pub volatility_window: Option<usize>,
+    pub resolutions: Option<Vec<String>>,
+    pub candles: Option<String>,
+    pub gap_threshold: Option<f64>,
+    /// e.g. `200d`
+    pub history_window: Option<String>,
+}
+
+///
+/// `[sinks]`: where the indicators are written
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct SinkSettings {
+    /// Write the CSV file of the default pipeline
+    pub csv: Option<bool>,
+    /// Start a new CSV file every day
+    pub csv_rotate_daily: Option<bool>,
+    /// Start a new CSV file once one has this size, e.g. `100MB`
+    pub csv_max_size: Option<String>,
+    /// Compress the CSV files that were rotated
+    pub csv_gzip: Option<bool>,
+    pub sqlite: Option<String>,
+    pub parquet: Option<String>,
+    pub parquet_rows: Option<usize>,
+    pub parquet_flush: Option<String>,
+    /// Rows every sink keeps for a retry while it can't write
+    pub queue: Option<usize>,
+    pub cold_dir: Option<String>,
+    pub cold_after: Option<String>,
+    pub webhook: Option<String>,
+    pub influx_url: Option<String>,
+    pub influx_bucket: Option<String>,
+    pub influx_org: Option<String>,
+    pub influx_token: Option<String>,
+    pub daily_summary: Option<String>,
+    pub group_csv: Option<String>,
+}
+
+///
+/// `[http]`: the API
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct HttpSettings {
+    pub listen: Option<String>,
+    pub threads: Option<usize>,
+    pub cache_ttl: Option<u64>,
+    pub max_concurrent_requests: Option<usize>,
+}
+
+///
+/// `[buffer]`: the buffers behind `/tail` and `/drain`
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct BufferSettings {
+    pub capacity: Option<usize>,
+    /// `drop-oldest`, `drop-newest`, or `aggregate-oldest`
+    pub overflow: Option<String>,
+}
+
+///
+/// Settings read from the `--config` TOML file
+///
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    /// Custom indicators calculated by `StockDataProcessor`
+    pub indicators: Vec<ScriptConfig>,
+    /// Alert conditions evaluated by the `AlertEngine`
+    pub alerts: Vec<ScriptConfig>,
+    /// Symbol groups with their own schedules. If present, these replace `--symbols`.
+    pub schedules: Vec<ScheduleConfig>,
+    /// Named watchlists that run next to the default pipeline
+    pub watchlists: BTreeMap<String, WatchlistConfig>,
+    /// Default thresholds for alert conditions
+    pub thresholds: BTreeMap<String, f64>,
+    /// Per-symbol overrides
+    pub symbols: BTreeMap<String, SymbolConfig>,
+    pub csv: CsvConfig,
+    /// Weights of the composite score
+    pub score: ScoreWeights,
+    /// Symbol pairs whose spread is monitored, e.g. `KO/PEP`
+    pub pairs: Vec<PairConfig>,
+    /// Trailing stops by symbol
+    pub trailing_stops: BTreeMap<String, TrailingStopConfig>,
+    /// Separators and decimals of the numbers
+    pub format: FormatConfig,
+    pub fetch: FetchSettings,
+    pub signals: SignalSettings,
+    pub sinks: SinkSettings,
+    pub http: HttpSettings,
+    pub buffer: BufferSettings,
+    /// Scheduled reports
+    pub reports: Option<ReportConfig>,
+    /// Positions by symbol, for the P&L in the reports
+    pub portfolio: BTreeMap<String, Position>,
+    /// Other names of symbols, e.g. `apple = "AAPL"`
+    pub aliases: BTreeMap<String, String>,
+    /// Symbols calculated from others, e.g. `SPREAD = "AAPL - MSFT"`
+    pub derived: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read config '{}'", path))?;
+        let config: Config =
+            toml::from_str(&text).with_context(|| format!("Invalid config '{}'", path))?;
+        for (symbol, overrides) in &config.symbols {
+            match overrides.provider.as_deref() {
+                Some(provider) if !PROVIDERS.contains(&provider) => {
+                    bail!("Unknown provider '{}' for {}", provider, symbol)
+                }
+                _ => {}
+            }
+            check_sma_window(overrides.sma_window, symbol)?;
+        }
+        for (name, watchlist) in &config.watchlists {
+            check_sma_window(watchlist.sma_window, &format!("watchlist '{}'", name))?;
+        }
+        config.score.validate()?;
+        Derived::from_config(&config.derived)?;
+        if let Some(reports) = &config.reports {
+            reports.validate()?;
+        }
+        NumberFormat::from_config(&config.format)?;
+        Ok(config)
+    }
+
+    ///
+    /// The settings of `[fetch]`, `[signals]`, `[sinks]`, and `[http]` as command line flags.
+    /// Flags given on the command line come after these, so they take precedence.
+    ///
+    pub fn flags(&self) -> Vec<String> {
+        let mut flags = vec![];
+        let mut flag = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                flags.push(format!("--{}={}", name, value));
+            }
+        };
+        let list = |values: &Option<Vec<String>>| values.as_ref().map(|v| v.join(","));
+        let text = |value: &Option<String>| value.clone();
+        let number = |value: Option<f64>| value.map(|v| v.to_string());
+
+        flag("symbols", list(&self.fetch.symbols));
+        flag("interval", text(&self.fetch.interval));
+        flag("schedule", text(&self.fetch.schedule));
+        flag("provider", text(&self.fetch.provider));
+        flag(
+            "max-concurrency",
+            self.fetch.max_concurrency.map(|v| v.to_string()),
+        );
+        flag(
+            "max-requests-per-minute",
+            self.fetch.max_requests_per_minute.map(|v| v.to_string()),
+        );
+        flag(
+            "retry-attempts",
+            self.fetch.retry_attempts.map(|v| v.to_string()),
+        );
+        flag("retry-delay", self.fetch.retry_delay.map(|v| v.to_string()));
+        flag("retry-jitter", number(self.fetch.retry_jitter));
+
+        let signals = &self.signals;
+        flag("ema-period", signals.ema_period.map(|v| v.to_string()));
+        flag("ema-smoothing", number(signals.ema_smoothing));
+        flag("rsi-period", signals.rsi_period.map(|v| v.to_string()));
+        flag(
+            "volatility-window",
+            signals.volatility_window.map(|v| v.to_string()),
+        );
+        flag("resolutions", list(&signals.resolutions));
+        flag("candles", text(&signals.candles));
+        flag("gap-threshold", number(signals.gap_threshold));
+        flag("history-window", text(&signals.history_window));
+
+        flag("csv-max-size", text(&self.sinks.csv_max_size));
+        flag("sqlite", text(&self.sinks.sqlite));
+        flag("parquet", text(&self.sinks.parquet));
+        flag(
+            "parquet-rows",
+            self.sinks.parquet_rows.map(|v| v.to_string()),
+        );
+        flag("parquet-flush", text(&self.sinks.parquet_flush));
+        flag("sink-queue", self.sinks.queue.map(|v| v.to_string()));
+        flag("cold-dir", text(&self.sinks.cold_dir));
+        flag("cold-after", text(&self.sinks.cold_after));
+        flag("webhook-sink", text(&self.sinks.webhook));
+        flag("influx-url", text(&self.sinks.influx_url));
+        flag("influx-bucket", text(&self.sinks.influx_bucket));
+        flag("influx-org", text(&self.sinks.influx_org));
+        flag("influx-token", text(&self.sinks.influx_token));
+        flag("daily-summary", text(&self.sinks.daily_summary));
+        flag("group-csv", text(&self.sinks.group_csv));
+
+        flag("listen", text(&self.http.listen));
+        flag("http-threads", self.http.threads.map(|v| v.to_string()));
+        flag("cache-ttl", self.http.cache_ttl.map(|v| v.to_string()));
+        flag(
+            "max-concurrent-requests",
+            self.http.max_concurrent_requests.map(|v| v.to_string()),
+        );
+        flag(
+            "buffer-capacity",
+            self.buffer.capacity.map(|v| v.to_string()),
+        );
+        flag("buffer-overflow", text(&self.buffer.overflow));
+        if self.sinks.csv == Some(false) {
+            flags.push("--no-csv".to_string());
+        }
+        if self.sinks.csv_rotate_daily == Some(true) {
+            flags.push("--csv-rotate-daily".to_string());
+        }
+        if self.sinks.csv_gzip == Some(true) {
+            flags.push("--csv-gzip".to_string());
+        }
+        flags
+    }
+
+    ///
+    /// The alert thresholds of a symbol: the defaults, overridden by the symbol's own
+    ///
+    pub fn thresholds(&self, symbol: &str) -> BTreeMap<String, f64> {
+        let mut thresholds = self.thresholds.clone();
+        if let Some(overrides) = self.symbols.get(symbol) {
+            thresholds.extend(overrides.thresholds.clone());
+        }
+        thresholds
+    }
+
+    ///
+    /// Replaces the aliases in the symbol lists, the per-symbol sections, and the pairs by
+    /// their tickers
+    ///
+    pub fn resolve_aliases(&mut self, aliases: &Aliases) {
+        let resolve = |symbols: &mut Vec<String>| {
+            for symbol in symbols.iter_mut() {
+                *symbol = aliases.resolve(symbol);
+            }
+        };
+        if let Some(symbols) = &mut self.fetch.symbols {
+            resolve(symbols);
+        }
+        for schedule in &mut self.schedules {
+            resolve(&mut schedule.symbols);
+        }
+        for watchlist in self.watchlists.values_mut() {
+            resolve(&mut watchlist.symbols);
+        }
+        for pair in &mut self.pairs {
+            if let Some((first, second)) = pair.pair.split_once('/') {
+                pair.pair = format!("{}/{}", aliases.resolve(first), aliases.resolve(second));
+            }
+        }
+        resolve_keys(&mut self.symbols, aliases);
+        resolve_keys(&mut self.trailing_stops, aliases);
+        resolve_keys(&mut self.portfolio, aliases);
+        for alert in &mut self.alerts {
+            if let Some(symbol) = alert.rule.as_mut().and_then(|r| r.symbol.as_mut()) {
+                *symbol = aliases.resolve(symbol);
+            }
+        }
+    }
+}
+
+///
+/// The moving average needs at least two prices
+///
+fn check_sma_window(window: Option<usize>, owner: &str) -> anyhow::Result<()> {
+    match window {
+        Some(window) if window < 2 => {
+            bail!(
+                "sma_window of {} needs to be at least 2, not {}",
+                owner,
+                window
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+fn resolve_keys<V>(map: &mut BTreeMap<String, V>, aliases: &Aliases) {
+    *map = std::mem::take(map)
+        .into_iter()
+        .map(|(symbol, value)| (aliases.resolve(&symbol), value))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::{ReportFormat, ReportPeriod};
+
+    #[test]
+    fn test_parse_config() {
+        let config: Config = toml::from_str(
+            r#"
+            [[indicators]]
+            name = "range"
+            script = "max(closes) - min(closes)"
+
+            [[alerts]]
+            name = "drop"
+            file = "drop.rhai"
+            notify = true
+
+            [[alerts]]
+            name = "high"
+            template = "new_52w_high"
+
+            [[schedules]]
+            name = "tech"
+            symbols = ["AAPL", "MSFT"]
+            cron = "*/5 9-16 * * MON-FRI"
+
+            [watchlists.crypto]
+            symbols = ["BTC-USD"]
+            interval = 60
+            sma_window = 10
+            signals = ["range"]
+
+            [thresholds]
+            drop = -0.05
+            rise = 0.05
+
+            [symbols.BTC-USD]
+            sma_window = 50
+            currency = "EUR"
+            interval = 10
+            thresholds = { drop = -0.15 }
+
+            [csv]
+            columns = ["timestamp", "symbol", "price", "rsi"]
+
+            [reports]
+            period = "weekly"
+            dir = "reports"
+
+            [portfolio]
+            AAPL = { quantity = 10, cost = 150.0 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.indicators.len(), 1);
+        assert_eq!(
+            config.indicators[0].source(Path::new(".")).unwrap(),
+            "max(closes) - min(closes)"
+        );
+        assert_eq!(config.alerts[0].file.as_deref(), Some("drop.rhai"));
+        assert!(config.alerts[0].notify);
+        assert!(!config.indicators[0].notify);
+        assert_eq!(
+            config.alerts[1].source(Path::new(".")).unwrap(),
+            "price >= high_52w"
+        );
+        assert_eq!(config.schedules[0].symbols, vec!["AAPL", "MSFT"]);
+        assert_eq!(config.schedules[0].interval, None);
+        let crypto = &config.watchlists["crypto"];
+        assert_eq!(crypto.interval, Some(60));
+        assert_eq!(crypto.sma_window, Some(10));
+        assert_eq!(crypto.csv, None);
+        let btc = &config.symbols["BTC-USD"];
+        assert_eq!(btc.sma_window, Some(50));
+        assert_eq!(btc.interval, Some(10));
+        assert_eq!(btc.provider, None);
+        assert_eq!(config.thresholds("BTC-USD")["drop"], -0.15);
+        assert_eq!(config.thresholds("BTC-USD")["rise"], 0.05);
+        assert_eq!(config.thresholds("AAPL")["drop"], -0.05);
+        assert_eq!(config.csv.columns.unwrap().len(), 4);
+        let reports = config.reports.unwrap();
+        assert_eq!(reports.period, ReportPeriod::Weekly);
+        assert_eq!(reports.format, ReportFormat::Html);
+        assert_eq!(reports.top, 5);
+        assert_eq!(config.portfolio["AAPL"].quantity, 10.0);
+
+        let empty = ScriptConfig {
+            name: "empty".to_string(),
+            ..Default::default()
+        };
+        assert!(empty.source(Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn test_load_rejects_sma_window() {
+        let path = std::env::temp_dir().join("config_sma_window.toml");
+        std::fs::write(
+            &path,
+            "[watchlists.crypto]\nsymbols = [\"BTC-USD\"]\nsma_window = 1\n",
+        )
+        .unwrap();
+        let error = Config::load(path.to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("watchlist 'crypto'"));
+
+        std::fs::write(
+            &path,
+            "[watchlists.crypto]\nsymbols = [\"BTC-USD\"]\nsma_window = 2\n",
+        )
+        .unwrap();
+        assert!(Config::load(path.to_str().unwrap()).is_ok());
+
+        std::fs::write(&path, "[symbols.AAPL]\nsma_window = 0\n").unwrap();
+        let error = Config::load(path.to_str().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("AAPL"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_aliases() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [aliases]
+            apple = "AAPL"
+            pepsi = "PEP"
+
+            [[schedules]]
+            name = "fast"
+            symbols = ["apple", "MSFT"]
+
+            [[pairs]]
+            pair = "KO/pepsi"
+
+            [symbols.Apple]
+            name = "Apple"
+
+            [trailing_stops.apple]
+            drop = 0.08
+
+            [[alerts]]
+            name = "apple_drop"
+            rule = "apple pct_change < -0.05"
+            "#,
+        )
+        .unwrap();
+        let aliases = Aliases::new(config.aliases.clone()).unwrap();
+        config.resolve_aliases(&aliases);
+        assert_eq!(config.schedules[0].symbols, vec!["AAPL", "MSFT"]);
+        assert_eq!(config.pairs[0].pair, "KO/PEP");
+        assert_eq!(config.symbols["AAPL"].name.as_deref(), Some("Apple"));
+        assert!(config.trailing_stops.contains_key("AAPL"));
+        assert_eq!(
+            config.alerts[0].rule.as_ref().unwrap().to_string(),
+            "AAPL pct_change < -0.05"
+        );
+    }
+
+    #[test]
+    fn test_flags() {
+        let config: Config = toml::from_str(
+            r#"
+            [fetch]
+            symbols = ["AAPL", "MSFT"]
+            interval = "1m"
+            max_concurrency = 8
+            retry_attempts = 5
+
+            [signals]
+            ema_period = 20
+            gap_threshold = -0.5
+
+            [sinks]
+            csv = false
+            csv_rotate_daily = true
+            csv_max_size = "100MB"
+            sqlite = "stocks.db"
+            parquet = "stocks.parquet"
+            parquet_flush = "30s"
+            queue = 500
+            cold_dir = "cold"
+            influx_url = "http://localhost:8086"
+
+            [http]
+            listen = "0.0.0.0:9000"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.flags(),
+            vec![
+                "--symbols=AAPL,MSFT",
+                "--interval=1m",
+                "--max-concurrency=8",
+                "--retry-attempts=5",
+                "--ema-period=20",
+                "--gap-threshold=-0.5",
+                "--csv-max-size=100MB",
+                "--sqlite=stocks.db",
+                "--parquet=stocks.parquet",
+                "--parquet-flush=30s",
+                "--sink-queue=500",
+                "--cold-dir=cold",
+                "--influx-url=http://localhost:8086",
+                "--listen=0.0.0.0:9000",
+                "--no-csv",
+                "--csv-rotate-daily",
+            ]
+        );
+        assert!(Config::default().flags().is_empty());
+    }
+}
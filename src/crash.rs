@@ -0,0 +1,226 @@
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::panic;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use serde::Serialize;
+
+///
+/// How often each actor instance (type and actor id) has been started (the first start is not a
+/// restart)
+///
+static STARTS: Mutex<BTreeMap<(&'static str, u64), u64>> = Mutex::new(BTreeMap::new());
+
+///
+/// How long a panic waits for the crash webhook at most
+///
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+///
+/// Name of the thread that posts a crash report, its own panics aren't posted again
+///
+const WEBHOOK_THREAD: &str = "crash-webhook";
+
+///
+/// What gets posted to the crash webhook
+///
+#[derive(Serialize, Debug, Clone)]
+pub struct CrashReport {
+    pub timestamp: DateTime<Utc>,
+    pub thread: String,
+    pub message: String,
+    pub location: String,
+    pub backtrace: String,
+}
+
+///
+/// Installs a panic hook that logs panics (including those in actor tasks) with a backtrace and
+/// optionally posts a `CrashReport` to the provided webhook URL.
+///
+pub fn install_panic_hook(webhook: Option<String>) {
+    panic::set_hook(Box::new(move |info| {
+        let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
+            s.to_string()
+        } else if let Some(s) = info.payload().downcast_ref::<String>() {
+            s.clone()
+        } else {
+            "<unknown panic payload>".to_string()
+        };
+        let report = CrashReport {
+            timestamp: Utc::now(),
+            thread: std::thread::current()
+                .name()
+                .unwrap_or("<unnamed>")
+                .to_string(),
+            message,
+            location: info
+                .location()
+                .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()))
+                .unwrap_or_default(),
+            backtrace: Backtrace::force_capture().to_string(),
+        };
+        tracing::error!(
+            "PANIC in thread '{}' at {}: {}\n{}",
+            report.thread,
+            report.location,
+            report.message,
+            report.backtrace
+        );
+
+        if let Some(url) = &webhook {
+            if std::thread::current().name() != Some(WEBHOOK_THREAD) {
+                post_report(url, &report, WEBHOOK_TIMEOUT);
+            }
+        }
+    }));
+}
+
+///
+/// Posts a crash report and waits for the response (up to `timeout`), so the report is out
+/// before a panic can end the process. The request runs on a thread of its own, since the
+/// panicking thread may be one of the executor's.
+///
+fn post_report(url: &str, report: &CrashReport, timeout: Duration) {
+    let (url, report) = (url.to_string(), report.clone());
+    let posting = std::thread::Builder::new()
+        .name(WEBHOOK_THREAD.to_string())
+        .spawn(move || {
+            async_std::task::block_on(async {
+                let request = reqwest::Client::new().post(&url).json(&report).send();
+                match async_std::future::timeout(timeout, request).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => {
+                        tracing::error!("Could not post crash report to '{}': {}", url, e)
+                    }
+                    Err(_) => tracing::error!(
+                        "Could not post crash report to '{}' within {}s",
+                        url,
+                        timeout.as_secs()
+                    ),
+                }
+            })
+        });
+    match posting {
+        Ok(posting) => {
+            let _ = posting.join();
+        }
+        Err(e) => tracing::error!("Could not post crash report: {}", e),
+    }
+}
+
+///
+/// Records that an actor of type `A` was started. Call this from `Actor::started` with
+/// `ctx.actor_id()` so restarts by a `Supervisor` are counted and logged. A `Supervisor` keeps
+/// the actor id across restarts, so several instances of the same type are told apart.
+///
+pub fn track_start<A>(id: u64) {
+    let name = actor_name::<A>();
+    let mut starts = STARTS.lock().unwrap();
+    let count = starts.entry((name, id)).or_insert(0);
+    *count += 1;
+    if *count > 1 {
+        tracing::warn!(
+            "Actor '{}' restarted ({} restarts so far)",
+            name,
+            *count - 1
+        );
+    }
+}
+
+///
+/// The number of restarts per actor type
+///
+pub fn restarts() -> BTreeMap<&'static str, u64> {
+    let mut restarts = BTreeMap::new();
+    for ((name, _), starts) in STARTS.lock().unwrap().iter() {
+        *restarts.entry(*name).or_insert(0) += starts.saturating_sub(1);
+    }
+    restarts
+}
+
+///
+/// Renders the restart counters in the Prometheus text format
+///
+pub fn render_restarts(out: &mut String) {
+    let _ = writeln!(out, "# HELP actor_restarts_total Number of actor restarts");
+    let _ = writeln!(out, "# TYPE actor_restarts_total counter");
+    for (name, count) in restarts() {
+        let _ = writeln!(out, "actor_restarts_total{{actor=\"{}\"}} {}", name, count);
+    }
+}
+
+fn actor_name<A>() -> &'static str {
+    let full = std::any::type_name::<A>();
+    full.rsplit("::").next().unwrap_or(full)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write as _};
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    struct TestActor;
+
+    fn report() -> CrashReport {
+        CrashReport {
+            timestamp: Utc::now(),
+            thread: "main".to_string(),
+            message: "boom".to_string(),
+            location: "src/main.rs:1:1".to_string(),
+            backtrace: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_post_report() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = String::new();
+            let mut buf = [0; 4096];
+            while !received.contains("boom") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    break;
+                }
+                received.push_str(&String::from_utf8_lossy(&buf[..n]));
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+            received
+        });
+        // the report is out once it returns
+        post_report(&url, &report(), Duration::from_secs(5));
+        let received = server.join().unwrap();
+        assert!(received.starts_with("POST /hook"));
+        assert!(received.contains("\"message\":\"boom\""));
+    }
+
+    #[test]
+    fn test_post_report_timeout() {
+        // accepts, but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let started = Instant::now();
+        post_report(&url, &report(), Duration::from_millis(200));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(listener);
+    }
+
+    #[test]
+    fn test_track_start() {
+        track_start::<TestActor>(1);
+        track_start::<TestActor>(2);
+        assert_eq!(restarts().get("TestActor"), Some(&0));
+        track_start::<TestActor>(1);
+        track_start::<TestActor>(1);
+        assert_eq!(restarts().get("TestActor"), Some(&2));
+    }
+}
@@ -0,0 +1,635 @@
+//!
+//! The columns of the CSV files written by `FileSink`.
+//!
+//! Every file starts with a comment like `# schema 1: timestamp,symbol,price` that names the
+//! columns, followed by the header row. Files without the comment use the default columns.
+//!
+//! In strict mode (`# schema 1 strict: ...`), cells hold plain numbers without `$` or `%`
+//! (the change is a fraction, not a percentage) and the header uses the column names.
+//!
+//! Otherwise numbers follow the configured `NumberFormat`; files with other separators than
+//! `.` say so in the comment, e.g. `# schema 1 decimal=, thousands=.: ...`.
+//!
+use anyhow::{anyhow, bail, Result};
+use chrono::prelude::*;
+
+use crate::number_format::NumberFormat;
+use crate::PerformanceIndicators;
+
+///
+/// Bump this whenever the meaning or format of an existing column changes
+///
+pub const SCHEMA_VERSION: u32 = 1;
+
+///
+/// A column of the CSV output. Anything that isn't a field of the indicators is looked up in
+/// the custom indicators (e.g. `rsi` or `ema`).
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Timestamp,
+    Symbol,
+    Price,
+    PctChange,
+    PeriodMin,
+    PeriodMax,
+    LastSma,
+    LastEma,
+    Rsi,
+    Volatility,
+    Score,
+    Currency,
+    Watchlist,
+    Name,
+    Exchange,
+    Sector,
+    High52w,
+    Low52w,
+    PctFromHigh52w,
+    PctFromLow52w,
+    GapPct,
+    ChangeFromPrevClose,
+    VwapSession,
+    Custom(String),
+}
+
+impl Column {
+    pub fn parse(name: &str) -> Self {
+        match name.trim() {
+            "timestamp" => Column::Timestamp,
+            "symbol" => Column::Symbol,
+            "price" => Column::Price,
+            "pct_change" => Column::PctChange,
+            "period_min" => Column::PeriodMin,
+            "period_max" => Column::PeriodMax,
+            "last_sma" => Column::LastSma,
+            "last_ema" => Column::LastEma,
+            "last_rsi" => Column::Rsi,
+            "volatility" => Column::Volatility,
+            "score" => Column::Score,
+            "currency" => Column::Currency,
+            "watchlist" => Column::Watchlist,
+            "name" => Column::Name,
+            "exchange" => Column::Exchange,
+            "sector" => Column::Sector,
+            "high_52w" => Column::High52w,
+            "low_52w" => Column::Low52w,
+            "pct_from_high_52w" => Column::PctFromHigh52w,
+            "pct_from_low_52w" => Column::PctFromLow52w,
+            "gap_pct" => Column::GapPct,
+            "change_from_prev_close" => Column::ChangeFromPrevClose,
+            "vwap_session" => Column::VwapSession,
+            custom => Column::Custom(custom.to_string()),
+        }
+    }
+
+    ///
+    /// The name used in the config file and the schema comment
+    ///
+    pub fn name(&self) -> &str {
+        match self {
+            Column::Timestamp => "timestamp",
+            Column::Symbol => "symbol",
+            Column::Price => "price",
+            Column::PctChange => "pct_change",
+            Column::PeriodMin => "period_min",
+            Column::PeriodMax => "period_max",
+            Column::LastSma => "last_sma",
+            Column::LastEma => "last_ema",
+            Column::Rsi => "last_rsi",
+            Column::Volatility => "volatility",
+            Column::Score => "score",
+            Column::Currency => "currency",
+            Column::Watchlist => "watchlist",
+            Column::Name => "name",
+            Column::Exchange => "exchange",
+            Column::Sector => "sector",
+            Column::High52w => "high_52w",
+            Column::Low52w => "low_52w",
+            Column::PctFromHigh52w => "pct_from_high_52w",
+            Column::PctFromLow52w => "pct_from_low_52w",
+            Column::GapPct => "gap_pct",
+            Column::ChangeFromPrevClose => "change_from_prev_close",
+            Column::VwapSession => "vwap_session",
+            Column::Custom(name) => name,
+        }
+    }
+
+    ///
+    /// The name in the header row
+    ///
+    fn title(&self) -> &str {
+        match self {
+            Column::Timestamp => "period start",
+            Column::PctChange => "change %",
+            Column::High52w => "52w high",
+            Column::Low52w => "52w low",
+            Column::PctFromHigh52w => "from 52w high %",
+            Column::PctFromLow52w => "from 52w low %",
+            Column::GapPct => "gap %",
+            Column::ChangeFromPrevClose => "day change %",
+            Column::VwapSession => "session vwap",
+            Column::PeriodMin => "min",
+            Column::PeriodMax => "max",
+            Column::LastSma => "30d avg",
+            Column::LastEma => "ema",
+            Column::Rsi => "rsi",
+            other => other.name(),
+        }
+    }
+
+    fn format(
+        &self,
+        row: &PerformanceIndicators,
+        strict: Option<usize>,
+        numbers: &NumberFormat,
+    ) -> String {
+        if let Some(precision) = strict {
+            let number = |v: f64| format!("{:.*}", precision, v);
+            match self {
+                Column::Timestamp => {
+                    return row.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+                }
+                Column::Price => return number(row.price),
+                Column::PctChange => return number(row.pct_change),
+                Column::PeriodMin => return number(row.period_min),
+                Column::PeriodMax => return number(row.period_max),
+                Column::LastSma => return number(row.last_sma),
+                Column::LastEma => return number(row.last_ema),
+                Column::Rsi => return row.rsi.map(number).unwrap_or_default(),
+                Column::Volatility => return row.volatility.map(number).unwrap_or_default(),
+                Column::Score => return row.score.map(number).unwrap_or_default(),
+                Column::High52w => return row.high_52w.map(number).unwrap_or_default(),
+                Column::Low52w => return row.low_52w.map(number).unwrap_or_default(),
+                Column::PctFromHigh52w => {
+                    return row.pct_from_high_52w.map(number).unwrap_or_default()
+                }
+                Column::PctFromLow52w => {
+                    return row.pct_from_low_52w.map(number).unwrap_or_default()
+                }
+                Column::GapPct => return row.gap_pct.map(number).unwrap_or_default(),
+                Column::ChangeFromPrevClose => {
+                    return row.change_from_prev_close.map(number).unwrap_or_default()
+                }
+                Column::VwapSession => return row.vwap_session.map(number).unwrap_or_default(),
+                Column::Custom(name) => {
+                    return row.custom.get(name).map(|v| number(*v)).unwrap_or_default()
+                }
+                _ => {}
+            }
+        }
+        let number =
+            |v: f64, default: usize| numbers.number(v, numbers.decimals(self.name(), default));
+        // prices are either `$1.00` or followed by a currency code, e.g. `1.00 EUR`
+        let money = |v: f64| match &row.currency {
+            Some(currency) => format!("{} {}", number(v, 2), currency),
+            None => format!("${}", number(v, 2)),
+        };
+        let percent = |v: Option<f64>| v.map(|v| format!("{}%", number(v * 100.0, 2)));
+        match self {
+            Column::Timestamp => row.timestamp.to_rfc3339(),
+            Column::Symbol => row.symbol.clone(),
+            Column::Price => money(row.price),
+            Column::PctChange => percent(Some(row.pct_change)).unwrap_or_default(),
+            Column::PeriodMin => money(row.period_min),
+            Column::PeriodMax => money(row.period_max),
+            Column::LastSma => money(row.last_sma),
+            Column::LastEma => money(row.last_ema),
+            Column::Rsi => row.rsi.map(|v| number(v, 2)).unwrap_or_default(),
+            Column::Volatility => percent(row.volatility).unwrap_or_default(),
+            Column::Score => row.score.map(|v| number(v, 1)).unwrap_or_default(),
+            Column::Currency => row.currency.clone().unwrap_or_default(),
+            Column::Watchlist => row.watchlist.clone().unwrap_or_default(),
+            Column::Name => row.name.clone().unwrap_or_default(),
+            Column::Exchange => row.exchange.clone().unwrap_or_default(),
+            Column::Sector => row.sector.clone().unwrap_or_default(),
+            Column::High52w => row.high_52w.map(money).unwrap_or_default(),
+            Column::Low52w => row.low_52w.map(money).unwrap_or_default(),
+            Column::PctFromHigh52w => percent(row.pct_from_high_52w).unwrap_or_default(),
+            Column::PctFromLow52w => percent(row.pct_from_low_52w).unwrap_or_default(),
+            Column::GapPct => percent(row.gap_pct).unwrap_or_default(),
+            Column::ChangeFromPrevClose => percent(row.change_from_prev_close).unwrap_or_default(),
+            Column::VwapSession => row.vwap_session.map(money).unwrap_or_default(),
+            Column::Custom(name) => row
+                .custom
+                .get(name)
+                .map(|v| number(*v, 4))
+                .unwrap_or_default(),
+        }
+    }
+
+    fn read(
+        &self,
+        cell: &str,
+        row: &mut PerformanceIndicators,
+        strict: bool,
+        numbers: &NumberFormat,
+    ) -> Result<()> {
+        // prices are either `$1.00` or followed by a currency code, e.g. `1.00 EUR`
+        let number = |s: &str| -> Result<f64> {
+            let s = s
+                .trim_start_matches('$')
+                .trim_end_matches('%')
+                .trim_end_matches(|c: char| c.is_ascii_alphabetic())
+                .trim();
+            if strict {
+                Ok(s.parse()?)
+            } else {
+                numbers.parse(s)
+            }
+        };
+        let text = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+        let optional = |s: &str| -> Result<Option<f64>> {
+            if s.is_empty() {
+                Ok(None)
+            } else {
+                number(s).map(Some)
+            }
+        };
+        if matches!(self, Column::Price) && !strict {
+            row.currency = cell.rsplit_once(' ').map(|(_, code)| code.to_string());
+        }
+        match self {
+            Column::Timestamp => {
+                row.timestamp = DateTime::parse_from_rfc3339(cell)?.with_timezone(&Utc)
+            }
+            Column::Symbol => row.symbol = cell.to_string(),
+            Column::Price => row.price = number(cell)?,
+            Column::PctChange if strict => row.pct_change = number(cell)?,
+            Column::PctChange => row.pct_change = number(cell)? / 100.0,
+            Column::PeriodMin => row.period_min = number(cell)?,
+            Column::PeriodMax => row.period_max = number(cell)?,
+            Column::LastSma => row.last_sma = number(cell)?,
+            Column::LastEma => row.last_ema = number(cell)?,
+            Column::Rsi => row.rsi = optional(cell)?,
+            Column::Volatility => {
+                row.volatility = optional(cell)?.map(|v| if strict { v } else { v / 100.0 })
+            }
+            Column::Score => row.score = optional(cell)?,
+            Column::Currency => row.currency = text(cell),
+            Column::Watchlist => row.watchlist = text(cell),
+            Column::Name => row.name = text(cell),
+            Column::Exchange => row.exchange = text(cell),
+            Column::Sector => row.sector = text(cell),
+            Column::High52w => row.high_52w = optional(cell)?,
+            Column::Low52w => row.low_52w = optional(cell)?,
+            Column::PctFromHigh52w => {
+                row.pct_from_high_52w = optional(cell)?.map(|v| if strict { v } else { v / 100.0 })
+            }
+            Column::PctFromLow52w => {
+                row.pct_from_low_52w = optional(cell)?.map(|v| if strict { v } else { v / 100.0 })
+            }
+            Column::GapPct => {
+                row.gap_pct = optional(cell)?.map(|v| if strict { v } else { v / 100.0 })
+            }
+            Column::ChangeFromPrevClose => {
+                row.change_from_prev_close =
+                    optional(cell)?.map(|v| if strict { v } else { v / 100.0 })
+            }
+            Column::VwapSession => row.vwap_session = optional(cell)?,
+            Column::Custom(name) => {
+                if !cell.is_empty() {
+                    row.custom.insert(name.clone(), number(cell)?);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+///
+/// The columns of a CSV file, in order
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CsvSchema {
+    pub columns: Vec<Column>,
+    /// Write plain numbers with this many decimals, if set
+    pub strict: Option<usize>,
+    /// Separators and decimals of the numbers unless strict
+    pub numbers: NumberFormat,
+}
+
+impl Default for CsvSchema {
+    fn default() -> Self {
+        CsvSchema {
+            columns: vec![
+                Column::Timestamp,
+                Column::Symbol,
+                Column::Price,
+                Column::PctChange,
+                Column::PeriodMin,
+                Column::PeriodMax,
+                Column::LastSma,
+                Column::LastEma,
+                Column::Rsi,
+                Column::Volatility,
+            ],
+            strict: None,
+            numbers: NumberFormat::default(),
+        }
+    }
+}
+
+impl CsvSchema {
+    ///
+    /// Columns chosen in the config file. Rows can only be read back with a timestamp and a
+    /// symbol.
+    ///
+    pub fn from_names(names: &[String]) -> Result<Self> {
+        let columns: Vec<Column> = names.iter().map(|n| Column::parse(n)).collect();
+        for required in &[Column::Timestamp, Column::Symbol] {
+            if !columns.contains(required) {
+                bail!("The CSV columns need to include '{}'", required.name());
+            }
+        }
+        Ok(CsvSchema {
+            columns,
+            strict: None,
+            numbers: NumberFormat::default(),
+        })
+    }
+
+    pub fn with_numbers(self, numbers: NumberFormat) -> Self {
+        CsvSchema { numbers, ..self }
+    }
+
+    pub fn strict(self, precision: usize) -> Self {
+        CsvSchema {
+            strict: Some(precision),
+            ..self
+        }
+    }
+
+    ///
+    /// The schema comment, e.g. `# schema 1: timestamp,symbol,price`
+    ///
+    pub fn comment(&self) -> String {
+        let names: Vec<&str> = self.columns.iter().map(|c| c.name()).collect();
+        let mut mode = String::new();
+        if self.strict.is_some() {
+            mode.push_str(" strict");
+        } else if !self.numbers.is_default() {
+            mode.push_str(&format!(" decimal={}", self.numbers.decimal));
+            if let Some(thousands) = self.numbers.thousands {
+                mode.push_str(&format!(" thousands={}", thousands));
+            }
+        }
+        format!("# schema {}{}: {}", SCHEMA_VERSION, mode, names.join(","))
+    }
+
+    ///
+    /// Reads a schema comment. Returns `None` for other comments.
+    ///
+    pub fn from_comment(line: &str) -> Option<Result<Self>> {
+        let (version, names) = line.strip_prefix("# schema ")?.split_once(':')?;
+        let parse = || {
+            let mut words = version.trim().split(' ');
+            let version = words.next().unwrap_or_default();
+            let mut strict = false;
+            let mut numbers = NumberFormat::default();
+            for word in words {
+                let separator = |value: &str| {
+                    let mut chars = value.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => Ok(c),
+                        _ => Err(anyhow!("invalid separator '{}'", value)),
+                    }
+                };
+                match word.split_once('=') {
+                    None if word == "strict" => strict = true,
+                    Some(("decimal", value)) => numbers.decimal = separator(value)?,
+                    Some(("thousands", value)) => numbers.thousands = Some(separator(value)?),
+                    _ => bail!("invalid schema version '{}'", version),
+                }
+            }
+            let version: u32 = version
+                .parse()
+                .map_err(|_| anyhow!("invalid schema version '{}'", version))?;
+            if version > SCHEMA_VERSION {
+                bail!(
+                    "schema version {} is not supported (only up to {})",
+                    version,
+                    SCHEMA_VERSION
+                );
+            }
+            let names: Vec<String> = names.split(',').map(|n| n.trim().to_string()).collect();
+            let schema = CsvSchema::from_names(&names)?.with_numbers(numbers);
+            // the precision doesn't matter for reading
+            Ok(if strict { schema.strict(0) } else { schema })
+        };
+        Some(parse())
+    }
+
+    pub fn header(&self) -> String {
+        let titles = self.columns.iter().map(|c| match self.strict {
+            Some(_) => c.name(),
+            None => c.title(),
+        });
+        join(titles)
+    }
+
+    pub fn format(&self, row: &PerformanceIndicators) -> String {
+        join(
+            self.columns
+                .iter()
+                .map(|c| c.format(row, self.strict, &self.numbers)),
+        )
+    }
+
+    pub fn parse(&self, line: &str) -> Result<PerformanceIndicators> {
+        let record = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(line.as_bytes())
+            .into_records()
+            .next()
+            .unwrap_or_else(|| Ok(csv::StringRecord::new()))?;
+        self.parse_record(&record)
+    }
+
+    ///
+    /// Whether a record is the header line of this schema
+    ///
+    pub fn is_header(&self, record: &csv::StringRecord) -> bool {
+        join(record.iter().map(str::trim)) == self.header()
+    }
+
+    ///
+    /// Reads a record of a CSV reader, the cells are trimmed
+    ///
+    pub fn parse_record(&self, record: &csv::StringRecord) -> Result<PerformanceIndicators> {
+        let cells: Vec<&str> = record.iter().map(str::trim).collect();
+        if cells.len() < self.columns.len() {
+            bail!(
+                "expected {} columns, found {}",
+                self.columns.len(),
+                cells.len()
+            );
+        }
+        let mut row = PerformanceIndicators::default();
+        for (column, cell) in self.columns.iter().zip(cells) {
+            column
+                .read(cell, &mut row, self.strict.is_some(), &self.numbers)
+                .map_err(|e| anyhow!("column '{}': {}", column.name(), e))?;
+        }
+        Ok(row)
+    }
+}
+
+///
+/// Joins cells into a CSV line, quoting them where needed
+///
+fn join<T: AsRef<[u8]>>(cells: impl Iterator<Item = T>) -> String {
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    let _ = writer.write_record(cells);
+    let line = writer.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&line)
+        .trim_end_matches('\n')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_columns() {
+        let row = CsvSchema::default()
+            .parse("2020-07-03T12:00:09+00:00,AAPL,$91.03,-1.25%,$60.55,$91.20,$87.74,$89.10,61.37,24.50%")
+            .unwrap();
+        assert_eq!(row.symbol, "AAPL");
+        assert_eq!(row.timestamp, Utc.timestamp_opt(1593777609, 0).unwrap());
+        assert_eq!(row.price, 91.03);
+        assert_eq!(row.pct_change, -0.0125);
+        assert_eq!(row.last_sma, 87.74);
+        assert_eq!(row.last_ema, 89.10);
+        assert_eq!(row.rsi, Some(61.37));
+        assert_eq!(row.volatility, Some(0.245));
+        assert_eq!(row.currency, None);
+        let row = CsvSchema::default()
+            .parse(
+                "2020-07-03T12:00:09+00:00,SAP.DE,91.03 EUR,-1.25%,60.55 EUR,91.20 EUR,87.74 EUR,89.10 EUR,,",
+            )
+            .unwrap();
+        assert_eq!(row.price, 91.03);
+        assert_eq!(row.currency.as_deref(), Some("EUR"));
+        assert!(CsvSchema::default()
+            .parse("2020-07-03T12:00:09+00:00,AAPL")
+            .is_err());
+    }
+
+    #[test]
+    fn test_custom_columns() {
+        let names: Vec<String> = vec!["symbol", "timestamp", "price", "rsi", "watchlist"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let schema = CsvSchema::from_names(&names).unwrap();
+        assert_eq!(schema.header(), "symbol,period start,price,rsi,watchlist");
+        assert_eq!(
+            schema.comment(),
+            "# schema 1: symbol,timestamp,price,rsi,watchlist"
+        );
+        assert_eq!(
+            CsvSchema::from_comment(&schema.comment()).unwrap().unwrap(),
+            schema
+        );
+
+        let mut row = schema
+            .parse("AAPL,2020-07-03T12:00:09+00:00,$91.03,,")
+            .unwrap();
+        assert_eq!(
+            schema.format(&row),
+            "AAPL,2020-07-03T12:00:09+00:00,$91.03,,"
+        );
+        row.custom.insert("rsi".to_string(), 55.5);
+        row.watchlist = Some("tech".to_string());
+        let line = schema.format(&row);
+        assert_eq!(line, "AAPL,2020-07-03T12:00:09+00:00,$91.03,55.5000,tech");
+        let parsed = schema.parse(&line).unwrap();
+        assert_eq!(parsed.custom["rsi"], 55.5);
+        assert_eq!(parsed.watchlist.as_deref(), Some("tech"));
+
+        let year: Vec<String> = vec!["timestamp", "symbol", "high_52w", "pct_from_high_52w"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let year = CsvSchema::from_names(&year).unwrap();
+        row.high_52w = Some(100.0);
+        row.pct_from_high_52w = Some(-0.0897);
+        let line = year.format(&row);
+        assert_eq!(line, "2020-07-03T12:00:09+00:00,AAPL,$100.00,-8.97%");
+        assert_eq!(year.parse(&line).unwrap().pct_from_high_52w, Some(-0.0897));
+
+        assert!(CsvSchema::from_names(&["price".to_string()]).is_err());
+        assert!(CsvSchema::from_comment("# schema 2: timestamp,symbol")
+            .unwrap()
+            .is_err());
+        assert!(CsvSchema::from_comment("# just a comment").is_none());
+    }
+
+    #[test]
+    fn test_number_format() {
+        let numbers = NumberFormat {
+            decimal: ',',
+            thousands: Some('.'),
+            precision: std::collections::BTreeMap::from([("price".to_string(), 3)]),
+        };
+        let schema = CsvSchema::default().with_numbers(numbers);
+        assert_eq!(
+            schema.comment(),
+            "# schema 1 decimal=, thousands=.: timestamp,symbol,price,pct_change,period_min,period_max,last_sma,last_ema,last_rsi,volatility"
+        );
+        let row = PerformanceIndicators {
+            timestamp: Utc.timestamp_opt(1593777609, 0).unwrap(),
+            symbol: "BRK-A".to_string(),
+            price: 412345.5,
+            pct_change: -0.0125,
+            period_min: 1234.0,
+            rsi: Some(61.37),
+            currency: Some("EUR".to_string()),
+            ..Default::default()
+        };
+        let line = schema.format(&row);
+        assert_eq!(
+            line,
+            "2020-07-03T12:00:09+00:00,BRK-A,\"412.345,500 EUR\",\"-1,25%\",\"1.234,00 EUR\",\"0,00 EUR\",\"0,00 EUR\",\"0,00 EUR\",\"61,37\","
+        );
+        let read = CsvSchema::from_comment(&schema.comment()).unwrap().unwrap();
+        let parsed = read.parse(&line).unwrap();
+        assert_eq!(parsed.price, 412345.5);
+        assert_eq!(parsed.pct_change, -0.0125);
+        assert_eq!(parsed.period_min, 1234.0);
+        assert_eq!(parsed.rsi, Some(61.37));
+        assert_eq!(parsed.currency.as_deref(), Some("EUR"));
+    }
+
+    #[test]
+    fn test_strict_columns() {
+        let names: Vec<String> = vec!["timestamp", "symbol", "pct_change", "watchlist"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let schema = CsvSchema::from_names(&names).unwrap().strict(3);
+        assert_eq!(schema.header(), "timestamp,symbol,pct_change,watchlist");
+        assert_eq!(
+            schema.comment(),
+            "# schema 1 strict: timestamp,symbol,pct_change,watchlist"
+        );
+        let mut row = CsvSchema::default()
+            .parse("2020-07-03T12:00:09+00:00,AAPL,$91.03,-1.25%,$60.55,$91.20,$87.74,$89.10,61.37,24.50%")
+            .unwrap();
+        row.watchlist = Some("tech, large caps".to_string());
+        let line = schema.format(&row);
+        assert_eq!(
+            line,
+            "2020-07-03T12:00:09Z,AAPL,-0.013,\"tech, large caps\""
+        );
+        let read = CsvSchema::from_comment(&schema.comment()).unwrap().unwrap();
+        let parsed = read.parse(&line).unwrap();
+        assert_eq!(parsed.timestamp, row.timestamp);
+        assert_eq!(parsed.pct_change, -0.013);
+        assert_eq!(parsed.watchlist, row.watchlist);
+    }
+}
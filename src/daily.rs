@@ -0,0 +1,507 @@
+//!
+//! End-of-day rollup: one summary per symbol and session (UTC day, closing early on the
+//! half-days of the calendar) with the session's bar and the latest indicators, written to a
+//! CSV file and optionally mailed as a digest.
+//!
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::calendar::Calendar;
+use crate::clock::SharedClock;
+use crate::gap::SESSION;
+use crate::quality::CleanQuotes;
+use crate::shutdown::Shutdown;
+use crate::signal::TickerQuote;
+use crate::PerformanceIndicators;
+
+const HEADER: &str =
+    "date,symbol,watchlist,open,high,low,close,volume,change,change %,sma,min,max,custom";
+
+///
+/// Published once a session of a symbol is over
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DailySummary {
+    pub symbol: String,
+    pub watchlist: Option<String>,
+    pub date: NaiveDate,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: u64,
+    /// Change of the close against the previous session's close (or the open, for the first
+    /// session)
+    pub change: f64,
+    pub pct_change: f64,
+    /// The latest indicators of the session, if any were calculated
+    pub indicators: Option<PerformanceIndicators>,
+}
+
+impl DailySummary {
+    ///
+    /// The summary as a row of the summary file
+    ///
+    pub fn csv_row(&self) -> String {
+        let indicators = self.indicators.as_ref();
+        let number = |value: Option<f64>| value.map(|v| format!("{:.4}", v)).unwrap_or_default();
+        let custom: Vec<String> = indicators
+            .map(|i| {
+                i.custom
+                    .iter()
+                    .map(|(name, value)| format!("{}={:.4}", name, value))
+                    .collect()
+            })
+            .unwrap_or_default();
+        format!(
+            "{},{},{},{:.4},{:.4},{:.4},{:.4},{},{:.4},{:.4},{},{},{},{}",
+            self.date,
+            self.symbol,
+            self.watchlist.as_deref().unwrap_or_default(),
+            self.open,
+            self.high,
+            self.low,
+            self.close,
+            self.volume,
+            self.change,
+            self.pct_change * 100.0,
+            number(indicators.map(|i| i.last_sma)),
+            number(indicators.map(|i| i.period_min)),
+            number(indicators.map(|i| i.period_max)),
+            custom.join(";")
+        )
+    }
+}
+
+///
+/// The bar of a session that is still open
+///
+#[derive(Debug, Clone)]
+struct SessionBar {
+    day: u64,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    volume: u64,
+    last: u64,
+    indicators: Option<PerformanceIndicators>,
+}
+
+impl SessionBar {
+    fn new(quote: &TickerQuote) -> Self {
+        SessionBar {
+            day: quote.timestamp / SESSION,
+            open: quote.open,
+            high: quote.high,
+            low: quote.low,
+            close: quote.close,
+            volume: quote.volume,
+            last: quote.timestamp,
+            indicators: None,
+        }
+    }
+
+    fn add(&mut self, quote: &TickerQuote) {
+        self.high = self.high.max(quote.high);
+        self.low = self.low.min(quote.low);
+        self.close = quote.close;
+        self.volume += quote.volume;
+        self.last = quote.timestamp;
+    }
+}
+
+type SessionKey = (Option<String>, String);
+
+///
+/// The date of a session
+///
+fn date(day: u64) -> NaiveDate {
+    Utc.timestamp_opt((day * SESSION) as i64, 0)
+        .unwrap()
+        .date_naive()
+}
+
+///
+/// Rolls quotes up into session bars and closes a session when the next one starts (or the
+/// day is over)
+///
+#[derive(Debug, Default)]
+pub struct SessionRollup {
+    open: HashMap<SessionKey, SessionBar>,
+    /// Close of the last finished session and the last quote seen, per symbol
+    closed: HashMap<SessionKey, (f64, u64)>,
+    /// Decides when a session closes
+    calendar: Calendar,
+}
+
+impl SessionRollup {
+    pub fn new(calendar: Calendar) -> Self {
+        SessionRollup {
+            calendar,
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// Adds the quotes of a fetch (sorted by time), returns the sessions they closed. Quotes
+    /// that were already seen are ignored.
+    ///
+    pub fn push(
+        &mut self,
+        symbol: &str,
+        watchlist: Option<&str>,
+        quotes: &[TickerQuote],
+    ) -> Vec<DailySummary> {
+        let key = (watchlist.map(str::to_string), symbol.to_string());
+        let mut summaries = vec![];
+        for quote in quotes {
+            let seen = match self.open.get(&key) {
+                Some(bar) => Some(bar.last),
+                None => self.closed.get(&key).map(|(_, last)| *last),
+            };
+            if seen.is_some_and(|seen| quote.timestamp <= seen) {
+                continue;
+            }
+            match self.open.get_mut(&key) {
+                Some(bar) if bar.day == quote.timestamp / SESSION => bar.add(quote),
+                _ => {
+                    if let Some(summary) = self.close(&key) {
+                        summaries.push(summary);
+                    }
+                    self.open.insert(key.clone(), SessionBar::new(quote));
+                }
+            }
+        }
+        summaries
+    }
+
+    ///
+    /// Keeps the indicators as the snapshot of their session
+    ///
+    pub fn snapshot(&mut self, indicators: &PerformanceIndicators) {
+        let key = (indicators.watchlist.clone(), indicators.symbol.clone());
+        let day = indicators.timestamp.timestamp().max(0) as u64 / SESSION;
+        if let Some(bar) = self.open.get_mut(&key).filter(|b| b.day == day) {
+            bar.indicators = Some(indicators.clone());
+        }
+    }
+
+    ///
+    /// Closes all sessions that closed before `now` on the calendar
+    ///
+    pub fn close_before(&mut self, now: DateTime<Utc>) -> Vec<DailySummary> {
+        let calendar = &self.calendar;
+        let mut keys: Vec<SessionKey> = self
+            .open
+            .iter()
+            .filter(|(_, bar)| calendar.session_close(date(bar.day)) <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.sort();
+        keys.iter().filter_map(|key| self.close(key)).collect()
+    }
+
+    fn close(&mut self, key: &SessionKey) -> Option<DailySummary> {
+        let bar = self.open.remove(key)?;
+        let reference = self
+            .closed
+            .get(key)
+            .map(|(close, _)| *close)
+            .unwrap_or(bar.open);
+        self.closed.insert(key.clone(), (bar.close, bar.last));
+        let change = bar.close - reference;
+        Some(DailySummary {
+            symbol: key.1.clone(),
+            watchlist: key.0.clone(),
+            date: date(bar.day),
+            open: bar.open,
+            high: bar.high,
+            low: bar.low,
+            close: bar.close,
+            volume: bar.volume,
+            change,
+            pct_change: if reference != 0.0 {
+                change / reference
+            } else {
+                0.0
+            },
+            indicators: bar.indicators,
+        })
+    }
+}
+
+#[message]
+#[derive(Clone)]
+struct EndOfDay;
+
+///
+/// Actor that writes a `DailySummary` per symbol to a CSV file (and publishes it) whenever a
+/// session is over. With an email address, the summaries are also sent as a digest via the
+/// local `sendmail`.
+///
+pub struct DailySummarizer {
+    pub filename: String,
+    pub email: Option<String>,
+    /// Decides when a day is over
+    clock: SharedClock,
+    rollup: SessionRollup,
+    /// Summaries that were not mailed yet
+    digest: Vec<DailySummary>,
+}
+
+impl DailySummarizer {
+    pub fn new(
+        filename: String,
+        email: Option<String>,
+        clock: SharedClock,
+        calendar: Calendar,
+    ) -> Self {
+        DailySummarizer {
+            filename,
+            email,
+            clock,
+            rollup: SessionRollup::new(calendar),
+            digest: vec![],
+        }
+    }
+
+    async fn emit(&mut self, summaries: Vec<DailySummary>) {
+        if summaries.is_empty() {
+            return;
+        }
+        if let Err(e) = self.append(&summaries) {
+            tracing::error!(
+                "Could not write daily summary to '{}': {}",
+                self.filename,
+                e
+            );
+        }
+        if self.email.is_some() {
+            self.digest.extend(summaries.iter().cloned());
+        }
+        let mut broker = Broker::from_registry().await.unwrap();
+        for summary in summaries {
+            if let Err(e) = broker.publish(summary) {
+                tracing::error!("{}", e);
+            }
+        }
+    }
+
+    fn append(&self, summaries: &[DailySummary]) -> std::io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.filename)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        for summary in summaries {
+            writeln!(file, "{}", summary.csv_row())?;
+        }
+        Ok(())
+    }
+
+    async fn send_digest(&mut self) {
+        let to = match &self.email {
+            Some(to) if !self.digest.is_empty() => to.clone(),
+            _ => return,
+        };
+        let summaries = std::mem::take(&mut self.digest);
+        if let Err(e) = sendmail(digest(&to, &summaries)).await {
+            tracing::error!("Could not send the daily digest to {}: {}", to, e);
+        }
+    }
+}
+
+///
+/// Hands a mail with its headers (`To:`, `Subject:`, ...) to the local `sendmail`
+///
+pub async fn sendmail(mail: String) -> std::io::Result<()> {
+    async_std::task::spawn_blocking(move || {
+        let mut child = Command::new("sendmail")
+            .arg("-t")
+            .stdin(Stdio::piped())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(mail.as_bytes())?;
+        match child.wait()? {
+            status if status.success() => Ok(()),
+            status => Err(std::io::Error::other(format!(
+                "sendmail exited with {}",
+                status
+            ))),
+        }
+    })
+    .await
+}
+
+///
+/// The email with the summaries, one line per symbol
+///
+fn digest(to: &str, summaries: &[DailySummary]) -> String {
+    let first = summaries.iter().map(|s| s.date).min().unwrap_or_default();
+    let mut mail = format!("To: {}\nSubject: Daily summary {}\n\n", to, first);
+    for s in summaries {
+        mail.push_str(&format!(
+            "{} {}{}: open {:.2} high {:.2} low {:.2} close {:.2} ({:+.2}%), volume {}\n",
+            s.date,
+            s.symbol,
+            s.watchlist
+                .as_deref()
+                .map(|w| format!(" ({})", w))
+                .unwrap_or_default(),
+            s.open,
+            s.high,
+            s.low,
+            s.close,
+            s.pct_change * 100.0,
+            s.volume
+        ));
+    }
+    mail
+}
+
+#[async_trait::async_trait]
+impl Actor for DailySummarizer {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.send_interval(EndOfDay, Duration::from_secs(60));
+        ctx.subscribe::<PerformanceIndicators>().await?;
+        ctx.subscribe::<CleanQuotes>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<CleanQuotes> for DailySummarizer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: CleanQuotes) {
+        let mut msg = msg.0;
+        msg.quotes.sort_by_cached_key(|q| q.timestamp);
+        let summaries = self
+            .rollup
+            .push(&msg.symbol, msg.watchlist.as_deref(), &msg.quotes);
+        self.emit(summaries).await;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for DailySummarizer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        if msg.resolution.is_none() {
+            self.rollup.snapshot(&msg);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<EndOfDay> for DailySummarizer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: EndOfDay) {
+        let summaries = self.rollup.close_before(self.clock.now());
+        self.emit(summaries).await;
+        self.send_digest().await;
+    }
+}
+
+///
+/// Writes the sessions that are over, and mails what wasn't mailed yet. The open sessions are
+/// left to the next run.
+///
+#[async_trait::async_trait]
+impl Handler<Shutdown> for DailySummarizer {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {
+        let summaries = self.rollup.close_before(self.clock.now());
+        self.emit(summaries).await;
+        self.send_digest().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(timestamp: u64, open: f64, close: f64, volume: u64) -> TickerQuote {
+        TickerQuote {
+            timestamp,
+            open,
+            high: open.max(close) + 1.0,
+            low: open.min(close) - 1.0,
+            volume,
+            close,
+            adjclose: close,
+        }
+    }
+
+    #[test]
+    fn test_session_rollup() {
+        let day = SESSION;
+        let mut rollup = SessionRollup::default();
+        let first = vec![
+            quote(day + 60, 10.0, 11.0, 100),
+            quote(day + 120, 11.0, 12.0, 50),
+        ];
+        assert!(rollup.push("AAPL", None, &first).is_empty());
+        let indicators = PerformanceIndicators {
+            symbol: "AAPL".to_string(),
+            timestamp: Utc.timestamp_opt((day + 120) as i64, 0).unwrap(),
+            last_sma: 11.5,
+            ..Default::default()
+        };
+        rollup.snapshot(&indicators);
+
+        // the refetch overlaps, the new quote starts the next session
+        let second = vec![
+            quote(day + 120, 11.0, 12.0, 50),
+            quote(2 * day, 13.0, 15.0, 10),
+        ];
+        let summaries = rollup.push("AAPL", None, &second);
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!(summary.date, NaiveDate::from_ymd_opt(1970, 1, 2).unwrap());
+        assert_eq!((summary.open, summary.high, summary.low), (10.0, 13.0, 9.0));
+        assert_eq!((summary.close, summary.volume), (12.0, 150));
+        assert_eq!(summary.change, 2.0);
+        assert_eq!(summary.indicators.as_ref().unwrap().last_sma, 11.5);
+        assert_eq!(
+            summary.csv_row(),
+            "1970-01-02,AAPL,,10.0000,13.0000,9.0000,12.0000,150,2.0000,20.0000,11.5000,0.0000,0.0000,"
+        );
+
+        assert!(rollup
+            .close_before(Utc.timestamp_opt((2 * day + 60) as i64, 0).unwrap())
+            .is_empty());
+        let summaries = rollup.close_before(Utc.timestamp_opt((3 * day) as i64, 0).unwrap());
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].change, 3.0);
+        assert_eq!(summaries[0].pct_change, 0.25);
+        assert!(summaries[0].indicators.is_none());
+        assert!(rollup.push("AAPL", None, &second).is_empty());
+    }
+
+    #[test]
+    fn test_early_close() {
+        let at = |h| Utc.with_ymd_and_hms(2024, 11, 29, h, 0, 0).unwrap();
+        let mut rollup = SessionRollup::new(Calendar::nyse());
+        let quotes = vec![quote(at(15).timestamp() as u64, 10.0, 11.0, 100)];
+        assert!(rollup.push("AAPL", None, &quotes).is_empty());
+
+        // the half-day after Thanksgiving closes at 18:00
+        assert!(rollup.close_before(at(17)).is_empty());
+        let summaries = rollup.close_before(at(18));
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(
+            summaries[0].date,
+            NaiveDate::from_ymd_opt(2024, 11, 29).unwrap()
+        );
+    }
+}
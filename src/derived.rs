@@ -0,0 +1,445 @@
+//!
+//! Derived symbols are calculated from the bars of other symbols, as in the config file:
+//!
+//! ```toml
+//! [derived]
+//! GOLD_SILVER = "GC / SI"
+//! SPREAD = "AAPL - MSFT"
+//! ```
+//!
+//! Their bars go through the signal calculation and to the sinks like fetched ones.
+//!
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use anyhow::bail;
+use xactor::*;
+
+use crate::quality::CleanQuotes;
+use crate::shutdown::Shutdown;
+use crate::signal::TickerQuote;
+use crate::Quotes;
+
+///
+/// Bars kept per symbol to line up with the bars of the others
+///
+const MAX_BARS: usize = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+///
+/// An arithmetic expression over symbols and numbers
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Symbol(String),
+    Neg(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Op(char),
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '+' | '-' | '*' | '/' => tokens.push(Token::Op(c)),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            c if is_word(c) => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.peek().copied().filter(|c| is_word(*c)) {
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            }
+            c => bail!("Unexpected '{}' in '{}'", c, source),
+        }
+    }
+    Ok(tokens)
+}
+
+///
+/// Characters of symbols (e.g. `BRK.B`, `GC=F`, `^GSPC`) and numbers
+///
+fn is_word(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '^' | '=')
+}
+
+///
+/// Recursive descent over `expr := term (('+' | '-') term)*`,
+/// `term := factor (('*' | '/') factor)*`, and `factor := '-' factor | '(' expr ')' | word`
+///
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[char],
+        operand: fn(&mut Self) -> anyhow::Result<Expr>,
+    ) -> anyhow::Result<Expr> {
+        let mut left = operand(self)?;
+        while let Some(Token::Op(c)) = self.peek().cloned().filter(|t| match t {
+            Token::Op(c) => ops.contains(c),
+            _ => false,
+        }) {
+            self.next();
+            let op = match c {
+                '+' => Op::Add,
+                '-' => Op::Sub,
+                '*' => Op::Mul,
+                _ => Op::Div,
+            };
+            left = Expr::Binary(Box::new(left), op, Box::new(operand(self)?));
+        }
+        Ok(left)
+    }
+
+    fn expr(&mut self) -> anyhow::Result<Expr> {
+        self.binary(&['+', '-'], Self::term)
+    }
+
+    fn term(&mut self) -> anyhow::Result<Expr> {
+        self.binary(&['*', '/'], Self::factor)
+    }
+
+    fn factor(&mut self) -> anyhow::Result<Expr> {
+        match self.next() {
+            Some(Token::Op('-')) => Ok(Expr::Neg(Box::new(self.factor()?))),
+            Some(Token::Open) => {
+                let expr = self.expr()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => bail!("Missing ')'"),
+                }
+            }
+            Some(Token::Word(word)) => Ok(match word.parse::<f64>() {
+                Ok(number) => Expr::Number(number),
+                Err(_) => Expr::Symbol(word),
+            }),
+            Some(token) => bail!("Unexpected {:?}", token),
+            None => bail!("Unexpected end"),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        };
+        let expr = parser.expr()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected {:?} in '{}'", token, source);
+        }
+        Ok(expr)
+    }
+
+    ///
+    /// The symbols the expression refers to
+    ///
+    pub fn symbols(&self) -> BTreeSet<String> {
+        let mut symbols = BTreeSet::new();
+        self.collect(&mut symbols);
+        symbols
+    }
+
+    fn collect(&self, symbols: &mut BTreeSet<String>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Symbol(symbol) => {
+                symbols.insert(symbol.clone());
+            }
+            Expr::Neg(expr) => expr.collect(symbols),
+            Expr::Binary(left, _, right) => {
+                left.collect(symbols);
+                right.collect(symbols);
+            }
+        }
+    }
+
+    ///
+    /// The value with the symbols' values, `None` if one is missing or for a division by 0
+    ///
+    pub fn eval(&self, value: &dyn Fn(&str) -> Option<f64>) -> Option<f64> {
+        match self {
+            Expr::Number(number) => Some(*number),
+            Expr::Symbol(symbol) => value(symbol),
+            Expr::Neg(expr) => expr.eval(value).map(|v| -v),
+            Expr::Binary(left, op, right) => {
+                let (left, right) = (left.eval(value)?, right.eval(value)?);
+                match op {
+                    Op::Add => Some(left + right),
+                    Op::Sub => Some(left - right),
+                    Op::Mul => Some(left * right),
+                    Op::Div => (right != 0.0).then(|| left / right),
+                }
+            }
+        }
+    }
+}
+
+///
+/// A derived symbol with the expression it is calculated with
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Derived {
+    pub name: String,
+    pub expr: Expr,
+    pub symbols: BTreeSet<String>,
+}
+
+impl Derived {
+    ///
+    /// The derived symbols of the config file. They can only refer to fetched symbols, not
+    /// to each other.
+    ///
+    pub fn from_config(config: &BTreeMap<String, String>) -> anyhow::Result<Vec<Self>> {
+        let mut derived = vec![];
+        for (name, source) in config {
+            let expr = match Expr::parse(source) {
+                Ok(expr) => expr,
+                Err(e) => bail!("Invalid derived symbol {}: {}", name, e),
+            };
+            let symbols = expr.symbols();
+            if symbols.is_empty() {
+                bail!("The derived symbol {} refers to no symbols", name);
+            }
+            if let Some(other) = symbols.iter().find(|s| config.contains_key(*s)) {
+                bail!(
+                    "The derived symbol {} refers to the derived {}",
+                    name,
+                    other
+                );
+            }
+            derived.push(Derived {
+                name: name.clone(),
+                expr,
+                symbols,
+            });
+        }
+        Ok(derived)
+    }
+
+    ///
+    /// The bars at the timestamps all symbols have one at. Open and close are calculated
+    /// from the symbols' opens and closes; high and low are the larger and smaller of the
+    /// two, since the symbols' extremes don't happen at the same time.
+    ///
+    fn bars(
+        &self,
+        timestamps: impl Iterator<Item = u64>,
+        bars: &HashMap<String, BTreeMap<u64, TickerQuote>>,
+    ) -> Vec<TickerQuote> {
+        timestamps
+            .filter_map(|timestamp| {
+                let field = |f: fn(&TickerQuote) -> f64| {
+                    self.expr
+                        .eval(&|symbol| bars.get(symbol)?.get(&timestamp).map(f))
+                };
+                let open = field(|q| q.open)?;
+                let close = field(|q| q.close)?;
+                Some(TickerQuote {
+                    timestamp,
+                    open,
+                    high: open.max(close),
+                    low: open.min(close),
+                    volume: 0,
+                    close,
+                    adjclose: field(|q| q.adjclose).unwrap_or(close),
+                })
+            })
+            .collect()
+    }
+}
+
+///
+/// Actor that calculates the bars of the derived symbols whenever one of their symbols has
+/// new ones, and publishes them like the checked bars of a fetch
+///
+pub struct DerivedSymbols {
+    derived: Vec<Derived>,
+    /// The latest bars by pipeline (`None` for the default one) and symbol
+    bars: HashMap<Option<String>, HashMap<String, BTreeMap<u64, TickerQuote>>>,
+}
+
+impl DerivedSymbols {
+    pub fn new(derived: Vec<Derived>) -> Self {
+        DerivedSymbols {
+            derived,
+            bars: HashMap::new(),
+        }
+    }
+
+    ///
+    /// Keeps the bars of a symbol and returns the new bars of the derived symbols
+    ///
+    fn update(&mut self, quotes: &Quotes) -> Vec<Quotes> {
+        if !self
+            .derived
+            .iter()
+            .any(|d| d.symbols.contains(&quotes.symbol))
+        {
+            return vec![];
+        }
+        let bars = self.bars.entry(quotes.watchlist.clone()).or_default();
+        let series = bars.entry(quotes.symbol.clone()).or_default();
+        for quote in &quotes.quotes {
+            series.insert(quote.timestamp, quote.clone());
+        }
+        while series.len() > MAX_BARS {
+            series.pop_first();
+        }
+        self.derived
+            .iter()
+            .filter(|d| d.symbols.contains(&quotes.symbol))
+            .map(|d| Quotes {
+                symbol: d.name.clone(),
+                quotes: d.bars(quotes.quotes.iter().map(|q| q.timestamp), bars),
+                watchlist: quotes.watchlist.clone(),
+            })
+            .filter(|q| !q.quotes.is_empty())
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for DerivedSymbols {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<CleanQuotes>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<CleanQuotes> for DerivedSymbols {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: CleanQuotes) {
+        for quotes in self.update(&msg.0) {
+            if let Err(e) = Broker::from_registry()
+                .await
+                .unwrap()
+                .publish(CleanQuotes(quotes))
+            {
+                tracing::error!("{}", e);
+            }
+        }
+    }
+}
+
+///
+/// Answered once the quotes that arrived before are combined and published
+///
+#[async_trait::async_trait]
+impl Handler<Shutdown> for DerivedSymbols {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(timestamp: u64, close: f64) -> TickerQuote {
+        TickerQuote {
+            timestamp,
+            open: close - 1.0,
+            high: close + 1.0,
+            low: close - 2.0,
+            volume: 100,
+            close,
+            adjclose: close,
+        }
+    }
+
+    fn quotes(symbol: &str, bars: Vec<TickerQuote>) -> Quotes {
+        Quotes {
+            symbol: symbol.to_string(),
+            quotes: bars,
+            watchlist: None,
+        }
+    }
+
+    #[test]
+    fn test_expr() {
+        let expr = Expr::parse("(GC=F - 2 * SI) / -BRK.B").unwrap();
+        assert_eq!(
+            expr.symbols(),
+            BTreeSet::from(["BRK.B".to_string(), "GC=F".to_string(), "SI".to_string()])
+        );
+        let values = |symbol: &str| match symbol {
+            "GC=F" => Some(10.0),
+            "SI" => Some(2.0),
+            "BRK.B" => Some(3.0),
+            _ => None,
+        };
+        assert_eq!(expr.eval(&values), Some(-2.0));
+        assert_eq!(Expr::parse("SI / 0").unwrap().eval(&values), None);
+        assert_eq!(Expr::parse("SI + XX").unwrap().eval(&values), None);
+        assert!(Expr::parse("AAPL -").is_err());
+        assert!(Expr::parse("(AAPL").is_err());
+        assert!(Expr::parse("AAPL MSFT").is_err());
+        assert!(Expr::parse("AAPL % 2").is_err());
+    }
+
+    #[test]
+    fn test_derived_symbols() {
+        let config = BTreeMap::from([
+            ("SPREAD".to_string(), "AAPL - MSFT".to_string()),
+            ("RATIO".to_string(), "AAPL / MSFT".to_string()),
+        ]);
+        let mut derived = DerivedSymbols::new(Derived::from_config(&config).unwrap());
+        // MSFT has no bars yet
+        let aapl = quotes("AAPL", vec![bar(60, 10.0), bar(120, 12.0)]);
+        assert!(derived.update(&aapl).is_empty());
+        // the bar at 180 has no AAPL bar to line up with
+        let msft = quotes("MSFT", vec![bar(60, 5.0), bar(120, 4.0), bar(180, 3.0)]);
+        let updates = derived.update(&msft);
+        let ratio = &updates[0];
+        assert_eq!(ratio.symbol, "RATIO");
+        let closes: Vec<f64> = ratio.quotes.iter().map(|q| q.close).collect();
+        assert_eq!(closes, vec![2.0, 3.0]);
+        let spread = &updates[1];
+        assert_eq!(spread.symbol, "SPREAD");
+        let spread_bar = &spread.quotes[1];
+        assert_eq!((spread_bar.open, spread_bar.close), (8.0, 8.0));
+        assert!(derived
+            .update(&quotes("GOOG", vec![bar(60, 1.0)]))
+            .is_empty());
+
+        let invalid = BTreeMap::from([
+            ("A".to_string(), "AAPL * 2".to_string()),
+            ("B".to_string(), "A + 1".to_string()),
+        ]);
+        assert!(Derived::from_config(&invalid).is_err());
+        let constant = BTreeMap::from([("C".to_string(), "1 + 2".to_string())]);
+        assert!(Derived::from_config(&constant).is_err());
+    }
+}
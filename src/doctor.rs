@@ -0,0 +1,227 @@
+//!
+//! The `doctor` command checks the setup before a run: whether the config file is valid, the
+//! providers answer, the API keys are there, the sinks can write their files, and the API's
+//! address is free. Every problem comes with a hint on how to fix it.
+//!
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::TcpListener;
+use std::path::Path;
+
+use chrono::prelude::*;
+use clap::Args;
+
+use crate::listen::Endpoint;
+use crate::provider::DataProvider;
+
+///
+/// Check the setup: config, providers, API keys, sink files, and the API's address
+///
+#[derive(Args, Debug)]
+pub struct DoctorOpts {
+    /// Don't send requests to the providers
+    #[clap(long)]
+    pub offline: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Ok,
+    Warning,
+    Failed,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Ok => write!(f, "[ ok ]"),
+            Level::Warning => write!(f, "[warn]"),
+            Level::Failed => write!(f, "[FAIL]"),
+        }
+    }
+}
+
+///
+/// The result of a single check
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub level: Level,
+    /// What was checked, e.g. `provider yahoo`
+    pub check: String,
+    pub message: String,
+    /// How to fix it
+    pub hint: Option<String>,
+}
+
+///
+/// The findings of all checks, in the order they ran
+///
+#[derive(Debug, Default)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    fn add(&mut self, level: Level, check: &str, message: String, hint: Option<&str>) {
+        self.findings.push(Finding {
+            level,
+            check: check.to_string(),
+            message,
+            hint: hint.map(str::to_string),
+        });
+    }
+
+    pub fn ok(&mut self, check: &str, message: impl Into<String>) {
+        self.add(Level::Ok, check, message.into(), None);
+    }
+
+    pub fn warn(&mut self, check: &str, message: impl Into<String>, hint: &str) {
+        self.add(Level::Warning, check, message.into(), Some(hint));
+    }
+
+    pub fn fail(&mut self, check: &str, message: impl Into<String>, hint: &str) {
+        self.add(Level::Failed, check, message.into(), Some(hint));
+    }
+
+    ///
+    /// Adds a finding for a check that either passes with a message or fails with an error
+    ///
+    pub fn check(&mut self, check: &str, result: Result<String, String>, hint: &str) {
+        match result {
+            Ok(message) => self.ok(check, message),
+            Err(error) => self.fail(check, error, hint),
+        }
+    }
+
+    pub fn failures(&self) -> usize {
+        self.findings
+            .iter()
+            .filter(|f| f.level == Level::Failed)
+            .count()
+    }
+
+    pub fn print(&self, out: &mut impl Write) -> io::Result<()> {
+        for finding in &self.findings {
+            writeln!(
+                out,
+                "{} {}: {}",
+                finding.level, finding.check, finding.message
+            )?;
+            if let Some(hint) = &finding.hint {
+                writeln!(out, "       -> {}", hint)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+///
+/// Whether a file can be written (appended to), without changing it. A file that doesn't exist
+/// yet is created and removed again.
+///
+pub fn writable(path: &Path) -> Result<String, String> {
+    let existed = path.exists();
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("'{}' can't be written: {}", path.display(), e))?;
+    if existed {
+        Ok(format!("'{}' is writable", path.display()))
+    } else {
+        std::fs::remove_file(path).ok();
+        Ok(format!("'{}' can be created", path.display()))
+    }
+}
+
+///
+/// Whether the API can listen on the endpoint
+///
+pub fn available(endpoint: &Endpoint) -> Result<String, String> {
+    match endpoint {
+        Endpoint::Tcp(address) => match TcpListener::bind(address) {
+            Ok(_) => Ok(format!("{} is free", address)),
+            Err(e) => Err(format!("Can't listen on {}: {}", address, e)),
+        },
+        Endpoint::Unix(path) => {
+            let dir = match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            if dir.is_dir() {
+                Ok(format!("unix:{} can be bound", path.display()))
+            } else {
+                Err(format!("The directory '{}' doesn't exist", dir.display()))
+            }
+        }
+    }
+}
+
+///
+/// Whether a provider answers with quotes of a symbol over the last week. An answer without
+/// any quotes is only a warning, the symbol may be wrong.
+///
+pub async fn reachable<P: DataProvider>(
+    report: &mut Report,
+    provider: &mut P,
+    symbol: &str,
+    hint: &str,
+) {
+    let check = format!("provider {}", provider.name());
+    let to = Utc::now();
+    let from = to - chrono::Duration::days(7);
+    match provider.fetch_quotes(symbol, from, to).await {
+        Ok(quotes) if quotes.is_empty() => report.warn(
+            &check,
+            format!("answered without quotes of {} over the last week", symbol),
+            "Check the symbol, e.g. with --symbols",
+        ),
+        Ok(quotes) => report.ok(
+            &check,
+            format!(
+                "answered with {} quotes of {} over the last week",
+                quotes.len(),
+                symbol
+            ),
+        ),
+        Err(e) => report.fail(&check, format!("fetching {} failed: {}", symbol, e), hint),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("doctor_probe.csv");
+        std::fs::remove_file(&path).ok();
+        assert!(writable(&path).unwrap().contains("can be created"));
+        // the probe doesn't leave a file behind
+        assert!(!path.exists());
+        assert!(writable(&dir.join("missing").join("out.csv")).is_err());
+
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = taken.local_addr().unwrap().to_string();
+        assert!(available(&Endpoint::Tcp(address)).is_err());
+        assert!(available(&Endpoint::parse("unix:/nonexistent/dir/api.sock")).is_err());
+
+        let mut report = Report::default();
+        report.ok("config", "valid");
+        report.check("csv", writable(&path), "pick another directory");
+        report.check("port", Err("taken".to_string()), "pass --listen");
+        assert_eq!(report.failures(), 1);
+        let mut out = vec![];
+        report.print(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[ ok ] config: valid\n\
+             [ ok ] csv: 'PATH' can be created\n\
+             [FAIL] port: taken\n       -> pass --listen\n"
+                .replace("PATH", &path.display().to_string())
+        );
+    }
+}
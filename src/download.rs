@@ -0,0 +1,213 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use flate2::read::GzDecoder;
+use futures::TryStreamExt;
+use tide::Body;
+
+use crate::csv_schema::CsvSchema;
+
+///
+/// Lines read ahead of the response at most
+///
+const READ_AHEAD: usize = 64;
+
+///
+/// Decides which lines of the CSV files belong to a symbol's download. Comments and the
+/// header are kept, so the download can be read like any other CSV file, but the schema
+/// comment and the header of the next file only if its schema is a different one.
+///
+#[derive(Default)]
+struct SymbolFilter {
+    schema: CsvSchema,
+    symbol: String,
+    /// The last header that was kept
+    header: Option<String>,
+}
+
+impl SymbolFilter {
+    fn keep(&mut self, line: &str) -> bool {
+        if let Some(Ok(schema)) = CsvSchema::from_comment(line) {
+            let repeated = self.header == Some(schema.header());
+            self.schema = schema;
+            return !repeated;
+        }
+        if line == self.schema.header() {
+            let repeated = self.header.as_deref() == Some(line);
+            self.header = Some(line.to_string());
+            return !repeated;
+        }
+        if line.starts_with('#') {
+            return true;
+        }
+        self.schema
+            .parse(line)
+            .is_ok_and(|row| row.symbol == self.symbol)
+    }
+}
+
+///
+/// Opens a CSV file, or its compressed version if the file is gone, e.g. because it was
+/// compressed after the files were listed
+///
+fn open(path: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    if path.ends_with(".gz") {
+        let file = File::open(path)?;
+        return Ok(Box::new(BufReader::new(GzDecoder::new(file))));
+    }
+    match File::open(path) {
+        Ok(file) => Ok(Box::new(BufReader::new(file))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let file = File::open(format!("{}.gz", path)).map_err(|_| e)?;
+            Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+///
+/// Streams the rows of a symbol from the CSV files written by `RollingFileSink` (oldest first,
+/// `.gz` ones are decompressed). The files are read line by line while the response is sent,
+/// so they never have to fit into memory.
+///
+pub async fn symbol_csv(paths: &[String], symbol: &str) -> io::Result<Body> {
+    // a missing file fails the request rather than the download halfway
+    let files = paths
+        .iter()
+        .map(|path| open(path))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut filter = SymbolFilter {
+        symbol: symbol.to_string(),
+        ..Default::default()
+    };
+    let (sender, lines) = async_std::channel::bounded(READ_AHEAD);
+    async_std::task::spawn_blocking(move || {
+        for line in files.into_iter().flat_map(|file| file.lines()) {
+            let line = match line {
+                Ok(line) if !filter.keep(&line) => continue,
+                Ok(line) => Ok(line + "\n"),
+                Err(e) => Err(e),
+            };
+            let failed = line.is_err();
+            // stops once the client is gone
+            if async_std::task::block_on(sender.send(line)).is_err() || failed {
+                return;
+            }
+        }
+    });
+    let reader = futures::io::BufReader::new(lines.into_async_read());
+    let mut body = Body::from_reader(reader, None);
+    body.set_mime("text/csv");
+    Ok(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_sink::{CurrentFile, DuplicateRows, RollingWriter, Rotation, SinkFiles};
+    use crate::PerformanceIndicators;
+    use chrono::prelude::*;
+
+    #[test]
+    fn test_symbol_filter() {
+        let mut filter = SymbolFilter {
+            symbol: "AAPL".to_string(),
+            ..Default::default()
+        };
+        assert!(
+            filter.keep("period start,symbol,price,change %,min,max,30d avg,ema,rsi,volatility")
+        );
+        assert!(filter
+            .keep("2020-07-03T12:00:09+00:00,AAPL,$1.00,2.00%,$1.00,$1.00,$1.00,$1.00,50.00,"));
+        assert!(!filter
+            .keep("2020-07-03T12:00:09+00:00,MSFT,$1.00,2.00%,$1.00,$1.00,$1.00,$1.00,50.00,"));
+
+        assert!(filter.keep("# schema 1: symbol,timestamp"));
+        assert!(filter.keep("symbol,period start"));
+        assert!(filter.keep("AAPL,2020-07-03T12:00:09+00:00"));
+        assert!(!filter.keep("2020-07-03T12:00:09+00:00,AAPL,$1.00,2.00%,$1.00,$1.00,$1.00"));
+
+        // the next file with the same schema
+        assert!(!filter.keep("# schema 1: symbol,timestamp"));
+        assert!(!filter.keep("symbol,period start"));
+        assert!(filter.keep("AAPL,2020-07-03T12:01:09+00:00"));
+    }
+
+    #[async_std::test]
+    async fn test_symbol_csv() {
+        let path = std::env::temp_dir().join("download_symbol.csv");
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(
+            &path,
+            "# schema 1: timestamp,symbol\nperiod start,symbol\n\
+             2020-07-03T12:00:09+00:00,AAPL\n2020-07-03T12:00:09+00:00,MSFT\n",
+        )
+        .unwrap();
+        let body = symbol_csv(&[path], "MSFT").await.unwrap();
+        assert_eq!(
+            body.into_string().await.unwrap(),
+            "# schema 1: timestamp,symbol\nperiod start,symbol\n2020-07-03T12:00:09+00:00,MSFT\n"
+        );
+        assert!(symbol_csv(&["does-not-exist.csv".to_string()], "MSFT")
+            .await
+            .is_err());
+    }
+
+    #[async_std::test]
+    async fn test_rotated_files() {
+        let dir = std::env::temp_dir().join("download_rotated");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("stocks.csv").to_str().unwrap().to_string();
+        let schema = CsvSchema::default();
+        let row = |i: i64, symbol: &str| PerformanceIndicators {
+            timestamp: Utc.timestamp_opt(1593777600 + i * 60, 0).unwrap(),
+            symbol: symbol.to_string(),
+            price: i as f64,
+            ..Default::default()
+        };
+        // a new file every few rows, the closed ones are compressed
+        let rotation = Rotation {
+            daily: true,
+            max_size: (schema.comment().len() + schema.header().len()) as u64 + 300,
+            gzip: true,
+        };
+        let files = SinkFiles {
+            base: base.clone(),
+            rotation: rotation.clone(),
+            current: CurrentFile::default(),
+        };
+        let now = Utc.timestamp_opt(1593777609, 0).unwrap();
+        let mut writer = RollingWriter::create(
+            &base,
+            rotation,
+            schema,
+            DuplicateRows::Skip,
+            files.current.clone(),
+            now,
+        )
+        .unwrap();
+        for i in 0..30 {
+            writer.write(&row(i, "AAPL"), now).unwrap();
+            writer.write(&row(i, "MSFT"), now).unwrap();
+        }
+        // the next day starts with a new file too
+        let tomorrow = now + chrono::Duration::days(1);
+        writer.write(&row(30, "AAPL"), tomorrow).unwrap();
+        writer.flush().unwrap();
+        // the files may still be compressed, listed either way
+        let parts = files.parts().unwrap();
+        assert!(parts.len() > 2);
+        assert_eq!(parts.last(), Some(&writer.path().to_string()));
+
+        let body = symbol_csv(&parts, "AAPL").await.unwrap();
+        let csv = body.into_string().await.unwrap();
+        let mut lines = csv.lines();
+        let schema = CsvSchema::from_comment(lines.next().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(lines.next(), Some(schema.header().as_str()));
+        let prices: Vec<f64> = lines.map(|l| schema.parse(l).unwrap().price).collect();
+        assert_eq!(prices, (0..=30).map(|i| i as f64).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,247 @@
+//!
+//! Every JSON response of the API comes in an envelope that tells clients how fresh the data
+//! is:
+//!
+//! ```json
+//! {"generated_at": "2024-01-05T15:30:00Z", "count": 10, "version": 1234, "lag_ms": 4200,
+//!  "data": [...]}
+//! ```
+//!
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use tide::{Body, Response, StatusCode};
+use xactor::*;
+
+use crate::clock::SharedClock;
+use crate::fetch::{FetchOutcome, FetchStatus};
+use crate::PerformanceIndicators;
+
+///
+/// Milliseconds since the last successful fetch
+///
+pub const LAG_HEADER: &str = "X-Pipeline-Lag-Ms";
+
+///
+/// Milliseconds the buffer took to answer, including the wait in its mailbox
+///
+pub const CALL_HEADER: &str = "X-Buffer-Call-Ms";
+
+///
+/// Calls to the buffers that were already waiting for an answer
+///
+pub const QUEUE_HEADER: &str = "X-Buffer-Queue-Depth";
+
+///
+/// A JSON response with its metadata
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Envelope<T> {
+    pub generated_at: DateTime<Utc>,
+    /// Number of entries, if the data is a list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    /// Number of indicators published so far, unchanged data has the same version
+    pub version: u64,
+    /// Milliseconds since the last successful fetch, `None` before the first one
+    pub lag_ms: Option<u64>,
+    pub data: T,
+}
+
+///
+/// How fresh the pipeline's data is, shared between the tracker and the handlers
+///
+#[derive(Debug, Clone)]
+pub struct Freshness {
+    clock: SharedClock,
+    version: Arc<AtomicU64>,
+    /// Unix milliseconds of the last successful fetch, `i64::MIN` before the first one
+    last_fetch: Arc<AtomicI64>,
+}
+
+impl Freshness {
+    pub fn new(clock: SharedClock) -> Self {
+        Freshness {
+            clock,
+            version: Arc::new(AtomicU64::new(0)),
+            last_fetch: Arc::new(AtomicI64::new(i64::MIN)),
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    ///
+    /// Records a successful fetch at the current time
+    ///
+    pub fn fetched(&self) {
+        let now = self.clock.now().timestamp_millis();
+        self.last_fetch.store(now, Ordering::Relaxed);
+    }
+
+    ///
+    /// Time since the last successful fetch
+    ///
+    pub fn lag(&self) -> Option<Duration> {
+        let last_fetch = self.last_fetch.load(Ordering::Relaxed);
+        if last_fetch == i64::MIN {
+            return None;
+        }
+        let lag = self
+            .clock
+            .now()
+            .timestamp_millis()
+            .saturating_sub(last_fetch);
+        Some(Duration::from_millis(lag.max(0) as u64))
+    }
+
+    ///
+    /// Wraps the data in an envelope
+    ///
+    pub fn wrap<T: Serialize>(&self, data: T) -> serde_json::Result<Envelope<serde_json::Value>> {
+        let data = serde_json::to_value(data)?;
+        Ok(Envelope {
+            generated_at: self.clock.now(),
+            count: data.as_array().map(Vec::len),
+            version: self.version(),
+            lag_ms: self.lag().map(|lag| lag.as_millis() as u64),
+            data,
+        })
+    }
+
+    ///
+    /// Brings the time and the lag of an envelope up to date, e.g. of a cached response
+    ///
+    pub fn refresh<T>(&self, envelope: &mut Envelope<T>) {
+        envelope.generated_at = self.clock.now();
+        envelope.lag_ms = self.lag().map(|lag| lag.as_millis() as u64);
+    }
+
+    ///
+    /// A `200 OK` response with the data in an envelope
+    ///
+    pub fn json<T: Serialize>(&self, data: T) -> tide::Result {
+        let mut response = Response::new(StatusCode::Ok);
+        response.set_body(Body::from_json(&self.wrap(data)?)?);
+        Ok(response)
+    }
+
+    ///
+    /// Adds the headers that tell a slow buffer from stale data: the pipeline lag, how long
+    /// the buffer call took, and how many calls were queued ahead of it
+    ///
+    pub fn trace(&self, response: &mut Response, call: Duration, queued: usize) {
+        if let Some(lag) = self.lag() {
+            response.insert_header(LAG_HEADER, lag.as_millis().to_string());
+        }
+        response.insert_header(CALL_HEADER, format!("{:.3}", call.as_secs_f64() * 1000.0));
+        response.insert_header(QUEUE_HEADER, queued.to_string());
+    }
+}
+
+///
+/// Counts the calls the handlers are waiting on
+///
+#[derive(Debug, Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    ///
+    /// Counts a call until the returned guard is dropped, along with the number of calls
+    /// that were in flight already
+    ///
+    pub fn enter(&self) -> (InFlightGuard, usize) {
+        let queued = self.0.fetch_add(1, Ordering::Relaxed);
+        (InFlightGuard(self.0.clone()), queued)
+    }
+}
+
+pub struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+///
+/// Actor that keeps the `Freshness` up to date
+///
+pub struct FreshnessTracker {
+    pub freshness: Freshness,
+}
+
+#[async_trait::async_trait]
+impl Actor for FreshnessTracker {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<FetchOutcome>().await?;
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<FetchOutcome> for FreshnessTracker {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: FetchOutcome) {
+        if let FetchStatus::Fetched { .. } = msg.status {
+            self.freshness.fetched();
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for FreshnessTracker {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: PerformanceIndicators) {
+        self.freshness.version.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+
+    #[test]
+    fn test_envelope() {
+        let start = Utc.with_ymd_and_hms(2022, 12, 2, 15, 0, 0).unwrap();
+        let clock = TestClock::new(start);
+        let freshness = Freshness::new(clock.shared());
+        let envelope = freshness.wrap(vec![1, 2, 3]).unwrap();
+        assert_eq!(envelope.generated_at, start);
+        assert_eq!(envelope.count, Some(3));
+        assert_eq!(envelope.version, 0);
+        assert_eq!(envelope.lag_ms, None);
+
+        freshness
+            .last_fetch
+            .store(start.timestamp_millis(), Ordering::Relaxed);
+        freshness.version.fetch_add(2, Ordering::Relaxed);
+        clock.advance(Duration::from_millis(1500));
+        let envelope = freshness.wrap("text").unwrap();
+        assert_eq!(envelope.count, None);
+        assert_eq!(envelope.version, 2);
+        assert_eq!(envelope.lag_ms, Some(1500));
+        assert_eq!(
+            serde_json::to_string(&envelope).unwrap(),
+            r#"{"generated_at":"2022-12-02T15:00:01.500Z","version":2,"lag_ms":1500,"data":"text"}"#
+        );
+
+        let in_flight = InFlight::default();
+        let (first, queued) = in_flight.enter();
+        assert_eq!(queued, 0);
+        let (_second, queued) = in_flight.enter();
+        assert_eq!(queued, 1);
+        drop(first);
+        assert_eq!(in_flight.enter().1, 1);
+
+        let mut response = freshness.json("text").unwrap();
+        freshness.trace(&mut response, Duration::from_micros(2500), 3);
+        assert_eq!(response[LAG_HEADER], "1500");
+        assert_eq!(response[CALL_HEADER], "2.500");
+        assert_eq!(response[QUEUE_HEADER], "3");
+    }
+}
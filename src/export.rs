@@ -0,0 +1,504 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use chrono::prelude::*;
+use clap::{ArgEnum, Args};
+use xactor::Sender;
+
+use crate::csv_schema::CsvSchema;
+use crate::parquet_file::ParquetWriter;
+use crate::recording::{Entry, Recorded};
+use crate::resample;
+use crate::signal::Resolution;
+use crate::sqlite_sink::SqliteStore;
+use crate::PerformanceIndicators;
+
+///
+/// Target formats of the `export` command
+///
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Apache Parquet, one row per indicator record
+    Parquet,
+    /// One JSON object per line
+    Jsonl,
+    /// CSV with one row per timestamp and one column per symbol
+    WideCsv,
+}
+
+///
+/// The indicator field used for the cells of a wide CSV
+///
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PivotField {
+    Price,
+    PctChange,
+    PeriodMin,
+    PeriodMax,
+    LastSma,
+    LastEma,
+}
+
+impl PivotField {
+    fn value(&self, row: &PerformanceIndicators) -> f64 {
+        match self {
+            PivotField::Price => row.price,
+            PivotField::PctChange => row.pct_change,
+            PivotField::PeriodMin => row.period_min,
+            PivotField::PeriodMax => row.period_max,
+            PivotField::LastSma => row.last_sma,
+            PivotField::LastEma => row.last_ema,
+        }
+    }
+}
+
+///
+/// Convert previously stored indicators (CSV sink output, SQLite, JSON lines, or a recording)
+/// into another format
+///
+#[derive(Args, Debug)]
+pub struct ExportOpts {
+    /// The file to read (.csv, .db/.sqlite, .jsonl, or a `--record` recording)
+    pub input: String,
+    /// Where to write the converted data
+    #[clap(short, long)]
+    pub output: String,
+    #[clap(long, arg_enum, default_value = "jsonl")]
+    pub format: ExportFormat,
+    /// Only export these (comma separated) symbols
+    #[clap(long)]
+    pub symbols: Option<String>,
+    /// Only export records at or after this time (RFC 3339)
+    #[clap(long)]
+    pub from: Option<DateTime<Utc>>,
+    /// Only export records before this time (RFC 3339)
+    #[clap(long)]
+    pub to: Option<DateTime<Utc>>,
+    /// The value used in the cells of a wide CSV
+    #[clap(long, arg_enum, default_value = "price")]
+    pub field: PivotField,
+    /// Only keep the last record per symbol in every bar of this size, e.g. `1h` or `1d`
+    #[clap(long)]
+    pub resample: Option<Resolution>,
+}
+
+///
+/// Runs the `export` command.
+///
+pub fn run(opts: &ExportOpts) -> Result<()> {
+    let symbols: Option<BTreeSet<String>> = opts
+        .symbols
+        .as_ref()
+        .map(|s| s.split(',').map(|s| s.trim().to_owned()).collect());
+    let rows: Vec<PerformanceIndicators> = read_indicators(&opts.input)?
+        .into_iter()
+        .filter(|r| symbols.as_ref().is_none_or(|s| s.contains(&r.symbol)))
+        .filter(|r| opts.from.is_none_or(|from| r.timestamp >= from))
+        .filter(|r| opts.to.is_none_or(|to| r.timestamp < to))
+        .collect();
+    let rows = match &opts.resample {
+        Some(resolution) => last_per_bar(rows, resolution),
+        None => rows,
+    };
+
+    match opts.format {
+        ExportFormat::Parquet => {
+            let mut writer = ParquetWriter::create(&opts.output)?;
+            writer.write(&rows)?;
+            writer.close()?;
+        }
+        ExportFormat::Jsonl => {
+            let mut out = BufWriter::new(File::create(&opts.output)?);
+            for row in &rows {
+                writeln!(out, "{}", serde_json::to_string(row)?)?;
+            }
+            out.flush()?;
+        }
+        ExportFormat::WideCsv => {
+            let mut out = BufWriter::new(File::create(&opts.output)?);
+            write_wide_csv(&mut out, &rows, opts.field)?;
+            out.flush()?;
+        }
+    }
+    tracing::info!("Exported {} records to '{}'", rows.len(), opts.output);
+    Ok(())
+}
+
+///
+/// Reads indicators from a file written by `FileSink` (.csv), a SQLite database written by
+/// `--sqlite` (.db, .sqlite, or .sqlite3), or JSON lines (.jsonl/.json): exported records or a
+/// `--record` recording, of which only the indicators are read.
+///
+pub fn read_indicators(path: &str) -> Result<Vec<PerformanceIndicators>> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "db" | "sqlite" | "sqlite3" => return read_sqlite(path),
+        _ => {}
+    }
+    let file = File::open(path).with_context(|| format!("Could not open '{}'", path))?;
+    match extension.as_str() {
+        "csv" => read_csv(path, file),
+        _ => read_json_lines(path, file),
+    }
+}
+
+///
+/// Reads the CSV records with the schema of the latest schema comment
+///
+fn read_csv(path: &str, file: File) -> Result<Vec<PerformanceIndicators>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(BufReader::new(file));
+    let mut rows = vec![];
+    let mut schema = CsvSchema::default();
+    for record in reader.records() {
+        let record = record.with_context(|| format!("Could not read '{}'", path))?;
+        let line = record.position().map_or(0, |p| p.line());
+        let first = record.get(0).unwrap_or_default();
+        if first.starts_with('#') {
+            // the commas of a comment split it into cells
+            let comment = record.iter().collect::<Vec<_>>().join(",");
+            if let Some(declared) = CsvSchema::from_comment(&comment) {
+                schema = declared.with_context(|| format!("{}:{}: invalid schema", path, line))?;
+            }
+            continue;
+        }
+        if record.iter().all(|cell| cell.trim().is_empty())
+            || first.starts_with("period start")
+            || schema.is_header(&record)
+        {
+            continue;
+        }
+        let row = schema
+            .parse_record(&record)
+            .with_context(|| format!("{}:{}: invalid record", path, line))?;
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+///
+/// Reads exported records, or the indicators of a recording
+///
+fn read_json_lines(path: &str, file: File) -> Result<Vec<PerformanceIndicators>> {
+    let mut rows = vec![];
+    for (no, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Ok(entry) = serde_json::from_str::<Entry>(&line) {
+            if let Recorded::Indicators(row) = entry.message {
+                rows.push(*row);
+            }
+            continue;
+        }
+        let row = serde_json::from_str(&line)
+            .map_err(|e| anyhow!(e))
+            .with_context(|| format!("{}:{}: invalid record", path, no + 1))?;
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+///
+/// Reads the `performance` table in the order the rows were written
+///
+fn read_sqlite(path: &str) -> Result<Vec<PerformanceIndicators>> {
+    const PAGE: usize = 10_000;
+    let store =
+        SqliteStore::open_read_only(path).with_context(|| format!("Could not open '{}'", path))?;
+    let mut rows = vec![];
+    let mut version = 0;
+    loop {
+        let page = store
+            .changes(version, PAGE)
+            .with_context(|| format!("Could not read '{}'", path))?;
+        match page.last() {
+            Some((last, _)) => version = *last,
+            None => break,
+        }
+        rows.extend(page.into_iter().map(|(_, row)| row));
+    }
+    Ok(rows)
+}
+
+///
+/// Sends previously exported indicators straight to the buffers and storage sinks, for
+/// `--import`. They don't go through the broker, so the alerts, notifications, and webhooks
+/// only see new data. Returns the number of records.
+///
+pub fn import(path: &str, targets: &[Sender<PerformanceIndicators>]) -> Result<usize> {
+    let rows = read_indicators(path)?;
+    for row in &rows {
+        for target in targets {
+            target.send(row.clone())?;
+        }
+    }
+    Ok(rows.len())
+}
+
+///
+/// Keeps the last record of every symbol and bar: indicators describe the series up to their
+/// timestamp, so the last one of a bar is what the bar closed with.
+///
+fn last_per_bar(
+    rows: Vec<PerformanceIndicators>,
+    resolution: &Resolution,
+) -> Vec<PerformanceIndicators> {
+    let mut bars: BTreeMap<(u64, String), PerformanceIndicators> = BTreeMap::new();
+    for row in rows {
+        let start =
+            resample::bucket_start(row.timestamp.timestamp().max(0) as u64, resolution.seconds);
+        match bars.get(&(start, row.symbol.clone())) {
+            Some(last) if last.timestamp > row.timestamp => {}
+            _ => {
+                bars.insert((start, row.symbol.clone()), row);
+            }
+        }
+    }
+    bars.into_values().collect()
+}
+
+///
+/// Pivots the rows by symbol: one line per timestamp, one column per symbol. Missing values
+/// are left empty.
+///
+fn write_wide_csv<W: Write>(
+    out: &mut W,
+    rows: &[PerformanceIndicators],
+    field: PivotField,
+) -> Result<()> {
+    let symbols: BTreeSet<&str> = rows.iter().map(|r| r.symbol.as_str()).collect();
+    let mut table: BTreeMap<DateTime<Utc>, BTreeMap<&str, f64>> = BTreeMap::new();
+    for row in rows {
+        table
+            .entry(row.timestamp)
+            .or_default()
+            .insert(row.symbol.as_str(), field.value(row));
+    }
+
+    write!(out, "timestamp")?;
+    for symbol in &symbols {
+        write!(out, ",{}", symbol)?;
+    }
+    writeln!(out)?;
+    for (timestamp, values) in table {
+        write!(out, "{}", timestamp.to_rfc3339())?;
+        for symbol in &symbols {
+            match values.get(symbol) {
+                Some(v) => write!(out, ",{}", v)?,
+                None => write!(out, ",")?,
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+
+    use xactor::{message, Actor, Broker, Context, Handler, Service};
+
+    use super::*;
+    use crate::alert::{Alert, AlertEngine, NamedRule};
+    use crate::buffer::{BufferSink, BufferSnapshotRequest, Overflow};
+
+    fn row(ts: i64, symbol: &str, price: f64) -> PerformanceIndicators {
+        PerformanceIndicators {
+            timestamp: Utc.timestamp_opt(ts, 0).unwrap(),
+            symbol: symbol.to_string(),
+            price,
+            pct_change: 0.0,
+            period_min: 0.0,
+            period_max: 0.0,
+            last_sma: 0.0,
+            custom: Default::default(),
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// Collects the fired alerts
+    ///
+    #[derive(Default)]
+    struct Fired(Vec<Alert>);
+
+    #[message(result = "Vec<Alert>")]
+    struct FiredRequest;
+
+    #[async_trait::async_trait]
+    impl Actor for Fired {
+        async fn started(&mut self, ctx: &mut Context<Self>) -> xactor::Result<()> {
+            crate::crash::track_start::<Self>(ctx.actor_id());
+            ctx.subscribe::<Alert>().await
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Handler<Alert> for Fired {
+        async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Alert) {
+            self.0.push(msg);
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Handler<FiredRequest> for Fired {
+        async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: FiredRequest) -> Vec<Alert> {
+            self.0.clone()
+        }
+    }
+
+    #[async_std::test]
+    async fn test_import_fires_no_alerts() {
+        let path = std::env::temp_dir().join("export_import.jsonl");
+        let rows = [row(0, "IMPRT", 10.0), row(60, "IMPRT", 10.0)];
+        let lines: Vec<String> = rows
+            .iter()
+            .map(|r| serde_json::to_string(r).unwrap())
+            .collect();
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let rules = ["IMPRT price < 50", "IMPRT price > 100"]
+            .iter()
+            .zip(["old", "new"])
+            .map(|(rule, name)| NamedRule {
+                name: name.to_string(),
+                rule: rule.parse().unwrap(),
+            })
+            .collect();
+        let _engine = AlertEngine::new(
+            vec![],
+            Arc::new(RwLock::new(rules)),
+            BTreeMap::new(),
+            HashMap::new(),
+        )
+        .start()
+        .await
+        .unwrap();
+        let fired = Fired::default().start().await.unwrap();
+        let buffer = BufferSink::new(None, 0, Overflow::default())
+            .start()
+            .await
+            .unwrap();
+
+        let count = import(path.to_str().unwrap(), &[buffer.sender()]).unwrap();
+        assert_eq!(count, 2);
+        // a live row afterwards: the engine sees it after anything published before
+        let mut broker = Broker::from_registry().await.unwrap();
+        broker.publish(row(120, "IMPRT", 200.0)).unwrap();
+        let mut alerts = vec![];
+        for _ in 0..100 {
+            alerts = fired.call(FiredRequest).await.unwrap();
+            if alerts.iter().any(|a| a.symbol == "IMPRT") {
+                break;
+            }
+            async_std::task::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let names: Vec<&str> = alerts
+            .iter()
+            .filter(|a| a.symbol == "IMPRT")
+            .map(|a| a.rule.as_str())
+            .collect();
+        assert_eq!(names, ["new"]);
+
+        let buffered = buffer.call(BufferSnapshotRequest).await.unwrap();
+        let imported = buffered
+            .iter()
+            .filter(|r| r.symbol == "IMPRT" && r.price == 10.0)
+            .count();
+        assert_eq!(imported, 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_indicators() {
+        let dir = std::env::temp_dir();
+        let mut apple = row(60, "AAPL", 1.5);
+        apple.name = Some("Apple, Inc.".to_string());
+
+        // the crate's own strict output quotes the cells with commas
+        let names: Vec<String> = ["timestamp", "symbol", "price", "name"]
+            .iter()
+            .map(|n| n.to_string())
+            .collect();
+        let schema = CsvSchema::from_names(&names).unwrap().strict(2);
+        let csv = dir.join("export_read.csv");
+        let lines = [schema.comment(), schema.header(), schema.format(&apple)];
+        std::fs::write(&csv, lines.join("\n") + "\n\n").unwrap();
+        let rows = read_indicators(csv.to_str().unwrap()).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].name.as_deref(), Some("Apple, Inc."));
+        assert_eq!(rows[0].price, 1.5);
+        std::fs::remove_file(&csv).unwrap();
+
+        let db = dir.join("export_read.db");
+        let _ = std::fs::remove_file(&db);
+        let mut store = SqliteStore::open(db.to_str().unwrap()).unwrap();
+        store.insert(&[row(0, "MSFT", 2.0), apple.clone()]).unwrap();
+        drop(store);
+        let rows = read_indicators(db.to_str().unwrap()).unwrap();
+        let symbols: Vec<&str> = rows.iter().map(|r| r.symbol.as_str()).collect();
+        assert_eq!(symbols, ["MSFT", "AAPL"]);
+        std::fs::remove_file(&db).unwrap();
+
+        // only the indicators of a recording
+        let recording = dir.join("export_read.jsonl");
+        let at = Utc.timestamp_opt(60, 0).unwrap();
+        let entries = vec![
+            Recorded::Quotes(crate::quote_log::LoggedBatch::from(
+                &crate::Quotes::default(),
+            )),
+            Recorded::Indicators(Box::new(apple)),
+        ];
+        let lines: Vec<String> = entries
+            .into_iter()
+            .map(|message| serde_json::to_string(&Entry { at, message }).unwrap())
+            .collect();
+        std::fs::write(&recording, lines.join("\n")).unwrap();
+        let rows = read_indicators(recording.to_str().unwrap()).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].symbol, "AAPL");
+        std::fs::remove_file(&recording).unwrap();
+    }
+
+    #[test]
+    fn test_write_wide_csv() {
+        let rows = vec![row(0, "B", 2.0), row(0, "A", 1.0), row(60, "A", 1.5)];
+        let mut out = vec![];
+        write_wide_csv(&mut out, &rows, PivotField::Price).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "timestamp,A,B\n1970-01-01T00:00:00+00:00,1,2\n1970-01-01T00:01:00+00:00,1.5,\n"
+        );
+    }
+
+    #[test]
+    fn test_last_per_bar() {
+        let rows = vec![
+            row(3700, "A", 2.0),
+            row(3650, "A", 1.0),
+            row(3660, "B", 5.0),
+            row(7300, "A", 3.0),
+        ];
+        let hourly = last_per_bar(rows, &"1h".parse().unwrap());
+        assert_eq!(
+            hourly
+                .iter()
+                .map(|r| (r.symbol.as_str(), r.price))
+                .collect::<Vec<_>>(),
+            vec![("A", 2.0), ("B", 5.0), ("A", 3.0)]
+        );
+    }
+}
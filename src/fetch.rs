@@ -0,0 +1,185 @@
+use std::fmt;
+
+use chrono::prelude::*;
+use xactor::*;
+
+use crate::repair;
+use crate::signal::{DataSourceError, TickerQuote};
+
+///
+/// What a single provider request returned
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum FetchStatus {
+    /// Quotes were returned (and published as `Quotes`)
+    Fetched { bars: usize },
+    /// The provider doesn't know the symbol
+    NotFound,
+    /// The provider refused the request because of its rate limit
+    RateLimited,
+    /// The provider couldn't be reached or sent something unusable
+    NetworkError(String),
+    /// The request succeeded, but there are no quotes in the requested period
+    EmptyRange,
+}
+
+impl FetchStatus {
+    ///
+    /// Classifies the result of a request
+    ///
+    pub fn from_result(result: &std::result::Result<Vec<TickerQuote>, DataSourceError>) -> Self {
+        match result {
+            Ok(quotes) if quotes.is_empty() => FetchStatus::EmptyRange,
+            Ok(quotes) => FetchStatus::Fetched { bars: quotes.len() },
+            Err(DataSourceError::FetchFailed(status)) if status.starts_with("404") => {
+                FetchStatus::NotFound
+            }
+            Err(DataSourceError::FetchFailed(status)) if status.starts_with("429") => {
+                FetchStatus::RateLimited
+            }
+            Err(DataSourceError::EmptyDataSet) => FetchStatus::EmptyRange,
+            Err(DataSourceError::FetchFailed(status)) => {
+                FetchStatus::NetworkError(format!("HTTP {}", status))
+            }
+            Err(e) => FetchStatus::NetworkError(e.to_string()),
+        }
+    }
+
+    ///
+    /// Label of the status in metrics
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            FetchStatus::Fetched { .. } => "fetched",
+            FetchStatus::NotFound => "not_found",
+            FetchStatus::RateLimited => "rate_limited",
+            FetchStatus::NetworkError(_) => "network_error",
+            FetchStatus::EmptyRange => "empty_range",
+        }
+    }
+
+    pub fn bars(&self) -> usize {
+        match self {
+            FetchStatus::Fetched { bars } => *bars,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for FetchStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FetchStatus::Fetched { bars } => write!(f, "{} bars", bars),
+            FetchStatus::NotFound => write!(f, "symbol not found"),
+            FetchStatus::RateLimited => write!(f, "rate limited"),
+            FetchStatus::NetworkError(e) => write!(f, "network error ({})", e),
+            FetchStatus::EmptyRange => write!(f, "no quotes in the requested period"),
+        }
+    }
+}
+
+///
+/// Published for every `QuoteRequest` a provider answered, whether there were quotes or not
+///
+#[message]
+#[derive(Debug, Clone)]
+pub struct FetchOutcome {
+    pub symbol: String,
+    pub watchlist: Option<String>,
+    pub provider: String,
+    pub status: FetchStatus,
+}
+
+///
+/// Drops the last quote (sorted by time) if its bar is still forming at `now`, i.e. the bar
+/// interval hasn't passed since it opened. The interval is the regular spacing of the quotes,
+/// or `known` if there are too few of them. Returns the interval.
+///
+pub fn drop_forming_bar(
+    quotes: &mut Vec<TickerQuote>,
+    now: DateTime<Utc>,
+    known: Option<u64>,
+) -> Option<u64> {
+    let timestamps: Vec<u64> = quotes.iter().map(|q| q.timestamp).collect();
+    let interval = repair::bar_interval(&timestamps).or(known)?;
+    let now = now.timestamp().max(0) as u64;
+    if quotes
+        .last()
+        .is_some_and(|last| last.timestamp + interval > now)
+    {
+        quotes.pop();
+    }
+    Some(interval)
+}
+
+///
+/// Publishes the outcome of a request, ignoring errors like the other best-effort reports
+///
+pub async fn record(outcome: FetchOutcome) {
+    if let Ok(mut broker) = Broker::from_registry().await {
+        let _ = broker.publish(outcome);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::synthetic;
+
+    #[test]
+    fn test_fetch_status() {
+        let start = Utc.with_ymd_and_hms(2022, 12, 2, 15, 0, 0).unwrap();
+        let status = |result| FetchStatus::from_result(&result);
+        assert_eq!(
+            status(Ok(synthetic(start, 10.0, 3))),
+            FetchStatus::Fetched { bars: 3 }
+        );
+        assert_eq!(status(Ok(vec![])), FetchStatus::EmptyRange);
+        assert_eq!(
+            status(Err(DataSourceError::EmptyDataSet)),
+            FetchStatus::EmptyRange
+        );
+        assert_eq!(
+            status(Err(DataSourceError::FetchFailed("404 Not Found".into()))),
+            FetchStatus::NotFound
+        );
+        assert_eq!(
+            status(Err(DataSourceError::FetchFailed(
+                "429 Too Many Requests".into()
+            ))),
+            FetchStatus::RateLimited
+        );
+        assert_eq!(
+            status(Err(DataSourceError::FetchFailed(
+                "503 Service Unavailable".into()
+            ))),
+            FetchStatus::NetworkError("HTTP 503 Service Unavailable".into())
+        );
+        assert_eq!(
+            status(Err(DataSourceError::InvalidJson)).name(),
+            "network_error"
+        );
+    }
+
+    #[test]
+    fn test_drop_forming_bar() {
+        let start = Utc.with_ymd_and_hms(2022, 12, 2, 15, 0, 0).unwrap();
+        let mut quotes = synthetic(start, 10.0, 3);
+        let interval = quotes[1].timestamp - quotes[0].timestamp;
+        let last = Utc.timestamp_opt(quotes[2].timestamp as i64, 0).unwrap();
+
+        // the last bar closes one interval after it opened
+        let closed = last + chrono::Duration::seconds(interval as i64);
+        assert_eq!(drop_forming_bar(&mut quotes, closed, None), Some(interval));
+        assert_eq!(quotes.len(), 3);
+        assert_eq!(drop_forming_bar(&mut quotes, last, None), Some(interval));
+        assert_eq!(quotes.len(), 2);
+
+        // a single bar needs the interval of an earlier response
+        let mut single = synthetic(start, 10.0, 1);
+        assert_eq!(drop_forming_bar(&mut single, start, None), None);
+        assert_eq!(single.len(), 1);
+        drop_forming_bar(&mut single, start, Some(interval));
+        assert!(single.is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use chrono::prelude::*;
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+
+use crate::provider::DataProvider;
+use crate::signal::{DataSourceError, TickerQuote};
+
+///
+/// Time of a bar in a file: Unix seconds, RFC 3339, or a date (midnight UTC)
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timestamp(u64);
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TimestampVisitor;
+
+        impl<'de> Visitor<'de> for TimestampVisitor {
+            type Value = Timestamp;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("Unix seconds, an RFC 3339 timestamp, or a date")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Timestamp, E> {
+                Ok(Timestamp(v))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Timestamp, E> {
+                if let Ok(seconds) = v.parse() {
+                    return Ok(Timestamp(seconds));
+                }
+                let time = match DateTime::parse_from_rfc3339(v) {
+                    Ok(time) => time.with_timezone(&Utc),
+                    Err(_) => NaiveDate::parse_from_str(v, "%Y-%m-%d")
+                        .map_err(|_| E::custom(format!("invalid timestamp '{}'", v)))?
+                        .and_hms_opt(0, 0, 0)
+                        .unwrap()
+                        .and_utc(),
+                };
+                u64::try_from(time.timestamp())
+                    .map(Timestamp)
+                    .map_err(|_| E::custom(format!("timestamp '{}' is before 1970", v)))
+            }
+        }
+
+        deserializer.deserialize_any(TimestampVisitor)
+    }
+}
+
+///
+/// A bar as stored in the files. Without `adjclose`, the close is taken.
+///
+#[derive(Deserialize, Debug)]
+struct FileQuote {
+    timestamp: Timestamp,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    #[serde(default)]
+    adjclose: Option<f64>,
+    #[serde(default)]
+    volume: u64,
+}
+
+impl From<FileQuote> for TickerQuote {
+    fn from(q: FileQuote) -> Self {
+        TickerQuote {
+            timestamp: q.timestamp.0,
+            open: q.open,
+            high: q.high,
+            low: q.low,
+            volume: q.volume,
+            close: q.close,
+            adjclose: q.adjclose.unwrap_or(q.close),
+        }
+    }
+}
+
+///
+/// Reads the quotes of a CSV file with a header, sorted by time
+///
+fn parse_csv(text: &str) -> anyhow::Result<Vec<TickerQuote>> {
+    let mut quotes = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .comment(Some(b'#'))
+        .from_reader(text.as_bytes())
+        .deserialize::<FileQuote>()
+        .map(|row| row.map(TickerQuote::from))
+        .collect::<Result<Vec<_>, _>>()?;
+    quotes.sort_by_key(|q| q.timestamp);
+    Ok(quotes)
+}
+
+///
+/// Reads the quotes of a JSON array, sorted by time
+///
+fn parse_json(text: &str) -> anyhow::Result<Vec<TickerQuote>> {
+    let rows: Vec<FileQuote> = serde_json::from_str(text)?;
+    let mut quotes: Vec<TickerQuote> = rows.into_iter().map(TickerQuote::from).collect();
+    quotes.sort_by_key(|q| q.timestamp);
+    Ok(quotes)
+}
+
+///
+/// Quotes from local files, one per symbol: `<dir>/<SYMBOL>.csv` or `<dir>/<SYMBOL>.json`.
+/// The files are read on every request, so runs over the same files are reproducible.
+///
+pub struct FileReplayProvider {
+    dir: PathBuf,
+}
+
+impl FileReplayProvider {
+    pub fn new(dir: PathBuf) -> Self {
+        FileReplayProvider { dir }
+    }
+
+    async fn read(&self, symbol: &str) -> std::result::Result<Vec<TickerQuote>, DataSourceError> {
+        for extension in &["csv", "json"] {
+            let path = self.dir.join(format!("{}.{}", symbol, extension));
+            let text = match async_std::fs::read_to_string(&path).await {
+                Ok(text) => text,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(DataSourceError::FetchFailed(format!(
+                        "500 {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            };
+            let quotes = match *extension {
+                "csv" => parse_csv(&text),
+                _ => parse_json(&text),
+            };
+            return quotes.map_err(|e| {
+                tracing::error!("Could not read '{}': {}", path.display(), e);
+                DataSourceError::InvalidJson
+            });
+        }
+        Err(DataSourceError::FetchFailed(format!(
+            "404 no file for {} in '{}'",
+            symbol,
+            self.dir.display()
+        )))
+    }
+}
+
+#[async_trait::async_trait]
+impl DataProvider for FileReplayProvider {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn fetch_quotes(
+        &mut self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> std::result::Result<Vec<TickerQuote>, DataSourceError> {
+        let (from, to) = (from.timestamp() as u64, to.timestamp() as u64);
+        Ok(self
+            .read(symbol)
+            .await?
+            .into_iter()
+            .filter(|q| (from..=to).contains(&q.timestamp))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::FetchStatus;
+
+    #[test]
+    fn test_parse() {
+        let csv = "# exported from a spreadsheet\n\
+                   timestamp,open,high,low,close,volume\n\
+                   2024-01-05, 160.0, 161.5, 159.1, 160.9, 3950000\n\
+                   2024-01-04T00:00:00Z, 158.5, 160.4, 158.0, 160.1, 4100000\n";
+        let quotes = parse_csv(csv).unwrap();
+        assert_eq!(quotes.len(), 2);
+        assert_eq!(quotes[0].timestamp, 1704326400);
+        assert_eq!(quotes[0].adjclose, 160.1);
+        assert_eq!(quotes[1].volume, 3950000);
+
+        let json = r#"[
+            {"timestamp": 1704412800, "open": 160.0, "high": 161.5, "low": 159.1,
+             "close": 160.9, "adjclose": 160.5}
+        ]"#;
+        let quotes = parse_json(json).unwrap();
+        assert_eq!(quotes[0].adjclose, 160.5);
+        assert_eq!(quotes[0].volume, 0);
+
+        assert!(parse_csv("timestamp,open,high,low,close\nyesterday,1,1,1,1\n").is_err());
+    }
+
+    #[async_std::test]
+    async fn test_fetch_quotes() {
+        let dir = std::env::temp_dir().join("file_replay_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("AAPL.csv"),
+            "timestamp,open,high,low,close\n100,1,1,1,1\n200,2,2,2,2\n300,3,3,3,3\n",
+        )
+        .unwrap();
+        let mut provider = FileReplayProvider::new(dir);
+        let at = |seconds| Utc.timestamp_opt(seconds, 0).unwrap();
+        let quotes = provider.fetch_quotes("AAPL", at(150), at(300)).await;
+        let closes: Vec<f64> = quotes.unwrap().iter().map(|q| q.close).collect();
+        assert_eq!(closes, vec![2.0, 3.0]);
+        assert_eq!(
+            FetchStatus::from_result(&provider.fetch_quotes("MSFT", at(0), at(300)).await),
+            FetchStatus::NotFound
+        );
+    }
+}
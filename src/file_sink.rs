@@ -0,0 +1,790 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+use anyhow::{anyhow, bail};
+use chrono::prelude::*;
+use clap::ArgEnum;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use xactor::*;
+
+use crate::audit::AuditEntry;
+use crate::csv_schema::CsvSchema;
+use crate::metrics::{self, Observation, Stage};
+use crate::shutdown::Shutdown;
+use crate::sink_queue::RetryQueue;
+use crate::PerformanceIndicators;
+
+///
+/// Rows are padded to this width (without the line break) when they may be overwritten
+///
+const ROW_WIDTH: usize = 127;
+
+///
+/// What happens to a row for the same bar as the last row written for a symbol, e.g. when
+/// a refetch overlaps the previous one
+///
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum DuplicateRows {
+    /// Only the first row of every bar is written
+    #[default]
+    Skip,
+    /// The row of the latest bar is replaced in place while the bar is still forming
+    Overwrite,
+}
+
+///
+/// Writes indicator rows, suppressing rows for bars that were already written
+///
+pub struct CsvWriter<W: Write + Seek> {
+    out: W,
+    schema: CsvSchema,
+    duplicates: DuplicateRows,
+    /// The end of the file
+    position: u64,
+    /// Timestamp and offset of the last row per symbol
+    last: HashMap<String, (DateTime<Utc>, u64)>,
+}
+
+impl<W: Write + Seek> CsvWriter<W> {
+    pub fn new(mut out: W, schema: CsvSchema, duplicates: DuplicateRows) -> io::Result<Self> {
+        let header = format!("{}\n{}\n", schema.comment(), schema.header());
+        out.write_all(header.as_bytes())?;
+        Ok(CsvWriter {
+            out,
+            schema,
+            duplicates,
+            position: header.len() as u64,
+            last: HashMap::new(),
+        })
+    }
+
+    ///
+    /// Writes a row unless it's a duplicate or older than the last row of its symbol. Returns
+    /// whether anything was written.
+    ///
+    pub fn write(&mut self, row: &PerformanceIndicators) -> io::Result<bool> {
+        let mut line = self.schema.format(row);
+        if self.duplicates == DuplicateRows::Overwrite && line.len() < ROW_WIDTH {
+            line = format!("{:width$}", line, width = ROW_WIDTH);
+        }
+        match self.last.get(&row.symbol) {
+            Some((timestamp, _)) if row.timestamp < *timestamp => return Ok(false),
+            Some((timestamp, offset)) if row.timestamp == *timestamp => {
+                if self.duplicates == DuplicateRows::Skip || line.len() != ROW_WIDTH {
+                    return Ok(false);
+                }
+                self.out.seek(SeekFrom::Start(*offset))?;
+                writeln!(self.out, "{}", line)?;
+                self.out.seek(SeekFrom::Start(self.position))?;
+                return Ok(true);
+            }
+            _ => {}
+        }
+        writeln!(self.out, "{}", line)?;
+        self.last
+            .insert(row.symbol.clone(), (row.timestamp, self.position));
+        self.position += line.len() as u64 + 1;
+        Ok(true)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    ///
+    /// Bytes written so far, including the header
+    ///
+    pub fn size(&self) -> u64 {
+        self.position
+    }
+
+    pub fn has_rows(&self) -> bool {
+        !self.last.is_empty()
+    }
+}
+
+///
+/// When a CSV file is closed and the next one started. Without either limit there's only one
+/// file.
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rotation {
+    /// Start a new file every calendar day (UTC)
+    pub daily: bool,
+    /// Start a new file once one has this many bytes (0 for no limit)
+    pub max_size: u64,
+    /// Compress the closed files with gzip
+    pub gzip: bool,
+}
+
+impl Rotation {
+    ///
+    /// The name of a file: that of the first one, with the day and the number of the file on
+    /// that day, e.g. `tech-2020-07-03.2.csv`
+    ///
+    pub fn filename(&self, base: &str, day: NaiveDate, part: u32) -> String {
+        if !self.daily && self.max_size == 0 {
+            return base.to_string();
+        }
+        let (stem, extension) = split_extension(base);
+        let mut name = stem.to_string();
+        if self.daily {
+            name.push_str(&day.format("-%Y-%m-%d").to_string());
+        }
+        if part > 0 {
+            name.push_str(&format!(".{}", part));
+        }
+        name + extension
+    }
+
+    ///
+    /// The day and the number of the file at `path`, if it is one of `base`'s. The path may
+    /// end with the `.gz` of a compressed file.
+    ///
+    fn part_of(&self, base: &str, path: &str) -> Option<(Option<NaiveDate>, u32)> {
+        let (stem, extension) = split_extension(base);
+        let path = path.strip_suffix(".gz").unwrap_or(path);
+        let mut rest = path.strip_prefix(stem)?.strip_suffix(extension)?;
+        let mut day = None;
+        if self.daily {
+            let date = rest.get(..11)?.strip_prefix('-')?;
+            day = Some(NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?);
+            rest = &rest[11..];
+        }
+        let part = match rest.strip_prefix('.') {
+            Some(number) if number.bytes().all(|b| b.is_ascii_digit()) => number.parse().ok()?,
+            Some(_) => return None,
+            None if rest.is_empty() => 0,
+            None => return None,
+        };
+        Some((day, part))
+    }
+}
+
+///
+/// Splits a path into everything up to the extension of the file name, and the extension
+/// (with the dot, empty if there is none)
+///
+fn split_extension(path: &str) -> (&str, &str) {
+    let name_start = path.rfind('/').map_or(0, |slash| slash + 1);
+    match path.rfind('.') {
+        Some(dot) if dot > name_start => path.split_at(dot),
+        _ => (path, ""),
+    }
+}
+
+///
+/// Parses a file size, e.g. `500`, `64KB`, `100MB`, or `1GB`
+///
+pub fn parse_size(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("Invalid size '{}', expected e.g. 100MB", s))?;
+    let factor = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => bail!("Invalid size '{}', expected e.g. 100MB", s),
+    };
+    Ok(number * factor)
+}
+
+///
+/// The file a sink currently writes to, shared with the API's downloads
+///
+pub type CurrentFile = Arc<RwLock<String>>;
+
+///
+/// The files of a sink, shared with the API's downloads
+///
+#[derive(Debug, Clone, Default)]
+pub struct SinkFiles {
+    /// Name of the first file
+    pub base: String,
+    pub rotation: Rotation,
+    pub current: CurrentFile,
+}
+
+impl SinkFiles {
+    ///
+    /// Every file written so far, oldest first: the rotated ones, compressed or not, and the
+    /// current one last. A file that is still being compressed is listed uncompressed.
+    ///
+    pub fn parts(&self) -> io::Result<Vec<String>> {
+        let current = self.current.read().unwrap().clone();
+        if !self.rotation.daily && self.rotation.max_size == 0 {
+            return Ok(vec![current]);
+        }
+        let dir = match self.base.rfind('/') {
+            Some(slash) => &self.base[..=slash],
+            None => "",
+        };
+        let mut parts = BTreeMap::new();
+        for entry in fs::read_dir(if dir.is_empty() { "." } else { dir })? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            let path = format!("{}{}", dir, name);
+            if let Some(key) = self.rotation.part_of(&self.base, &path) {
+                let compressed = name.ends_with(".gz");
+                let listed = parts.entry(key).or_insert_with(|| path.clone());
+                if !compressed {
+                    *listed = path;
+                }
+            }
+        }
+        if parts.is_empty() {
+            return Ok(vec![current]);
+        }
+        Ok(parts.into_values().collect())
+    }
+}
+
+///
+/// Compresses a file to `<path>.gz` and removes it
+///
+pub fn gzip(path: &str) -> io::Result<()> {
+    let mut input = File::open(path)?;
+    let output = File::create(format!("{}.gz", path))?;
+    let mut encoder = GzEncoder::new(BufWriter::new(output), Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    let output = encoder.finish()?.into_inner().map_err(|e| e.into_error())?;
+    output.sync_all()?;
+    fs::remove_file(path)
+}
+
+///
+/// Writes rows to CSV files, starting a new file (with the comment and header) whenever the
+/// rotation says so
+///
+pub struct RollingWriter {
+    /// Name of the first file
+    base: String,
+    rotation: Rotation,
+    schema: CsvSchema,
+    duplicates: DuplicateRows,
+    current: CurrentFile,
+    path: String,
+    day: NaiveDate,
+    /// Number of the file on `day`
+    part: u32,
+    writer: CsvWriter<BufWriter<File>>,
+}
+
+impl RollingWriter {
+    pub fn create(
+        base: &str,
+        rotation: Rotation,
+        schema: CsvSchema,
+        duplicates: DuplicateRows,
+        current: CurrentFile,
+        now: DateTime<Utc>,
+    ) -> io::Result<Self> {
+        let day = now.date_naive();
+        let path = rotation.filename(base, day, 0);
+        let writer = Self::open(&path, &schema, duplicates)?;
+        *current.write().unwrap() = path.clone();
+        Ok(RollingWriter {
+            base: base.to_string(),
+            rotation,
+            schema,
+            duplicates,
+            current,
+            path,
+            day,
+            part: 0,
+            writer,
+        })
+    }
+
+    fn open(
+        path: &str,
+        schema: &CsvSchema,
+        duplicates: DuplicateRows,
+    ) -> io::Result<CsvWriter<BufWriter<File>>> {
+        let file = File::create(path)?;
+        CsvWriter::new(BufWriter::new(file), schema.clone(), duplicates)
+    }
+
+    ///
+    /// Writes a row, first starting a new file if it's a new day or the file is full
+    ///
+    pub fn write(&mut self, row: &PerformanceIndicators, now: DateTime<Utc>) -> io::Result<bool> {
+        let day = now.date_naive();
+        if self.rotation.daily && day != self.day {
+            self.roll(day, 0)?;
+        } else if self.rotation.max_size > 0
+            && self.writer.has_rows()
+            && self.writer.size() >= self.rotation.max_size
+        {
+            self.roll(self.day, self.part + 1)?;
+        }
+        self.writer.write(row)
+    }
+
+    ///
+    /// Closes the current file and starts the next one. A bar that is still forming is written
+    /// to the new file again.
+    ///
+    fn roll(&mut self, day: NaiveDate, part: u32) -> io::Result<()> {
+        let path = self.rotation.filename(&self.base, day, part);
+        let writer = Self::open(&path, &self.schema, self.duplicates)?;
+        self.writer.flush()?;
+        self.writer = writer;
+        let closed = std::mem::replace(&mut self.path, path.clone());
+        (self.day, self.part) = (day, part);
+        *self.current.write().unwrap() = path;
+        tracing::info!("Continuing '{}' in '{}'", closed, self.path);
+        if self.rotation.gzip {
+            // in the background, big files take a while
+            std::thread::spawn(move || {
+                if let Err(e) = gzip(&closed) {
+                    tracing::error!("Could not compress '{}': {}", closed, e);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+///
+/// Actor for storing incoming messages in CSV files, rotated by day or size
+///
+pub struct RollingFileSink {
+    /// Name of the first file, the rotated ones are named after it
+    pub filename: String,
+    pub writer: Option<RollingWriter>,
+    /// Only indicators of this watchlist are written, `None` for the default pipeline
+    pub watchlist: Option<String>,
+    pub schema: CsvSchema,
+    pub duplicates: DuplicateRows,
+    pub rotation: Rotation,
+    pub current: CurrentFile,
+    /// Rows that couldn't be written yet
+    pub queue: RetryQueue<PerformanceIndicators>,
+}
+
+impl RollingFileSink {
+    ///
+    /// Writes the queued rows, oldest first, unless the last attempt failed too recently
+    ///
+    async fn write_queued(&mut self) {
+        let writer = match &mut self.writer {
+            Some(writer) if self.queue.is_ready() => writer,
+            _ => return,
+        };
+        let failing = self.queue.is_failing();
+        let started = Instant::now();
+        let now = Utc::now();
+        let mut written = 0;
+        let mut error = None;
+        for row in self.queue.rows() {
+            match writer.write(row, now) {
+                Ok(_) => written += 1,
+                Err(e) => {
+                    error = Some(e);
+                    break;
+                }
+            }
+        }
+        let rows = self.queue.delivered(written);
+        if let Some(e) = error {
+            let backoff = self.queue.failed();
+            tracing::error!(
+                "Could not write to '{}', retrying in {}s: {}",
+                writer.path(),
+                backoff.as_secs(),
+                e
+            );
+        }
+        // healthy sinks don't report every row
+        if failing || self.queue.is_failing() {
+            self.queue.report().await;
+        }
+        if rows.is_empty() {
+            return;
+        }
+        let elapsed = started.elapsed() / rows.len() as u32;
+        for row in rows {
+            metrics::record(Observation::duration(
+                Stage::SinkWrite("file"),
+                &row.symbol,
+                elapsed,
+            ))
+            .await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for RollingFileSink {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        let writer = RollingWriter::create(
+            &self.filename,
+            self.rotation.clone(),
+            self.schema.clone(),
+            self.duplicates,
+            self.current.clone(),
+            Utc::now(),
+        )
+        .map_err(|e| anyhow!("Could not open target file '{}': {}", self.filename, e))?;
+        self.writer = Some(writer);
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+
+    async fn stopped(&mut self, ctx: &mut Context<Self>) {
+        if let Some(writer) = &mut self.writer {
+            writer
+                .flush()
+                .expect("Something happened when flushing. Data loss :(")
+        };
+        ctx.stop(None);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for RollingFileSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        // the CSV has no column for the resolution, so only the fetched quotes are written
+        if msg.watchlist != self.watchlist || msg.resolution.is_some() {
+            return;
+        }
+        if self.writer.is_some() {
+            self.queue.push(msg);
+            self.write_queued().await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Shutdown> for RollingFileSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {
+        self.queue.retry_now();
+        self.write_queued().await;
+        // nothing is written after a shutdown, so a restart can't truncate the file
+        if let Some(mut writer) = self.writer.take() {
+            if let Err(e) = writer.flush() {
+                tracing::error!("Could not flush '{}': {}", writer.path(), e);
+            }
+        }
+    }
+}
+
+///
+/// Actor that appends the audited API calls to their own file as JSON lines, whether there
+/// is a CSV sink or not
+///
+pub struct AuditFileSink {
+    pub filename: String,
+    /// Entries that couldn't be written yet
+    pub queue: RetryQueue<AuditEntry>,
+}
+
+impl AuditFileSink {
+    pub fn new(filename: String, capacity: usize) -> Self {
+        AuditFileSink {
+            filename,
+            queue: RetryQueue::new("audit", capacity),
+        }
+    }
+
+    ///
+    /// Appends the queued entries, oldest first, unless the last attempt failed too recently
+    ///
+    async fn write_queued(&mut self) {
+        if !self.queue.is_ready() {
+            return;
+        }
+        let path = &self.filename;
+        let failing = self.queue.is_failing();
+        let mut lines = String::new();
+        for entry in self.queue.rows() {
+            match serde_json::to_string(entry) {
+                Ok(line) => lines.push_str(&line),
+                Err(e) => tracing::error!("Could not serialize audit entry: {}", e),
+            }
+            lines.push('\n');
+        }
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(lines.as_bytes()));
+        match written {
+            Ok(()) => {
+                self.queue.delivered(self.queue.len());
+            }
+            Err(e) => {
+                let backoff = self.queue.failed();
+                tracing::error!(
+                    "Could not write audit entries to '{}', retrying in {}s: {}",
+                    path,
+                    backoff.as_secs(),
+                    e
+                );
+            }
+        }
+        if failing || self.queue.is_failing() {
+            self.queue.report().await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for AuditFileSink {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<AuditEntry>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<AuditEntry> for AuditFileSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: AuditEntry) {
+        self.queue.push(msg);
+        self.write_queued().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Shutdown> for AuditFileSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {
+        self.queue.retry_now();
+        self.write_queued().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn row(ts: i64, symbol: &str, price: f64) -> PerformanceIndicators {
+        PerformanceIndicators {
+            timestamp: Utc.timestamp_opt(ts, 0).unwrap(),
+            symbol: symbol.to_string(),
+            price,
+            pct_change: 0.0,
+            period_min: price,
+            period_max: price,
+            last_sma: price,
+            custom: Default::default(),
+            ..Default::default()
+        }
+    }
+
+    impl PerformanceIndicators {
+        fn format(&self) -> String {
+            CsvSchema::default().format(self)
+        }
+    }
+
+    fn lines(writer: CsvWriter<Cursor<Vec<u8>>>) -> Vec<String> {
+        String::from_utf8(writer.out.into_inner())
+            .unwrap()
+            .lines()
+            .skip(2)
+            .map(|l| l.trim_end().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_skip_duplicates() {
+        let mut writer = CsvWriter::new(
+            Cursor::new(vec![]),
+            CsvSchema::default(),
+            DuplicateRows::Skip,
+        )
+        .unwrap();
+        assert!(writer.write(&row(60, "AAPL", 1.0)).unwrap());
+        assert!(writer.write(&row(60, "MSFT", 2.0)).unwrap());
+        assert!(!writer.write(&row(60, "AAPL", 1.5)).unwrap());
+        assert!(!writer.write(&row(0, "AAPL", 1.5)).unwrap());
+        assert!(writer.write(&row(120, "AAPL", 3.0)).unwrap());
+        assert_eq!(
+            lines(writer),
+            vec![
+                row(60, "AAPL", 1.0).format(),
+                row(60, "MSFT", 2.0).format(),
+                row(120, "AAPL", 3.0).format()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_overwrite_forming_bar() {
+        let mut writer = CsvWriter::new(
+            Cursor::new(vec![]),
+            CsvSchema::default(),
+            DuplicateRows::Overwrite,
+        )
+        .unwrap();
+        writer.write(&row(60, "AAPL", 1.0)).unwrap();
+        writer.write(&row(60, "MSFT", 2.0)).unwrap();
+        assert!(writer.write(&row(60, "AAPL", 1.5)).unwrap());
+        writer.write(&row(120, "MSFT", 2.5)).unwrap();
+        assert!(writer.write(&row(120, "MSFT", 2.75)).unwrap());
+        assert!(!writer.write(&row(60, "MSFT", 2.0)).unwrap());
+        assert_eq!(
+            lines(writer),
+            vec![
+                row(60, "AAPL", 1.5).format(),
+                row(60, "MSFT", 2.0).format(),
+                row(120, "MSFT", 2.75).format()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let day = NaiveDate::from_ymd_opt(2020, 7, 3).unwrap();
+        let daily = Rotation {
+            daily: true,
+            ..Default::default()
+        };
+        let sized = Rotation {
+            max_size: 100,
+            ..Default::default()
+        };
+        assert_eq!(
+            Rotation::default().filename("out/tech.csv", day, 0),
+            "out/tech.csv"
+        );
+        assert_eq!(
+            daily.filename("out/tech.csv", day, 0),
+            "out/tech-2020-07-03.csv"
+        );
+        assert_eq!(daily.filename("tech.csv", day, 2), "tech-2020-07-03.2.csv");
+        assert_eq!(sized.filename("v1.0/tech", day, 1), "v1.0/tech.1");
+
+        // and back
+        let part = |rotation: &Rotation, path| rotation.part_of("out/tech.csv", path);
+        assert_eq!(
+            part(&daily, "out/tech-2020-07-03.2.csv.gz"),
+            Some((Some(day), 2))
+        );
+        assert_eq!(
+            part(&daily, "out/tech-2020-07-03.csv"),
+            Some((Some(day), 0))
+        );
+        assert_eq!(part(&daily, "out/tech.csv"), None);
+        assert_eq!(part(&sized, "out/tech.12.csv"), Some((None, 12)));
+        assert_eq!(part(&sized, "out/tech.x.csv"), None);
+        assert_eq!(part(&sized, "out/tech-2.csv"), None);
+
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(parse_size("64KB").unwrap(), 64 << 10);
+        assert_eq!(parse_size("100 mb").unwrap(), 100 << 20);
+        assert!(parse_size("1TB").is_err());
+        assert!(parse_size("MB").is_err());
+    }
+
+    #[test]
+    fn test_rolling_writer() {
+        let dir = std::env::temp_dir().join("file_sink_rolling");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("stocks.csv");
+        let base = base.to_str().unwrap();
+        let schema = CsvSchema::default();
+        // full after the header and two rows
+        let lines = [
+            schema.comment(),
+            schema.header(),
+            row(60, "AAPL", 1.0).format(),
+            row(60, "MSFT", 2.0).format(),
+        ];
+        let rotation = Rotation {
+            daily: true,
+            max_size: lines.iter().map(|l| l.len() as u64 + 1).sum(),
+            gzip: false,
+        };
+        let current = CurrentFile::default();
+        let now = Utc.timestamp_opt(1593777609, 0).unwrap();
+        let mut writer = RollingWriter::create(
+            base,
+            rotation,
+            CsvSchema::default(),
+            DuplicateRows::Skip,
+            current.clone(),
+            now,
+        )
+        .unwrap();
+        // the header alone doesn't fill a file
+        writer.write(&row(60, "AAPL", 1.0), now).unwrap();
+        writer.write(&row(60, "MSFT", 2.0), now).unwrap();
+        let full = writer.path().to_string();
+        writer.write(&row(120, "AAPL", 3.0), now).unwrap();
+        assert_ne!(writer.path(), full);
+        assert!(full.ends_with("stocks-2020-07-03.csv"));
+        assert!(writer.path().ends_with("stocks-2020-07-03.1.csv"));
+        writer
+            .write(&row(180, "AAPL", 4.0), now + chrono::Duration::days(1))
+            .unwrap();
+        writer.flush().unwrap();
+        assert!(writer.path().ends_with("stocks-2020-07-04.csv"));
+        assert_eq!(*current.read().unwrap(), writer.path());
+
+        // every file has its own header
+        let next_day = fs::read_to_string(writer.path()).unwrap();
+        assert_eq!(
+            next_day,
+            format!(
+                "{}\n{}\n{}\n",
+                schema.comment(),
+                schema.header(),
+                row(180, "AAPL", 4.0).format()
+            )
+        );
+
+        gzip(&full).unwrap();
+        assert!(!std::path::Path::new(&full).exists());
+        let mut text = String::new();
+        let file = File::open(format!("{}.gz", full)).unwrap();
+        io::Read::read_to_string(&mut flate2::read::GzDecoder::new(file), &mut text).unwrap();
+        assert_eq!(text.lines().count(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_audit_log() {
+        let dir = std::env::temp_dir().join("file_sink_audit");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let audit_log = dir.join("audit.jsonl").to_str().unwrap().to_string();
+        let sink = AuditFileSink::new(audit_log.clone(), 0)
+            .start()
+            .await
+            .unwrap();
+        let entry = AuditEntry {
+            timestamp: Utc.timestamp_opt(1593777609, 0).unwrap(),
+            remote: Some("127.0.0.1:4711".to_string()),
+            method: "POST".to_string(),
+            path: "/symbols".to_string(),
+            status: 201,
+        };
+        sink.call(entry.clone()).await.unwrap();
+        sink.call(Shutdown).await.unwrap();
+        let lines = fs::read_to_string(&audit_log).unwrap();
+        let written: Vec<AuditEntry> = lines
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert!(written.contains(&entry));
+    }
+}
@@ -0,0 +1,159 @@
+//!
+//! Opening gaps: the difference between the first open of a session and the last close of the
+//! session before, and the VWAP since the open. Sessions are UTC days.
+//!
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::signal::TickerQuote;
+
+pub const SESSION: u64 = 24 * 60 * 60;
+
+///
+/// Published for every session that opens with a gap of at least `--gap-threshold`
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GapEvent {
+    pub symbol: String,
+    pub watchlist: Option<String>,
+    /// Time of the first quote of the session
+    pub timestamp: DateTime<Utc>,
+    pub prev_close: f64,
+    pub open: f64,
+    /// Relative gap, positive for gap-ups
+    pub gap: f64,
+}
+
+///
+/// The opening gap of a session
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gap {
+    pub timestamp: u64,
+    pub prev_close: f64,
+    pub open: f64,
+    pub gap: f64,
+}
+
+///
+/// Finds the opening gap of every session after the first one in a series of quotes (sorted
+/// by time)
+///
+pub fn session_gaps(quotes: &[TickerQuote]) -> Vec<Gap> {
+    quotes
+        .windows(2)
+        .filter(|w| w[0].timestamp / SESSION != w[1].timestamp / SESSION)
+        .filter(|w| w[0].close != 0.0)
+        .map(|w| Gap {
+            timestamp: w[1].timestamp,
+            prev_close: w[0].close,
+            open: w[1].open,
+            gap: w[1].open / w[0].close - 1.0,
+        })
+        .collect()
+}
+
+///
+/// The first quote of the latest session and its gap, if there is a session before it
+///
+pub fn latest_session(quotes: &[TickerQuote]) -> Option<(&TickerQuote, Option<Gap>)> {
+    let start = session_start(quotes)?;
+    let gap = start
+        .checked_sub(1)
+        .and_then(|prev| session_gaps(&quotes[prev..=start]).pop());
+    Some((&quotes[start], gap))
+}
+
+///
+/// Index of the first quote of the latest session
+///
+fn session_start(quotes: &[TickerQuote]) -> Option<usize> {
+    let session = quotes.last()?.timestamp / SESSION;
+    Some(
+        quotes
+            .iter()
+            .rposition(|q| q.timestamp / SESSION != session)
+            .map_or(0, |i| i + 1),
+    )
+}
+
+///
+/// The volume weighted average of the typical prices (high, low, and close) since the open of
+/// the latest session. Unlike an average over the whole range it starts over every session.
+/// None without volume, e.g. for indices.
+///
+pub fn session_vwap(quotes: &[TickerQuote]) -> Option<f64> {
+    let session = &quotes[session_start(quotes)?..];
+    let volume: f64 = session.iter().map(|q| q.volume as f64).sum();
+    let traded: f64 = session
+        .iter()
+        .map(|q| (q.high + q.low + q.close) / 3.0 * q.volume as f64)
+        .sum();
+    Some(traded / volume).filter(|_| volume > 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(timestamp: u64, open: f64, close: f64) -> TickerQuote {
+        TickerQuote {
+            timestamp,
+            open,
+            high: open.max(close),
+            low: open.min(close),
+            volume: 10,
+            close,
+            adjclose: close,
+        }
+    }
+
+    #[test]
+    fn test_session_gaps() {
+        let day = SESSION;
+        let quotes = vec![
+            quote(day + 100, 10.0, 10.5),
+            quote(day + 200, 10.5, 10.0),
+            quote(2 * day + 100, 11.0, 11.2),
+            quote(2 * day + 200, 11.2, 11.0),
+            quote(5 * day, 9.9, 10.0),
+        ];
+        let gaps = session_gaps(&quotes);
+        assert_eq!(gaps.len(), 2);
+        assert_eq!(gaps[0].timestamp, 2 * day + 100);
+        assert_eq!(gaps[0].prev_close, 10.0);
+        assert!((gaps[0].gap - 0.1).abs() < 1e-9);
+        assert!((gaps[1].gap + 0.1).abs() < 1e-9);
+        assert!(session_gaps(&quotes[..2]).is_empty());
+
+        let (open, gap) = latest_session(&quotes[..4]).unwrap();
+        assert_eq!(open.timestamp, 2 * day + 100);
+        assert_eq!(gap, Some(gaps[0]));
+        let (open, gap) = latest_session(&quotes[..2]).unwrap();
+        assert_eq!(open.timestamp, day + 100);
+        assert_eq!(gap, None);
+        assert!(latest_session(&[]).is_none());
+    }
+
+    #[test]
+    fn test_session_vwap() {
+        let day = SESSION;
+        let bar = |timestamp: u64, price: f64, volume: u64| TickerQuote {
+            volume,
+            ..quote(timestamp, price, price)
+        };
+        let quotes = vec![
+            bar(day + 100, 50.0, 1000),
+            bar(2 * day + 100, 10.0, 100),
+            bar(2 * day + 200, 13.0, 200),
+            bar(2 * day + 300, 20.0, 0),
+        ];
+        // the day before doesn't count
+        assert_eq!(session_vwap(&quotes), Some(12.0));
+        assert_eq!(session_vwap(&quotes[..1]), Some(50.0));
+        assert_eq!(session_vwap(&quotes[3..]), None);
+        assert_eq!(session_vwap(&[]), None);
+    }
+}
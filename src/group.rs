@@ -0,0 +1,280 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::metadata::SymbolMetadata;
+use crate::PerformanceIndicators;
+
+const HEADER: &str = "timestamp,group,members,avg change %,breadth";
+
+///
+/// Indicators of a group of symbols (a config tag or a sector), from the latest indicators of
+/// its members
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GroupIndicators {
+    pub group: String,
+    /// The latest timestamp of the members
+    pub timestamp: DateTime<Utc>,
+    /// Members with indicators
+    pub members: usize,
+    pub avg_pct_change: f64,
+    /// Fraction of the members that are up
+    pub breadth: f64,
+}
+
+impl GroupIndicators {
+    pub fn csv_row(&self) -> String {
+        format!(
+            "{},{},{},{:.2}%,{:.4}",
+            self.timestamp.to_rfc3339(),
+            self.group,
+            self.members,
+            self.avg_pct_change * 100.0,
+            self.breadth
+        )
+    }
+}
+
+///
+/// Request the indicators of a single group
+///
+#[message(result = "Option<GroupIndicators>")]
+pub struct GroupRequest(pub String);
+
+///
+/// Request the indicators of all groups
+///
+#[message(result = "Vec<GroupIndicators>")]
+pub struct GroupsRequest;
+
+///
+/// The groups of every symbol and the latest change of each member
+///
+#[derive(Debug, Default)]
+pub struct Groups {
+    /// Groups from the config file's tags
+    tags: HashMap<String, BTreeSet<String>>,
+    /// Sectors from the symbol metadata
+    sectors: HashMap<String, String>,
+    /// Latest timestamp and change per symbol
+    latest: BTreeMap<String, (DateTime<Utc>, f64)>,
+}
+
+impl Groups {
+    pub fn new(tags: HashMap<String, BTreeSet<String>>) -> Self {
+        Groups {
+            tags,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_sector(&mut self, symbol: &str, sector: Option<&str>) {
+        match sector {
+            Some(sector) => self.sectors.insert(symbol.to_string(), sector.to_string()),
+            None => self.sectors.remove(symbol),
+        };
+    }
+
+    ///
+    /// Keeps the indicators, returns the groups of their symbol
+    ///
+    pub fn update(&mut self, data: &PerformanceIndicators) -> Vec<String> {
+        self.latest
+            .insert(data.symbol.clone(), (data.timestamp, data.pct_change));
+        self.groups_of(&data.symbol).into_iter().collect()
+    }
+
+    fn groups_of(&self, symbol: &str) -> BTreeSet<String> {
+        let mut groups = self.tags.get(symbol).cloned().unwrap_or_default();
+        groups.extend(self.sectors.get(symbol).cloned());
+        groups
+    }
+
+    pub fn names(&self) -> BTreeSet<String> {
+        self.latest
+            .keys()
+            .flat_map(|symbol| self.groups_of(symbol))
+            .collect()
+    }
+
+    ///
+    /// The indicators of a group, `None` if none of its members has indicators yet
+    ///
+    pub fn indicators(&self, group: &str) -> Option<GroupIndicators> {
+        let members: Vec<&(DateTime<Utc>, f64)> = self
+            .latest
+            .iter()
+            .filter(|(symbol, _)| self.groups_of(symbol).contains(group))
+            .map(|(_, latest)| latest)
+            .collect();
+        let timestamp = members.iter().map(|(t, _)| *t).max()?;
+        let n = members.len() as f64;
+        Some(GroupIndicators {
+            group: group.to_string(),
+            timestamp,
+            members: members.len(),
+            avg_pct_change: members.iter().map(|(_, c)| c).sum::<f64>() / n,
+            breadth: members.iter().filter(|(_, c)| *c > 0.0).count() as f64 / n,
+        })
+    }
+}
+
+///
+/// Actor that aggregates the indicators of groups of symbols whenever a member changes,
+/// publishes them, and optionally appends them to a CSV file
+///
+pub struct GroupAggregator {
+    groups: Groups,
+    csv: Option<String>,
+}
+
+impl GroupAggregator {
+    ///
+    /// `tags` are the groups of every symbol from the config file
+    ///
+    pub fn new(tags: HashMap<String, BTreeSet<String>>, csv: Option<String>) -> Self {
+        GroupAggregator {
+            groups: Groups::new(tags),
+            csv,
+        }
+    }
+
+    fn append(&self, path: &str, rows: &[GroupIndicators]) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", HEADER)?;
+        }
+        for row in rows {
+            writeln!(file, "{}", row.csv_row())?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for GroupAggregator {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<SymbolMetadata>().await?;
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<SymbolMetadata> for GroupAggregator {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: SymbolMetadata) {
+        self.groups.set_sector(&msg.symbol, msg.sector.as_deref());
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for GroupAggregator {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        if msg.resolution.is_some() {
+            return;
+        }
+        let rows: Vec<GroupIndicators> = self
+            .groups
+            .update(&msg)
+            .iter()
+            .filter_map(|group| self.groups.indicators(group))
+            .collect();
+        if rows.is_empty() {
+            return;
+        }
+        if let Some(path) = &self.csv {
+            if let Err(e) = self.append(path, &rows) {
+                tracing::error!("Could not write group indicators to '{}': {}", path, e);
+            }
+        }
+        let mut broker = Broker::from_registry().await.unwrap();
+        for row in rows {
+            if let Err(e) = broker.publish(row) {
+                tracing::error!("{}", e);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<GroupRequest> for GroupAggregator {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: GroupRequest,
+    ) -> Option<GroupIndicators> {
+        self.groups.indicators(&msg.0)
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<GroupsRequest> for GroupAggregator {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: GroupsRequest,
+    ) -> Vec<GroupIndicators> {
+        self.groups
+            .names()
+            .iter()
+            .filter_map(|group| self.groups.indicators(group))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(symbol: &str, ts: i64, pct_change: f64) -> PerformanceIndicators {
+        PerformanceIndicators {
+            symbol: symbol.to_string(),
+            timestamp: Utc.timestamp_opt(ts, 0).unwrap(),
+            pct_change,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_groups() {
+        let tags = HashMap::from([
+            ("AAPL".to_string(), BTreeSet::from(["faang".to_string()])),
+            ("MSFT".to_string(), BTreeSet::from(["cloud".to_string()])),
+        ]);
+        let mut groups = Groups::new(tags);
+        groups.set_sector("AAPL", Some("Technology"));
+        groups.set_sector("MSFT", Some("Technology"));
+        assert_eq!(groups.indicators("Technology"), None);
+
+        assert_eq!(
+            groups.update(&row("AAPL", 10, 0.02)),
+            vec!["Technology", "faang"]
+        );
+        assert_eq!(
+            groups.update(&row("MSFT", 20, -0.04)),
+            vec!["Technology", "cloud"]
+        );
+        assert!(groups.update(&row("XOM", 30, 0.01)).is_empty());
+
+        let tech = groups.indicators("Technology").unwrap();
+        assert_eq!(tech.members, 2);
+        assert_eq!(tech.timestamp, Utc.timestamp_opt(20, 0).unwrap());
+        assert!((tech.avg_pct_change + 0.01).abs() < 1e-12);
+        assert_eq!(tech.breadth, 0.5);
+        assert_eq!(groups.indicators("faang").unwrap().breadth, 1.0);
+        assert_eq!(
+            groups.names().into_iter().collect::<Vec<_>>(),
+            vec!["Technology", "cloud", "faang"]
+        );
+        assert_eq!(
+            tech.csv_row(),
+            "1970-01-01T00:00:20+00:00,Technology,2,-1.00%,0.5000"
+        );
+    }
+}
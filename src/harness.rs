@@ -0,0 +1,252 @@
+//!
+//! The pipeline in-process, for the end-to-end tests under `tests/`: a `Pipeline` from the
+//! builder with a mock provider that serves a synthetic series, the HTTP API's state, a
+//! temporary CSV file, a test clock, and fetches that only happen when a test asks for them.
+//!
+//! The brokers are global, so only one pipeline should run at a time in a test binary.
+//!
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use xactor::*;
+
+use crate::alias::Aliases;
+use crate::buffer::{BufferSink, BufferSnapshotRequest};
+use crate::clock::TestClock;
+use crate::http_runtime::HttpRuntime;
+use crate::listen::{Endpoint, Listener};
+use crate::metadata::SymbolMetadata;
+use crate::provider::DataProvider;
+use crate::signal::{DataSourceError, TickerQuote};
+use crate::{PerformanceIndicators, Pipeline, State};
+
+///
+/// Seconds between two bars of the synthetic series
+///
+pub const BAR: u64 = 3600;
+
+///
+/// Pipelines started by this process so far, tells their directories apart
+///
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+///
+/// The synthetic series: one bar per hour from `start`, the close of bar `i` is
+/// `base + i`
+///
+pub fn synthetic(start: DateTime<Utc>, base: f64, bars: usize) -> Vec<TickerQuote> {
+    (0..bars)
+        .map(|i| {
+            let price = base + i as f64;
+            TickerQuote {
+                timestamp: start.timestamp() as u64 + i as u64 * BAR,
+                open: price - 0.5,
+                high: price + 1.0,
+                low: price - 1.0,
+                volume: 1000,
+                close: price,
+                adjclose: price,
+            }
+        })
+        .collect()
+}
+
+///
+/// Serves the part of a fixed series in the requested period, and nothing for the other
+/// symbols
+///
+#[derive(Clone)]
+struct MockProvider {
+    series: Arc<BTreeMap<String, Vec<TickerQuote>>>,
+}
+
+#[async_trait::async_trait]
+impl DataProvider for MockProvider {
+    fn name(&self) -> &str {
+        "mock"
+    }
+
+    async fn fetch_quotes(
+        &mut self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> std::result::Result<Vec<TickerQuote>, DataSourceError> {
+        let (from, to) = (from.timestamp() as u64, to.timestamp() as u64);
+        match self.series.get(symbol) {
+            Some(series) => Ok(series
+                .iter()
+                .filter(|q| q.timestamp >= from && q.timestamp < to)
+                .cloned()
+                .collect()),
+            None => Err(DataSourceError::FetchFailed(format!("404 {}", symbol))),
+        }
+    }
+}
+
+///
+/// A running pipeline
+///
+pub struct TestPipeline {
+    pub clock: TestClock,
+    pub csv_file: PathBuf,
+    /// The temporary directory of the files, removed with the pipeline
+    pub dir: PathBuf,
+    /// `None` once it's stopped
+    pipeline: Option<Pipeline>,
+    state: State,
+    app: tide::Server<State>,
+}
+
+impl TestPipeline {
+    ///
+    /// Starts a pipeline fetching the symbols of `series`, with the files in a new temporary
+    /// directory named after `name`. Nothing is fetched until `tick` is called.
+    ///
+    pub async fn start(
+        name: &str,
+        start: DateTime<Utc>,
+        series: BTreeMap<String, Vec<TickerQuote>>,
+    ) -> anyhow::Result<Self> {
+        let dir = std::env::temp_dir().join(format!(
+            "{}-{}-{}",
+            name,
+            std::process::id(),
+            STARTED.fetch_add(1, Ordering::SeqCst)
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let clock = TestClock::new(start);
+        let symbols: Vec<String> = series.keys().cloned().collect();
+
+        // knowing the metadata keeps the directory from looking the symbols up
+        let metadata: BTreeMap<String, SymbolMetadata> = symbols
+            .iter()
+            .map(|s| {
+                let metadata = SymbolMetadata {
+                    symbol: s.clone(),
+                    name: Some(format!("{} Inc.", s)),
+                    ..Default::default()
+                };
+                (s.clone(), metadata)
+            })
+            .collect();
+        let metadata_cache = dir.join("symbols.json");
+        std::fs::write(&metadata_cache, serde_json::to_string(&metadata)?)?;
+        let csv_file = dir.join("stocks.csv");
+        let audit_log = dir.join("audit.jsonl");
+
+        let provider = MockProvider {
+            series: Arc::new(series),
+        };
+        let pipeline = Pipeline::builder()
+            .symbols(symbols)
+            .from(start)
+            .provider(move || provider.clone())
+            // never fires during a test
+            .interval(Duration::from_secs(24 * 3600))
+            .buffer_capacity(0)
+            .csv(csv_file.to_str().unwrap())
+            .clock(clock.shared())
+            .api(
+                audit_log.to_str().unwrap(),
+                metadata_cache.to_str().unwrap(),
+            )
+            .build()
+            .await?;
+        let state = pipeline.state().cloned().unwrap();
+        Ok(TestPipeline {
+            clock,
+            csv_file,
+            dir,
+            app: crate::server(state.clone()),
+            state,
+            pipeline: Some(pipeline),
+        })
+    }
+
+    pub fn buffer(&self) -> &Addr<BufferSink> {
+        &self.state.buffer
+    }
+
+    ///
+    /// Moves the clock forward and fetches everything up to the new time
+    ///
+    pub fn tick(&self, advance: Duration) -> Result<()> {
+        self.clock.advance(advance);
+        match &self.pipeline {
+            Some(pipeline) => pipeline.fetch_now(),
+            None => Ok(()),
+        }
+    }
+
+    ///
+    /// Waits until the buffer holds `n` indicators and the API counted them (or a few seconds
+    /// have passed) and returns them
+    ///
+    pub async fn buffered(&self, n: usize) -> Result<Vec<PerformanceIndicators>> {
+        for _ in 0..100 {
+            let data = self.buffer().call(BufferSnapshotRequest).await?;
+            if data.len() >= n && self.state.freshness.version() >= n as u64 {
+                return Ok(data);
+            }
+            async_std::task::sleep(Duration::from_millis(50)).await;
+        }
+        self.buffer().call(BufferSnapshotRequest).await
+    }
+
+    ///
+    /// Answers a request to the API
+    ///
+    pub async fn respond(
+        &self,
+        request: tide::http::Request,
+    ) -> tide::http::Result<tide::http::Response> {
+        self.app.respond(request).await
+    }
+
+    ///
+    /// Resolves the symbols of the API requests with `aliases`
+    ///
+    pub fn with_aliases(mut self, aliases: Aliases) -> Self {
+        self.state.cache = self.state.cache.clone().with_aliases(aliases.clone());
+        self.state.aliases = Arc::new(aliases);
+        self.app = crate::server(self.state.clone());
+        self
+    }
+
+    ///
+    /// Serves the API on a free local port, e.g. for websocket connections. Returns the
+    /// address and the runtime to stop afterwards.
+    ///
+    pub fn serve(&self) -> std::io::Result<(String, HttpRuntime)> {
+        let listener = Listener::open(&Endpoint::parse("127.0.0.1:0"))?;
+        let address = listener.describe().replace("http://", "");
+        let runtime = HttpRuntime::start(self.state.executor.clone(), 1)?;
+        let executor = runtime.executor().clone();
+        let app = self.app.clone();
+        runtime.spawn(async move {
+            listener.serve(app, executor).await.ok();
+        });
+        Ok((address, runtime))
+    }
+
+    ///
+    /// Stops the pipeline so everything is flushed, and reads the CSV rows back
+    ///
+    pub async fn csv_rows(&mut self) -> anyhow::Result<Vec<PerformanceIndicators>> {
+        if let Some(pipeline) = self.pipeline.take() {
+            pipeline.stop().await?;
+        }
+        crate::export::read_indicators(self.csv_file.to_str().unwrap())
+    }
+}
+
+impl Drop for TestPipeline {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,672 @@
+//!
+//! Compressed in-memory storage for quote histories.
+//!
+//! Timestamps are stored with delta-of-delta encoding and prices with the XOR scheme from
+//! Facebook's Gorilla paper. Regular intraday bars compress to a few bits per timestamp and
+//! roughly half the size per price, so months of minute bars fit comfortably into RAM.
+//! Series are append-only and decompressed in full whenever a signal window is needed.
+//!
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::quote_log::LoggedQuote;
+use crate::signal::TickerQuote;
+
+///
+/// Bits packed MSB first into bytes
+///
+#[derive(Debug, Clone, Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.len.is_multiple_of(8) {
+            self.bytes.push(0);
+        }
+        if bit {
+            let last = self.bytes.len() - 1;
+            self.bytes[last] |= 0x80 >> (self.len % 8);
+        }
+        self.len += 1;
+    }
+
+    /// Writes the lowest `n` bits of `value`
+    fn write_bits(&mut self, value: u64, n: u32) {
+        for i in (0..n).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, pos: 0 }
+    }
+
+    fn read_bit(&mut self) -> bool {
+        let bit = self.bytes[self.pos / 8] & (0x80 >> (self.pos % 8)) != 0;
+        self.pos += 1;
+        bit
+    }
+
+    fn read_bits(&mut self, n: u32) -> u64 {
+        (0..n).fold(0, |acc, _| (acc << 1) | self.read_bit() as u64)
+    }
+}
+
+///
+/// Buckets for the delta-of-delta: (control bits, control length, value bits)
+///
+const DOD_BUCKETS: [(u64, u32, u32); 3] = [(0b10, 2, 7), (0b110, 3, 9), (0b1110, 4, 12)];
+
+fn fits(value: i64, bits: u32) -> bool {
+    let limit = 1i64 << (bits - 1);
+    (-limit..limit).contains(&value)
+}
+
+fn sign_extend(value: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+///
+/// Delta-of-delta encoding of integer timestamps. Evenly spaced timestamps cost one bit each.
+///
+#[derive(Debug, Clone, Default)]
+struct TimestampEncoder {
+    bits: BitWriter,
+    count: usize,
+    prev: i64,
+    prev_delta: i64,
+}
+
+impl TimestampEncoder {
+    fn push(&mut self, ts: i64) {
+        match self.count {
+            0 => self.bits.write_bits(ts as u64, 64),
+            1 => self.bits.write_bits(ts.wrapping_sub(self.prev) as u64, 64),
+            _ => {
+                let dod = ts.wrapping_sub(self.prev).wrapping_sub(self.prev_delta);
+                if dod == 0 {
+                    self.bits.write_bit(false);
+                } else if let Some((control, control_len, n)) =
+                    DOD_BUCKETS.iter().find(|(_, _, n)| fits(dod, *n))
+                {
+                    self.bits.write_bits(*control, *control_len);
+                    self.bits.write_bits(dod as u64, *n);
+                } else {
+                    self.bits.write_bits(0b1111, 4);
+                    self.bits.write_bits(dod as u64, 64);
+                }
+            }
+        }
+        if self.count > 0 {
+            self.prev_delta = ts.wrapping_sub(self.prev);
+        }
+        self.prev = ts;
+        self.count += 1;
+    }
+
+    fn decode(&self) -> Vec<i64> {
+        let mut reader = BitReader::new(&self.bits.bytes);
+        let mut values = Vec::with_capacity(self.count);
+        let (mut prev, mut delta) = (0i64, 0i64);
+        for i in 0..self.count {
+            let value = match i {
+                0 => reader.read_bits(64) as i64,
+                1 => prev.wrapping_add(reader.read_bits(64) as i64),
+                _ => {
+                    let mut control_len = 0;
+                    while control_len < 4 && reader.read_bit() {
+                        control_len += 1;
+                    }
+                    let dod = match control_len {
+                        0 => 0,
+                        4 => reader.read_bits(64) as i64,
+                        _ => {
+                            let n = DOD_BUCKETS[control_len - 1].2;
+                            sign_extend(reader.read_bits(n), n)
+                        }
+                    };
+                    prev.wrapping_add(delta).wrapping_add(dod)
+                }
+            };
+            if i > 0 {
+                delta = value.wrapping_sub(prev);
+            }
+            prev = value;
+            values.push(value);
+        }
+        values
+    }
+}
+
+///
+/// Gorilla XOR encoding of floats. Unchanged values cost one bit, small changes only store the
+/// bits that differ from the previous value.
+///
+#[derive(Debug, Clone, Default)]
+struct FloatEncoder {
+    bits: BitWriter,
+    count: usize,
+    prev: u64,
+    /// Leading and trailing zeros of the last stored XOR block
+    window: Option<(u32, u32)>,
+}
+
+impl FloatEncoder {
+    fn push(&mut self, value: f64) {
+        let value = value.to_bits();
+        if self.count == 0 {
+            self.bits.write_bits(value, 64);
+        } else {
+            let xor = value ^ self.prev;
+            if xor == 0 {
+                self.bits.write_bit(false);
+            } else {
+                self.bits.write_bit(true);
+                // the leading zeros have to fit into 5 bits
+                let leading = xor.leading_zeros().min(31);
+                let trailing = xor.trailing_zeros();
+                match self.window {
+                    Some((l, t)) if leading >= l && trailing >= t => {
+                        self.bits.write_bit(false);
+                        self.bits.write_bits(xor >> t, 64 - l - t);
+                    }
+                    _ => {
+                        let meaningful = 64 - leading - trailing;
+                        self.bits.write_bit(true);
+                        self.bits.write_bits(leading as u64, 5);
+                        // 64 meaningful bits are stored as 0
+                        self.bits.write_bits(meaningful as u64 & 0x3f, 6);
+                        self.bits.write_bits(xor >> trailing, meaningful);
+                        self.window = Some((leading, trailing));
+                    }
+                }
+            }
+        }
+        self.prev = value;
+        self.count += 1;
+    }
+
+    fn decode(&self) -> Vec<f64> {
+        let mut reader = BitReader::new(&self.bits.bytes);
+        let mut values = Vec::with_capacity(self.count);
+        let mut prev = 0u64;
+        let (mut leading, mut trailing) = (0, 0);
+        for i in 0..self.count {
+            if i == 0 {
+                prev = reader.read_bits(64);
+            } else if reader.read_bit() {
+                if reader.read_bit() {
+                    leading = reader.read_bits(5) as u32;
+                    let meaningful = match reader.read_bits(6) as u32 {
+                        0 => 64,
+                        n => n,
+                    };
+                    trailing = 64 - leading - meaningful;
+                }
+                let xor = reader.read_bits(64 - leading - trailing) << trailing;
+                prev ^= xor;
+            }
+            values.push(f64::from_bits(prev));
+        }
+        values
+    }
+}
+
+///
+/// An append-only, compressed series of quotes
+///
+#[derive(Debug, Clone, Default)]
+pub struct CompressedSeries {
+    timestamps: TimestampEncoder,
+    opens: FloatEncoder,
+    highs: FloatEncoder,
+    lows: FloatEncoder,
+    closes: FloatEncoder,
+    adjcloses: FloatEncoder,
+    /// The bits of the volumes go through the float encoding unchanged, so every volume is
+    /// stored losslessly
+    volumes: FloatEncoder,
+}
+
+impl CompressedSeries {
+    pub fn len(&self) -> usize {
+        self.timestamps.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///
+    /// Timestamp of the oldest quote
+    ///
+    pub fn first_timestamp(&self) -> Option<u64> {
+        let mut reader = BitReader::new(&self.timestamps.bits.bytes);
+        Some(reader.read_bits(64)).filter(|_| !self.is_empty())
+    }
+
+    ///
+    /// Timestamp of the newest quote
+    ///
+    pub fn last_timestamp(&self) -> Option<u64> {
+        Some(self.timestamps.prev as u64).filter(|_| !self.is_empty())
+    }
+
+    ///
+    /// Appends a quote. Quotes that aren't newer than the last one are ignored, so
+    /// overlapping responses can be pushed as they are.
+    ///
+    pub fn push(&mut self, quote: &TickerQuote) -> bool {
+        if self
+            .last_timestamp()
+            .is_some_and(|last| quote.timestamp <= last)
+        {
+            return false;
+        }
+        self.timestamps.push(quote.timestamp as i64);
+        self.opens.push(quote.open);
+        self.highs.push(quote.high);
+        self.lows.push(quote.low);
+        self.closes.push(quote.close);
+        self.adjcloses.push(quote.adjclose);
+        self.volumes.push(f64::from_bits(quote.volume));
+        true
+    }
+
+    ///
+    /// Decompresses all quotes (oldest first)
+    ///
+    pub fn quotes(&self) -> Vec<TickerQuote> {
+        let timestamps = self.timestamps.decode();
+        let opens = self.opens.decode();
+        let highs = self.highs.decode();
+        let lows = self.lows.decode();
+        let closes = self.closes.decode();
+        let adjcloses = self.adjcloses.decode();
+        let volumes = self.volumes.decode();
+        (0..self.len())
+            .map(|i| TickerQuote {
+                timestamp: timestamps[i] as u64,
+                open: opens[i],
+                high: highs[i],
+                low: lows[i],
+                volume: volumes[i].to_bits(),
+                close: closes[i],
+                adjclose: adjcloses[i],
+            })
+            .collect()
+    }
+}
+
+const DAY: u64 = 24 * 60 * 60;
+
+///
+/// Days in the trailing 52 weeks
+///
+const YEAR_DAYS: u64 = 52 * 7;
+
+///
+/// The trailing 52-week high and low of a symbol, kept as one high/low pair per day (UTC).
+/// Quotes have to be pushed in order.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct YearRange {
+    days: VecDeque<(u64, f64, f64)>,
+}
+
+impl YearRange {
+    pub fn push(&mut self, quote: &TickerQuote) {
+        let day = quote.timestamp / DAY;
+        match self.days.back_mut() {
+            Some((last, high, low)) if *last == day => {
+                *high = high.max(quote.high);
+                *low = low.min(quote.low);
+            }
+            _ => self.days.push_back((day, quote.high, quote.low)),
+        }
+        let newest = self.days.back().map(|(d, _, _)| *d).unwrap_or(day);
+        while self
+            .days
+            .front()
+            .is_some_and(|(d, _, _)| *d + YEAR_DAYS <= newest)
+        {
+            self.days.pop_front();
+        }
+    }
+
+    pub fn high(&self) -> Option<f64> {
+        self.days.iter().map(|(_, high, _)| *high).reduce(f64::max)
+    }
+
+    pub fn low(&self) -> Option<f64> {
+        self.days.iter().map(|(_, _, low)| *low).reduce(f64::min)
+    }
+}
+
+///
+/// Compressed quote histories by symbol
+///
+#[derive(Debug, Clone, Default)]
+pub struct QuoteStore {
+    series: HashMap<String, CompressedSeries>,
+    ranges: HashMap<String, YearRange>,
+    /// Only the quotes this far back from the newest one count, if set
+    window: Option<u64>,
+}
+
+///
+/// The quotes and 52-week ranges of a `QuoteStore`, as written to a snapshot
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SavedHistory {
+    pub(crate) series: BTreeMap<String, Vec<LoggedQuote>>,
+    pub(crate) ranges: BTreeMap<String, YearRange>,
+}
+
+///
+/// Request a copy of the processor's quote histories
+///
+#[message(result = "SavedHistory")]
+pub struct HistorySnapshotRequest;
+
+///
+/// Put previously snapshotted histories back into the processor
+///
+#[message]
+pub struct HistoryRestore {
+    pub history: SavedHistory,
+}
+
+impl QuoteStore {
+    ///
+    /// A store that keeps a trailing window of every symbol, e.g. 200 days. Older quotes are
+    /// dropped, except from the 52-week range.
+    ///
+    pub fn with_window(window: Option<Duration>) -> Self {
+        QuoteStore {
+            window: window.map(|w| w.as_secs()),
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// The quotes of a series within the window
+    ///
+    fn trailing(&self, series: &CompressedSeries) -> Vec<TickerQuote> {
+        let mut quotes = series.quotes();
+        if let (Some(window), Some(newest)) = (self.window, series.last_timestamp()) {
+            let start = quotes.partition_point(|q| q.timestamp + window <= newest);
+            quotes.drain(..start);
+        }
+        quotes
+    }
+
+    ///
+    /// Adds the quotes (sorted by time) that are newer than what is stored for the symbol and
+    /// returns the number of quotes added.
+    ///
+    pub fn append(&mut self, symbol: &str, quotes: &[TickerQuote]) -> usize {
+        let series = self.series.entry(symbol.to_string()).or_default();
+        let range = self.ranges.entry(symbol.to_string()).or_default();
+        let added = quotes
+            .iter()
+            .filter(|q| series.push(q))
+            .inspect(|q| range.push(q))
+            .count();
+        // recompressing is expensive, so the quotes outside the window are only dropped once
+        // they take up as much time as the window
+        if let Some(window) = self.window {
+            let series = &self.series[symbol];
+            let span = series
+                .first_timestamp()
+                .zip(series.last_timestamp())
+                .map_or(0, |(first, last)| last - first);
+            if span >= 2 * window {
+                let mut trimmed = CompressedSeries::default();
+                for quote in self.trailing(series) {
+                    trimmed.push(&quote);
+                }
+                self.series.insert(symbol.to_string(), trimmed);
+            }
+        }
+        added
+    }
+
+    ///
+    /// The trailing 52-week range of a symbol
+    ///
+    pub fn year_range(&self, symbol: &str) -> Option<&YearRange> {
+        self.ranges.get(symbol)
+    }
+
+    ///
+    /// Whether any quotes of the symbol are stored
+    ///
+    pub fn contains(&self, symbol: &str) -> bool {
+        self.series.get(symbol).is_some_and(|s| !s.is_empty())
+    }
+
+    ///
+    /// The stored quotes (within the window) and the 52-week ranges of every symbol
+    ///
+    pub fn save(&self) -> SavedHistory {
+        SavedHistory {
+            series: self
+                .series
+                .iter()
+                .map(|(symbol, series)| {
+                    let quotes = self.trailing(series);
+                    (
+                        symbol.clone(),
+                        quotes.iter().map(LoggedQuote::from).collect(),
+                    )
+                })
+                .collect(),
+            ranges: self
+                .ranges
+                .iter()
+                .map(|(symbol, range)| (symbol.clone(), range.clone()))
+                .collect(),
+        }
+    }
+
+    ///
+    /// Adds saved quotes like `append` does. The saved 52-week ranges replace the ones
+    /// rebuilt from the quotes, they reach further back than the window.
+    ///
+    pub fn restore(&mut self, saved: SavedHistory) {
+        for (symbol, quotes) in saved.series {
+            let quotes: Vec<TickerQuote> = quotes.into_iter().map(TickerQuote::from).collect();
+            self.append(&symbol, &quotes);
+        }
+        self.ranges.extend(saved.ranges);
+    }
+
+    ///
+    /// The decompressed history of a symbol, all of it or the trailing window
+    ///
+    pub fn quotes(&self, symbol: &str) -> Vec<TickerQuote> {
+        self.series
+            .get(symbol)
+            .map(|s| self.trailing(s))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn quote(timestamp: u64, close: f64, volume: u64) -> TickerQuote {
+        TickerQuote {
+            timestamp,
+            open: close - 0.25,
+            high: close + 0.5,
+            low: close - 0.5,
+            volume,
+            close,
+            adjclose: close,
+        }
+    }
+
+    #[test]
+    fn test_series_roundtrip() {
+        let mut series = CompressedSeries::default();
+        let quotes = vec![
+            quote(1_600_000_000, 100.0, 1000),
+            quote(1_600_000_060, 100.0, 1000),
+            quote(1_600_000_120, 100.01, 1200),
+            quote(1_600_000_180, 99.5, 0),
+            // gaps of all sizes, including a weekend
+            quote(1_600_000_300, 1e-9, 5),
+            quote(1_600_003_000, -3.75, u32::MAX as u64),
+            quote(1_600_250_000, f64::MAX, 7),
+            quote(1_600_250_001, 0.0, 7),
+        ];
+        for q in &quotes {
+            assert!(series.push(q));
+        }
+        // older or duplicate quotes are skipped
+        assert!(!series.push(&quotes[2]));
+        assert_eq!(series.len(), quotes.len());
+        assert_eq!(series.quotes(), quotes);
+        assert_eq!(series.last_timestamp(), Some(1_600_250_001));
+    }
+
+    proptest! {
+        #[test]
+        fn test_series_roundtrip_any_values(
+            bars in prop::collection::vec(
+                (1u64..1_000_000, any::<f64>(), any::<f64>(), any::<u64>()),
+                1..100,
+            )
+        ) {
+            let mut series = CompressedSeries::default();
+            let mut timestamp = 1_600_000_000;
+            let mut quotes = vec![];
+            for (step, open, close, volume) in bars {
+                timestamp += step;
+                let quote = TickerQuote {
+                    timestamp,
+                    open,
+                    high: close,
+                    low: open,
+                    volume,
+                    close,
+                    adjclose: close,
+                };
+                prop_assert!(series.push(&quote));
+                quotes.push(quote);
+            }
+            // compare the bits, NaN != NaN
+            let bits = |q: &TickerQuote| {
+                (q.timestamp, q.open.to_bits(), q.close.to_bits(), q.volume)
+            };
+            let decoded: Vec<_> = series.quotes().iter().map(bits).collect();
+            prop_assert_eq!(decoded, quotes.iter().map(bits).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn test_series_compression() {
+        let mut series = CompressedSeries::default();
+        let mut price = 150.0;
+        for i in 0..10_000u64 {
+            price += ((i * 7919) % 11) as f64 * 0.01 - 0.05;
+            series.push(&quote(
+                1_600_000_000 + i * 60,
+                (price * 100.0f64).round() / 100.0,
+                100,
+            ));
+        }
+        let encoders = [
+            &series.opens,
+            &series.highs,
+            &series.lows,
+            &series.closes,
+            &series.adjcloses,
+            &series.volumes,
+        ];
+        let size = series.timestamps.bits.bytes.len()
+            + encoders.iter().map(|e| e.bits.bytes.len()).sum::<usize>();
+        let raw = series.len() * std::mem::size_of::<TickerQuote>();
+        assert!(size < raw / 2, "{} bytes compressed vs. {} raw", size, raw);
+        assert_eq!(series.quotes().len(), 10_000);
+    }
+
+    #[test]
+    fn test_quote_store_append() {
+        let mut store = QuoteStore::default();
+        let first = vec![quote(60, 1.0, 1), quote(120, 2.0, 1)];
+        assert_eq!(store.append("AAPL", &first), 2);
+        let overlapping = vec![quote(60, 1.0, 1), quote(120, 2.0, 1), quote(180, 3.0, 1)];
+        assert_eq!(store.append("AAPL", &overlapping), 1);
+        assert_eq!(store.quotes("AAPL"), overlapping);
+        assert!(store.quotes("MSFT").is_empty());
+        assert!(store.contains("AAPL"));
+        assert!(!store.contains("MSFT"));
+    }
+
+    #[test]
+    fn test_quote_store_window() {
+        let mut store = QuoteStore::with_window(Some(Duration::from_secs(3 * DAY)));
+        let days: Vec<TickerQuote> = (0..10).map(|d| quote(d * DAY, d as f64, 1)).collect();
+        store.append("AAPL", &days[..5]);
+        assert_eq!(store.quotes("AAPL"), days[2..5]);
+        // the older quotes are still stored
+        assert_eq!(store.series["AAPL"].len(), 5);
+
+        store.append("AAPL", &days[5..7]);
+        assert_eq!(store.quotes("AAPL"), days[4..7]);
+        assert_eq!(store.series["AAPL"].len(), 3);
+        store.append("AAPL", &days[7..]);
+        assert_eq!(store.quotes("AAPL"), days[7..]);
+        // the 52-week range isn't trimmed
+        assert_eq!(store.year_range("AAPL").unwrap().low(), Some(-0.5));
+
+        // a restored store has the window and the whole range
+        let mut restored = QuoteStore::with_window(Some(Duration::from_secs(3 * DAY)));
+        restored.restore(store.save());
+        assert_eq!(restored.quotes("AAPL"), days[7..]);
+        assert_eq!(restored.year_range("AAPL"), store.year_range("AAPL"));
+    }
+
+    #[test]
+    fn test_year_range() {
+        let mut store = QuoteStore::default();
+        assert!(store.year_range("AAPL").is_none());
+        let start = 1_600_000_000 - 1_600_000_000 % DAY;
+        store.append(
+            "AAPL",
+            &[quote(start, 100.0, 0), quote(start + 60, 120.0, 0)],
+        );
+        let range = store.year_range("AAPL").unwrap();
+        assert_eq!(range.high(), Some(120.5));
+        assert_eq!(range.low(), Some(99.5));
+
+        // a year later, the old highs and lows have dropped out
+        let later = start + YEAR_DAYS * DAY;
+        store.append(
+            "AAPL",
+            &[quote(later - DAY, 110.0, 0), quote(later, 105.0, 0)],
+        );
+        let range = store.year_range("AAPL").unwrap();
+        assert_eq!(range.high(), Some(110.5));
+        assert_eq!(range.low(), Some(104.5));
+    }
+}
@@ -0,0 +1,93 @@
+//!
+//! The HTTP API runs on its own executor and threads, apart from the actors of the pipeline
+//! (on the async-std executor). The handlers only talk to the actors through their mailboxes,
+//! so parsing requests and serializing responses never delays a fetch or a sink write.
+//!
+use std::io;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use async_executor::Executor;
+use async_std::channel::{self, Sender};
+use futures::Future;
+
+///
+/// An executor with dedicated threads
+///
+pub struct HttpRuntime {
+    executor: Arc<Executor<'static>>,
+    /// Closed to stop the threads
+    stop: Sender<()>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl HttpRuntime {
+    ///
+    /// Starts the threads (at least one) that run the executor's tasks
+    ///
+    pub fn start(executor: Arc<Executor<'static>>, threads: usize) -> io::Result<Self> {
+        let (stop, stopped) = channel::bounded::<()>(1);
+        let threads = (0..threads.max(1))
+            .map(|i| {
+                let executor = executor.clone();
+                let stopped = stopped.clone();
+                std::thread::Builder::new()
+                    .name(format!("http-{}", i))
+                    .spawn(move || {
+                        async_std::task::block_on(executor.run(async {
+                            stopped.recv().await.ok();
+                        }))
+                    })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(HttpRuntime {
+            executor,
+            stop,
+            threads,
+        })
+    }
+
+    pub fn executor(&self) -> &Arc<Executor<'static>> {
+        &self.executor
+    }
+
+    ///
+    /// Runs a task on the threads of the runtime, until it's done or the runtime stops
+    ///
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.executor.spawn(future).detach();
+    }
+
+    ///
+    /// Stops the threads once their current requests are handled, dropping all other tasks
+    ///
+    pub fn stop(self) {
+        self.stop.close();
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_http_runtime() {
+        let runtime = HttpRuntime::start(Arc::new(Executor::new()), 2).unwrap();
+        let (tx, rx) = channel::bounded(1);
+        runtime.spawn(async move {
+            let name = std::thread::current().name().map(String::from);
+            tx.send(name).await.unwrap();
+        });
+        let name = async_std::task::block_on(rx.recv()).unwrap();
+        assert!(name.is_some_and(|n| n.starts_with("http-")));
+        // a task that never finishes doesn't keep the runtime from stopping
+        runtime.spawn(futures::future::pending());
+        runtime.stop();
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+
+use anyhow::{anyhow, bail, Context as _};
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+use yahoo_finance_api as yahoo;
+
+use crate::quota::{self, QuotaUsage};
+
+const OPENFIGI_URL: &str = "https://api.openfigi.com/v3/mapping";
+
+///
+/// A security identifier from a portfolio statement instead of an exchange ticker
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identifier {
+    /// International Securities Identification Number, e.g. `US0378331005`
+    Isin(String),
+    /// Committee on Uniform Securities Identification Procedures number, e.g. `037833100`
+    Cusip(String),
+}
+
+impl Identifier {
+    ///
+    /// Recognizes ISINs and CUSIPs by their length and check digit; everything else is a
+    /// ticker
+    ///
+    pub fn parse(entry: &str) -> Option<Self> {
+        let code = entry.trim().to_ascii_uppercase();
+        if is_isin(&code) {
+            Some(Identifier::Isin(code))
+        } else if is_cusip(&code) {
+            Some(Identifier::Cusip(code))
+        } else {
+            None
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            Identifier::Isin(code) | Identifier::Cusip(code) => code,
+        }
+    }
+
+    ///
+    /// The `idType` of the identifier in OpenFIGI's mapping API
+    ///
+    fn figi_type(&self) -> &'static str {
+        match self {
+            Identifier::Isin(_) => "ID_ISIN",
+            Identifier::Cusip(_) => "ID_CUSIP",
+        }
+    }
+}
+
+///
+/// Value of an ISIN or CUSIP character: digits as they are, letters from 10
+///
+fn char_value(c: char) -> Option<u32> {
+    c.to_digit(36)
+}
+
+///
+/// Two country letters, nine alphanumeric characters, and a Luhn check digit over the
+/// characters with letters expanded to two digits
+///
+fn is_isin(code: &str) -> bool {
+    let chars: Vec<char> = code.chars().collect();
+    if chars.len() != 12
+        || !chars[..2].iter().all(|c| c.is_ascii_uppercase())
+        || !chars[2..11].iter().all(|c| c.is_ascii_alphanumeric())
+        || !chars[11].is_ascii_digit()
+    {
+        return false;
+    }
+    let digits: Vec<u32> = chars
+        .iter()
+        .filter_map(|c| char_value(*c))
+        .flat_map(|v| {
+            if v >= 10 {
+                vec![v / 10, v % 10]
+            } else {
+                vec![v]
+            }
+        })
+        .collect();
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| match i % 2 {
+            0 => *d,
+            _ => (d * 2) / 10 + (d * 2) % 10,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+///
+/// Eight alphanumeric characters and a check digit over them, with every second value doubled
+///
+fn is_cusip(code: &str) -> bool {
+    let chars: Vec<char> = code.chars().collect();
+    if chars.len() != 9
+        || !chars[..8].iter().all(|c| c.is_ascii_alphanumeric())
+        || !chars[8].is_ascii_digit()
+    {
+        return false;
+    }
+    let sum: u32 = chars[..8]
+        .iter()
+        .filter_map(|c| char_value(*c))
+        .enumerate()
+        .map(|(i, v)| if i % 2 == 1 { v * 2 } else { v })
+        .map(|v| v / 10 + v % 10)
+        .sum();
+    chars[8].to_digit(10) == Some((10 - sum % 10) % 10)
+}
+
+///
+/// Where identifiers are looked up
+///
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum TickerLookup {
+    /// The provider's search, which returns the provider's own tickers (e.g. `SAP.DE`)
+    #[default]
+    Yahoo,
+    /// OpenFIGI's mapping API, which returns the primary listing's ticker
+    Openfigi,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FigiJob<'a> {
+    id_type: &'a str,
+    id_value: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FigiInstrument {
+    ticker: Option<String>,
+    exch_code: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FigiResult {
+    #[serde(default)]
+    data: Vec<FigiInstrument>,
+    warning: Option<String>,
+    error: Option<String>,
+}
+
+///
+/// The ticker in an OpenFIGI mapping response, preferring the US composite listing
+///
+fn figi_ticker(response: &str) -> anyhow::Result<String> {
+    let results: Vec<FigiResult> = serde_json::from_str(response)?;
+    let result = results
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("empty response"))?;
+    if let Some(message) = result.error.as_ref().or(result.warning.as_ref()) {
+        bail!("{}", message);
+    }
+    result
+        .data
+        .iter()
+        .find(|i| i.exch_code.as_deref() == Some("US"))
+        .or_else(|| result.data.first())
+        .and_then(|i| i.ticker.clone())
+        .ok_or_else(|| anyhow!("no ticker"))
+}
+
+///
+/// Replaces ISINs and CUSIPs in symbol lists with provider tickers. Mappings are kept, and
+/// optionally stored in a cache file, so every identifier is only looked up once.
+///
+#[derive(Debug, Clone, Default)]
+pub struct TickerResolver {
+    pub lookup: TickerLookup,
+    /// OpenFIGI API key, which raises its rate limit
+    pub api_key: Option<String>,
+    pub cache: Option<String>,
+    /// Tickers by identifier
+    mappings: BTreeMap<String, String>,
+}
+
+impl TickerResolver {
+    pub fn new(lookup: TickerLookup, api_key: Option<String>, cache: Option<String>) -> Self {
+        let mappings = match &cache {
+            Some(path) => load(path).unwrap_or_else(|e| {
+                tracing::error!("{:#}", e);
+                BTreeMap::new()
+            }),
+            None => BTreeMap::new(),
+        };
+        TickerResolver {
+            lookup,
+            api_key,
+            cache,
+            mappings,
+        }
+    }
+
+    ///
+    /// Replaces every identifier with its ticker. Identifiers that can't be resolved are
+    /// left out, the result has no duplicates and keeps the order of the entries.
+    ///
+    pub async fn resolve(&mut self, entries: Vec<String>) -> Vec<String> {
+        let mut symbols: Vec<String> = vec![];
+        let mut added = false;
+        for entry in entries {
+            let symbol = match Identifier::parse(&entry) {
+                Some(id) => match self.mappings.get(id.code()) {
+                    Some(ticker) => ticker.clone(),
+                    None => match self.fetch(&id).await {
+                        Ok(ticker) => {
+                            tracing::info!("Resolved {} to {}", id.code(), ticker);
+                            self.mappings.insert(id.code().to_string(), ticker.clone());
+                            added = true;
+                            ticker
+                        }
+                        Err(e) => {
+                            tracing::error!("Could not resolve '{}' to a ticker: {}", id.code(), e);
+                            continue;
+                        }
+                    },
+                },
+                None => entry,
+            };
+            if !symbols.contains(&symbol) {
+                symbols.push(symbol);
+            }
+        }
+        if let (true, Some(path)) = (added, &self.cache) {
+            if let Err(e) = save(path, &self.mappings) {
+                tracing::error!("Could not write ticker cache '{}': {}", path, e);
+            }
+        }
+        symbols
+    }
+
+    async fn fetch(&self, id: &Identifier) -> anyhow::Result<String> {
+        match self.lookup {
+            TickerLookup::Yahoo => {
+                let search = yahoo::YahooConnector::new()
+                    .search_ticker_opt(id.code())
+                    .await;
+                quota::record(QuotaUsage {
+                    provider: "yahoo".to_string(),
+                    remaining: None,
+                    rate_limited: false,
+                })
+                .await;
+                search?
+                    .quotes
+                    .into_iter()
+                    .next()
+                    .map(|q| q.symbol)
+                    .ok_or_else(|| anyhow!("no search results"))
+            }
+            TickerLookup::Openfigi => {
+                let mut request = reqwest::Client::new().post(OPENFIGI_URL).json(&[FigiJob {
+                    id_type: id.figi_type(),
+                    id_value: id.code(),
+                }]);
+                if let Some(key) = &self.api_key {
+                    request = request.header("X-OPENFIGI-APIKEY", key);
+                }
+                let response = request.send().await?.error_for_status()?.text().await?;
+                figi_ticker(&response)
+            }
+        }
+    }
+}
+
+///
+/// Reads a ticker cache file. A missing file is an empty cache.
+///
+fn load(path: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    match File::open(path) {
+        Ok(file) => serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Invalid ticker cache '{}'", path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e).with_context(|| format!("Could not open '{}'", path)),
+    }
+}
+
+fn save(path: &str, mappings: &BTreeMap<String, String>) -> anyhow::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer_pretty(&mut writer, mappings)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_identifier() {
+        assert_eq!(
+            Identifier::parse("US0378331005"),
+            Some(Identifier::Isin("US0378331005".to_string()))
+        );
+        assert_eq!(
+            Identifier::parse("de0007164600"),
+            Some(Identifier::Isin("DE0007164600".to_string()))
+        );
+        assert_eq!(
+            Identifier::parse("037833100"),
+            Some(Identifier::Cusip("037833100".to_string()))
+        );
+        assert_eq!(
+            Identifier::parse("38259P508"),
+            Some(Identifier::Cusip("38259P508".to_string()))
+        );
+        // wrong check digits
+        assert_eq!(Identifier::parse("US0378331006"), None);
+        assert_eq!(Identifier::parse("037833101"), None);
+        for ticker in &["AAPL", "BRK-B", "SAP.DE", "BTC-USD", "SYN0001"] {
+            assert_eq!(Identifier::parse(ticker), None);
+        }
+    }
+
+    #[test]
+    fn test_figi_ticker() {
+        let response = r#"[{"data": [
+            {"figi": "BBG000B9XVV8", "ticker": "AAPL", "exchCode": "UW"},
+            {"figi": "BBG000B9XRY4", "ticker": "AAPL", "exchCode": "US"}
+        ]}]"#;
+        assert_eq!(figi_ticker(response).unwrap(), "AAPL");
+        assert!(figi_ticker(r#"[{"warning": "No identifier found."}]"#).is_err());
+        assert!(figi_ticker("[]").is_err());
+    }
+
+    #[async_std::test]
+    async fn test_resolve_cached() {
+        let path = std::env::temp_dir().join("identifier_cache.json");
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(&path, r#"{"US0378331005": "AAPL"}"#).unwrap();
+        let mut resolver = TickerResolver::new(TickerLookup::Yahoo, None, Some(path));
+        let entries = vec![
+            "US0378331005".to_string(),
+            "AAPL".to_string(),
+            "MSFT".to_string(),
+        ];
+        assert_eq!(resolver.resolve(entries).await, vec!["AAPL", "MSFT"]);
+    }
+}
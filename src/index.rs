@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+
+///
+/// S&P 500 members as of December 2022, used if there is no other constituents source
+///
+const SP500: &str = include_str!("../sp500.dec.2022.txt");
+
+const INDEX_PREFIX: &str = "index:";
+
+///
+/// Where index members (e.g. `index:sp500`) come from and how often they are refreshed
+///
+#[derive(Debug, Clone)]
+pub struct Constituents {
+    /// URL returning the members as comma or line separated text, `{index}` is replaced by
+    /// the index name
+    pub url: Option<String>,
+    pub refresh: Option<Duration>,
+}
+
+impl Default for Constituents {
+    fn default() -> Self {
+        Constituents {
+            url: None,
+            refresh: Some(Duration::from_secs(24 * 60 * 60)),
+        }
+    }
+}
+
+impl Constituents {
+    ///
+    /// Replaces every `index:<name>` entry with the index members. The result has no duplicates
+    /// and keeps the order of the entries.
+    ///
+    pub async fn resolve(&self, entries: &[String]) -> anyhow::Result<Vec<String>> {
+        let mut symbols: Vec<String> = vec![];
+        for entry in entries {
+            let members = match entry.strip_prefix(INDEX_PREFIX) {
+                Some(index) => self.members(index).await?,
+                None => vec![entry.clone()],
+            };
+            for symbol in members {
+                if !symbols.contains(&symbol) {
+                    symbols.push(symbol);
+                }
+            }
+        }
+        Ok(symbols)
+    }
+
+    async fn members(&self, index: &str) -> anyhow::Result<Vec<String>> {
+        let bundled = match index {
+            "sp500" => Some(SP500),
+            _ => None,
+        };
+        let text = match (&self.url, bundled) {
+            (Some(url), _) => match fetch(&url.replace("{index}", index)).await {
+                Ok(text) => text,
+                Err(e) => match bundled {
+                    Some(text) => {
+                        tracing::warn!("Using bundled members of '{}': {}", index, e);
+                        text.to_string()
+                    }
+                    None => return Err(e),
+                },
+            },
+            (None, Some(text)) => text.to_string(),
+            (None, None) => bail!(
+                "No constituents source for '{}', use --constituents-url",
+                index
+            ),
+        };
+        let members = parse_symbols(&text);
+        if members.is_empty() {
+            bail!("Index '{}' has no members", index);
+        }
+        Ok(members)
+    }
+}
+
+///
+/// True if any entry needs to be resolved
+///
+pub fn has_index(entries: &[String]) -> bool {
+    entries.iter().any(|e| e.starts_with(INDEX_PREFIX))
+}
+
+async fn fetch(url: &str) -> anyhow::Result<String> {
+    let response = reqwest::get(url)
+        .await
+        .map_err(|e| anyhow!("Could not fetch '{}': {}", url, e))?;
+    Ok(response.error_for_status()?.text().await?)
+}
+
+///
+/// Parses comma and/or line separated symbols. Class shares are written with a dash as the
+/// provider expects (`BRK.B` becomes `BRK-B`), lines starting with `#` are ignored.
+///
+pub fn parse_symbols(text: &str) -> Vec<String> {
+    text.lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .flat_map(|l| l.split(','))
+        .map(|s| s.trim().replace('.', "-"))
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_symbols() {
+        assert_eq!(
+            parse_symbols("# members\nAAPL, MSFT\nBRK.B\n\n"),
+            vec!["AAPL", "MSFT", "BRK-B"]
+        );
+        assert_eq!(parse_symbols(SP500).len(), 503);
+    }
+
+    #[async_std::test]
+    async fn test_resolve() {
+        let constituents = Constituents::default();
+        let entries = vec!["TSLA".to_string(), "index:sp500".to_string()];
+        assert!(has_index(&entries));
+        let symbols = constituents.resolve(&entries).await.unwrap();
+        assert_eq!(symbols[0], "TSLA");
+        assert_eq!(symbols.len(), 503);
+        assert!(symbols.contains(&"BF-B".to_string()));
+        assert!(constituents
+            .resolve(&["index:nasdaq100".to_string()])
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//!
+//! Writes the indicators to InfluxDB in its line protocol, e.g. for Grafana dashboards:
+//!
+//! ```text
+//! indicators,symbol=AAPL price=91.03,pct_change=-0.0125,rsi=55.2 1593777609
+//! ```
+//!
+use std::time::Duration;
+
+use crate::wal::Delivery;
+use crate::PerformanceIndicators;
+
+///
+/// Where and how the lines are written
+///
+#[derive(Debug, Clone, Default)]
+pub struct InfluxConfig {
+    /// e.g. `http://localhost:8086`
+    pub url: String,
+    pub bucket: String,
+    /// Organization of InfluxDB 2, not needed with a token that is scoped to one
+    pub org: Option<String>,
+    pub token: Option<String>,
+    pub measurement: String,
+}
+
+///
+/// Posts batches of indicators to the write API of InfluxDB 2 (and 1.8's compatible one).
+/// Runs behind a `WalSink`, which retries failed writes.
+///
+#[derive(Clone)]
+pub struct InfluxSink {
+    config: InfluxConfig,
+    client: reqwest::Client,
+}
+
+impl InfluxSink {
+    pub fn new(config: InfluxConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        Ok(InfluxSink { config, client })
+    }
+}
+
+#[async_trait::async_trait]
+impl Delivery for InfluxSink {
+    fn name(&self) -> String {
+        format!("InfluxDB '{}'", self.config.url)
+    }
+
+    fn kind(&self) -> &'static str {
+        "influx"
+    }
+
+    async fn deliver(&self, rows: &[PerformanceIndicators]) -> anyhow::Result<()> {
+        let body: String = rows
+            .iter()
+            .map(|row| line(&self.config.measurement, row) + "\n")
+            .collect();
+        let url = format!("{}/api/v2/write", self.config.url.trim_end_matches('/'));
+        let mut query = vec![("bucket", self.config.bucket.as_str()), ("precision", "s")];
+        if let Some(org) = &self.config.org {
+            query.push(("org", org));
+        }
+        let mut request = self.client.post(url).query(&query).body(body);
+        if let Some(token) = &self.config.token {
+            request = request.header("Authorization", format!("Token {}", token));
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+///
+/// Backslashes the characters that end a measurement, tag, or field key or a tag value
+///
+fn escape(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn key(text: &str) -> String {
+    escape(text, &[',', '=', ' '])
+}
+
+///
+/// A row as a line: the symbol, watchlist, and resolution are tags, the indicators fields.
+/// Missing and non-finite indicators are left out.
+///
+pub fn line(measurement: &str, row: &PerformanceIndicators) -> String {
+    let mut line = escape(measurement, &[',', ' ']);
+    let tags = [
+        ("symbol", Some(&row.symbol)),
+        ("watchlist", row.watchlist.as_ref()),
+        ("resolution", row.resolution.as_ref()),
+    ];
+    for (name, value) in tags {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            line.push_str(&format!(",{}={}", name, key(value)));
+        }
+    }
+    let fields = [
+        ("price", Some(row.price)),
+        ("pct_change", Some(row.pct_change)),
+        ("period_min", Some(row.period_min)),
+        ("period_max", Some(row.period_max)),
+        ("last_sma", Some(row.last_sma)),
+        ("last_ema", Some(row.last_ema)),
+        ("rsi", row.rsi),
+        ("volatility", row.volatility),
+        ("score", row.score),
+        ("high_52w", row.high_52w),
+        ("low_52w", row.low_52w),
+        ("pct_from_high_52w", row.pct_from_high_52w),
+        ("pct_from_low_52w", row.pct_from_low_52w),
+        ("vwap_session", row.vwap_session),
+        ("gap_pct", row.gap_pct),
+        ("change_from_prev_close", row.change_from_prev_close),
+    ];
+    let custom = row.custom.iter().map(|(k, v)| (k.as_str(), Some(*v)));
+    let fields: Vec<String> = fields
+        .iter()
+        .copied()
+        .chain(custom)
+        .filter_map(|(name, value)| Some((name, value.filter(|v| v.is_finite())?)))
+        .map(|(name, value)| format!("{}={}", key(name), value))
+        .collect();
+    line.push(' ');
+    line.push_str(&fields.join(","));
+    line.push_str(&format!(" {}", row.timestamp.timestamp()));
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    #[test]
+    fn test_line() {
+        let mut row = PerformanceIndicators {
+            timestamp: Utc.timestamp_opt(1593777609, 0).unwrap(),
+            symbol: "BRK B".to_string(),
+            price: 91.03,
+            pct_change: -0.0125,
+            rsi: Some(55.2),
+            volatility: Some(f64::NAN),
+            watchlist: Some("tech,growth".to_string()),
+            ..Default::default()
+        };
+        row.custom.insert("z score".to_string(), 1.5);
+        assert_eq!(
+            line("stock indicators", &row),
+            "stock\\ indicators,symbol=BRK\\ B,watchlist=tech\\,growth \
+             price=91.03,pct_change=-0.0125,period_min=0,period_max=0,last_sma=0,last_ema=0,\
+             rsi=55.2,z\\ score=1.5 1593777609"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//!
+//! Relations between the indicators that hold for every series. The processor checks them in
+//! debug builds and logs violations, the property tests check them for generated series.
+//!
+use crate::PerformanceIndicators;
+
+///
+/// Tolerance for rounding errors, relative to the values compared
+///
+const EPSILON: f64 = 1e-9;
+
+fn at_most(a: f64, b: f64) -> bool {
+    a <= b + EPSILON * a.abs().max(b.abs()).max(1.0)
+}
+
+///
+/// Describes every invariant the indicators violate
+///
+pub fn violations(data: &PerformanceIndicators) -> Vec<String> {
+    let mut violations = vec![];
+    let mut check = |holds: bool, description: String| {
+        if !holds {
+            violations.push(description);
+        }
+    };
+    let numbers = [
+        ("price", Some(data.price)),
+        ("pct_change", Some(data.pct_change)),
+        ("period_min", Some(data.period_min)),
+        ("period_max", Some(data.period_max)),
+        ("last_sma", Some(data.last_sma)),
+        ("last_ema", Some(data.last_ema)),
+        ("rsi", data.rsi),
+        ("volatility", data.volatility),
+        ("score", data.score),
+        ("high_52w", data.high_52w),
+        ("low_52w", data.low_52w),
+        ("pct_from_high_52w", data.pct_from_high_52w),
+        ("pct_from_low_52w", data.pct_from_low_52w),
+        ("vwap_session", data.vwap_session),
+        ("gap_pct", data.gap_pct),
+        ("change_from_prev_close", data.change_from_prev_close),
+    ];
+    for (name, value) in numbers.iter() {
+        if let Some(value) = value {
+            check(value.is_finite(), format!("{} is {}", name, value));
+        }
+    }
+    for (name, value) in &data.custom {
+        check(value.is_finite(), format!("{} is {}", name, value));
+    }
+    let (min, max) = (data.period_min, data.period_max);
+    check(
+        at_most(min, max),
+        format!("period_min {} > period_max {}", min, max),
+    );
+    check(
+        at_most(min, data.price) && at_most(data.price, max),
+        format!("price {} outside of [{}, {}]", data.price, min, max),
+    );
+    // no SMA is 0 until the series fills a window
+    check(
+        data.last_sma == 0.0 || (at_most(min, data.last_sma) && at_most(data.last_sma, max)),
+        format!("last_sma {} outside of [{}, {}]", data.last_sma, min, max),
+    );
+    check(
+        data.last_ema == 0.0 || (at_most(min, data.last_ema) && at_most(data.last_ema, max)),
+        format!("last_ema {} outside of [{}, {}]", data.last_ema, min, max),
+    );
+    if let Some(rsi) = data.rsi {
+        check(
+            (0.0..=100.0).contains(&rsi),
+            format!("rsi {} outside of [0, 100]", rsi),
+        );
+    }
+    if let Some(volatility) = data.volatility {
+        check(
+            volatility >= 0.0,
+            format!("volatility {} is negative", volatility),
+        );
+    }
+    if let Some(score) = data.score {
+        check(
+            (0.0..=100.0).contains(&score),
+            format!("score {} outside of [0, 100]", score),
+        );
+    }
+    if let (Some(low), Some(high)) = (data.low_52w, data.high_52w) {
+        check(
+            at_most(low, high),
+            format!("low_52w {} > high_52w {}", low, high),
+        );
+    }
+    if let Some(drawdown) = data.pct_from_high_52w {
+        check(
+            at_most(drawdown, 0.0),
+            format!("pct_from_high_52w {} > 0", drawdown),
+        );
+    }
+    if let Some(rally) = data.pct_from_low_52w {
+        check(
+            at_most(0.0, rally),
+            format!("pct_from_low_52w {} < 0", rally),
+        );
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::QuoteStore;
+    use crate::signal::{SignalSet, TickerQuote};
+    use crate::ProcessorConfig;
+    use proptest::prelude::*;
+
+    ///
+    /// Random walks with daily bars, including flat stretches and large jumps
+    ///
+    fn quote_series() -> impl Strategy<Value = Vec<TickerQuote>> {
+        (
+            0.01f64..10_000.0,
+            prop::collection::vec((-0.6f64..0.6, 0.0f64..0.1, 0u64..1_000_000), 1..120),
+        )
+            .prop_map(|(start, steps)| {
+                let mut close = start;
+                steps
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, (ret, spread, volume))| {
+                        let open = close;
+                        close = (close * (1.0 + ret)).max(0.01);
+                        TickerQuote {
+                            timestamp: 1_600_000_000 + i as u64 * 86_400,
+                            open,
+                            high: open.max(close) * (1.0 + spread),
+                            low: open.min(close) * (1.0 - spread),
+                            volume,
+                            close,
+                            adjclose: close,
+                        }
+                    })
+                    .collect()
+            })
+    }
+
+    #[test]
+    fn test_violations() {
+        let data = PerformanceIndicators {
+            price: 5.0,
+            period_min: 4.0,
+            period_max: 6.0,
+            last_sma: 5.0,
+            high_52w: Some(6.0),
+            low_52w: Some(4.0),
+            pct_from_high_52w: Some(-1.0 / 6.0),
+            ..Default::default()
+        };
+        assert!(violations(&data).is_empty());
+        let broken = PerformanceIndicators {
+            period_min: 7.0,
+            last_sma: f64::NAN,
+            pct_from_high_52w: Some(0.1),
+            ..data
+        };
+        assert_eq!(violations(&broken).len(), 5, "{:?}", violations(&broken));
+    }
+
+    proptest! {
+        #[test]
+        fn test_indicators_hold_invariants(
+            quotes in quote_series(),
+            sma_window in 2usize..40,
+        ) {
+            let processor = ProcessorConfig::default().processor();
+            let signals = SignalSet { sma_window, ..Default::default() };
+            let mut store = QuoteStore::default();
+            store.append("X", &quotes);
+            let history = store.quotes("X");
+            let mut data =
+                async_std::task::block_on(processor.indicators("X", &history, &signals));
+            data.set_year_range(store.year_range("X").unwrap());
+            prop_assert!(violations(&data).is_empty(), "{:?}", violations(&data));
+        }
+    }
+}
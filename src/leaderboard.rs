@@ -0,0 +1,243 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::quality::CleanQuotes;
+use crate::PerformanceIndicators;
+
+///
+/// Number of returns the volatility is calculated over
+///
+const VOLATILITY_WINDOW: usize = 30;
+
+///
+/// What the leaderboard is sorted by
+///
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RankBy {
+    PctChange,
+    Volatility,
+    Volume,
+    Score,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RankOrder {
+    Asc,
+    Desc,
+}
+
+///
+/// A symbol on the leaderboard
+///
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct LeaderboardEntry {
+    pub symbol: String,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub price: Option<f64>,
+    pub pct_change: Option<f64>,
+    /// Standard deviation of the latest returns
+    pub volatility: Option<f64>,
+    /// Volume of the latest bar
+    pub volume: Option<u64>,
+    /// Composite score of the latest indicators
+    pub score: Option<f64>,
+}
+
+impl LeaderboardEntry {
+    fn key(&self, by: RankBy) -> Option<f64> {
+        match by {
+            RankBy::PctChange => self.pct_change,
+            RankBy::Volatility => self.volatility,
+            RankBy::Volume => self.volume.map(|v| v as f64),
+            RankBy::Score => self.score,
+        }
+    }
+}
+
+#[message(result = "Vec<LeaderboardEntry>")]
+pub struct LeaderboardRequest {
+    pub by: RankBy,
+    pub order: RankOrder,
+    pub n: usize,
+}
+
+#[derive(Debug, Default)]
+struct Tracked {
+    entry: LeaderboardEntry,
+    /// The latest closes by timestamp
+    closes: VecDeque<(u64, f64)>,
+}
+
+impl Tracked {
+    fn add_closes(&mut self, closes: impl Iterator<Item = (u64, f64)>) {
+        for (timestamp, close) in closes {
+            match self.closes.back() {
+                Some((last, _)) if *last >= timestamp => continue,
+                _ => self.closes.push_back((timestamp, close)),
+            }
+            if self.closes.len() > VOLATILITY_WINDOW + 1 {
+                self.closes.pop_front();
+            }
+        }
+        let returns: Vec<f64> = self
+            .closes
+            .iter()
+            .zip(self.closes.iter().skip(1))
+            .filter(|((_, a), _)| *a != 0.0)
+            .map(|((_, a), (_, b))| b / a - 1.0)
+            .collect();
+        self.entry.volatility = if returns.len() > 1 {
+            let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+            let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>()
+                / (returns.len() - 1) as f64;
+            Some(variance.sqrt())
+        } else {
+            None
+        };
+    }
+}
+
+///
+/// Returns the top `n` entries. Symbols without a value for the key come last.
+///
+fn rank(mut entries: Vec<LeaderboardEntry>, request: &LeaderboardRequest) -> Vec<LeaderboardEntry> {
+    entries.sort_by(|a, b| match (a.key(request.by), b.key(request.by)) {
+        (Some(a), Some(b)) => match request.order {
+            RankOrder::Asc => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            RankOrder::Desc => b.partial_cmp(&a).unwrap_or(Ordering::Equal),
+        },
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+    entries.truncate(request.n);
+    entries
+}
+
+///
+/// Actor that keeps the latest key figures of every symbol for `/leaderboard`
+///
+#[derive(Default)]
+pub struct Leaderboard {
+    symbols: BTreeMap<String, Tracked>,
+}
+
+#[async_trait::async_trait]
+impl Actor for Leaderboard {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<CleanQuotes>().await?;
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<CleanQuotes> for Leaderboard {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: CleanQuotes) {
+        let mut quotes = msg.0.quotes;
+        if quotes.is_empty() {
+            return;
+        }
+        quotes.sort_by_key(|q| q.timestamp);
+        let tracked = self.symbols.entry(msg.0.symbol.clone()).or_default();
+        tracked.entry.symbol = msg.0.symbol;
+        tracked.entry.volume = quotes.last().map(|q| q.volume);
+        tracked.add_closes(quotes.iter().map(|q| (q.timestamp, q.close)));
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for Leaderboard {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        if msg.resolution.is_some() {
+            return;
+        }
+        let tracked = self.symbols.entry(msg.symbol.clone()).or_default();
+        tracked.entry.symbol = msg.symbol;
+        tracked.entry.timestamp = Some(msg.timestamp);
+        tracked.entry.price = Some(msg.price);
+        tracked.entry.pct_change = Some(msg.pct_change);
+        tracked.entry.score = msg.score;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<LeaderboardRequest> for Leaderboard {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: LeaderboardRequest,
+    ) -> Vec<LeaderboardEntry> {
+        let entries = self.symbols.values().map(|t| t.entry.clone()).collect();
+        rank(entries, &msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(symbol: &str, pct_change: Option<f64>, volume: Option<u64>) -> LeaderboardEntry {
+        LeaderboardEntry {
+            symbol: symbol.to_string(),
+            pct_change,
+            volume,
+            ..Default::default()
+        }
+    }
+
+    fn symbols(entries: &[LeaderboardEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.symbol.as_str()).collect()
+    }
+
+    #[test]
+    fn test_rank() {
+        let entries = vec![
+            entry("A", Some(0.01), None),
+            entry("B", None, Some(10)),
+            entry("C", Some(-0.05), Some(30)),
+            entry("D", Some(0.07), Some(20)),
+        ];
+        let request = |by, order, n| LeaderboardRequest { by, order, n };
+        let top = rank(
+            entries.clone(),
+            &request(RankBy::PctChange, RankOrder::Desc, 2),
+        );
+        assert_eq!(symbols(&top), vec!["D", "A"]);
+        let flop = rank(
+            entries.clone(),
+            &request(RankBy::PctChange, RankOrder::Asc, 10),
+        );
+        assert_eq!(symbols(&flop), vec!["C", "A", "D", "B"]);
+        let volume = rank(
+            entries.clone(),
+            &request(RankBy::Volume, RankOrder::Desc, 10),
+        );
+        assert_eq!(symbols(&volume), vec!["C", "D", "B", "A"]);
+        let scored: Vec<LeaderboardEntry> = entries
+            .into_iter()
+            .zip(vec![Some(40.0), Some(80.0), None, Some(55.0)])
+            .map(|(entry, score)| LeaderboardEntry { score, ..entry })
+            .collect();
+        let best = rank(scored, &request(RankBy::Score, RankOrder::Desc, 10));
+        assert_eq!(symbols(&best), vec!["B", "D", "A", "C"]);
+    }
+
+    #[test]
+    fn test_volatility() {
+        let mut tracked = Tracked::default();
+        tracked.add_closes(vec![(1, 100.0), (2, 100.0)].into_iter());
+        assert_eq!(tracked.entry.volatility, None);
+        tracked.add_closes(vec![(2, 50.0), (3, 100.0), (4, 100.0)].into_iter());
+        assert_eq!(tracked.entry.volatility, Some(0.0));
+        tracked.add_closes((5..100).map(|i| (i, if i % 2 == 0 { 110.0 } else { 100.0 })));
+        assert_eq!(tracked.closes.len(), VOLATILITY_WINDOW + 1);
+        assert!(tracked.entry.volatility.unwrap() > 0.09);
+    }
+}
@@ -0,0 +1,1730 @@
+//!
+//! Fetches stock quotes, calculates indicators over them with actors, and hands them to
+//! sinks. `Pipeline` embeds the pipeline in another service, `cli` is the command line the
+//! binary runs. The signals, the buffer, the actors, and their messages are public for
+//! setting up a pipeline in other ways.
+//!
+use async_executor::Executor;
+use buffer::{BufferDataRequest, ChangesRequest, LatestRequest};
+use chrono::prelude::*;
+use futures::TryStreamExt;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tide::Request;
+use tide::Response;
+use tide::StatusCode;
+use xactor::*;
+
+pub mod alert;
+pub mod alias;
+pub mod alphavantage;
+pub mod anomaly;
+pub mod audit;
+pub mod backfill;
+pub mod broadcast;
+pub mod buffer;
+pub mod calendar;
+pub mod candles;
+pub mod checkpoint;
+pub mod circuit_breaker;
+pub mod cli;
+pub mod clock;
+pub mod concurrency_limit;
+pub mod config;
+pub mod crash;
+pub mod csv_schema;
+pub mod daily;
+pub mod derived;
+pub mod doctor;
+pub mod download;
+pub mod envelope;
+pub mod export;
+pub mod fetch;
+pub mod file_replay;
+pub mod file_sink;
+pub mod gap;
+pub mod group;
+#[doc(hidden)]
+pub mod harness;
+pub mod history;
+pub mod http_runtime;
+pub mod identifier;
+pub mod index;
+pub mod influx;
+pub mod invariants;
+pub mod leaderboard;
+pub mod listen;
+pub mod logging;
+pub mod metadata;
+pub mod metrics;
+pub mod notify;
+pub mod number_format;
+mod options;
+pub mod pairs;
+pub mod parquet_file;
+pub mod pipeline;
+pub mod plugin;
+pub mod prometheus_rules;
+pub mod provider;
+pub mod quality;
+pub mod quota;
+pub mod quote_log;
+pub mod rate_limit;
+pub mod recording;
+pub mod registry;
+pub mod repair;
+pub mod replica;
+pub mod report;
+pub mod resample;
+pub mod response_cache;
+pub mod retry;
+pub mod scheduler;
+pub mod score;
+pub mod script;
+pub mod shutdown;
+pub mod signal;
+pub mod sink_queue;
+pub mod snapshot;
+pub mod sqlite_sink;
+pub mod synthetic;
+pub mod tiering;
+pub mod trailing_stop;
+pub mod volatility;
+pub mod wal;
+pub mod webhook;
+pub mod websocket;
+
+use alert::{Alert, NamedRule, ThresholdRule, ThresholdRules};
+use alias::Aliases;
+use anomaly::{Anomaly, AnomalyDetector};
+use audit::{AuditLog, AuditMiddleware, AuditRequest};
+use backfill::{BackfillStatusRequest, BackfillTracker};
+use broadcast::{Broadcaster, Connect};
+use circuit_breaker::CircuitBreaker;
+use concurrency_limit::ConcurrencyLimit;
+use config::SymbolConfig;
+use csv_schema::CsvSchema;
+use envelope::{Freshness, InFlight};
+use fetch::{FetchOutcome, FetchStatus};
+use file_sink::SinkFiles;
+use gap::GapEvent;
+use group::{GroupAggregator, GroupRequest, GroupsRequest};
+use history::{HistoryRestore, HistorySnapshotRequest, QuoteStore, SavedHistory, YearRange};
+use leaderboard::{Leaderboard, LeaderboardRequest, RankBy, RankOrder};
+use metadata::{SymbolDirectory, SymbolMetadata, SymbolsRequest};
+use metrics::{Metrics, MetricsMiddleware, MetricsRequest, Observation, Stage};
+use number_format::NumberFormat;
+use pairs::{Pair, PairMonitor};
+pub use pipeline::{Pipeline, PipelineBuilder};
+use plugin::SignalPlugin;
+use provider::{AssignmentsRequest, DataProvider, Drain, ProviderRouter, SwitchProvider};
+use quality::{CleanQuotes, DataQuality, QualityRequest};
+use quota::{QuotaRequest, QuotaTracker, QuotaUsage};
+use rate_limit::RateLimiter;
+use registry::{AddSymbol, RemoveSymbol, SymbolRegistry};
+use response_cache::ResponseCache;
+use retry::{DeadLetter, Jitter, RetryPolicy};
+use score::ScoreWeights;
+use script::Script;
+use shutdown::Shutdown;
+use signal::{
+    AsyncStockSignal, ExponentialMovingAverage, RelativeStrengthIndex, RollingVolatility,
+    SignalSet, TickerQuote, WindowedSMA,
+};
+use tiering::{HistoryRequest, Tiering};
+use trailing_stop::{TrailingStop, TrailingStopsRequest};
+use volatility::{VolatilityCone, VolatilityRequest};
+use websocket::Subscriptions;
+
+use crate::buffer::BufferSink;
+
+///
+/// Shared state for the HTTP handlers
+///
+#[derive(Clone)]
+struct State {
+    buffer: Addr<BufferSink>,
+    metrics: Addr<Metrics>,
+    audit: Addr<AuditLog>,
+    quality: Addr<DataQuality>,
+    quota: Addr<QuotaTracker>,
+    leaderboard: Addr<Leaderboard>,
+    symbols: Addr<SymbolDirectory>,
+    groups: Addr<GroupAggregator>,
+    backfill: Addr<BackfillTracker>,
+    providers: Addr<ProviderRouter>,
+    /// Pushes new indicators to `/stream` and `/ws`
+    broadcaster: Addr<Broadcaster>,
+    /// Runs the WebSocket connections, on the threads of the HTTP API
+    executor: Arc<Executor<'static>>,
+    /// Symbols added and removed at runtime
+    registry: Addr<SymbolRegistry>,
+    /// Accepted in place of the tickers in the paths
+    aliases: Arc<Aliases>,
+    trailing_stops: Addr<TrailingStop>,
+    /// Answers with the volatility cones over the history
+    processor: Addr<StockDataProcessor>,
+    /// Responses of the endpoints dashboards poll
+    cache: ResponseCache,
+    /// Shared by the data endpoints
+    limit: ConcurrencyLimit,
+    watchlists: Arc<BTreeMap<String, Addr<BufferSink>>>,
+    /// The CSV file of the default pipeline
+    csv_files: SinkFiles,
+    /// The threshold alert rules, edited at `/alerts/rules`
+    alert_rules: ThresholdRules,
+    /// Rounds the indicators in the JSON responses
+    numbers: Arc<NumberFormat>,
+    /// Metadata of the JSON responses
+    freshness: Freshness,
+    /// Calls to the buffers the handlers wait on
+    buffer_calls: InFlight,
+    /// Answers with the history over the hot and cold storage tiers
+    tiering: Addr<Tiering>,
+}
+
+#[message]
+#[derive(Debug, Default, Clone)]
+pub struct Quotes {
+    pub symbol: String,
+    pub quotes: Vec<TickerQuote>,
+    pub watchlist: Option<String>,
+}
+
+#[message]
+#[derive(Debug, Clone)]
+pub struct QuoteRequest {
+    pub symbol: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// The watchlist that requested the data, `None` for the default pipeline
+    pub watchlist: Option<String>,
+}
+
+///
+/// Performance indicators of a stock data time series
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PerformanceIndicators {
+    pub symbol: String,
+    pub timestamp: DateTime<Utc>,
+    pub price: f64,
+    pub pct_change: f64,
+    pub period_min: f64,
+    pub period_max: f64,
+    pub last_sma: f64,
+    /// Last exponential moving average, 0 until the series fills a period
+    #[serde(default)]
+    pub last_ema: f64,
+    /// Last relative strength index (0 to 100), `None` until there are enough price changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rsi: Option<f64>,
+    /// Last annualized standard deviation of the log returns, `None` until the series fills a
+    /// window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volatility: Option<f64>,
+    /// Composite of momentum, volatility, and trend from 0 to 100, weighted as configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// Last values of the signals provided by plugins, by plugin name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom: BTreeMap<String, f64>,
+    /// The watchlist these indicators were calculated for, `None` for the default pipeline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watchlist: Option<String>,
+    /// The bar size the quotes were resampled to (e.g. `1h`), `None` for the fetched quotes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<String>,
+    /// Currency code of the prices, `None` for dollars
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Highest high of the trailing 52 weeks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub high_52w: Option<f64>,
+    /// Lowest low of the trailing 52 weeks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_52w: Option<f64>,
+    /// Relative distance of the price from the 52-week high (zero or negative)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pct_from_high_52w: Option<f64>,
+    /// Relative distance of the price from the 52-week low (zero or positive)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pct_from_low_52w: Option<f64>,
+    /// First open of the latest session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_open: Option<f64>,
+    /// Volume weighted average price since the open of the latest session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vwap_session: Option<f64>,
+    /// Opening gap of the latest session relative to the previous close
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gap_pct: Option<f64>,
+    /// Change of the price against the close of the previous session ("today's change"),
+    /// unlike `pct_change` which covers the full range
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_from_prev_close: Option<f64>,
+    /// Company name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exchange: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sector: Option<String>,
+    /// Number of older rows of the symbol folded into this one by a full buffer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rolled_up: Option<u32>,
+    /// Calculated for a bar before the latest one of a fetch, with `--backfill-history` or
+    /// `--per-bar`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub historical: bool,
+}
+
+impl PerformanceIndicators {
+    ///
+    /// Formats a price in the symbol's currency, e.g. `$1.00` or `1.00 EUR`
+    ///
+    pub fn money(&self, value: f64) -> String {
+        match &self.currency {
+            Some(currency) => format!("{:.2} {}", value, currency),
+            None => format!("${:.2}", value),
+        }
+    }
+
+    ///
+    /// Sets the 52-week range and the distance of the price from it
+    ///
+    pub fn set_year_range(&mut self, range: &YearRange) {
+        self.high_52w = range.high();
+        self.low_52w = range.low();
+        let price = self.price;
+        let distance = |extreme: f64| (extreme != 0.0).then(|| price / extreme - 1.0);
+        self.pct_from_high_52w = self.high_52w.and_then(distance);
+        self.pct_from_low_52w = self.low_52w.and_then(distance);
+    }
+
+    ///
+    /// Adds the descriptive fields of the symbol
+    ///
+    pub fn set_metadata(&mut self, metadata: &SymbolMetadata) {
+        self.name = metadata.name.clone();
+        self.exchange = metadata.exchange.clone();
+        self.sector = metadata.sector.clone();
+    }
+}
+
+///
+/// Actor that downloads stock data for a specified symbol and period from a data provider
+///
+struct StockDataDownloader<P> {
+    provider: P,
+    /// Leave out the latest bar while it's still forming
+    complete_bars: bool,
+    /// The bar interval of every symbol, as seen in earlier responses
+    intervals: HashMap<String, u64>,
+    /// Suspends symbols that fail over and over
+    breaker: CircuitBreaker,
+    /// Retries network errors
+    retry: RetryPolicy,
+    jitter: Jitter,
+    /// Shared by the downloaders of the provider
+    limiter: Option<Addr<RateLimiter>>,
+}
+
+impl<P: DataProvider> StockDataDownloader<P> {
+    fn new(provider: P, complete_bars: bool, breaker: CircuitBreaker, retry: RetryPolicy) -> Self {
+        StockDataDownloader {
+            provider,
+            complete_bars,
+            intervals: HashMap::new(),
+            breaker,
+            retry,
+            jitter: Jitter::default(),
+            limiter: None,
+        }
+    }
+
+    fn rate_limited(mut self, limiter: Option<Addr<RateLimiter>>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+}
+
+impl<P: DataProvider> StockDataDownloader<P> {
+    ///
+    /// Fetches the quotes of a request (retrying network errors) and publishes them
+    ///
+    #[tracing::instrument(
+        name = "fetch",
+        skip_all,
+        fields(symbol = %msg.symbol, provider = %self.provider.name(), watchlist = msg.watchlist.as_deref())
+    )]
+    async fn fetch(&mut self, msg: QuoteRequest) {
+        let symbol = msg.symbol.clone();
+        let provider = self.provider.name().to_string();
+        if !self.breaker.allows(&symbol, msg.to) {
+            return;
+        }
+
+        let mut attempt = 1;
+        let (result, status, started) = loop {
+            rate_limit::acquire(&self.limiter).await;
+            let started = Instant::now();
+            let result = self
+                .provider
+                .fetch_quotes(&msg.symbol, msg.from, msg.to)
+                .await;
+            let status = FetchStatus::from_result(&result);
+            if !matches!(status, FetchStatus::NetworkError(_)) || attempt >= self.retry.attempts {
+                break (result, status, started);
+            }
+            // every attempt counts against the quota
+            quota::record(QuotaUsage {
+                provider: provider.clone(),
+                remaining: None,
+                rate_limited: false,
+            })
+            .await;
+            let delay = self.retry.delay(attempt, self.jitter.sample());
+            tracing::warn!(
+                "Fetching '{}' failed: {}, retrying in {:?}",
+                symbol,
+                status,
+                delay
+            );
+            async_std::task::sleep(delay).await;
+            attempt += 1;
+        };
+        // unknown symbols and rate limits are dealt with elsewhere
+        let succeeded = match status {
+            FetchStatus::Fetched { .. } | FetchStatus::EmptyRange => Some(true),
+            FetchStatus::NetworkError(_) => Some(false),
+            FetchStatus::NotFound | FetchStatus::RateLimited => None,
+        };
+        if let Some(succeeded) = succeeded {
+            if let Some(suspended) = self.breaker.record(&symbol, &provider, succeeded, msg.to) {
+                tracing::warn!(
+                    "Fetching {} failed {} times in a row, suspended until {}",
+                    symbol,
+                    suspended.failures,
+                    suspended.until
+                );
+                if let Err(e) = Broker::from_registry().await.unwrap().publish(suspended) {
+                    tracing::error!("{}", e);
+                }
+            }
+        }
+        quota::record(QuotaUsage {
+            provider: provider.clone(),
+            remaining: None,
+            rate_limited: status == FetchStatus::RateLimited,
+        })
+        .await;
+        metrics::record(Observation::duration(
+            Stage::ProviderLatency,
+            &symbol,
+            started.elapsed(),
+        ))
+        .await;
+        metrics::record(Observation {
+            stage: Stage::QuotesPerResponse,
+            symbol: symbol.clone(),
+            value: status.bars() as f64,
+        })
+        .await;
+        let result = match result {
+            Ok(mut quotes) if self.complete_bars => {
+                // the forming bar is fetched again once it's complete, since the checkpoint
+                // stays before it
+                let known = self.intervals.get(&symbol).copied();
+                if let Some(interval) = fetch::drop_forming_bar(&mut quotes, msg.to, known) {
+                    self.intervals.insert(symbol.clone(), interval);
+                }
+                Ok(quotes)
+            }
+            result => result,
+        };
+        match result {
+            Ok(quotes) if !quotes.is_empty() => {
+                tracing::debug!(bars = quotes.len(), "Fetched");
+                let data = Quotes {
+                    symbol: symbol.clone(),
+                    quotes,
+                    watchlist: msg.watchlist.clone(),
+                };
+                if let Err(e) = Broker::from_registry().await.unwrap().publish(data) {
+                    tracing::error!("{}", e);
+                }
+            }
+            Ok(_) => {}
+            Err(_) => tracing::warn!("Fetching '{}' failed: {}", symbol, status),
+        }
+        if let FetchStatus::NetworkError(error) = &status {
+            let dead = DeadLetter {
+                symbol: symbol.clone(),
+                watchlist: msg.watchlist.clone(),
+                provider: provider.clone(),
+                from: msg.from,
+                to: msg.to,
+                attempts: attempt,
+                error: error.clone(),
+            };
+            if let Err(e) = Broker::from_registry().await.unwrap().publish(dead) {
+                tracing::error!("{}", e);
+            }
+        }
+        fetch::record(FetchOutcome {
+            symbol,
+            watchlist: msg.watchlist,
+            provider,
+            status,
+        })
+        .await;
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: DataProvider> Handler<QuoteRequest> for StockDataDownloader<P> {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: QuoteRequest) {
+        self.fetch(msg).await;
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: DataProvider> Actor for StockDataDownloader<P> {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crash::track_start::<Self>(ctx.actor_id());
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: DataProvider> Handler<Drain> for StockDataDownloader<P> {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Drain) {}
+}
+
+///
+/// Starts the rate limiter of a provider, if there is a limit
+///
+async fn start_rate_limiter(per_minute: Option<u32>) -> Result<Option<Addr<RateLimiter>>> {
+    match per_minute {
+        Some(per_minute) => Ok(Some(
+            Supervisor::start(move || RateLimiter::new(per_minute)).await?,
+        )),
+        None => Ok(None),
+    }
+}
+
+///
+/// Starts a pool of downloaders, each fetching one symbol at a time
+///
+async fn start_downloaders<P, F>(
+    concurrency: usize,
+    create: F,
+) -> Result<Vec<Addr<StockDataDownloader<P>>>>
+where
+    P: DataProvider,
+    F: Fn() -> StockDataDownloader<P> + Clone + Send + 'static,
+{
+    let mut pool = vec![];
+    for _ in 0..concurrency.max(1) {
+        pool.push(Supervisor::start(create.clone()).await?);
+    }
+    Ok(pool)
+}
+
+///
+/// Actor to create performance indicators from incoming stock data
+///
+#[derive(Default)]
+struct StockDataProcessor {
+    plugins: Vec<SignalPlugin>,
+    scripts: Vec<Script>,
+    engine: rhai::Engine,
+    /// Signals of the default pipeline
+    default_signals: SignalSet,
+    /// Signals of the named watchlists
+    signal_sets: HashMap<String, SignalSet>,
+    /// Per-symbol settings from the config file
+    overrides: HashMap<String, SymbolConfig>,
+    /// Everything received so far (or the trailing window), signals are calculated over it
+    history: QuoteStore,
+    /// Adds a `zscore` indicator and publishes `Anomaly`s, if set
+    anomaly: Option<AnomalyDetector>,
+    /// Opening gaps of at least this size are published as `GapEvent`s
+    gap_threshold: f64,
+    /// Metadata of the symbols, as far as it's known
+    metadata: HashMap<String, SymbolMetadata>,
+    /// Weights of the composite score
+    score: ScoreWeights,
+    /// Spreads of the configured symbol pairs
+    pairs: PairMonitor,
+    /// Format of the rows printed to the console, if they are
+    console: Option<CsvSchema>,
+    /// Calculate the indicators for every bar of the first batch of a symbol
+    backfill_history: bool,
+    /// Calculate the indicators for every new bar of a batch
+    per_bar: bool,
+}
+
+impl StockDataProcessor {
+    ///
+    /// The signals and the currency of a symbol in a watchlist (`None` for the default
+    /// pipeline)
+    ///
+    fn settings(&self, symbol: &str, watchlist: Option<&str>) -> (SignalSet, Option<String>) {
+        let mut signals = watchlist
+            .and_then(|w| self.signal_sets.get(w))
+            .unwrap_or(&self.default_signals)
+            .clone();
+        let overrides = self.overrides.get(symbol);
+        if let Some(overrides) = overrides {
+            signals = signals.with_overrides(overrides);
+        }
+        (signals, overrides.and_then(|o| o.currency.clone()))
+    }
+
+    ///
+    /// Calculates the indicators over a series of quotes (sorted by time, not empty)
+    ///
+    async fn indicators(
+        &self,
+        symbol: &str,
+        data: &[TickerQuote],
+        signals: &SignalSet,
+    ) -> PerformanceIndicators {
+        self.indicator_series(symbol, data, &[data.len()], signals)
+            .await
+            .pop()
+            .unwrap()
+    }
+
+    ///
+    /// Calculates the indicators over the first `end` bars for every end in `ends` (ascending,
+    /// at least 1), in one pass over the bars. Plugins and scripts are opaque and still see
+    /// every prefix on its own.
+    ///
+    async fn indicator_series(
+        &self,
+        symbol: &str,
+        bars: &[TickerQuote],
+        ends: &[usize],
+        signals: &SignalSet,
+    ) -> Vec<PerformanceIndicators> {
+        let closes: Vec<f64> = bars.iter().map(|q| q.close).collect();
+        // each series over all bars, the value over a prefix is the one at the prefix's end
+        let sma = WindowedSMA {
+            window_size: signals.sma_window,
+        }
+        .calculate(&closes)
+        .await
+        .unwrap_or_default();
+        let ema = ExponentialMovingAverage {
+            period: signals.ema_period,
+            smoothing: signals.ema_smoothing,
+        }
+        .calculate(&closes)
+        .await
+        .unwrap_or_default();
+        let rsi = RelativeStrengthIndex {
+            period: signals.rsi_period,
+        }
+        .calculate(&closes)
+        .await
+        .unwrap_or_default();
+        let volatility = RollingVolatility {
+            window: signals.volatility_window,
+            ..Default::default()
+        }
+        .calculate(&closes)
+        .await
+        .unwrap_or_default();
+
+        let mut rows = Vec::with_capacity(ends.len());
+        let mut ends = ends.iter().copied().peekable();
+        let (mut period_min, mut period_max) = (f64::MAX, f64::MIN);
+        // log returns so far (prices of 0 or below have none), see `RollingVolatility`
+        let mut returns: usize = 0;
+        // first bar of the latest session, and the volume and turnover since then
+        let (mut session, mut volume, mut traded) = (0, 0.0, 0.0);
+        for (i, bar) in bars.iter().enumerate() {
+            if ends.peek().is_none() {
+                break;
+            }
+            period_min = period_min.min(bar.close);
+            period_max = period_max.max(bar.close);
+            if i > 0 {
+                let previous = &bars[i - 1];
+                if previous.close > 0.0 && bar.close > 0.0 {
+                    returns += 1;
+                }
+                if previous.timestamp / gap::SESSION != bar.timestamp / gap::SESSION {
+                    session = i;
+                    volume = 0.0;
+                    traded = 0.0;
+                }
+            }
+            volume += bar.volume as f64;
+            traded += (bar.high + bar.low + bar.close) / 3.0 * bar.volume as f64;
+
+            let n = i + 1;
+            if ends.peek() != Some(&n) {
+                continue;
+            }
+            let first = if closes[0] == 0.0 { 1.0 } else { closes[0] };
+            let gap = session
+                .checked_sub(1)
+                .and_then(|previous| gap::session_gaps(&bars[previous..=session]).pop());
+            let mut custom = BTreeMap::new();
+            for plugin in self.plugins.iter().filter(|p| signals.includes(&p.name)) {
+                if let Some(last) = plugin
+                    .calculate(&closes[..n])
+                    .await
+                    .and_then(|v| v.last().copied())
+                {
+                    custom.insert(plugin.name.clone(), last);
+                }
+            }
+            for script in self.scripts.iter().filter(|s| signals.includes(&s.name)) {
+                match script.indicator(&self.engine, symbol, &bars[..n]) {
+                    Ok(value) => {
+                        custom.insert(script.name.clone(), value);
+                    }
+                    Err(e) => {
+                        tracing::error!("Indicator '{}' failed for {}: {}", script.name, symbol, e)
+                    }
+                }
+            }
+            let mut data = PerformanceIndicators {
+                // quotes out of range are dropped before, see `drop_out_of_range`
+                timestamp: quote_time(bar.timestamp).unwrap_or_default(),
+                symbol: symbol.to_string(),
+                price: bar.close,
+                pct_change: (bar.close - closes[0]) / first,
+                period_min,
+                period_max,
+                last_sma: n
+                    .checked_sub(signals.sma_window)
+                    .and_then(|k| sma.get(k))
+                    .copied()
+                    .unwrap_or(0.0),
+                last_ema: n
+                    .checked_sub(signals.ema_period)
+                    .and_then(|k| ema.get(k))
+                    .copied()
+                    .unwrap_or(0.0),
+                rsi: (n - 1)
+                    .checked_sub(signals.rsi_period)
+                    .and_then(|k| rsi.get(k))
+                    .copied(),
+                volatility: returns
+                    .checked_sub(signals.volatility_window)
+                    .and_then(|k| volatility.get(k))
+                    .copied(),
+                custom,
+                session_open: Some(bars[session].open),
+                vwap_session: Some(traded / volume).filter(|_| volume > 0.0),
+                gap_pct: gap.map(|g| g.gap),
+                change_from_prev_close: gap.map(|g| bar.close / g.prev_close - 1.0),
+                ..Default::default()
+            };
+            data.score = score::composite(&self.score, &data, score::volatility(&closes[..n]));
+            // with renko bricks several ends can be the same
+            while ends.next_if_eq(&n).is_some() {
+                rows.push(data.clone());
+            }
+        }
+        rows
+    }
+}
+
+impl StockDataProcessor {
+    ///
+    /// Calculates and publishes the indicators of a fetched batch
+    ///
+    #[tracing::instrument(
+        name = "indicators",
+        skip_all,
+        fields(symbol = %msg.0.symbol, watchlist = msg.0.watchlist.as_deref(), bars = msg.0.quotes.len())
+    )]
+    async fn process(&mut self, msg: CleanQuotes) {
+        let mut msg = msg.0;
+        drop_out_of_range(&msg.symbol, &mut msg.quotes);
+        if !msg.quotes.is_empty() {
+            let started = Instant::now();
+            // ensure that the data is sorted by time (asc)
+            msg.quotes.sort_by_cached_key(|k| k.timestamp);
+            let cold_start = self.backfill_history && !self.history.contains(&msg.symbol);
+            let added = self.history.append(&msg.symbol, &msg.quotes);
+            let history = self.history.quotes(&msg.symbol);
+            let (signals, currency) = self.settings(&msg.symbol, msg.watchlist.as_deref());
+            let bars = signals.candles.apply(&history);
+
+            let year_range = self.history.year_range(&msg.symbol).cloned();
+            let mut data = self.indicators(&msg.symbol, &bars, &signals).await;
+            data.watchlist = msg.watchlist.clone();
+            data.currency = currency.clone();
+            if let Some(range) = &year_range {
+                data.set_year_range(range);
+            }
+            let metadata = self.metadata.get(&msg.symbol);
+            if let Some(metadata) = metadata {
+                data.set_metadata(metadata);
+            }
+            // the rows of the bars before the latest one, each over the history up to the bar
+            let earlier = if cold_start {
+                history.len() - 1
+            } else if self.per_bar {
+                added.saturating_sub(1)
+            } else {
+                0
+            };
+            let mut backfill = vec![];
+            if earlier > 0 {
+                let first = history.len() - 1 - earlier;
+                // the bars (renko bricks may span several quotes) up to each earlier quote
+                let mut ends = Vec::with_capacity(earlier);
+                let mut end = 0;
+                for quote in &history[first..history.len() - 1] {
+                    while end < bars.len() && bars[end].timestamp <= quote.timestamp {
+                        end += 1;
+                    }
+                    ends.push(end);
+                }
+                let rows = self
+                    .indicator_series(&msg.symbol, &bars, &ends, &signals)
+                    .await;
+                let mut range = YearRange::default();
+                for quote in &history[..first] {
+                    range.push(quote);
+                }
+                for (quote, mut indicators) in history[first..].iter().zip(rows) {
+                    range.push(quote);
+                    indicators.watchlist = msg.watchlist.clone();
+                    indicators.currency = currency.clone();
+                    indicators.set_year_range(&range);
+                    if let Some(metadata) = metadata {
+                        indicators.set_metadata(metadata);
+                    }
+                    indicators.historical = true;
+                    backfill.push(indicators);
+                }
+            }
+            if let Some(detector) = &self.anomaly {
+                let closes: Vec<f64> = bars.iter().map(|q| q.close).collect();
+                if let Some(zscore) = detector.zscore(&closes).await {
+                    data.custom.insert("zscore".to_string(), zscore);
+                    if detector.is_anomaly(zscore) {
+                        let anomaly = Anomaly {
+                            symbol: msg.symbol.clone(),
+                            watchlist: msg.watchlist.clone(),
+                            timestamp: data.timestamp,
+                            zscore,
+                        };
+                        if let Err(e) = Broker::from_registry().await.unwrap().publish(anomaly) {
+                            tracing::error!("{}", e);
+                        }
+                    }
+                }
+            }
+            for gap in gap::session_gaps(&history)
+                .into_iter()
+                .filter(|g| g.timestamp >= msg.quotes[0].timestamp)
+                .filter(|g| g.gap.abs() >= self.gap_threshold)
+            {
+                let event = GapEvent {
+                    symbol: msg.symbol.clone(),
+                    watchlist: msg.watchlist.clone(),
+                    timestamp: Utc.timestamp_opt(gap.timestamp as i64, 0).unwrap(),
+                    prev_close: gap.prev_close,
+                    open: gap.open,
+                    gap: gap.gap,
+                };
+                tracing::info!(
+                    "GAP {} opened {:+.2}% at {}",
+                    event.symbol,
+                    event.gap * 100.0,
+                    data.money(event.open)
+                );
+                if let Err(e) = Broker::from_registry().await.unwrap().publish(event) {
+                    tracing::error!("{}", e);
+                }
+            }
+            let mut resampled = vec![];
+            let cascaded = resample::cascade(&history, &signals.resolutions);
+            for (resolution, resampled_bars) in signals.resolutions.iter().zip(cascaded) {
+                let resampled_bars = signals.candles.apply(&resampled_bars);
+                let mut indicators = self
+                    .indicators(&msg.symbol, &resampled_bars, &signals)
+                    .await;
+                indicators.watchlist = msg.watchlist.clone();
+                indicators.resolution = Some(resolution.label.clone());
+                indicators.currency = currency.clone();
+                if let Some(range) = &year_range {
+                    indicators.set_year_range(range);
+                }
+                if let Some(metadata) = metadata {
+                    indicators.set_metadata(metadata);
+                }
+                resampled.push(indicators);
+            }
+            metrics::record(Observation::duration(
+                Stage::SignalComputation,
+                &msg.symbol,
+                started.elapsed(),
+            ))
+            .await;
+
+            if cfg!(debug_assertions) {
+                for indicators in backfill.iter().chain([&data]).chain(&resampled) {
+                    for violation in invariants::violations(indicators) {
+                        tracing::error!(
+                            "Invariant violated for {}: {}",
+                            indicators.symbol,
+                            violation
+                        );
+                    }
+                }
+            }
+            if let Some(console) = &self.console {
+                for indicators in backfill.iter().chain([&data]) {
+                    println!("{}", console.format(indicators));
+                }
+            }
+            tracing::debug!(
+                rows = backfill.len() + 1 + resampled.len(),
+                "Calculated the indicators"
+            );
+            let mut broker = Broker::from_registry().await.unwrap();
+            for indicators in backfill.into_iter().chain([data]).chain(resampled) {
+                if let Err(e) = broker.publish(indicators) {
+                    tracing::error!("{}", e);
+                }
+            }
+            for (spread, diverged) in self.pairs.update(&msg.symbol, &self.history).await {
+                if diverged {
+                    let alert = Alert {
+                        rule: "pair_divergence".to_string(),
+                        symbol: spread.pair.clone(),
+                        timestamp: spread.timestamp,
+                        message: format!(
+                            "{} diverged: ratio {:.4} at a Z-score of {:.2}",
+                            spread.pair,
+                            spread.ratio,
+                            spread.zscore.unwrap_or_default()
+                        ),
+                    };
+                    tracing::info!("ALERT {}", alert.message);
+                    if let Err(e) = Broker::from_registry().await.unwrap().publish(alert) {
+                        tracing::error!("{}", e);
+                    }
+                }
+                if let Err(e) = Broker::from_registry().await.unwrap().publish(spread) {
+                    tracing::error!("{}", e);
+                }
+            }
+        } else {
+            tracing::debug!("Got nothing");
+        }
+    }
+}
+
+///
+/// The time of a quote's timestamp, if it is one
+///
+fn quote_time(timestamp: u64) -> Option<DateTime<Utc>> {
+    let seconds = i64::try_from(timestamp).ok()?;
+    Utc.timestamp_opt(seconds, 0).single()
+}
+
+///
+/// Drops the quotes whose timestamp can't be a date, e.g. from a corrupt file
+///
+pub(crate) fn drop_out_of_range(symbol: &str, quotes: &mut Vec<TickerQuote>) {
+    quotes.retain(|quote| {
+        let valid = quote_time(quote.timestamp).is_some();
+        if !valid {
+            tracing::warn!(
+                "Skipping a quote of {} with the invalid timestamp {}",
+                symbol,
+                quote.timestamp
+            );
+        }
+        valid
+    });
+}
+
+#[async_trait::async_trait]
+impl Handler<CleanQuotes> for StockDataProcessor {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: CleanQuotes) {
+        self.process(msg).await;
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for StockDataProcessor {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<SymbolMetadata>().await?;
+        ctx.subscribe::<CleanQuotes>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<SymbolMetadata> for StockDataProcessor {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: SymbolMetadata) {
+        self.metadata.insert(msg.symbol.clone(), msg);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<VolatilityRequest> for StockDataProcessor {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: VolatilityRequest,
+    ) -> Option<VolatilityCone> {
+        let history = self.history.quotes(&msg.0);
+        let last = history.last()?;
+        let closes: Vec<f64> = history.iter().map(|q| q.close).collect();
+        Some(VolatilityCone {
+            as_of: Utc.timestamp_opt(last.timestamp as i64, 0).unwrap(),
+            windows: volatility::cone(&closes, &volatility::WINDOWS).await,
+            symbol: msg.0,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<HistorySnapshotRequest> for StockDataProcessor {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: HistorySnapshotRequest,
+    ) -> SavedHistory {
+        self.history.save()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<HistoryRestore> for StockDataProcessor {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: HistoryRestore) {
+        self.history.restore(msg.history);
+    }
+}
+
+///
+/// Answered once the quotes that arrived before are calculated and published
+///
+#[async_trait::async_trait]
+impl Handler<Shutdown> for StockDataProcessor {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {}
+}
+
+///
+/// Everything needed to create a `StockDataProcessor`
+///
+#[derive(Clone, Default)]
+struct ProcessorConfig {
+    plugins: Vec<SignalPlugin>,
+    scripts: Vec<Script>,
+    default_signals: SignalSet,
+    signal_sets: HashMap<String, SignalSet>,
+    overrides: HashMap<String, SymbolConfig>,
+    anomaly: Option<AnomalyDetector>,
+    gap_threshold: f64,
+    score: ScoreWeights,
+    pairs: Vec<Pair>,
+    numbers: NumberFormat,
+    backfill_history: bool,
+    history_window: Option<Duration>,
+    per_bar: bool,
+    /// Print the rows to stdout
+    console: bool,
+}
+
+impl ProcessorConfig {
+    ///
+    /// The history the longest window of any watchlist or symbol needs
+    ///
+    fn lookback(&self) -> Duration {
+        std::iter::once(&self.default_signals)
+            .chain(self.signal_sets.values())
+            .flat_map(|set| {
+                std::iter::once(set.clone()).chain(
+                    self.overrides
+                        .values()
+                        .map(move |o| set.clone().with_overrides(o)),
+                )
+            })
+            .map(|set| set.lookback())
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn processor(&self) -> StockDataProcessor {
+        StockDataProcessor {
+            plugins: self.plugins.clone(),
+            scripts: self.scripts.clone(),
+            engine: script::engine(),
+            default_signals: self.default_signals.clone(),
+            signal_sets: self.signal_sets.clone(),
+            overrides: self.overrides.clone(),
+            history: QuoteStore::with_window(self.history_window),
+            anomaly: self.anomaly.clone(),
+            gap_threshold: self.gap_threshold,
+            metadata: HashMap::new(),
+            score: self.score,
+            pairs: PairMonitor::new(self.pairs.clone()),
+            console: Some(CsvSchema::default().with_numbers(self.numbers.clone()))
+                .filter(|_| self.console),
+            backfill_history: self.backfill_history,
+            per_bar: self.per_bar,
+        }
+    }
+}
+
+///
+/// The HTTP API with all routes and middleware
+///
+fn server(state: State) -> tide::Server<State> {
+    let cache = state.cache.clone();
+    let limit = state.limit.clone();
+    let mut app = tide::with_state(state);
+    app.with(tide::log::LogMiddleware::new());
+    app.with(AuditMiddleware);
+    app.with(MetricsMiddleware);
+    // cached responses don't count against the limit
+    app.at("/tail/:n")
+        .with(cache.clone())
+        .with(limit.clone())
+        .get(tail);
+    app.at("/drain/:n").with(limit.clone()).post(drain);
+    app.at("/stream").get(stream);
+    app.at("/ws").get(websocket);
+    app.at("/metrics").get(prometheus);
+    app.at("/audit").get(audit_trail);
+    app.at("/quality").get(data_quality);
+    app.at("/quota").get(provider_quota);
+    app.at("/leaderboard")
+        .with(cache.clone())
+        .with(limit.clone())
+        .get(top_symbols);
+    app.at("/symbols").get(symbol_list).post(add_symbol);
+    app.at("/symbols/:symbol").delete(remove_symbol);
+    app.at("/changes/:symbol").get(symbol_changes);
+    app.at("/history/:symbol")
+        .with(limit.clone())
+        .get(symbol_history);
+    app.at("/volatility/:symbol").get(volatility_cone);
+    app.at("/symbols/:symbol/latest")
+        .with(cache.clone())
+        .with(limit.clone())
+        .get(symbol_latest);
+    app.at("/groups").with(limit.clone()).get(group_list);
+    app.at("/groups/:name").with(limit.clone()).get(group);
+    app.at("/backfill/status").get(backfill_status);
+    app.at("/trailing-stops").get(trailing_stops);
+    app.at("/alerts/rules")
+        .get(alert_rule_list)
+        .post(add_alert_rule);
+    app.at("/alerts/rules/:name").delete(remove_alert_rule);
+    app.at("/admin/provider")
+        .get(provider_assignments)
+        .post(switch_provider);
+    app.at("/watchlists").get(watchlists);
+    app.at("/watchlists/:name/tail/:n")
+        .with(cache)
+        .with(limit.clone())
+        .get(watchlist_tail);
+    app.at("/watchlists/:name/drain/:n")
+        .with(limit.clone())
+        .post(watchlist_drain);
+    app.at("/download/:file").with(limit).get(download);
+    app
+}
+
+/// REST handler
+///
+async fn tail(req: Request<State>) -> tide::Result {
+    let amount: usize = req.param("n")?.parse()?;
+    buffered(
+        &req,
+        &req.state().buffer,
+        BufferDataRequest::Peek { n: amount },
+    )
+    .await
+}
+
+///
+/// Like `/tail/:n`, but removes the oldest `n` indicators from the buffer and returns them
+///
+async fn drain(req: Request<State>) -> tide::Result {
+    let amount: usize = req.param("n")?.parse()?;
+    buffered(
+        &req,
+        &req.state().buffer,
+        BufferDataRequest::Drain { n: amount },
+    )
+    .await
+}
+
+///
+/// Serves the indicators a buffer returns for the request, with the time the call took
+///
+async fn buffered<M>(req: &Request<State>, buffer: &Addr<BufferSink>, request: M) -> tide::Result
+where
+    M: Message<Result = Vec<PerformanceIndicators>>,
+    BufferSink: Handler<M>,
+{
+    let (call, queued) = req.state().buffer_calls.enter();
+    let started = Instant::now();
+    let mut data = buffer.call(request).await?;
+    let elapsed = started.elapsed();
+    drop(call);
+    data.iter_mut()
+        .for_each(|row| req.state().numbers.round(row));
+    let mut response = req.state().freshness.json(&data)?;
+    req.state().freshness.trace(&mut response, elapsed, queued);
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+struct StreamQuery {
+    symbol: Option<String>,
+}
+
+///
+/// Sends every new row of indicators as a Server-Sent Event, e.g. `/stream?symbol=AAPL`
+///
+async fn stream(req: Request<State>) -> tide::Result {
+    let query: StreamQuery = req.query()?;
+    let rows = req
+        .state()
+        .broadcaster
+        .call(Connect {
+            symbol: query.symbol,
+        })
+        .await?;
+    let numbers = req.state().numbers.clone();
+    let events = broadcast::events(rows, move |mut row| {
+        numbers.round(&mut row);
+        serde_json::to_string(&row)
+    });
+    let reader = Box::pin(events).into_async_read();
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_content_type(tide::http::mime::SSE);
+    response.insert_header("Cache-Control", "no-cache");
+    response.set_body(tide::Body::from_reader(reader, None));
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+struct WebSocketQuery {
+    symbols: Option<String>,
+}
+
+///
+/// Pushes new indicators over a WebSocket, to the symbols the client subscribes to, e.g.
+/// `/ws?symbols=AAPL,MSFT`
+///
+async fn websocket(req: Request<State>) -> tide::Result {
+    let query: WebSocketQuery = req.query()?;
+    let rows = req
+        .state()
+        .broadcaster
+        .call(Connect { symbol: None })
+        .await?;
+    let client = websocket::Client {
+        rows,
+        numbers: req.state().numbers.clone(),
+        subscriptions: Subscriptions::parse(query.symbols.as_deref().unwrap_or_default()),
+    };
+    websocket::upgrade(&req, &req.state().executor, client).await
+}
+
+///
+/// Lists the known symbols with their metadata
+///
+async fn symbol_list(req: Request<State>) -> tide::Result {
+    let symbols = req.state().symbols.call(SymbolsRequest).await?;
+    req.state().freshness.json(&symbols)
+}
+
+///
+/// Starts fetching a symbol in the default pipeline, e.g. `{"symbol": "NVDA"}`
+///
+async fn add_symbol(mut req: Request<State>) -> tide::Result {
+    let mut add: AddSymbol = req.body_json().await?;
+    if add.symbol.trim().is_empty() {
+        let mut response = Response::new(StatusCode::BadRequest);
+        response.set_body("The symbol is empty");
+        return Ok(response);
+    }
+    add.symbol = req.state().aliases.resolve(&add.symbol);
+    let changes = req.state().registry.call(add).await?;
+    req.state().freshness.json(&changes)
+}
+
+///
+/// The `:symbol` of the path, with an alias replaced by its ticker
+///
+fn symbol_param(req: &Request<State>) -> tide::Result<String> {
+    Ok(req.state().aliases.resolve(req.param("symbol")?))
+}
+
+///
+/// Stops fetching a symbol in the default pipeline
+///
+async fn remove_symbol(req: Request<State>) -> tide::Result {
+    let symbol = symbol_param(&req)?;
+    let changes = req.state().registry.call(RemoveSymbol { symbol }).await?;
+    req.state().freshness.json(&changes)
+}
+
+///
+/// Serves the latest indicators of a symbol in the default pipeline
+///
+async fn symbol_latest(req: Request<State>) -> tide::Result {
+    let symbol = symbol_param(&req)?;
+    match req.state().buffer.call(LatestRequest { symbol }).await? {
+        Some(mut latest) => {
+            req.state().numbers.round(&mut latest);
+            req.state().freshness.json(&latest)
+        }
+        None => Ok(Response::new(StatusCode::NotFound)),
+    }
+}
+
+#[derive(Deserialize)]
+struct ChangesQuery {
+    since: Option<DateTime<Utc>>,
+}
+
+///
+/// Serves the buffered indicators of a symbol newer than a timestamp, e.g.
+/// `/changes/AAPL?since=2024-01-02T14:30:00Z`, for clients that poll
+///
+async fn symbol_changes(req: Request<State>) -> tide::Result {
+    let query: ChangesQuery = req.query()?;
+    let request = ChangesRequest {
+        symbol: symbol_param(&req)?,
+        since: query.since,
+    };
+    buffered(&req, &req.state().buffer, request).await
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    watchlist: Option<String>,
+    resolution: Option<String>,
+}
+
+///
+/// Serves the indicators of a symbol from the hot and cold storage tiers, oldest first, e.g.
+/// `/history/AAPL?from=2024-01-01T00:00:00Z&to=2024-02-01T00:00:00Z&resolution=1h`
+///
+async fn symbol_history(req: Request<State>) -> tide::Result {
+    let query: HistoryQuery = req.query()?;
+    let request = HistoryRequest {
+        symbol: symbol_param(&req)?,
+        from: query.from,
+        to: query.to,
+        watchlist: query.watchlist,
+        resolution: query.resolution,
+    };
+    let mut rows = req.state().tiering.call(request).await??;
+    rows.iter_mut()
+        .for_each(|row| req.state().numbers.round(row));
+    req.state().freshness.json(&rows)
+}
+
+///
+/// Serves the realized volatility of a symbol over 10, 20, 60, and 120 bars, each with its
+/// range over the history, e.g. `/volatility/AAPL`
+///
+async fn volatility_cone(req: Request<State>) -> tide::Result {
+    let symbol = symbol_param(&req)?;
+    match req
+        .state()
+        .processor
+        .call(VolatilityRequest(symbol))
+        .await?
+    {
+        Some(cone) => req.state().freshness.json(&cone),
+        None => Ok(Response::new(StatusCode::NotFound)),
+    }
+}
+
+///
+/// Serves the threshold alert rules
+///
+async fn alert_rule_list(req: Request<State>) -> tide::Result {
+    let rules = req.state().alert_rules.read().unwrap().clone();
+    req.state().freshness.json(&rules)
+}
+
+#[derive(Deserialize)]
+struct AddAlertRule {
+    /// The name of the alerts, the rule itself by default
+    name: Option<String>,
+    rule: String,
+}
+
+///
+/// Adds a threshold alert rule, e.g. `{"name": "apple_drop", "rule": "AAPL pct_change < -0.05"}`
+///
+async fn add_alert_rule(mut req: Request<State>) -> tide::Result {
+    let add: AddAlertRule = req.body_json().await?;
+    let mut rule: ThresholdRule = match add.rule.parse() {
+        Ok(rule) => rule,
+        Err(e) => {
+            let mut response = Response::new(StatusCode::BadRequest);
+            response.set_body(e.to_string());
+            return Ok(response);
+        }
+    };
+    if let Some(symbol) = &mut rule.symbol {
+        *symbol = req.state().aliases.resolve(symbol);
+    }
+    let name = match add.name {
+        Some(name) if !name.trim().is_empty() => name.trim().to_string(),
+        _ => rule.to_string(),
+    };
+    let rules = {
+        let mut rules = req.state().alert_rules.write().unwrap();
+        if rules.iter().any(|r| r.name == name) {
+            let mut response = Response::new(StatusCode::Conflict);
+            response.set_body(format!("There already is a rule '{}'", name));
+            return Ok(response);
+        }
+        rules.push(NamedRule { name, rule });
+        rules.clone()
+    };
+    req.state().freshness.json(&rules)
+}
+
+///
+/// Removes a threshold alert rule by its name
+///
+async fn remove_alert_rule(req: Request<State>) -> tide::Result {
+    let name = req.param("name")?;
+    let rules = {
+        let mut rules = req.state().alert_rules.write().unwrap();
+        let before = rules.len();
+        rules.retain(|r| r.name != name);
+        if rules.len() == before {
+            return Ok(Response::new(StatusCode::NotFound));
+        }
+        rules.clone()
+    };
+    req.state().freshness.json(&rules)
+}
+
+///
+/// Serves the trailing stops with their highs
+///
+async fn trailing_stops(req: Request<State>) -> tide::Result {
+    let stops = req
+        .state()
+        .trailing_stops
+        .call(TrailingStopsRequest)
+        .await?;
+    req.state().freshness.json(&stops)
+}
+
+///
+/// Serves the indicators of all groups
+///
+async fn group_list(req: Request<State>) -> tide::Result {
+    let groups = req.state().groups.call(GroupsRequest).await?;
+    req.state().freshness.json(&groups)
+}
+
+///
+/// Serves the indicators of a tag or sector, e.g. `/groups/Technology`
+///
+async fn group(req: Request<State>) -> tide::Result {
+    let name = req.param("name")?.to_string();
+    let indicators = match req.state().groups.call(GroupRequest(name)).await? {
+        Some(indicators) => indicators,
+        None => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    req.state().freshness.json(&indicators)
+}
+
+///
+/// Serves which provider fetches which symbols
+///
+async fn provider_assignments(req: Request<State>) -> tide::Result {
+    let assignments = req.state().providers.call(AssignmentsRequest).await?;
+    req.state().freshness.json(&assignments)
+}
+
+///
+/// Switches the provider (of a symbol) and responds once the old provider handled its
+/// requests in flight
+///
+async fn switch_provider(mut req: Request<State>) -> tide::Result {
+    let switch: SwitchProvider = req.body_json().await?;
+    let switched = match req.state().providers.call(switch).await? {
+        Ok(switched) => switched,
+        Err(e) => {
+            let mut response = Response::new(StatusCode::BadRequest);
+            response.set_body(e.to_string());
+            return Ok(response);
+        }
+    };
+    for previous in switched.previous {
+        previous.call(Drain).await?;
+    }
+    req.state().freshness.json(&switched.assignments)
+}
+
+///
+/// Serves the progress of the backfill (the first fetch of every symbol)
+///
+async fn backfill_status(req: Request<State>) -> tide::Result {
+    let progress = req.state().backfill.call(BackfillStatusRequest).await?;
+    req.state().freshness.json(&progress)
+}
+
+///
+/// Serves the data quality statistics and the most recently quarantined bars
+///
+async fn data_quality(req: Request<State>) -> tide::Result {
+    let summary = req.state().quality.call(QualityRequest).await?;
+    req.state().freshness.json(&summary)
+}
+
+///
+/// Serves the API usage and remaining quota per provider
+///
+async fn provider_quota(req: Request<State>) -> tide::Result {
+    let quotas = req.state().quota.call(QuotaRequest).await?;
+    req.state().freshness.json(&quotas)
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    #[serde(default = "default_rank_by")]
+    by: RankBy,
+    #[serde(default = "default_rank_order")]
+    order: RankOrder,
+    #[serde(default = "default_leaderboard_entries")]
+    n: usize,
+}
+
+fn default_rank_by() -> RankBy {
+    RankBy::Score
+}
+
+fn default_rank_order() -> RankOrder {
+    RankOrder::Desc
+}
+
+fn default_leaderboard_entries() -> usize {
+    10
+}
+
+///
+/// Ranks the symbols by their score, e.g. `/leaderboard?by=volatility&n=5` or
+/// `/leaderboard?order=asc`
+///
+async fn top_symbols(req: Request<State>) -> tide::Result {
+    let query: LeaderboardQuery = req.query()?;
+    let entries = req
+        .state()
+        .leaderboard
+        .call(LeaderboardRequest {
+            by: query.by,
+            order: query.order,
+            n: query.n,
+        })
+        .await?;
+    req.state().freshness.json(&entries)
+}
+
+///
+/// Streams all rows of a symbol from the CSV files, e.g. `/download/AAPL.csv`
+///
+async fn download(req: Request<State>) -> tide::Result {
+    let symbol = match req.param("file")?.strip_suffix(".csv") {
+        Some(symbol) if !symbol.is_empty() => req.state().aliases.resolve(symbol),
+        _ => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    let paths = req.state().csv_files.parts()?;
+    let body = download::symbol_csv(&paths, &symbol).await?;
+    let mut response = Response::new(StatusCode::Ok);
+    response.insert_header(
+        "Content-Disposition",
+        format!("attachment; filename=\"{}.csv\"", symbol),
+    );
+    response.set_body(body);
+    Ok(response)
+}
+
+///
+/// Lists the names of the configured watchlists
+///
+async fn watchlists(req: Request<State>) -> tide::Result {
+    let names: Vec<&String> = req.state().watchlists.keys().collect();
+    req.state().freshness.json(&names)
+}
+
+///
+/// Like `/tail/:n`, but for a single watchlist
+///
+async fn watchlist_tail(req: Request<State>) -> tide::Result {
+    let amount: usize = req.param("n")?.parse()?;
+    let buffer = match req.state().watchlists.get(req.param("name")?) {
+        Some(buffer) => buffer,
+        None => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    buffered(&req, buffer, BufferDataRequest::Peek { n: amount }).await
+}
+
+///
+/// Like `/drain/:n`, but for a single watchlist
+///
+async fn watchlist_drain(req: Request<State>) -> tide::Result {
+    let amount: usize = req.param("n")?.parse()?;
+    let buffer = match req.state().watchlists.get(req.param("name")?) {
+        Some(buffer) => buffer,
+        None => return Ok(Response::new(StatusCode::NotFound)),
+    };
+    buffered(&req, buffer, BufferDataRequest::Drain { n: amount }).await
+}
+
+///
+/// Serves the collected metrics in the Prometheus text format
+///
+async fn prometheus(req: Request<State>) -> tide::Result {
+    let text = req.state().metrics.call(MetricsRequest).await?;
+    let mut response = Response::new(StatusCode::Ok);
+    response.set_content_type("text/plain; version=0.0.4");
+    response.set_body(text);
+    Ok(response)
+}
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    #[serde(default = "default_audit_entries")]
+    n: usize,
+}
+
+fn default_audit_entries() -> usize {
+    100
+}
+
+///
+/// Returns the most recent audited API calls, e.g. `/audit?n=20`
+///
+async fn audit_trail(req: Request<State>) -> tide::Result {
+    let query: AuditQuery = req.query()?;
+    let entries = req.state().audit.call(AuditRequest { n: query.n }).await?;
+    req.state().freshness.json(&entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_indicator_series() {
+        let processor = StockDataProcessor::default();
+        let signals = SignalSet {
+            sma_window: 3,
+            ema_period: 4,
+            rsi_period: 5,
+            volatility_window: 3,
+            ..Default::default()
+        };
+        // 6 bars a session, with a price of 0 that has no log returns
+        let bars: Vec<TickerQuote> = (0..40u64)
+            .map(|i| {
+                let close = if i == 17 {
+                    0.0
+                } else {
+                    100.0 + (i % 7) as f64 - (i % 3) as f64
+                };
+                TickerQuote {
+                    timestamp: i * gap::SESSION / 6,
+                    open: close - 0.5,
+                    high: close + 1.0,
+                    low: close - 1.0,
+                    volume: i % 4,
+                    close,
+                    adjclose: close,
+                }
+            })
+            .collect();
+        let ends: Vec<usize> = (1..=bars.len()).chain([bars.len()]).collect();
+        let rows = processor
+            .indicator_series("ACME", &bars, &ends, &signals)
+            .await;
+        assert_eq!(rows.len(), ends.len());
+        for (end, row) in ends.iter().zip(&rows) {
+            let prefix = processor.indicators("ACME", &bars[..*end], &signals).await;
+            assert_eq!(
+                format!("{:?}", row),
+                format!("{:?}", prefix),
+                "{} bars",
+                end
+            );
+            // the same as the signals over the prefix on its own
+            let closes: Vec<f64> = bars[..*end].iter().map(|q| q.close).collect();
+            let last = |v: Option<Vec<f64>>| v.and_then(|v| v.last().copied());
+            let sma = WindowedSMA { window_size: 3 }.calculate(&closes).await;
+            assert_eq!(row.last_sma, last(sma).unwrap_or(0.0));
+            let rsi = RelativeStrengthIndex { period: 5 }.calculate(&closes).await;
+            assert_eq!(row.rsi, last(rsi));
+            let volatility = RollingVolatility {
+                window: 3,
+                ..Default::default()
+            };
+            assert_eq!(row.volatility, last(volatility.calculate(&closes).await));
+            assert_eq!(row.vwap_session, gap::session_vwap(&bars[..*end]));
+            assert_eq!(
+                row.gap_pct,
+                gap::latest_session(&bars[..*end]).and_then(|(_, gap)| gap.map(|g| g.gap))
+            );
+        }
+        // the first bar of a session and a later one, against the close of the session before
+        assert_eq!(rows[6].change_from_prev_close, Some(106.0 / 103.0 - 1.0));
+        assert_eq!(rows[8].change_from_prev_close, Some(99.0 / 103.0 - 1.0));
+        // no session before the first one, and none with a close to compare to after the 0
+        assert_eq!(rows[0].change_from_prev_close, None);
+        assert_eq!(rows[5].change_from_prev_close, None);
+        assert_eq!(rows[18].change_from_prev_close, None);
+        assert!(rows[24].change_from_prev_close.is_some());
+    }
+
+    #[test]
+    fn test_drop_out_of_range() {
+        let quote = |timestamp| TickerQuote {
+            timestamp,
+            open: 1.0,
+            high: 1.0,
+            low: 1.0,
+            volume: 0,
+            close: 1.0,
+            adjclose: 1.0,
+        };
+        let mut quotes = vec![
+            quote(60),
+            quote(u64::MAX),
+            quote(i64::MAX as u64),
+            quote(120),
+        ];
+        drop_out_of_range("ACME", &mut quotes);
+        let timestamps: Vec<u64> = quotes.iter().map(|q| q.timestamp).collect();
+        assert_eq!(timestamps, vec![60, 120]);
+    }
+}
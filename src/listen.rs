@@ -0,0 +1,272 @@
+use std::io;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::{
+    fs::FileTypeExt,
+    io::{FromRawFd, IntoRawFd},
+    net::{UnixDatagram, UnixListener},
+};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_executor::Executor;
+
+///
+/// The first file descriptor passed by systemd's socket activation
+///
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+///
+/// Where the HTTP API is served, from `--listen`
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    /// A TCP address, e.g. `localhost:8080`
+    Tcp(String),
+    /// A Unix domain socket, given as `unix:/run/stocks/api.sock`
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    pub fn parse(s: &str) -> Self {
+        match s.strip_prefix("unix:") {
+            Some(path) => Endpoint::Unix(PathBuf::from(path)),
+            None => Endpoint::Tcp(s.to_string()),
+        }
+    }
+}
+
+///
+/// A bound socket the server accepts connections on
+///
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    ///
+    /// Takes the socket systemd passed, or binds the endpoint
+    ///
+    pub fn open(endpoint: &Endpoint) -> io::Result<Self> {
+        #[cfg(unix)]
+        {
+            if let Some(listener) = systemd_listener()? {
+                return Ok(listener);
+            }
+        }
+        match endpoint {
+            Endpoint::Tcp(address) => Ok(Listener::Tcp(TcpListener::bind(address)?)),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                // a socket left behind by a previous run would fail the bind
+                if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            }
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            )),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => match listener.local_addr() {
+                Ok(address) => format!("http://{}", address),
+                Err(_) => "a TCP socket".to_string(),
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => match listener
+                .local_addr()
+                .ok()
+                .and_then(|a| a.as_pathname().map(|p| p.display().to_string()))
+            {
+                Some(path) => format!("unix:{}", path),
+                None => "a Unix domain socket".to_string(),
+            },
+        }
+    }
+
+    ///
+    /// Serves the app until accepting fails. Every connection is handled on the executor.
+    ///
+    pub async fn serve<S>(
+        self,
+        app: tide::Server<S>,
+        executor: Arc<Executor<'static>>,
+    ) -> io::Result<()>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        match self {
+            Listener::Tcp(listener) => {
+                let listener = async_std::net::TcpListener::from(listener);
+                loop {
+                    let (stream, peer) = listener.accept().await?;
+                    let local = stream.local_addr().ok();
+                    let app = app.clone();
+                    executor
+                        .spawn(async move {
+                            let served = async_h1::accept(stream, |mut req| async {
+                                req.set_local_addr(local);
+                                req.set_peer_addr(Some(peer));
+                                app.respond(req).await
+                            });
+                            if let Err(e) = served.await {
+                                tide::log::debug!("HTTP connection failed: {}", e);
+                            }
+                        })
+                        .detach();
+                }
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let listener = async_std::os::unix::net::UnixListener::from(listener);
+                let local = listener
+                    .local_addr()
+                    .ok()
+                    .and_then(|a| a.as_pathname().map(|p| p.display().to_string()))
+                    .map(|path| format!("http+unix://{}", path));
+                loop {
+                    let (stream, _) = listener.accept().await?;
+                    let local = local.clone();
+                    let app = app.clone();
+                    executor
+                        .spawn(async move {
+                            let served = async_h1::accept(stream, |mut req| async {
+                                req.set_local_addr(local.as_ref());
+                                app.respond(req).await
+                            });
+                            if let Err(e) = served.await {
+                                tide::log::debug!("HTTP connection failed: {}", e);
+                            }
+                        })
+                        .detach();
+                }
+            }
+        }
+    }
+}
+
+///
+/// The socket passed by systemd's socket activation (`LISTEN_PID` and `LISTEN_FDS`), if any.
+/// Only the first socket is used.
+///
+#[cfg(unix)]
+fn systemd_listener() -> io::Result<Option<Listener>> {
+    let for_us = std::env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    let fds: usize = std::env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(0);
+    if !for_us || fds == 0 {
+        return Ok(None);
+    }
+    // child processes must not take the socket as well
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+    if fds > 1 {
+        tracing::warn!("systemd passed {} sockets, only the first is used", fds);
+    }
+    // Safety: systemd passes the sockets open from fd 3 on, and nothing else owns them
+    let tcp = unsafe { TcpListener::from_raw_fd(SD_LISTEN_FDS_START) };
+    if tcp.local_addr().is_ok() {
+        return Ok(Some(Listener::Tcp(tcp)));
+    }
+    let unix = unsafe { UnixListener::from_raw_fd(tcp.into_raw_fd()) };
+    match unix.local_addr() {
+        Ok(_) => Ok(Some(Listener::Unix(unix))),
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!(
+                "The socket passed by systemd is neither TCP nor Unix: {}",
+                e
+            ),
+        )),
+    }
+}
+
+///
+/// Tells systemd the service is ready (`Type=notify`), if it runs under systemd
+///
+pub fn notify_ready(status: &str) {
+    #[cfg(unix)]
+    {
+        if let Err(e) = notify(&format!("READY=1\nSTATUS={}", status)) {
+            tracing::error!("Could not notify systemd: {}", e);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = status;
+}
+
+#[cfg(unix)]
+fn notify(state: &str) -> io::Result<()> {
+    let path = match std::env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let socket = UnixDatagram::unbound()?;
+    let path = path.to_string_lossy();
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &address)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "abstract sockets are only supported on Linux",
+            ))
+        }
+        None => {
+            socket.send_to(state.as_bytes(), &*path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            Endpoint::parse("localhost:8080"),
+            Endpoint::Tcp("localhost:8080".to_string())
+        );
+        assert_eq!(
+            Endpoint::parse("unix:/run/stocks/api.sock"),
+            Endpoint::Unix(PathBuf::from("/run/stocks/api.sock"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_listener() {
+        let path = std::env::temp_dir().join("listen_test.sock");
+        let endpoint = Endpoint::Unix(path.clone());
+        let first = Listener::open(&endpoint).unwrap();
+        assert_eq!(first.describe(), format!("unix:{}", path.display()));
+        drop(first);
+        // the stale socket file is replaced
+        let second = Listener::open(&endpoint).unwrap();
+        assert!(matches!(second, Listener::Unix(_)));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+//!
+//! Logging through `tracing`. Everything goes to stderr (stdout is reserved for the CSV
+//! output), as text or as JSON lines. Each fetch and each calculation runs in a span with its
+//! symbol, so the lines of one symbol's `QuoteRequest` -> `Quotes` -> `PerformanceIndicators`
+//! flow can be told apart.
+//!
+use std::io::IsTerminal;
+
+use clap::ArgEnum;
+use tracing_subscriber::EnvFilter;
+
+///
+/// How log lines are written
+///
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, with the fields of the event and its spans
+    Json,
+}
+
+///
+/// The filter directives: `RUST_LOG` if set, otherwise `--log-level`, a level like `debug` or
+/// directives like `info,tide=warn`
+///
+fn directives(level: &str, env: Option<String>) -> String {
+    env.filter(|d| !d.trim().is_empty())
+        .unwrap_or_else(|| level.to_string())
+}
+
+pub fn filter(level: &str) -> anyhow::Result<EnvFilter> {
+    let env = std::env::var(EnvFilter::DEFAULT_ENV).ok();
+    Ok(EnvFilter::try_new(directives(level, env))?)
+}
+
+///
+/// Installs the subscriber for the process (and forwards the `log` records of tide)
+///
+pub fn init(level: &str, format: LogFormat) -> anyhow::Result<()> {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter(level)?)
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal());
+    let result = match format {
+        LogFormat::Text => builder.try_init(),
+        LogFormat::Json => builder.json().with_current_span(true).try_init(),
+    };
+    result.map_err(|e| anyhow::anyhow!("Could not set up logging: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        assert_eq!(directives("debug", None), "debug");
+        assert_eq!(directives("debug", Some("warn".to_string())), "warn");
+        assert_eq!(directives("debug", Some(" ".to_string())), "debug");
+        assert!(EnvFilter::try_new(directives("info,tide=warn", None)).is_ok());
+        assert!(EnvFilter::try_new(directives("tide=loud", None)).is_err());
+    }
+}
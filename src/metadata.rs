@@ -0,0 +1,308 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+
+use anyhow::Context as _;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+use yahoo_finance_api as yahoo;
+
+use crate::alias::Aliases;
+use crate::circuit_breaker::SymbolSuspended;
+use crate::config::SymbolConfig;
+use crate::quota::{self, QuotaUsage};
+use crate::signal::DataSourceError;
+use crate::QuoteRequest;
+
+///
+/// Descriptive data of a symbol, published whenever it becomes known
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SymbolMetadata {
+    pub symbol: String,
+    /// Company (or fund) name
+    pub name: Option<String>,
+    pub exchange: Option<String>,
+    pub sector: Option<String>,
+    /// Currency the provider quotes the symbol in
+    pub currency: Option<String>,
+    /// Not fetched until then, after failing over and over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suspended_until: Option<DateTime<Utc>>,
+    /// Other names the symbol is accepted under, e.g. `apple`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+}
+
+impl SymbolMetadata {
+    ///
+    /// Name and sector from the config file take precedence over the provider's
+    ///
+    pub fn with_overrides(mut self, config: Option<&SymbolConfig>) -> Self {
+        if let Some(config) = config {
+            self.name = config.name.clone().or(self.name);
+            self.sector = config.sector.clone().or(self.sector);
+        }
+        self
+    }
+}
+
+///
+/// Looks up a symbol at the provider (name and exchange from the search, the currency from
+/// the latest quote)
+///
+pub async fn fetch(symbol: &str) -> std::result::Result<SymbolMetadata, DataSourceError> {
+    let provider = yahoo::YahooConnector::new();
+    let search = provider.search_ticker_opt(symbol).await;
+    let quotes = provider.get_latest_quotes(symbol, "1d").await;
+    for _ in 0..2 {
+        quota::record(QuotaUsage {
+            provider: "yahoo".to_string(),
+            remaining: None,
+            rate_limited: false,
+        })
+        .await;
+    }
+    let item = search?
+        .quotes
+        .into_iter()
+        .find(|q| q.symbol.eq_ignore_ascii_case(symbol));
+    Ok(SymbolMetadata {
+        symbol: symbol.to_string(),
+        name: item
+            .as_ref()
+            .and_then(|i| i.long_name.clone().or_else(|| i.short_name.clone())),
+        exchange: item.map(|i| i.exchange),
+        sector: None,
+        currency: quotes?
+            .chart
+            .result
+            .into_iter()
+            .next()
+            .map(|r| r.meta.currency),
+        suspended_until: None,
+        aliases: vec![],
+    })
+}
+
+///
+/// Reads a metadata cache file. A missing file is an empty cache.
+///
+pub fn load(path: &str) -> anyhow::Result<BTreeMap<String, SymbolMetadata>> {
+    match File::open(path) {
+        Ok(file) => serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Invalid metadata cache '{}'", path)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e).with_context(|| format!("Could not open '{}'", path)),
+    }
+}
+
+fn save(path: &str, symbols: &BTreeMap<String, SymbolMetadata>) -> anyhow::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer_pretty(&mut writer, symbols)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[message(result = "Vec<SymbolMetadata>")]
+pub struct SymbolsRequest;
+
+///
+/// Actor that looks up the metadata of every symbol the first time it's requested and keeps
+/// it, optionally in a cache file that survives restarts
+///
+pub struct SymbolDirectory {
+    cache: Option<String>,
+    overrides: HashMap<String, SymbolConfig>,
+    /// Metadata as fetched from the provider
+    symbols: BTreeMap<String, SymbolMetadata>,
+    /// Symbols that were looked up already, including the failed ones
+    requested: HashSet<String>,
+    /// Look up new symbols at the provider
+    lookups: bool,
+    /// Symbols the downloaders suspended, and until when
+    suspended: HashMap<String, DateTime<Utc>>,
+    aliases: Aliases,
+}
+
+impl SymbolDirectory {
+    pub fn new(cache: Option<String>, overrides: HashMap<String, SymbolConfig>) -> Self {
+        SymbolDirectory {
+            cache,
+            overrides,
+            symbols: BTreeMap::new(),
+            requested: HashSet::new(),
+            lookups: true,
+            suspended: HashMap::new(),
+            aliases: Aliases::default(),
+        }
+    }
+
+    ///
+    /// Lists the symbols with their aliases
+    ///
+    pub fn with_aliases(mut self, aliases: Aliases) -> Self {
+        self.aliases = aliases;
+        self
+    }
+
+    ///
+    /// Only serves the cached metadata and the config file's, e.g. for made up symbols
+    ///
+    pub fn offline(mut self) -> Self {
+        self.lookups = false;
+        self
+    }
+
+    fn metadata(&self, fetched: &SymbolMetadata) -> SymbolMetadata {
+        fetched
+            .clone()
+            .with_overrides(self.overrides.get(&fetched.symbol))
+    }
+
+    async fn publish(&self, fetched: &SymbolMetadata) {
+        if let Err(e) = Broker::from_registry()
+            .await
+            .unwrap()
+            .publish(self.metadata(fetched))
+        {
+            tracing::error!("{}", e);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for SymbolDirectory {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        if let Some(path) = &self.cache {
+            match load(path) {
+                Ok(symbols) => self.symbols = symbols,
+                Err(e) => tracing::error!("{:#}", e),
+            }
+        }
+        self.requested.extend(self.symbols.keys().cloned());
+        for fetched in self.symbols.values() {
+            self.publish(fetched).await;
+        }
+        ctx.subscribe::<SymbolSuspended>().await?;
+        if !self.lookups {
+            return Ok(());
+        }
+        ctx.subscribe::<QuoteRequest>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<QuoteRequest> for SymbolDirectory {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: QuoteRequest) {
+        if !self.requested.insert(msg.symbol.clone()) {
+            return;
+        }
+        match fetch(&msg.symbol).await {
+            Ok(fetched) => {
+                self.publish(&fetched).await;
+                self.symbols.insert(fetched.symbol.clone(), fetched);
+                if let Some(path) = &self.cache {
+                    if let Err(e) = save(path, &self.symbols) {
+                        tracing::error!("Could not write metadata cache '{}': {}", path, e);
+                    }
+                }
+            }
+            Err(e) => tracing::error!("Could not look up metadata of '{}': {}", msg.symbol, e),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<SymbolSuspended> for SymbolDirectory {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: SymbolSuspended) {
+        self.suspended.insert(msg.symbol, msg.until);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<SymbolsRequest> for SymbolDirectory {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: SymbolsRequest,
+    ) -> Vec<SymbolMetadata> {
+        let mut symbols: BTreeMap<String, SymbolMetadata> = self
+            .symbols
+            .values()
+            .map(|m| (m.symbol.clone(), self.metadata(m)))
+            .collect();
+        // symbols that couldn't be looked up are listed with what the config file knows
+        for symbol in &self.requested {
+            symbols.entry(symbol.clone()).or_insert_with(|| {
+                SymbolMetadata {
+                    symbol: symbol.clone(),
+                    ..Default::default()
+                }
+                .with_overrides(self.overrides.get(symbol))
+            });
+        }
+        for (symbol, until) in &self.suspended {
+            if let Some(metadata) = symbols.get_mut(symbol) {
+                metadata.suspended_until = Some(*until);
+            }
+        }
+        for metadata in symbols.values_mut() {
+            metadata.aliases = self.aliases.of(&metadata.symbol);
+        }
+        symbols.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides() {
+        let fetched = SymbolMetadata {
+            symbol: "AAPL".to_string(),
+            name: Some("Apple Inc.".to_string()),
+            exchange: Some("NMS".to_string()),
+            sector: None,
+            currency: Some("USD".to_string()),
+            suspended_until: None,
+            aliases: vec![],
+        };
+        let config = SymbolConfig {
+            name: Some("Apple".to_string()),
+            sector: Some("Technology".to_string()),
+            ..Default::default()
+        };
+        let merged = fetched.clone().with_overrides(Some(&config));
+        assert_eq!(merged.name.as_deref(), Some("Apple"));
+        assert_eq!(merged.sector.as_deref(), Some("Technology"));
+        assert_eq!(merged.exchange, fetched.exchange);
+        assert_eq!(fetched.clone().with_overrides(None), fetched);
+    }
+
+    #[test]
+    fn test_cache_roundtrip() {
+        let path = std::env::temp_dir().join("metadata_cache_roundtrip.json");
+        let path = path.to_str().unwrap();
+        let mut symbols = BTreeMap::new();
+        symbols.insert(
+            "MSFT".to_string(),
+            SymbolMetadata {
+                symbol: "MSFT".to_string(),
+                name: Some("Microsoft Corporation".to_string()),
+                ..Default::default()
+            },
+        );
+        save(path, &symbols).unwrap();
+        assert_eq!(load(path).unwrap(), symbols);
+        std::fs::remove_file(path).unwrap();
+        assert!(load(path).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,667 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use tide::{Middleware, Next, Request};
+use xactor::*;
+
+use crate::buffer::{BufferLevel, BufferOverflow, Shed};
+use crate::fetch::FetchOutcome;
+use crate::sink_queue::SinkHealth;
+use crate::PerformanceIndicators;
+
+///
+/// Bucket upper bounds (in seconds) for everything that measures time
+///
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+///
+/// Bucket upper bounds for the number of quotes in a single provider response
+///
+const COUNT_BUCKETS: &[f64] = &[0.0, 1.0, 10.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 5000.0];
+
+type Gauge = fn(&PerformanceIndicators) -> Option<f64>;
+
+type SinkValue = fn(&SinkHealth) -> u64;
+
+///
+/// Indicators exported as gauges, `indicator_<name>{symbol="..."}`, with the names alert
+/// conditions know them by. Missing values aren't exported.
+///
+pub const GAUGES: &[(&str, Gauge)] = &[
+    ("price", |i| Some(i.price)),
+    ("pct_change", |i| Some(i.pct_change)),
+    ("period_min", |i| Some(i.period_min)),
+    ("period_max", |i| Some(i.period_max)),
+    ("last_sma", |i| Some(i.last_sma)),
+    ("last_ema", |i| Some(i.last_ema)),
+    ("high_52w", |i| i.high_52w),
+    ("low_52w", |i| i.low_52w),
+    ("pct_from_high_52w", |i| i.pct_from_high_52w),
+    ("pct_from_low_52w", |i| i.pct_from_low_52w),
+    ("session_open", |i| i.session_open),
+    ("vwap_session", |i| i.vwap_session),
+    ("rsi", |i| i.rsi),
+    ("volatility", |i| i.volatility),
+    ("score", |i| i.score),
+    ("gap_pct", |i| i.gap_pct),
+    ("change_from_prev_close", |i| i.change_from_prev_close),
+];
+
+///
+/// Series exported per sink from its `SinkHealth`: name, type, help, and value
+///
+const SINK_SERIES: &[(&str, &str, &str, SinkValue)] = &[
+    (
+        "sink_failures_total",
+        "counter",
+        "Failed write attempts of a sink",
+        |h| h.failures,
+    ),
+    (
+        "sink_dropped_rows_total",
+        "counter",
+        "Rows a sink dropped from its full retry queue",
+        |h| h.dropped,
+    ),
+    (
+        "sink_queue_rows",
+        "gauge",
+        "Rows waiting in the retry queue of a sink",
+        |h| h.queued as u64,
+    ),
+];
+
+///
+/// The pipeline stages we keep histograms for
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    /// Round trip time of a provider request
+    ProviderLatency,
+    /// Number of quotes returned by a single provider request
+    QuotesPerResponse,
+    /// Time spent calculating all signals for one `Quotes` message
+    SignalComputation,
+    /// Time a sink (identified by name) needed to store one record
+    SinkWrite(&'static str),
+}
+
+impl Stage {
+    fn metric_name(&self) -> &'static str {
+        match self {
+            Stage::ProviderLatency => "provider_latency_seconds",
+            Stage::QuotesPerResponse => "quotes_per_response",
+            Stage::SignalComputation => "signal_computation_seconds",
+            Stage::SinkWrite(_) => "sink_write_seconds",
+        }
+    }
+
+    fn help(&self) -> &'static str {
+        match self {
+            Stage::ProviderLatency => "Duration of data provider requests",
+            Stage::QuotesPerResponse => "Number of quotes in a data provider response",
+            Stage::SignalComputation => "Time spent calculating signals",
+            Stage::SinkWrite(_) => "Time spent writing a record to a sink",
+        }
+    }
+
+    fn buckets(&self) -> &'static [f64] {
+        match self {
+            Stage::QuotesPerResponse => COUNT_BUCKETS,
+            _ => LATENCY_BUCKETS,
+        }
+    }
+
+    fn labels(&self, symbol: &str) -> String {
+        match self {
+            Stage::SinkWrite(sink) => format!("sink=\"{}\",symbol=\"{}\"", sink, symbol),
+            _ => format!("symbol=\"{}\"", symbol),
+        }
+    }
+}
+
+///
+/// A histogram with fixed buckets, similar to what Prometheus uses
+///
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Non-cumulative counts per bucket, the last entry counts everything above the last bound
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let idx = self
+            .bounds
+            .iter()
+            .position(|b| value <= *b)
+            .unwrap_or(self.bounds.len());
+        self.counts[idx] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count > 0 {
+            self.sum / self.count as f64
+        } else {
+            0.0
+        }
+    }
+
+    ///
+    /// Estimates the quantile `q` (0..1) as the upper bound of the bucket it falls in.
+    ///
+    /// # Returns
+    ///
+    /// The bucket's upper bound or `f64::INFINITY` if the quantile is in the overflow bucket.
+    ///
+    pub fn quantile(&self, q: f64) -> f64 {
+        let rank = (q * self.count as f64).ceil() as u64;
+        let mut seen = 0;
+        for (i, c) in self.counts.iter().enumerate() {
+            seen += c;
+            if seen >= rank && seen > 0 {
+                return self.bounds.get(i).copied().unwrap_or(f64::INFINITY);
+            }
+        }
+        f64::INFINITY
+    }
+
+    ///
+    /// Writes the histogram as Prometheus `_bucket`, `_sum`, and `_count` series.
+    ///
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (i, c) in self.counts.iter().enumerate() {
+            cumulative += c;
+            let le = self
+                .bounds
+                .get(i)
+                .map(|b| b.to_string())
+                .unwrap_or_else(|| "+Inf".to_string());
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, le, cumulative
+            );
+        }
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+///
+/// A single measurement for one of the pipeline stages
+///
+#[message]
+#[derive(Debug, Clone)]
+pub struct Observation {
+    pub stage: Stage,
+    pub symbol: String,
+    pub value: f64,
+}
+
+impl Observation {
+    pub fn duration(stage: Stage, symbol: &str, duration: Duration) -> Self {
+        Observation {
+            stage,
+            symbol: symbol.to_string(),
+            value: duration.as_secs_f64(),
+        }
+    }
+}
+
+///
+/// Publishes an observation to whoever is interested, ignoring errors since metrics are best-effort.
+///
+pub async fn record(observation: Observation) {
+    if let Ok(mut broker) = Broker::from_registry().await {
+        let _ = broker.publish(observation);
+    }
+}
+
+///
+/// A request served by the API
+///
+#[message]
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    /// The first segment of the path, e.g. `/tail` for `/tail/10`, so symbols and other
+    /// parameters don't make up series of their own
+    pub endpoint: String,
+    pub status: u16,
+}
+
+///
+/// tide middleware that reports every request to the metrics
+///
+#[derive(Default, Debug)]
+pub struct MetricsMiddleware;
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for MetricsMiddleware {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        let method = req.method().to_string();
+        let endpoint = endpoint(req.url().path());
+        let response = next.run(req).await;
+        let request = HttpRequest {
+            method,
+            endpoint,
+            status: response.status().into(),
+        };
+        if let Ok(mut broker) = Broker::from_registry().await {
+            let _ = broker.publish(request);
+        }
+        Ok(response)
+    }
+}
+
+fn endpoint(path: &str) -> String {
+    let first = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    format!("/{}", first)
+}
+
+///
+/// Request the metrics in the Prometheus text exposition format
+///
+#[message(result = "String")]
+pub struct MetricsRequest;
+
+#[message]
+#[derive(Clone)]
+struct PrintSummary;
+
+///
+/// Actor collecting histograms for all pipeline stages per symbol
+///
+#[derive(Default)]
+pub struct Metrics {
+    /// Print a console summary at this interval (if any)
+    pub summary_interval: Option<Duration>,
+    histograms: BTreeMap<(Stage, String), Histogram>,
+    /// Provider requests per provider, symbol, and outcome
+    outcomes: BTreeMap<(String, String, &'static str), u64>,
+    /// The latest indicators of every symbol, at the fetched resolution
+    latest: BTreeMap<String, PerformanceIndicators>,
+    /// Rows shed by full buffers per buffer and action
+    shed: BTreeMap<(String, Shed), u64>,
+    /// Rows in every buffer
+    buffered: BTreeMap<String, usize>,
+    /// Indicators calculated per pipeline (the default one or a watchlist)
+    indicators: BTreeMap<String, u64>,
+    /// API requests per method, endpoint, and status
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// The latest health of every sink with a retry queue
+    sinks: BTreeMap<String, SinkHealth>,
+}
+
+impl Metrics {
+    pub fn new(summary_interval: Option<Duration>) -> Self {
+        Metrics {
+            summary_interval,
+            histograms: BTreeMap::new(),
+            outcomes: BTreeMap::new(),
+            latest: BTreeMap::new(),
+            shed: BTreeMap::new(),
+            buffered: BTreeMap::new(),
+            indicators: BTreeMap::new(),
+            requests: BTreeMap::new(),
+            sinks: BTreeMap::new(),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let mut last_name = "";
+        for ((stage, symbol), histogram) in &self.histograms {
+            let name = stage.metric_name();
+            if name != last_name {
+                let _ = writeln!(out, "# HELP {} {}", name, stage.help());
+                let _ = writeln!(out, "# TYPE {} histogram", name);
+                last_name = name;
+            }
+            histogram.render(&mut out, name, &stage.labels(symbol));
+        }
+        if !self.outcomes.is_empty() {
+            let _ = writeln!(
+                out,
+                "# HELP fetch_outcomes_total Provider requests by outcome"
+            );
+            let _ = writeln!(out, "# TYPE fetch_outcomes_total counter");
+        }
+        for ((provider, symbol, outcome), count) in &self.outcomes {
+            let _ = writeln!(
+                out,
+                "fetch_outcomes_total{{provider=\"{}\",symbol=\"{}\",outcome=\"{}\"}} {}",
+                provider, symbol, outcome, count
+            );
+        }
+        if !self.shed.is_empty() {
+            let _ = writeln!(
+                out,
+                "# HELP buffer_shed_total Rows full buffers dropped or aggregated"
+            );
+            let _ = writeln!(out, "# TYPE buffer_shed_total counter");
+        }
+        for ((buffer, action), count) in &self.shed {
+            let _ = writeln!(
+                out,
+                "buffer_shed_total{{buffer=\"{}\",action=\"{}\"}} {}",
+                buffer,
+                action.name(),
+                count
+            );
+        }
+        if !self.buffered.is_empty() {
+            let _ = writeln!(out, "# HELP buffer_rows Rows waiting in a buffer");
+            let _ = writeln!(out, "# TYPE buffer_rows gauge");
+        }
+        for (buffer, rows) in &self.buffered {
+            let _ = writeln!(out, "buffer_rows{{buffer=\"{}\"}} {}", buffer, rows);
+        }
+        if !self.indicators.is_empty() {
+            let _ = writeln!(
+                out,
+                "# HELP indicators_total Indicators calculated per pipeline"
+            );
+            let _ = writeln!(out, "# TYPE indicators_total counter");
+        }
+        for (pipeline, count) in &self.indicators {
+            let _ = writeln!(
+                out,
+                "indicators_total{{pipeline=\"{}\"}} {}",
+                pipeline, count
+            );
+        }
+        if !self.requests.is_empty() {
+            let _ = writeln!(out, "# HELP http_requests_total API requests by status");
+            let _ = writeln!(out, "# TYPE http_requests_total counter");
+        }
+        for ((method, endpoint, status), count) in &self.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",endpoint=\"{}\",status=\"{}\"}} {}",
+                method, endpoint, status, count
+            );
+        }
+        for (name, kind, help, value) in SINK_SERIES {
+            if self.sinks.is_empty() {
+                break;
+            }
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for (sink, health) in &self.sinks {
+                let _ = writeln!(out, "{}{{sink=\"{}\"}} {}", name, sink, value(health));
+            }
+        }
+        for (name, value) in GAUGES {
+            let mut header = true;
+            for (symbol, indicators) in &self.latest {
+                let value = match value(indicators) {
+                    Some(value) if value.is_finite() => value,
+                    _ => continue,
+                };
+                if header {
+                    let _ = writeln!(out, "# HELP indicator_{} Latest {} of a symbol", name, name);
+                    let _ = writeln!(out, "# TYPE indicator_{} gauge", name);
+                    header = false;
+                }
+                let _ = writeln!(out, "indicator_{}{{symbol=\"{}\"}} {}", name, symbol, value);
+            }
+        }
+        crate::crash::render_restarts(&mut out);
+        out
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for Metrics {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        if let Some(interval) = self.summary_interval {
+            ctx.send_interval(PrintSummary, interval);
+        }
+        ctx.subscribe::<FetchOutcome>().await?;
+        ctx.subscribe::<BufferOverflow>().await?;
+        ctx.subscribe::<BufferLevel>().await?;
+        ctx.subscribe::<HttpRequest>().await?;
+        ctx.subscribe::<SinkHealth>().await?;
+        ctx.subscribe::<PerformanceIndicators>().await?;
+        ctx.subscribe::<Observation>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<FetchOutcome> for Metrics {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: FetchOutcome) {
+        *self
+            .outcomes
+            .entry((msg.provider, msg.symbol, msg.status.name()))
+            .or_default() += 1;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<BufferOverflow> for Metrics {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: BufferOverflow) {
+        let buffer = msg.watchlist.unwrap_or_else(|| "default".to_string());
+        *self.shed.entry((buffer, msg.action)).or_default() += 1;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<BufferLevel> for Metrics {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: BufferLevel) {
+        let buffer = msg.watchlist.unwrap_or_else(|| "default".to_string());
+        self.buffered.insert(buffer, msg.rows);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<HttpRequest> for Metrics {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: HttpRequest) {
+        *self
+            .requests
+            .entry((msg.method, msg.endpoint, msg.status))
+            .or_default() += 1;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<SinkHealth> for Metrics {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: SinkHealth) {
+        self.sinks.insert(msg.sink.clone(), msg);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for Metrics {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        let pipeline = msg.watchlist.as_deref().unwrap_or("default");
+        *self.indicators.entry(pipeline.to_string()).or_default() += 1;
+        // like the alert rules, the gauges follow the current prices
+        if msg.historical || msg.resolution.is_some() {
+            return;
+        }
+        let newer = self
+            .latest
+            .get(&msg.symbol)
+            .is_none_or(|latest| latest.timestamp <= msg.timestamp);
+        if newer {
+            self.latest.insert(msg.symbol.clone(), msg);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Observation> for Metrics {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Observation) {
+        let stage = msg.stage;
+        self.histograms
+            .entry((stage, msg.symbol))
+            .or_insert_with(|| Histogram::new(stage.buckets()))
+            .observe(msg.value);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<MetricsRequest> for Metrics {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: MetricsRequest) -> String {
+        self.render()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PrintSummary> for Metrics {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: PrintSummary) {
+        // stdout is reserved for CSV output
+        for ((stage, symbol), h) in &self.histograms {
+            tracing::info!(
+                "[metrics] {:<28} {:<8} n={:<6} mean={:.4} p50<={} p95<={}",
+                stage.metric_name(),
+                symbol,
+                h.count(),
+                h.mean(),
+                h.quantile(0.5),
+                h.quantile(0.95)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_observe() {
+        let mut h = Histogram::new(&[1.0, 5.0, 10.0]);
+        assert_eq!(h.count(), 0);
+        assert_eq!(h.mean(), 0.0);
+
+        h.observe(0.5);
+        h.observe(3.0);
+        h.observe(4.0);
+        h.observe(20.0);
+        assert_eq!(h.count(), 4);
+        assert_eq!(h.mean(), 6.875);
+        assert_eq!(h.counts, vec![1, 2, 0, 1]);
+        assert_eq!(h.quantile(0.5), 5.0);
+        assert_eq!(h.quantile(0.25), 1.0);
+        assert_eq!(h.quantile(1.0), f64::INFINITY);
+    }
+
+    #[test]
+    fn test_histogram_render() {
+        let mut h = Histogram::new(&[1.0, 5.0]);
+        h.observe(2.0);
+        let mut out = String::new();
+        h.render(&mut out, "x", "symbol=\"A\"");
+        assert_eq!(
+            out,
+            "x_bucket{symbol=\"A\",le=\"1\"} 0\n\
+             x_bucket{symbol=\"A\",le=\"5\"} 1\n\
+             x_bucket{symbol=\"A\",le=\"+Inf\"} 1\n\
+             x_sum{symbol=\"A\"} 2\n\
+             x_count{symbol=\"A\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn test_render_outcomes() {
+        let mut metrics = Metrics::new(None);
+        assert!(!metrics.render().contains("fetch_outcomes_total"));
+        metrics
+            .outcomes
+            .insert(("yahoo".to_string(), "AAPL".to_string(), "not_found"), 2);
+        assert!(metrics.render().contains(
+            "# TYPE fetch_outcomes_total counter\n\
+             fetch_outcomes_total{provider=\"yahoo\",symbol=\"AAPL\",outcome=\"not_found\"} 2\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_counters() {
+        let mut metrics = Metrics::new(None);
+        metrics.buffered.insert("default".to_string(), 12);
+        metrics.indicators.insert("default".to_string(), 40);
+        metrics
+            .requests
+            .insert(("GET".to_string(), endpoint("/tail/10"), 200), 3);
+        let out = metrics.render();
+        assert!(out.contains("buffer_rows{buffer=\"default\"} 12\n"));
+        assert!(out.contains("indicators_total{pipeline=\"default\"} 40\n"));
+        assert!(out.contains(
+            "# TYPE http_requests_total counter\n\
+             http_requests_total{method=\"GET\",endpoint=\"/tail\",status=\"200\"} 3\n"
+        ));
+        assert_eq!(endpoint("/"), "/");
+    }
+
+    #[test]
+    fn test_render_sinks() {
+        let mut metrics = Metrics::new(None);
+        assert!(!metrics.render().contains("sink_"));
+        let health = SinkHealth {
+            sink: "sqlite".to_string(),
+            queued: 250,
+            failures: 3,
+            dropped: 10,
+        };
+        metrics.sinks.insert(health.sink.clone(), health);
+        let out = metrics.render();
+        assert!(out.contains(
+            "# TYPE sink_failures_total counter\n\
+             sink_failures_total{sink=\"sqlite\"} 3\n"
+        ));
+        assert!(out.contains("sink_dropped_rows_total{sink=\"sqlite\"} 10\n"));
+        assert!(out.contains(
+            "# TYPE sink_queue_rows gauge\n\
+             sink_queue_rows{sink=\"sqlite\"} 250\n"
+        ));
+    }
+
+    #[test]
+    fn test_render_gauges() {
+        let mut metrics = Metrics::new(None);
+        metrics.latest.insert(
+            "AAPL".to_string(),
+            PerformanceIndicators {
+                symbol: "AAPL".to_string(),
+                price: 190.5,
+                rsi: Some(71.0),
+                ..Default::default()
+            },
+        );
+        let out = metrics.render();
+        assert!(out.contains(
+            "# HELP indicator_price Latest price of a symbol\n\
+             # TYPE indicator_price gauge\n\
+             indicator_price{symbol=\"AAPL\"} 190.5\n"
+        ));
+        assert!(out.contains("indicator_rsi{symbol=\"AAPL\"} 71\n"));
+        // no volatility yet
+        assert!(!out.contains("indicator_volatility"));
+    }
+}
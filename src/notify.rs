@@ -0,0 +1,56 @@
+use std::collections::HashSet;
+
+use xactor::*;
+
+use crate::alert::Alert;
+
+///
+/// Actor that raises an OS notification for every fired alert of the rules that ask for it
+/// (`notify = true` in the config file)
+///
+pub struct DesktopNotifySink {
+    rules: HashSet<String>,
+}
+
+impl DesktopNotifySink {
+    pub fn new(rules: HashSet<String>) -> Self {
+        DesktopNotifySink { rules }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for DesktopNotifySink {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<Alert>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Alert> for DesktopNotifySink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Alert) {
+        if !self.rules.contains(&msg.rule) {
+            return;
+        }
+        let summary = format!("{}: {}", msg.symbol, msg.rule);
+        if let Err(e) = show(summary, msg.message).await {
+            tracing::error!("Could not show desktop notification: {}", e);
+        }
+    }
+}
+
+///
+/// Shows an OS notification
+///
+pub async fn show(summary: String, body: String) -> notify_rust::error::Result<()> {
+    // showing a notification talks to the desktop session synchronously
+    async_std::task::spawn_blocking(move || {
+        notify_rust::Notification::new()
+            .summary(&summary)
+            .body(&body)
+            .appname("stock-tracker")
+            .show()
+            .map(|_| ())
+    })
+    .await
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+
+use crate::PerformanceIndicators;
+
+///
+/// How numbers are written, as in the config file:
+///
+/// ```toml
+/// [format]
+/// locale = "de"
+/// precision = { price = 3, pct_change = 1 }
+/// ```
+///
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct FormatConfig {
+    /// Preset separators: `en` (`1,234.56`), `de` (`1.234,56`), `fr` (`1 234,56`), or `ch`
+    /// (`1'234.56`). Without a locale, numbers have no thousands separators.
+    pub locale: Option<String>,
+    /// Overrides the locale's decimal separator
+    pub decimal_separator: Option<char>,
+    /// Overrides the locale's thousands separator
+    pub thousands_separator: Option<char>,
+    /// Decimals per column name, e.g. `price` or a custom indicator. Percentages count the
+    /// decimals of the percentage, not of the fraction.
+    pub precision: BTreeMap<String, usize>,
+}
+
+///
+/// Separators and decimals of the numbers in the console, the CSV files, and the JSON
+/// responses. JSON numbers keep the `.` and are only rounded to the configured precision.
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub decimal: char,
+    pub thousands: Option<char>,
+    pub precision: BTreeMap<String, usize>,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        NumberFormat {
+            decimal: '.',
+            thousands: None,
+            precision: BTreeMap::new(),
+        }
+    }
+}
+
+impl NumberFormat {
+    pub fn from_config(config: &FormatConfig) -> anyhow::Result<Self> {
+        let (decimal, thousands) = match config.locale.as_deref() {
+            None => ('.', None),
+            Some("en") => ('.', Some(',')),
+            Some("de") => (',', Some('.')),
+            // narrow no-break space, which keeps the number together
+            Some("fr") => (',', Some('\u{202f}')),
+            Some("ch") => ('.', Some('\'')),
+            Some(other) => bail!("Unknown locale '{}', expected en, de, fr, or ch", other),
+        };
+        let format = NumberFormat {
+            decimal: config.decimal_separator.unwrap_or(decimal),
+            thousands: config.thousands_separator.or(thousands),
+            precision: config.precision.clone(),
+        };
+        format.validate()?;
+        Ok(format)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let invalid = |c: char| c.is_ascii_digit() || c == '-' || c == ':' || c == ' ';
+        if invalid(self.decimal) || self.thousands.is_some_and(invalid) {
+            bail!("Separators can't be digits, '-', ':', or spaces");
+        }
+        if self.thousands == Some(self.decimal) {
+            bail!("The decimal and thousands separators need to differ");
+        }
+        Ok(())
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.decimal == '.' && self.thousands.is_none()
+    }
+
+    ///
+    /// The decimals of a column, `default` unless configured
+    ///
+    pub fn decimals(&self, column: &str, default: usize) -> usize {
+        self.precision.get(column).copied().unwrap_or(default)
+    }
+
+    ///
+    /// Writes a number with the separators, e.g. `-1.234,50`
+    ///
+    pub fn number(&self, value: f64, decimals: usize) -> String {
+        let plain = format!("{:.*}", decimals, value);
+        if !value.is_finite() {
+            return plain;
+        }
+        let (sign, digits) = match plain.strip_prefix('-') {
+            Some(digits) => ("-", digits),
+            None => ("", plain.as_str()),
+        };
+        let (integer, fraction) = match digits.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (digits, None),
+        };
+        let mut text = sign.to_string();
+        for (i, digit) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i).is_multiple_of(3) {
+                text.extend(self.thousands);
+            }
+            text.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            text.push(self.decimal);
+            text.push_str(fraction);
+        }
+        text
+    }
+
+    ///
+    /// Reads a number written by `number`
+    ///
+    pub fn parse(&self, text: &str) -> anyhow::Result<f64> {
+        let plain: String = text
+            .chars()
+            .filter(|c| Some(*c) != self.thousands)
+            .map(|c| if c == self.decimal { '.' } else { c })
+            .collect();
+        plain
+            .parse()
+            .map_err(|_| anyhow!("invalid number '{}'", text))
+    }
+
+    ///
+    /// Rounds the fields with a configured precision, for the JSON responses
+    ///
+    pub fn round(&self, row: &mut PerformanceIndicators) {
+        if self.precision.is_empty() {
+            return;
+        }
+        // percentages are stored as fractions, which have two more decimals
+        let round = |column: &str, percent: bool, value: &mut f64| {
+            if let Some(decimals) = self.precision.get(column) {
+                let scale = 10f64.powi((decimals + if percent { 2 } else { 0 }) as i32);
+                *value = (*value * scale).round() / scale;
+            }
+        };
+        round("price", false, &mut row.price);
+        round("pct_change", true, &mut row.pct_change);
+        round("period_min", false, &mut row.period_min);
+        round("period_max", false, &mut row.period_max);
+        round("last_sma", false, &mut row.last_sma);
+        round("last_ema", false, &mut row.last_ema);
+        row.rsi.iter_mut().for_each(|v| round("last_rsi", false, v));
+        row.score.iter_mut().for_each(|v| round("score", false, v));
+        row.volatility
+            .iter_mut()
+            .for_each(|v| round("volatility", true, v));
+        row.high_52w
+            .iter_mut()
+            .for_each(|v| round("high_52w", false, v));
+        row.low_52w
+            .iter_mut()
+            .for_each(|v| round("low_52w", false, v));
+        row.pct_from_high_52w
+            .iter_mut()
+            .for_each(|v| round("pct_from_high_52w", true, v));
+        row.pct_from_low_52w
+            .iter_mut()
+            .for_each(|v| round("pct_from_low_52w", true, v));
+        row.vwap_session
+            .iter_mut()
+            .for_each(|v| round("vwap_session", false, v));
+        row.gap_pct
+            .iter_mut()
+            .for_each(|v| round("gap_pct", true, v));
+        row.change_from_prev_close
+            .iter_mut()
+            .for_each(|v| round("change_from_prev_close", true, v));
+        for (name, value) in row.custom.iter_mut() {
+            round(name, false, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locale(name: &str) -> NumberFormat {
+        NumberFormat::from_config(&FormatConfig {
+            locale: Some(name.to_string()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_number() {
+        let plain = NumberFormat::default();
+        assert_eq!(plain.number(1234567.891, 2), "1234567.89");
+        let de = locale("de");
+        assert_eq!(de.number(1234567.891, 2), "1.234.567,89");
+        assert_eq!(de.number(-1234.5, 1), "-1.234,5");
+        assert_eq!(de.number(123.0, 0), "123");
+        assert_eq!(de.parse("-1.234,5").unwrap(), -1234.5);
+        let en = locale("en");
+        assert_eq!(en.number(-999.999, 2), "-1,000.00");
+        assert_eq!(en.parse("1,000.25").unwrap(), 1000.25);
+        assert_eq!(locale("fr").number(1234.5, 2), "1\u{202f}234,50");
+        assert_eq!(en.number(f64::NAN, 2), "NaN");
+        assert!(de.parse("abc").is_err());
+
+        assert!(NumberFormat::from_config(&FormatConfig {
+            locale: Some("xx".to_string()),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(NumberFormat::from_config(&FormatConfig {
+            decimal_separator: Some(','),
+            thousands_separator: Some(','),
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_round() {
+        let format = NumberFormat {
+            precision: BTreeMap::from([("price".to_string(), 1), ("pct_change".to_string(), 0)]),
+            ..Default::default()
+        };
+        let mut row = PerformanceIndicators {
+            price: 91.26,
+            pct_change: -0.01254,
+            last_sma: 87.7412,
+            ..Default::default()
+        };
+        format.round(&mut row);
+        assert_eq!(row.price, 91.3);
+        assert_eq!(row.pct_change, -0.01);
+        assert_eq!(row.last_sma, 87.7412);
+    }
+}
@@ -0,0 +1,491 @@
+//!
+//! The flags of the command line, and how they turn into the settings of the pipeline
+//!
+use std::collections::HashMap;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+
+use crate::alias::Aliases;
+use crate::anomaly::AnomalyDetector;
+use crate::buffer::Overflow;
+use crate::candles::Candles;
+use crate::config::Config;
+use crate::csv_schema::CsvSchema;
+use crate::file_sink::{self, DuplicateRows};
+use crate::identifier::TickerLookup;
+use crate::number_format::NumberFormat;
+use crate::pairs::Pair;
+use crate::plugin::SignalPlugin;
+use crate::replica::ReplicaOpts;
+use crate::script::{self, Script};
+use crate::signal::{Resolution, SignalSet};
+use crate::{doctor, export, logging, prometheus_rules, scheduler, ProcessorConfig};
+
+#[derive(Parser, Debug)]
+#[clap(
+    version = "1.0",
+    author = "Claus Matzinger",
+    about = "A Manning LiveProject: async Rust",
+    subcommand_negates_reqs = true,
+    args_override_self = true
+)]
+pub(crate) struct Opts {
+    #[clap(subcommand)]
+    pub(crate) command: Option<Command>,
+    /// Symbols to fetch, `index:sp500` adds all members of an index
+    #[clap(short, long, default_value = "AAPL,MSFT,UBER,GOOG")]
+    pub(crate) symbols: String,
+    /// Fetch index members from this URL (`{index}` is replaced by the index name)
+    #[clap(long)]
+    pub(crate) constituents_url: Option<String>,
+    /// Seconds between two refreshes of the index members (0 to disable)
+    #[clap(long, default_value = "86400")]
+    pub(crate) constituents_refresh: u64,
+    /// Where ISINs and CUSIPs in the symbol lists are looked up
+    #[clap(long, arg_enum, default_value = "yahoo")]
+    pub(crate) ticker_lookup: TickerLookup,
+    /// API key for `--ticker-lookup openfigi`
+    #[clap(long)]
+    pub(crate) openfigi_key: Option<String>,
+    /// Keep the tickers of ISINs and CUSIPs in this file across restarts
+    #[clap(long)]
+    pub(crate) ticker_cache: Option<String>,
+    #[clap(short, long, required_unless_present_any = &["synthetic", "replay"])]
+    pub(crate) from: Option<String>,
+    /// Fetch on a cron schedule (UTC) instead of every `--interval`, e.g.
+    /// "*/5 9-16 * * MON-FRI"
+    #[clap(long)]
+    pub(crate) schedule: Option<String>,
+    /// Time between two fetches, in seconds or e.g. `15s`, `1m`, or `1h`. Also the default of
+    /// the schedules and watchlists in the config file.
+    #[clap(long, default_value = "30s", parse(try_from_str = scheduler::parse_interval))]
+    pub(crate) interval: Duration,
+    /// Print a summary of the pipeline metrics every n seconds (0 to disable)
+    #[clap(long, default_value = "60")]
+    pub(crate) metrics_summary: u64,
+    /// Log this level and above, e.g. `debug`, or filter directives like `info,tide=debug`
+    /// (`RUST_LOG` takes precedence)
+    #[clap(long, default_value = "info,tide=warn")]
+    pub(crate) log_level: String,
+    /// Log as human readable `text` or as `json` lines, with the symbol of every fetch
+    #[clap(long, arg_enum, default_value = "text")]
+    pub(crate) log_format: logging::LogFormat,
+    /// Post a JSON crash report to this URL whenever a panic occurs
+    #[clap(long)]
+    pub(crate) panic_webhook: Option<String>,
+    /// Post the indicators as JSON arrays to this URL. Rows go through the write-ahead log
+    /// `--webhook-wal` first, so they are retried and survive restarts.
+    #[clap(long)]
+    pub(crate) webhook_sink: Option<String>,
+    /// The write-ahead log of `--webhook-sink`
+    #[clap(long, default_value = "webhook-wal.jsonl")]
+    pub(crate) webhook_wal: String,
+    /// Write the indicators to InfluxDB at this URL, e.g. `http://localhost:8086`, in the line
+    /// protocol. Rows go through the write-ahead log `--influx-wal` first.
+    #[clap(long)]
+    pub(crate) influx_url: Option<String>,
+    /// The bucket (or `database/retention-policy` of InfluxDB 1.8) of `--influx-url`
+    #[clap(long, default_value = "stocks")]
+    pub(crate) influx_bucket: String,
+    /// The organization of `--influx-url`, unless the token is scoped to one
+    #[clap(long)]
+    pub(crate) influx_org: Option<String>,
+    /// API token of `--influx-url`
+    #[clap(long)]
+    pub(crate) influx_token: Option<String>,
+    /// The measurement the indicators are written to
+    #[clap(long, default_value = "indicators")]
+    pub(crate) influx_measurement: String,
+    /// The write-ahead log of `--influx-url`
+    #[clap(long, default_value = "influx-wal.jsonl")]
+    pub(crate) influx_wal: String,
+    /// Store the indicators of all pipelines in the `performance` table of this SQLite
+    /// database
+    #[clap(long)]
+    pub(crate) sqlite: Option<String>,
+    /// Write the indicators of all pipelines to this Parquet file
+    #[clap(long)]
+    pub(crate) parquet: Option<String>,
+    /// Rows in a row group of `--parquet`
+    #[clap(long, default_value = "1000")]
+    pub(crate) parquet_rows: usize,
+    /// Write a row group of `--parquet` at least this often, even if it's not full
+    #[clap(long, default_value = "1m", parse(try_from_str = scheduler::parse_interval))]
+    pub(crate) parquet_flush: Duration,
+    /// Start a new CSV file every day (UTC), named after the first one with the date
+    #[clap(long)]
+    pub(crate) csv_rotate_daily: bool,
+    /// Start a new CSV file once one has this size, e.g. 100MB (0 for no limit)
+    #[clap(long, default_value = "0", parse(try_from_str = file_sink::parse_size))]
+    pub(crate) csv_max_size: u64,
+    /// Compress CSV files with gzip once the next one is started
+    #[clap(long)]
+    pub(crate) csv_gzip: bool,
+    /// Move the indicators of bars older than `--cold-after` from the buffers and the SQLite
+    /// database into a Parquet file per month in this directory
+    #[clap(long)]
+    pub(crate) cold_dir: Option<String>,
+    /// Age of the bars moved to `--cold-dir`
+    #[clap(long, default_value = "30d", parse(try_from_str = scheduler::parse_interval))]
+    pub(crate) cold_after: Duration,
+    /// Rows every sink keeps for a retry while it can't write (0 for no limit), the oldest
+    /// are dropped beyond that
+    #[clap(long, default_value = "10000")]
+    pub(crate) sink_queue: usize,
+    /// Don't write the indicators of the default pipeline to a CSV file
+    #[clap(long)]
+    pub(crate) no_csv: bool,
+    /// Append audited API calls to this file (JSON lines), also with `--no-csv`
+    #[clap(long, default_value = "audit.jsonl")]
+    pub(crate) audit_log: String,
+    /// Seed the buffer and sinks with previously stored indicators (.csv, .db, or .jsonl)
+    #[clap(long)]
+    pub(crate) import: Option<String>,
+    /// Periodically (and at shutdown) write the application state to this file
+    #[clap(long)]
+    pub(crate) snapshot: Option<String>,
+    /// Seconds between two snapshots
+    #[clap(long, default_value = "300")]
+    pub(crate) snapshot_interval: u64,
+    /// Restore the application state from a snapshot file on startup
+    #[clap(long)]
+    pub(crate) restore: Option<String>,
+    /// Keep the last fetched timestamp per symbol in this file and resume from there
+    #[clap(long)]
+    pub(crate) checkpoints: Option<String>,
+    /// Register a WASM signal plugin as `name=path.wasm` (can be repeated)
+    #[clap(long = "plugin")]
+    pub(crate) plugins: Vec<String>,
+    /// Request limit of a provider as `provider=requests/seconds`, e.g. `yahoo=2000/3600`.
+    /// Fetch intervals are stretched when the limit comes close.
+    #[clap(long = "quota")]
+    pub(crate) quotas: Vec<String>,
+    /// Another name of a symbol as `alias=ticker`, e.g. `apple=AAPL`, accepted in
+    /// `--symbols`, the config file, and the API (can be repeated)
+    #[clap(long = "alias")]
+    pub(crate) aliases: Vec<String>,
+    /// Period of the exponential moving average
+    #[clap(long, default_value = "12")]
+    pub(crate) ema_period: usize,
+    /// Smoothing factor of the exponential moving average; values are weighted with
+    /// `smoothing / (period + 1)`
+    #[clap(long, default_value = "2.0")]
+    pub(crate) ema_smoothing: f64,
+    /// Number of price changes the relative strength index is calculated over
+    #[clap(long, default_value = "14")]
+    pub(crate) rsi_period: usize,
+    /// Number of daily returns the annualized volatility is calculated over
+    #[clap(long, default_value = "20")]
+    pub(crate) volatility_window: usize,
+    /// Also calculate the indicators over resampled bars, e.g. `1h,1d`
+    #[clap(long, default_value = "")]
+    pub(crate) resolutions: String,
+    /// Candles the signals are calculated over: `raw`, `heikin-ashi` (smoothed), or Renko
+    /// bricks of a price or percentage, e.g. `renko:2` or `renko:1%`
+    #[clap(long, default_value = "raw")]
+    pub(crate) candles: Candles,
+    /// Flag a symbol when the Z-score of its latest return reaches this value
+    #[clap(long)]
+    pub(crate) anomaly_zscore: Option<f64>,
+    /// Number of returns the Z-score is calculated against
+    #[clap(long, default_value = "30")]
+    pub(crate) anomaly_window: usize,
+    /// Fetch flagged symbols every n seconds (0 to keep the regular schedule)
+    #[clap(long, default_value = "10")]
+    pub(crate) anomaly_interval: u64,
+    /// Seconds after the last anomaly until a symbol returns to the regular schedule
+    #[clap(long, default_value = "300")]
+    pub(crate) anomaly_period: u64,
+    /// Publish opening gaps of at least this size (relative to the previous close)
+    #[clap(long, default_value = "0.01")]
+    pub(crate) gap_threshold: f64,
+    /// Read settings, custom indicators, and alert rules from this TOML file. Flags on the
+    /// command line take precedence over the file's settings.
+    #[clap(long)]
+    pub(crate) config: Option<String>,
+    /// Write plain numbers and ISO timestamps to the CSV files (no `$` or `%`)
+    #[clap(long)]
+    pub(crate) strict_csv: bool,
+    /// Decimals of the numbers in `--strict-csv` mode
+    #[clap(long, default_value = "4")]
+    pub(crate) csv_precision: usize,
+    /// What the CSV sinks do with a row for a bar they already wrote
+    #[clap(long, arg_enum, default_value = "skip")]
+    pub(crate) duplicate_rows: DuplicateRows,
+    /// Rows the buffers behind `/tail` and `/drain` hold at most (0 for no limit)
+    #[clap(long, default_value = "10000")]
+    pub(crate) buffer_capacity: usize,
+    /// What a full buffer does with new rows
+    #[clap(long, arg_enum, default_value = "drop-oldest")]
+    pub(crate) buffer_overflow: Overflow,
+    /// Append the checked quotes of every fetch to this file, for `recompute`
+    #[clap(long)]
+    pub(crate) quote_log: Option<String>,
+    /// Record the messages in and out of the pipeline to this file, for `--replay`
+    #[clap(long)]
+    pub(crate) record: Option<String>,
+    /// Feed the fetched quotes and metadata of a `--record` file through the pipeline again,
+    /// instead of fetching
+    #[clap(long, conflicts_with = "once")]
+    pub(crate) replay: Option<String>,
+    /// Replay at the recorded pace, this many times as fast (by default without pauses)
+    #[clap(long, requires = "replay")]
+    pub(crate) replay_speed: Option<f64>,
+    /// Seconds between two scans of the quote log for missing bars (0 to disable)
+    #[clap(long, default_value = "3600")]
+    pub(crate) repair_interval: u64,
+    /// TOML file with holidays and half-days that change or replace the embedded NYSE calendar
+    #[clap(long)]
+    pub(crate) holidays: Option<String>,
+    /// Soak test: track this many made up symbols (random walks) instead of fetching any data
+    #[clap(long)]
+    pub(crate) synthetic: Option<usize>,
+    /// Provider of the symbols without one in the config file: `yahoo`, `alphavantage`,
+    /// `file`, or `synthetic` (defaults to `yahoo`, or `synthetic` with `--synthetic`)
+    #[clap(long)]
+    pub(crate) provider: Option<String>,
+    /// API key of Alpha Vantage
+    #[clap(long)]
+    pub(crate) api_key: Option<String>,
+    /// Directory with a CSV or JSON file of quotes per symbol (e.g. `AAPL.csv`) for the `file`
+    /// provider
+    #[clap(long)]
+    pub(crate) replay_dir: Option<std::path::PathBuf>,
+    /// Seconds between two synthetic bars of a symbol
+    #[clap(long, default_value = "1")]
+    pub(crate) synthetic_interval: u64,
+    /// Print the throughput and memory use every n seconds in `--synthetic` mode
+    #[clap(long, default_value = "10")]
+    pub(crate) soak_report: u64,
+    /// Keep the symbol metadata (name, exchange, currency) in this file across restarts
+    #[clap(long)]
+    pub(crate) metadata_cache: Option<String>,
+    /// Write a summary per symbol and session (UTC day) to this CSV file
+    #[clap(long)]
+    pub(crate) daily_summary: Option<String>,
+    /// Append the indicators of the symbol groups (tags and sectors) to this CSV file
+    #[clap(long)]
+    pub(crate) group_csv: Option<String>,
+    /// Also mail the daily summaries to this address (via the local `sendmail`)
+    #[clap(long, requires = "daily-summary")]
+    pub(crate) summary_email: Option<String>,
+    /// Fetch every symbol once since `--from` with a progress bar and exit, without a server
+    #[clap(long)]
+    pub(crate) once: bool,
+    /// Calculate and write the indicators for every bar of the first fetch of each symbol,
+    /// not just the latest one, then follow incrementally
+    #[clap(long)]
+    pub(crate) backfill_history: bool,
+    /// Calculate the indicators over the trailing history of this length, e.g. `200d`,
+    /// instead of everything since `--from`
+    #[clap(long, parse(try_from_str = scheduler::parse_interval))]
+    pub(crate) history_window: Option<Duration>,
+    /// Calculate and write the indicators for every new bar of a fetch, not just the latest
+    /// one, so the output is a time series
+    #[clap(long)]
+    pub(crate) per_bar: bool,
+    /// Leave out the latest bar of every response while it's still forming (its interval hasn't
+    /// passed yet), so it doesn't change retroactively. It's fetched again once complete.
+    #[clap(long)]
+    pub(crate) complete_bars: bool,
+    /// Symbols fetched at the same time per provider. A symbol's fetches never overlap.
+    #[clap(long, default_value = "4")]
+    pub(crate) max_concurrency: usize,
+    /// Requests sent to each remote provider (Yahoo, Alpha Vantage) per minute at most.
+    /// Requests over the budget wait for their turn.
+    #[clap(long)]
+    pub(crate) max_requests_per_minute: Option<u32>,
+    /// Consecutive failed fetches after which a symbol is suspended (0 to never suspend)
+    #[clap(long, default_value = "5")]
+    pub(crate) breaker_failures: u32,
+    /// How long a symbol is suspended, e.g. `10m`
+    #[clap(long, default_value = "10m", parse(try_from_str = scheduler::parse_interval))]
+    pub(crate) breaker_backoff: Duration,
+    /// Attempts of a fetch that fails with a network error, including the first one
+    #[clap(long, default_value = "3")]
+    pub(crate) retry_attempts: u32,
+    /// Milliseconds before the first retry, doubling with every further one
+    #[clap(long, default_value = "500")]
+    pub(crate) retry_delay: u64,
+    /// Share of the retry delay that is random, from 0 to 1
+    #[clap(long, default_value = "0.5")]
+    pub(crate) retry_jitter: f64,
+    /// Append the fetches that failed every attempt to this file (JSON lines)
+    #[clap(long)]
+    pub(crate) dead_letters: Option<String>,
+    /// Address the API is served on, or a Unix domain socket as `unix:/path/to/api.sock`.
+    /// A socket passed by systemd's socket activation takes precedence.
+    #[clap(long, default_value = "localhost:8080")]
+    pub(crate) listen: String,
+    /// Milliseconds the responses of `/tail` and `/leaderboard` are cached (0 to disable)
+    #[clap(long, default_value = "1000")]
+    pub(crate) cache_ttl: u64,
+    /// Requests the data endpoints handle at once, more are refused with
+    /// `503 Service Unavailable` (0 for no limit)
+    #[clap(long, default_value = "16")]
+    pub(crate) max_concurrent_requests: usize,
+    /// Threads of the HTTP API, apart from those of the pipeline
+    #[clap(long, default_value = "2")]
+    pub(crate) http_threads: usize,
+}
+
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Convert stored indicators into another format
+    Export(export::ExportOpts),
+    /// Calculate the indicators again from a quote log, e.g. with another SMA window
+    Recompute(RecomputeOpts),
+    /// Write the alert conditions as Prometheus alerting rules
+    PrometheusRules(prometheus_rules::PrometheusRulesOpts),
+    /// Check the config, providers, API keys, sink files, and the API's address
+    Doctor(doctor::DoctorOpts),
+    /// Serve the API from the SQLite database of another instance, without fetching
+    Replica(ReplicaOpts),
+}
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct RecomputeOpts {
+    /// The quote log written with `--quote-log`
+    pub(crate) input: String,
+    /// The CSV file to write the indicators to
+    #[clap(short, long)]
+    pub(crate) output: String,
+    /// Window of the moving average for all symbols (overrides the config file)
+    #[clap(long)]
+    pub(crate) sma_window: Option<usize>,
+    /// Period of the exponential moving average for all symbols (overrides the config file)
+    #[clap(long)]
+    pub(crate) ema_period: Option<usize>,
+    /// Recompute this watchlist instead of the default pipeline
+    #[clap(long)]
+    pub(crate) watchlist: Option<String>,
+}
+
+impl ProcessorConfig {
+    pub(crate) fn load(opts: &Opts, config: &Config) -> anyhow::Result<Self> {
+        let max_smoothing = (opts.ema_period + 1) as f64;
+        if !(opts.ema_smoothing > 0.0 && opts.ema_smoothing <= max_smoothing) {
+            anyhow::bail!(
+                "--ema-smoothing needs to be above 0 and at most --ema-period + 1 ({})",
+                max_smoothing
+            );
+        }
+        let default_signals = SignalSet {
+            ema_period: opts.ema_period,
+            ema_smoothing: opts.ema_smoothing,
+            rsi_period: opts.rsi_period,
+            volatility_window: opts.volatility_window,
+            resolutions: Resolution::parse_list(&opts.resolutions)?,
+            candles: opts.candles,
+            ..Default::default()
+        };
+        let signal_sets = config
+            .watchlists
+            .iter()
+            .map(|(name, w)| {
+                let resolutions = match &w.resolutions {
+                    Some(resolutions) => resolutions
+                        .iter()
+                        .map(|r| r.parse())
+                        .collect::<anyhow::Result<Vec<_>>>()?,
+                    None => default_signals.resolutions.clone(),
+                };
+                let set = SignalSet {
+                    sma_window: w.sma_window.unwrap_or(30),
+                    ema_period: w.ema_period.unwrap_or(default_signals.ema_period),
+                    ema_smoothing: default_signals.ema_smoothing,
+                    rsi_period: w.rsi_period.unwrap_or(default_signals.rsi_period),
+                    volatility_window: w
+                        .volatility_window
+                        .unwrap_or(default_signals.volatility_window),
+                    custom: w.signals.clone(),
+                    resolutions,
+                    candles: w.candles.unwrap_or(default_signals.candles),
+                };
+                Ok((name.clone(), set))
+            })
+            .collect::<anyhow::Result<HashMap<String, SignalSet>>>()?;
+        let engine = script::engine();
+        let scripts = config
+            .indicators
+            .iter()
+            .map(|c| Script::from_config(&engine, c, &config_dir(opts)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let plugins = opts
+            .plugins
+            .iter()
+            .map(|p| SignalPlugin::from_arg(p))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(ProcessorConfig {
+            plugins,
+            scripts,
+            default_signals,
+            signal_sets,
+            overrides: config
+                .symbols
+                .iter()
+                .map(|(symbol, o)| (symbol.clone(), o.clone()))
+                .collect(),
+            anomaly: opts.anomaly_zscore.map(|threshold| AnomalyDetector {
+                window: opts.anomaly_window,
+                threshold,
+            }),
+            gap_threshold: opts.gap_threshold,
+            score: config.score,
+            pairs: config
+                .pairs
+                .iter()
+                .map(Pair::from_config)
+                .collect::<anyhow::Result<Vec<_>>>()?,
+            numbers: NumberFormat::from_config(&config.format)?,
+            backfill_history: opts.backfill_history,
+            history_window: opts.history_window,
+            per_bar: opts.per_bar,
+            console: true,
+        })
+    }
+}
+
+///
+/// Scripts in the config file are relative to the config file
+///
+pub(crate) fn config_dir(opts: &Opts) -> std::path::PathBuf {
+    opts.config
+        .as_deref()
+        .and_then(|p| std::path::Path::new(p).parent())
+        .unwrap_or_else(|| std::path::Path::new("."))
+        .to_path_buf()
+}
+
+pub(crate) fn csv_schema(opts: &Opts, config: &Config) -> anyhow::Result<CsvSchema> {
+    let schema = match &config.csv.columns {
+        Some(columns) => CsvSchema::from_names(columns)?,
+        None => CsvSchema::default(),
+    }
+    .with_numbers(NumberFormat::from_config(&config.format)?);
+    Ok(if opts.strict_csv {
+        schema.strict(opts.csv_precision)
+    } else {
+        schema
+    })
+}
+
+pub(crate) fn load_config(opts: &Opts) -> anyhow::Result<Config> {
+    match &opts.config {
+        Some(path) => Config::load(path),
+        None => Ok(Config::default()),
+    }
+}
+
+///
+/// The aliases of the config file and `--alias`
+///
+pub(crate) fn load_aliases(opts: &Opts, config: &Config) -> anyhow::Result<Aliases> {
+    let cli_aliases = opts
+        .aliases
+        .iter()
+        .map(|a| Aliases::parse_arg(a))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Aliases::new(config.aliases.clone().into_iter().chain(cli_aliases))
+}
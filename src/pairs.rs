@@ -0,0 +1,248 @@
+use std::collections::HashSet;
+
+use anyhow::bail;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::history::QuoteStore;
+use crate::signal::{AsyncStockSignal, TickerQuote, ZScore};
+
+///
+/// A pair of symbols whose prices usually move together, as in the config file:
+///
+/// ```toml
+/// [[pairs]]
+/// pair = "KO/PEP"
+/// window = 30
+/// threshold = 2.0
+/// ```
+///
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PairConfig {
+    pub pair: String,
+    /// Number of ratios before the latest one that make up the baseline (defaults to 30)
+    #[serde(default)]
+    pub window: Option<usize>,
+    /// The pair diverges when the Z-score of the latest ratio reaches this value (defaults
+    /// to 2)
+    #[serde(default)]
+    pub threshold: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pair {
+    pub first: String,
+    pub second: String,
+    pub window: usize,
+    pub threshold: f64,
+}
+
+impl Pair {
+    pub fn from_config(config: &PairConfig) -> anyhow::Result<Self> {
+        let (first, second) = match config.pair.split_once('/') {
+            Some((first, second)) if !first.trim().is_empty() && !second.trim().is_empty() => {
+                (first.trim(), second.trim())
+            }
+            _ => bail!("Invalid pair '{}', expected e.g. KO/PEP", config.pair),
+        };
+        if first == second {
+            bail!("The pair '{}' needs two different symbols", config.pair);
+        }
+        Ok(Pair {
+            first: first.to_string(),
+            second: second.to_string(),
+            window: config.window.unwrap_or(30),
+            threshold: config.threshold.unwrap_or(2.0),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.first, self.second)
+    }
+
+    fn contains(&self, symbol: &str) -> bool {
+        self.first == symbol || self.second == symbol
+    }
+}
+
+///
+/// The latest ratio and spread of a pair
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PairSpread {
+    pub pair: String,
+    pub timestamp: DateTime<Utc>,
+    /// Close of the first symbol divided by the second's
+    pub ratio: f64,
+    /// Close of the first symbol minus the second's
+    pub spread: f64,
+    /// Z-score of the ratio against the window before it, `None` until the window is filled
+    pub zscore: Option<f64>,
+    /// The Z-score reached the threshold
+    pub diverged: bool,
+}
+
+///
+/// The ratios of the bars both symbols have, as (timestamp, ratio, spread)
+///
+pub fn ratios(first: &[TickerQuote], second: &[TickerQuote]) -> Vec<(u64, f64, f64)> {
+    let mut second = second.iter().peekable();
+    first
+        .iter()
+        .filter_map(|a| {
+            while second.peek().is_some_and(|b| b.timestamp < a.timestamp) {
+                second.next();
+            }
+            let b = second.peek().filter(|b| b.timestamp == a.timestamp)?;
+            (b.close != 0.0).then(|| (a.timestamp, a.close / b.close, a.close - b.close))
+        })
+        .collect()
+}
+
+///
+/// Watches the spreads of the configured pairs; a pair is reported as diverging once when its
+/// Z-score reaches the threshold and again only after it came back
+///
+#[derive(Debug, Clone, Default)]
+pub struct PairMonitor {
+    pub pairs: Vec<Pair>,
+    /// Pairs that are currently diverged
+    diverged: HashSet<String>,
+}
+
+impl PairMonitor {
+    pub fn new(pairs: Vec<Pair>) -> Self {
+        PairMonitor {
+            pairs,
+            diverged: HashSet::new(),
+        }
+    }
+
+    ///
+    /// The spreads of the pairs with `symbol`, and whether each one just started to diverge
+    ///
+    pub async fn update(&mut self, symbol: &str, history: &QuoteStore) -> Vec<(PairSpread, bool)> {
+        let mut spreads = vec![];
+        for pair in self.pairs.iter().filter(|p| p.contains(symbol)) {
+            let ratios = ratios(&history.quotes(&pair.first), &history.quotes(&pair.second));
+            let (timestamp, ratio, spread) = match ratios.last() {
+                Some(last) => *last,
+                None => continue,
+            };
+            let series: Vec<f64> = ratios.iter().map(|(_, ratio, _)| *ratio).collect();
+            let zscore = ZScore {
+                window_size: pair.window,
+            }
+            .calculate(&series)
+            .await;
+            let name = pair.name();
+            let diverged = zscore.is_some_and(|z| z.abs() >= pair.threshold);
+            let started = if diverged {
+                self.diverged.insert(name.clone())
+            } else {
+                self.diverged.remove(&name);
+                false
+            };
+            spreads.push((
+                PairSpread {
+                    pair: name,
+                    timestamp: Utc.timestamp_opt(timestamp as i64, 0).unwrap(),
+                    ratio,
+                    spread,
+                    zscore,
+                    diverged,
+                },
+                started,
+            ));
+        }
+        spreads
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quotes(closes: &[(u64, f64)]) -> Vec<TickerQuote> {
+        closes
+            .iter()
+            .map(|(timestamp, close)| TickerQuote {
+                timestamp: *timestamp,
+                open: *close,
+                high: *close,
+                low: *close,
+                volume: 0,
+                close: *close,
+                adjclose: *close,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pair_config() {
+        let config = |pair: &str| PairConfig {
+            pair: pair.to_string(),
+            window: None,
+            threshold: None,
+        };
+        let pair = Pair::from_config(&config("KO / PEP")).unwrap();
+        assert_eq!(pair.name(), "KO/PEP");
+        assert_eq!((pair.window, pair.threshold), (30, 2.0));
+        assert!(Pair::from_config(&config("KO")).is_err());
+        assert!(Pair::from_config(&config("KO/")).is_err());
+        assert!(Pair::from_config(&config("KO/KO")).is_err());
+    }
+
+    #[test]
+    fn test_ratios() {
+        let first = quotes(&[(1, 10.0), (2, 12.0), (4, 9.0), (5, 8.0)]);
+        let second = quotes(&[(2, 6.0), (3, 5.0), (4, 3.0), (5, 0.0)]);
+        assert_eq!(ratios(&first, &second), vec![(2, 2.0, 6.0), (4, 3.0, 6.0)]);
+    }
+
+    #[async_std::test]
+    async fn test_divergence() {
+        let mut monitor = PairMonitor::new(vec![Pair {
+            first: "KO".to_string(),
+            second: "PEP".to_string(),
+            window: 4,
+            threshold: 2.0,
+        }]);
+        let mut history = QuoteStore::default();
+        history.append(
+            "PEP",
+            &quotes(&[
+                (1, 10.0),
+                (2, 10.0),
+                (3, 10.0),
+                (4, 10.0),
+                (5, 10.0),
+                (6, 10.0),
+                (7, 10.0),
+            ]),
+        );
+        history.append(
+            "KO",
+            &quotes(&[(1, 5.0), (2, 5.1), (3, 4.9), (4, 5.0), (5, 5.05)]),
+        );
+        assert!(monitor.update("AAPL", &history).await.is_empty());
+        let (spread, started) = monitor.update("KO", &history).await.remove(0);
+        assert_eq!(spread.pair, "KO/PEP");
+        assert_eq!(spread.ratio, 0.505);
+        assert!(!spread.diverged && !started);
+
+        history.append("KO", &quotes(&[(6, 7.0)]));
+        let (spread, started) = monitor.update("KO", &history).await.remove(0);
+        assert!(spread.zscore.unwrap() > 2.0);
+        assert!(spread.diverged && started);
+        // still diverged, but not reported again
+        let (_, started) = monitor.update("PEP", &history).await.remove(0);
+        assert!(!started);
+
+        history.append("KO", &quotes(&[(7, 5.0)]));
+        let (spread, _) = monitor.update("KO", &history).await.remove(0);
+        assert!(!spread.diverged);
+    }
+}
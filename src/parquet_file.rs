@@ -0,0 +1,410 @@
+//!
+//! Parquet files of the indicators, written by `export` and, as they come in, by the
+//! `ParquetSink`.
+//!
+use std::fs::File;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use chrono::prelude::*;
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::file::writer::SerializedFileWriter;
+use parquet::record::RowAccessor;
+use parquet::schema::parser::parse_message_type;
+use xactor::*;
+
+use crate::metrics::{self, Observation, Stage};
+use crate::shutdown::Shutdown;
+use crate::sink_queue::RetryQueue;
+use crate::PerformanceIndicators;
+
+///
+/// Parquet schema of a `PerformanceIndicators` row
+///
+const SCHEMA: &str = "
+message performance_indicators {
+    REQUIRED INT64 timestamp (TIMESTAMP(MILLIS,true));
+    REQUIRED BYTE_ARRAY symbol (UTF8);
+    REQUIRED DOUBLE price;
+    REQUIRED DOUBLE pct_change;
+    REQUIRED DOUBLE period_min;
+    REQUIRED DOUBLE period_max;
+    REQUIRED DOUBLE last_sma;
+    REQUIRED DOUBLE last_ema;
+    OPTIONAL DOUBLE rsi;
+    OPTIONAL DOUBLE volatility;
+    OPTIONAL DOUBLE score;
+    OPTIONAL BYTE_ARRAY watchlist (UTF8);
+    OPTIONAL BYTE_ARRAY resolution (UTF8);
+}
+";
+
+///
+/// Writes `PerformanceIndicators` into a Parquet file, one row group per call to `write`.
+///
+pub struct ParquetWriter {
+    inner: SerializedFileWriter<File>,
+}
+
+impl ParquetWriter {
+    pub fn create(path: &str) -> anyhow::Result<Self> {
+        let schema = Arc::new(parse_message_type(SCHEMA)?);
+        let props = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        );
+        let file = File::create(path)?;
+        Ok(ParquetWriter {
+            inner: SerializedFileWriter::new(file, schema, props)?,
+        })
+    }
+
+    ///
+    /// Writes all rows as a single row group.
+    ///
+    pub fn write(&mut self, rows: &[PerformanceIndicators]) -> anyhow::Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let mut row_group = self.inner.next_row_group()?;
+        let mut index = 0;
+        while let Some(mut column) = row_group.next_column()? {
+            match index {
+                0 => {
+                    let values: Vec<i64> = rows
+                        .iter()
+                        .map(|r| r.timestamp.timestamp_millis())
+                        .collect();
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&values, None, None)?;
+                }
+                1 => {
+                    let values: Vec<ByteArray> = rows
+                        .iter()
+                        .map(|r| ByteArray::from(r.symbol.as_str()))
+                        .collect();
+                    column
+                        .typed::<ByteArrayType>()
+                        .write_batch(&values, None, None)?;
+                }
+                2..=7 => {
+                    let values: Vec<f64> = rows.iter().map(|r| double_column(r, index)).collect();
+                    column
+                        .typed::<DoubleType>()
+                        .write_batch(&values, None, None)?;
+                }
+                8..=10 => {
+                    let cells: Vec<Option<f64>> =
+                        rows.iter().map(|r| optional_column(r, index)).collect();
+                    let values: Vec<f64> = cells.iter().flatten().copied().collect();
+                    column.typed::<DoubleType>().write_batch(
+                        &values,
+                        Some(&levels(&cells)),
+                        None,
+                    )?;
+                }
+                _ => {
+                    let cells: Vec<Option<&str>> = rows
+                        .iter()
+                        .map(|r| match index {
+                            11 => r.watchlist.as_deref(),
+                            _ => r.resolution.as_deref(),
+                        })
+                        .collect();
+                    let values: Vec<ByteArray> = cells
+                        .iter()
+                        .flatten()
+                        .map(|v| ByteArray::from(*v))
+                        .collect();
+                    column.typed::<ByteArrayType>().write_batch(
+                        &values,
+                        Some(&levels(&cells)),
+                        None,
+                    )?;
+                }
+            }
+            column.close()?;
+            index += 1;
+        }
+        row_group.close()?;
+        Ok(())
+    }
+
+    ///
+    /// Writes the file footer. The file is unreadable without it.
+    ///
+    pub fn close(self) -> anyhow::Result<()> {
+        self.inner.close()?;
+        Ok(())
+    }
+}
+
+///
+/// Reads all rows of a file written by `ParquetWriter`
+///
+pub fn read(path: &str) -> anyhow::Result<Vec<PerformanceIndicators>> {
+    let reader = SerializedFileReader::new(File::open(path)?)?;
+    let mut rows = vec![];
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        let millis = row.get_timestamp_millis(0)?;
+        let timestamp = Utc
+            .timestamp_millis_opt(millis)
+            .single()
+            .ok_or_else(|| anyhow!("Invalid timestamp {} in '{}'", millis, path))?;
+        rows.push(PerformanceIndicators {
+            timestamp,
+            symbol: row.get_string(1)?.clone(),
+            price: row.get_double(2)?,
+            pct_change: row.get_double(3)?,
+            period_min: row.get_double(4)?,
+            period_max: row.get_double(5)?,
+            last_sma: row.get_double(6)?,
+            last_ema: row.get_double(7)?,
+            rsi: row.get_double(8).ok(),
+            volatility: row.get_double(9).ok(),
+            score: row.get_double(10).ok(),
+            watchlist: row.get_string(11).ok().cloned(),
+            resolution: row.get_string(12).ok().cloned(),
+            ..Default::default()
+        });
+    }
+    Ok(rows)
+}
+
+fn double_column(row: &PerformanceIndicators, index: usize) -> f64 {
+    match index {
+        2 => row.price,
+        3 => row.pct_change,
+        4 => row.period_min,
+        5 => row.period_max,
+        6 => row.last_sma,
+        _ => row.last_ema,
+    }
+}
+
+fn optional_column(row: &PerformanceIndicators, index: usize) -> Option<f64> {
+    match index {
+        8 => row.rsi,
+        9 => row.volatility,
+        _ => row.score,
+    }
+}
+
+///
+/// The definition levels of an optional column: 1 for a value, 0 for null
+///
+fn levels<T>(cells: &[Option<T>]) -> Vec<i16> {
+    cells.iter().map(|c| c.is_some() as i16).collect()
+}
+
+#[message]
+#[derive(Clone)]
+struct Flush;
+
+///
+/// Actor that writes the indicators of all pipelines to a Parquet file, a row group every
+/// `rows` rows or `interval`, whichever comes first. The footer is written on shutdown; the
+/// file can't be read before. Row groups that can't be written are kept in a retry queue.
+///
+pub struct ParquetSink {
+    path: String,
+    rows: usize,
+    interval: Duration,
+    writer: Option<ParquetWriter>,
+    pending: Vec<PerformanceIndicators>,
+    queue: RetryQueue<PerformanceIndicators>,
+}
+
+impl ParquetSink {
+    pub fn new(path: String, rows: usize, interval: Duration, queue_capacity: usize) -> Self {
+        ParquetSink {
+            path,
+            rows: rows.max(1),
+            interval,
+            writer: None,
+            pending: vec![],
+            queue: RetryQueue::new("parquet", queue_capacity),
+        }
+    }
+
+    async fn flush(&mut self) {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        self.queue.extend(self.pending.drain(..));
+        if !self.queue.is_ready() {
+            if !self.queue.is_empty() {
+                self.queue.report().await;
+            }
+            return;
+        }
+        let count = self.queue.len();
+        let started = Instant::now();
+        if let Err(e) = writer.write(self.queue.rows()) {
+            let backoff = self.queue.failed();
+            tracing::error!(
+                "Could not write {} rows to '{}', retrying in {}s: {}",
+                count,
+                self.path,
+                backoff.as_secs(),
+                e
+            );
+            self.queue.report().await;
+            return;
+        }
+        let elapsed = started.elapsed() / count as u32;
+        let rows = self.queue.delivered(count);
+        self.queue.report().await;
+        for row in rows {
+            metrics::record(Observation::duration(
+                Stage::SinkWrite("parquet"),
+                &row.symbol,
+                elapsed,
+            ))
+            .await;
+        }
+    }
+
+    ///
+    /// Writes what's pending and the footer. Nothing is written after that.
+    ///
+    async fn close(&mut self) {
+        self.queue.retry_now();
+        self.flush().await;
+        if let Some(writer) = self.writer.take() {
+            if let Err(e) = writer.close() {
+                tracing::error!("Could not close '{}': {}", self.path, e);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for ParquetSink {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        self.writer = Some(ParquetWriter::create(&self.path)?);
+        ctx.send_interval(Flush, self.interval);
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+
+    async fn stopped(&mut self, _ctx: &mut Context<Self>) {
+        self.close().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for ParquetSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        if self.writer.is_none() {
+            return;
+        }
+        self.pending.push(msg);
+        if self.pending.len() >= self.rows {
+            self.flush().await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Flush> for ParquetSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Flush) {
+        self.flush().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Shutdown> for ParquetSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {
+        self.close().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parquet_roundtrip() {
+        let path = std::env::temp_dir().join("parquet_file_roundtrip.parquet");
+        let path = path.to_str().unwrap();
+        let row = PerformanceIndicators {
+            timestamp: Utc.timestamp_opt(1593777609, 0).unwrap(),
+            symbol: "AAPL".to_string(),
+            price: 91.03,
+            pct_change: -0.0125,
+            period_min: 60.55,
+            period_max: 91.2,
+            last_sma: 87.74,
+            custom: Default::default(),
+            ..Default::default()
+        };
+        let rated = PerformanceIndicators {
+            rsi: Some(55.0),
+            watchlist: Some("tech".to_string()),
+            ..row.clone()
+        };
+        let mut writer = ParquetWriter::create(path).unwrap();
+        writer.write(&[row.clone(), rated]).unwrap();
+        writer.write(&[row]).unwrap();
+        writer.close().unwrap();
+
+        let reader = SerializedFileReader::new(File::open(path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(rows[0].get_string(1).unwrap(), "AAPL");
+        assert_eq!(rows[0].get_double(2).unwrap(), 91.03);
+        assert!(rows[0].get_double(8).is_err());
+        assert_eq!(rows[1].get_double(8).unwrap(), 55.0);
+        assert_eq!(rows[1].get_string(11).unwrap(), "tech");
+        assert!(rows[2].get_string(11).is_err());
+
+        let read = read(path).unwrap();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[1].timestamp, Utc.timestamp_opt(1593777609, 0).unwrap());
+        assert_eq!(read[1].last_sma, 87.74);
+        assert_eq!(read[1].rsi, Some(55.0));
+        assert_eq!(read[1].watchlist.as_deref(), Some("tech"));
+        assert_eq!((read[2].rsi, read[2].resolution.as_ref()), (None, None));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_sink_row_groups() {
+        let path = std::env::temp_dir().join("parquet_file_sink.parquet");
+        let path = path.to_str().unwrap().to_string();
+        let mut sink = ParquetSink::new(path.clone(), 2, Duration::from_secs(60), 100);
+        sink.writer = Some(ParquetWriter::create(&path).unwrap());
+        for price in [1.0, 2.0, 3.0] {
+            sink.pending.push(PerformanceIndicators {
+                symbol: "AAPL".to_string(),
+                price,
+                ..Default::default()
+            });
+            if sink.pending.len() >= sink.rows {
+                sink.flush().await;
+            }
+        }
+        // the timer writes the rest as a smaller row group
+        sink.flush().await;
+        sink.close().await;
+
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
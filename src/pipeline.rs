@@ -0,0 +1,1459 @@
+//!
+//! The pipeline for embedding in another service: fetching, quality checks, and the signal
+//! calculation, with the indicators going to callbacks, CSV files, and a buffer. The command
+//! line, the config file, and the HTTP API stay out of it, unless the builder gets the
+//! command line's flags (see `cli::run`).
+//!
+//! ```no_run
+//! use std::time::Duration;
+//!
+//! use manning_lp_async_rust_project_2_m1_solution::provider::YahooProvider;
+//! use manning_lp_async_rust_project_2_m1_solution::Pipeline;
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let pipeline = Pipeline::builder()
+//!     .symbols(["AAPL", "MSFT"])
+//!     .provider(YahooProvider::default)
+//!     .interval(Duration::from_secs(60))
+//!     .sink(|indicators| println!("{} {}", indicators.symbol, indicators.price))
+//!     .build()
+//!     .await?;
+//! // ...
+//! pipeline.stop().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The actors talk through global brokers, so there is one pipeline per process.
+//!
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_executor::Executor;
+use chrono::prelude::*;
+use futures::future::BoxFuture;
+use xactor::*;
+
+use crate::alert::{AlertEngine, NamedRule, ThresholdRules};
+use crate::alphavantage::AlphaVantageProvider;
+use crate::anomaly::Boost;
+use crate::audit::AuditLog;
+use crate::backfill::{BackfillTracker, ProgressBar, ProgressEvent};
+use crate::broadcast::Broadcaster;
+use crate::buffer::{BufferSink, Overflow};
+use crate::calendar::Calendar;
+use crate::checkpoint::Checkpoints;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::clock::SharedClock;
+use crate::concurrency_limit::ConcurrencyLimit;
+use crate::csv_schema::CsvSchema;
+use crate::daily::DailySummarizer;
+use crate::derived::{Derived, DerivedSymbols};
+use crate::envelope::{Freshness, FreshnessTracker, InFlight};
+use crate::file_replay::FileReplayProvider;
+use crate::file_sink::{
+    AuditFileSink, CurrentFile, DuplicateRows, RollingFileSink, Rotation, SinkFiles,
+};
+use crate::group::GroupAggregator;
+use crate::http_runtime::HttpRuntime;
+use crate::identifier::TickerResolver;
+use crate::index::Constituents;
+use crate::influx::{InfluxConfig, InfluxSink};
+use crate::leaderboard::Leaderboard;
+use crate::listen::{Endpoint, Listener};
+use crate::metadata::SymbolDirectory;
+use crate::metrics::Metrics;
+use crate::notify::DesktopNotifySink;
+use crate::number_format::NumberFormat;
+use crate::options::{config_dir, csv_schema, load_aliases, load_config, Opts};
+use crate::parquet_file::ParquetSink;
+use crate::provider::{
+    Assignments, DataProvider, Drain, DrainAllRequest, Provider, ProviderRouter, YahooProvider,
+    PROVIDERS,
+};
+use crate::quality::{CleanQuotes, DataQuality};
+use crate::quota::{QuotaLimit, QuotaTracker};
+use crate::quote_log::QuoteLog;
+use crate::recording::Recorder;
+use crate::registry::SymbolRegistry;
+use crate::repair::RepairJob;
+use crate::report::Reporter;
+use crate::response_cache::{CacheInvalidator, ResponseCache};
+use crate::retry::{DeadLetterLog, RetryPolicy};
+use crate::scheduler::{Fire, ScheduleGroup, Scheduler, Trigger};
+use crate::script::Script;
+use crate::shutdown::Shutdown;
+use crate::signal::SignalSet;
+use crate::sink_queue::{self, RetryQueue};
+use crate::snapshot::{AppState, Snapshotter, TakeSnapshot};
+use crate::sqlite_sink::SqliteSink;
+use crate::synthetic::{SoakReport, SyntheticProvider};
+use crate::tiering::{ColdStore, Tiering};
+use crate::trailing_stop::TrailingStop;
+use crate::wal::WalSink;
+use crate::webhook::WebhookSink;
+use crate::{
+    clock, export, listen, recording, scheduler, script, shutdown, snapshot, synthetic,
+    trailing_stop,
+};
+use crate::{
+    server, start_downloaders, start_rate_limiter, PerformanceIndicators, ProcessorConfig,
+    QuoteRequest, Quotes, State, StockDataDownloader,
+};
+
+type Callback = Box<dyn FnMut(&PerformanceIndicators) + Send>;
+
+type StartRouter =
+    Box<dyn FnOnce(usize) -> BoxFuture<'static, Result<Addr<ProviderRouter>>> + Send>;
+
+///
+/// Sets up a `Pipeline`, see the module docs
+///
+pub struct PipelineBuilder {
+    symbols: Vec<String>,
+    from: Option<DateTime<Utc>>,
+    trigger: Trigger,
+    once: bool,
+    router: Option<StartRouter>,
+    concurrency: usize,
+    signals: SignalSet,
+    callbacks: Vec<Callback>,
+    csv_files: Vec<String>,
+    buffer_capacity: usize,
+    clock: SharedClock,
+    api: Option<ApiFiles>,
+    options: Option<Box<Opts>>,
+}
+
+///
+/// The files of the HTTP API's state, see `PipelineBuilder::api`
+///
+struct ApiFiles {
+    audit_log: String,
+    metadata_cache: String,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        PipelineBuilder {
+            symbols: vec![],
+            from: None,
+            trigger: Trigger::Every(Duration::from_secs(30)),
+            once: false,
+            router: None,
+            concurrency: 4,
+            signals: SignalSet::default(),
+            callbacks: vec![],
+            csv_files: vec![],
+            buffer_capacity: 10000,
+            clock: clock::system(),
+            api: None,
+            options: None,
+        }
+    }
+}
+
+impl PipelineBuilder {
+    pub fn symbols<I, S>(mut self, symbols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.symbols = symbols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    ///
+    /// Fetch from this time on (defaults to now)
+    ///
+    pub fn from(mut self, from: DateTime<Utc>) -> Self {
+        self.from = Some(from);
+        self
+    }
+
+    ///
+    /// Time between two fetches (defaults to 30 seconds, at least a second)
+    ///
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.trigger = Trigger::Every(interval);
+        self
+    }
+
+    ///
+    /// Fetch when a cron expression matches instead of at an interval
+    ///
+    pub fn schedule(mut self, cron: &str) -> anyhow::Result<Self> {
+        self.trigger = Trigger::from_config(Some(cron), None, Duration::ZERO)?;
+        Ok(self)
+    }
+
+    ///
+    /// Fetch everything up to now once, then stop (see `Pipeline::wait`)
+    ///
+    pub fn once(mut self) -> Self {
+        self.once = true;
+        self
+    }
+
+    ///
+    /// Where the quotes come from. `create` makes a provider for each of the downloaders,
+    /// which retry network errors and suspend symbols that fail over and over like those of
+    /// the command line.
+    ///
+    pub fn provider<P, F>(mut self, create: F) -> Self
+    where
+        P: DataProvider,
+        F: Fn() -> P + Clone + Send + Sync + 'static,
+    {
+        let start: StartRouter = Box::new(move |concurrency| {
+            Box::pin(async move {
+                let pool = start_downloaders(concurrency, move || {
+                    StockDataDownloader::new(
+                        create(),
+                        false,
+                        CircuitBreaker::new(5, Duration::from_secs(600)),
+                        RetryPolicy {
+                            attempts: 3,
+                            base: Duration::from_millis(500),
+                            jitter: 0.5,
+                        },
+                    )
+                })
+                .await?;
+                // the router keeps the downloaders alive
+                Supervisor::start(move || {
+                    let provider = Provider::pool(&pool);
+                    let name = "embedded".to_string();
+                    ProviderRouter::new(
+                        BTreeMap::from([(name.clone(), provider)]),
+                        Assignments {
+                            default: name,
+                            symbols: BTreeMap::new(),
+                        },
+                    )
+                })
+                .await
+            })
+        });
+        self.router = Some(start);
+        self
+    }
+
+    ///
+    /// Symbols fetched at the same time (defaults to 4)
+    ///
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    ///
+    /// The signals calculated for every symbol
+    ///
+    pub fn signals(mut self, signals: SignalSet) -> Self {
+        self.signals = signals;
+        self
+    }
+
+    ///
+    /// Calls `sink` with every row of indicators
+    ///
+    pub fn sink<F>(mut self, sink: F) -> Self
+    where
+        F: FnMut(&PerformanceIndicators) + Send + 'static,
+    {
+        self.callbacks.push(Box::new(sink));
+        self
+    }
+
+    ///
+    /// Writes the indicators to a CSV file, like the command line does
+    ///
+    pub fn csv(mut self, filename: impl Into<String>) -> Self {
+        self.csv_files.push(filename.into());
+        self
+    }
+
+    ///
+    /// The rows `Pipeline::buffer` holds at most (defaults to 10000, 0 for no limit)
+    ///
+    pub fn buffer_capacity(mut self, capacity: usize) -> Self {
+        self.buffer_capacity = capacity;
+        self
+    }
+
+    ///
+    /// Source of the current time (defaults to the system's), e.g. a test clock. Fetches
+    /// request the quotes up to it.
+    ///
+    pub(crate) fn clock(mut self, clock: SharedClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    ///
+    /// Also sets up the state of the HTTP API (see `Pipeline::state`) without serving it.
+    /// Audited calls are appended to `audit_log`, and the symbols' metadata is only read from
+    /// `metadata_cache`, never looked up.
+    ///
+    pub(crate) fn api(
+        mut self,
+        audit_log: impl Into<String>,
+        metadata_cache: impl Into<String>,
+    ) -> Self {
+        self.api = Some(ApiFiles {
+            audit_log: audit_log.into(),
+            metadata_cache: metadata_cache.into(),
+        });
+        self
+    }
+
+    ///
+    /// Sets up everything from the flags of the command line (and its config file) instead:
+    /// the providers, the sinks, the HTTP API, the snapshots, and the rest. The other settings
+    /// of the builder are ignored.
+    ///
+    pub(crate) fn options(mut self, opts: Opts) -> Self {
+        self.options = Some(Box::new(opts));
+        self
+    }
+
+    ///
+    /// Starts the actors and the first fetch
+    ///
+    pub async fn build(self) -> anyhow::Result<Pipeline> {
+        if let Some(opts) = self.options {
+            return from_options(*opts).await;
+        }
+        if self.symbols.is_empty() {
+            anyhow::bail!("The pipeline has no symbols");
+        }
+        if let Trigger::Every(interval) = self.trigger {
+            scheduler::check_interval(interval, self.symbols.len(), "", &[])?;
+        }
+        let start_router = match self.router {
+            Some(start_router) => start_router,
+            None => anyhow::bail!("The pipeline has no provider"),
+        };
+        let router = start_router(self.concurrency.max(1)).await?;
+        let quality = DataQuality::default().start().await?;
+        let processor = ProcessorConfig {
+            default_signals: self.signals,
+            gap_threshold: 0.01,
+            ..Default::default()
+        }
+        .processor()
+        .start()
+        .await?;
+        let buffer = BufferSink::new(None, self.buffer_capacity, Overflow::default())
+            .start()
+            .await?;
+        let callbacks = match self.callbacks.is_empty() {
+            true => None,
+            false => Some(Callbacks(self.callbacks).start().await?),
+        };
+        let mut sinks: Vec<Caller<Shutdown>> = callbacks.iter().map(Addr::caller).collect();
+        let mut files = vec![];
+        let mut csv_files = vec![];
+        for filename in self.csv_files {
+            let current = CurrentFile::new(RwLock::new(filename.clone()));
+            let sink = RollingFileSink {
+                filename: filename.clone(),
+                writer: None,
+                watchlist: None,
+                schema: CsvSchema::default(),
+                duplicates: DuplicateRows::Skip,
+                rotation: Rotation::default(),
+                current: current.clone(),
+                queue: RetryQueue::new("file", sink_queue::DEFAULT_CAPACITY),
+            };
+            let file = sink.start().await?;
+            sinks.push(file.caller());
+            files.push(file);
+            csv_files.push(SinkFiles {
+                base: filename,
+                rotation: Rotation::default(),
+                current,
+            });
+        }
+        let clock = self.clock;
+        // started before the first request, to see all of them
+        let backfill = BackfillTracker::new(clock.clone()).start().await?;
+        let backfilled = match self.once {
+            true => Some(Backfilled.start().await?),
+            false => None,
+        };
+        let registry = SymbolRegistry::default().start().await?;
+        let mut api_actors: Vec<Box<dyn Any + Send>> = vec![];
+        // before the first fetch, which the metadata is published for
+        let state = match self.api {
+            Some(api) => {
+                let audit_sink =
+                    AuditFileSink::new(api.audit_log.clone(), sink_queue::DEFAULT_CAPACITY)
+                        .start()
+                        .await?;
+                sinks.push(audit_sink.caller());
+                let freshness = Freshness::new(clock.clone());
+                let tracker = FreshnessTracker {
+                    freshness: freshness.clone(),
+                }
+                .start()
+                .await?;
+                api_actors.push(Box::new(audit_sink));
+                api_actors.push(Box::new(tracker));
+                Some(State {
+                    buffer: buffer.clone(),
+                    metrics: Metrics::new(None).start().await?,
+                    audit: AuditLog::new(api.audit_log).start().await?,
+                    quality: quality.clone(),
+                    quota: QuotaTracker::new(&[], clock.clone()).start().await?,
+                    leaderboard: Leaderboard::default().start().await?,
+                    symbols: SymbolDirectory::new(Some(api.metadata_cache), HashMap::new())
+                        .offline()
+                        .start()
+                        .await?,
+                    groups: GroupAggregator::new(HashMap::new(), None).start().await?,
+                    backfill: backfill.clone(),
+                    providers: router.clone(),
+                    broadcaster: Broadcaster::default().start().await?,
+                    executor: Arc::new(Executor::new()),
+                    registry: registry.clone(),
+                    aliases: Default::default(),
+                    trailing_stops: TrailingStop::new(&BTreeMap::new(), clock.now())
+                        .start()
+                        .await?,
+                    processor: processor.clone(),
+                    // every request reaches the actors
+                    cache: ResponseCache::new(Duration::ZERO, freshness.clone()),
+                    limit: ConcurrencyLimit::new(0),
+                    watchlists: Arc::new(BTreeMap::new()),
+                    csv_files: csv_files.into_iter().next().unwrap_or_default(),
+                    alert_rules: Default::default(),
+                    numbers: Arc::new(NumberFormat::default()),
+                    freshness,
+                    buffer_calls: InFlight::default(),
+                    tiering: Tiering::new(None, Duration::ZERO, vec![buffer.clone()], None)
+                        .start()
+                        .await?,
+                })
+            }
+            None => None,
+        };
+        let scheduler = Scheduler {
+            from: self.from.unwrap_or_else(|| clock.now()),
+            groups: vec![ScheduleGroup {
+                name: "default".to_string(),
+                symbols: self.symbols,
+                trigger: self.trigger,
+                watchlist: None,
+            }],
+            checkpoints: Checkpoints::default(),
+            checkpoint_file: None,
+            warmup: Duration::ZERO,
+            warmed: HashSet::new(),
+            constituents: Constituents {
+                url: None,
+                refresh: None,
+            },
+            tickers: TickerResolver::default(),
+            resolved: vec![],
+            unknown: HashSet::new(),
+            throttle: 1.0,
+            boost: None,
+            boosted: HashMap::new(),
+            clock,
+            once: self.once,
+            calendar: Calendar::default(),
+            registry,
+        }
+        .start()
+        .await?;
+        Ok(Pipeline {
+            buffer,
+            scheduler: Some(scheduler),
+            router,
+            quality: quality.caller(),
+            derived: None,
+            processor: processor.caller(),
+            sinks,
+            backfilled,
+            http_runtime: None,
+            snapshotter: None,
+            state,
+            _actors: vec![
+                Box::new(backfill),
+                Box::new(quality),
+                Box::new(processor),
+                Box::new(callbacks),
+                Box::new(files),
+                Box::new(api_actors),
+            ],
+        })
+    }
+}
+
+///
+/// Actor that hands every row to the callbacks of `PipelineBuilder::sink`
+///
+struct Callbacks(Vec<Callback>);
+
+#[async_trait::async_trait]
+impl Actor for Callbacks {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for Callbacks {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        for callback in &mut self.0 {
+            callback(&msg);
+        }
+    }
+}
+
+///
+/// Answered once the callbacks saw the rows that arrived before
+///
+#[async_trait::async_trait]
+impl Handler<Shutdown> for Callbacks {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {}
+}
+
+///
+/// Actor that stops once every symbol was fetched the first time
+///
+struct Backfilled;
+
+#[async_trait::async_trait]
+impl Actor for Backfilled {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<ProgressEvent>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<ProgressEvent> for Backfilled {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: ProgressEvent) {
+        if msg.complete {
+            ctx.stop(None);
+        }
+    }
+}
+
+///
+/// A running pipeline. Dropping it stops the actors, `stop` also waits for the fetches in
+/// flight and flushes the sinks.
+///
+pub struct Pipeline {
+    buffer: Addr<BufferSink>,
+    /// `None` while a recording is replayed
+    scheduler: Option<Addr<Scheduler>>,
+    router: Addr<ProviderRouter>,
+    /// The stages between the providers and the sinks, see `stop`
+    quality: Caller<Shutdown>,
+    derived: Option<Caller<Shutdown>>,
+    processor: Caller<Shutdown>,
+    /// Everything that gets the indicators, flushed in this order
+    sinks: Vec<Caller<Shutdown>>,
+    /// Stops after the first fetch of every symbol, with `once`
+    backfilled: Option<Addr<Backfilled>>,
+    http_runtime: Option<HttpRuntime>,
+    snapshotter: Option<Addr<Snapshotter>>,
+    /// The state of the HTTP API, if the pipeline has one
+    state: Option<State>,
+    /// Actors stop once their last address is gone
+    _actors: Vec<Box<dyn Any + Send>>,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    ///
+    /// The latest indicators, to query with `BufferDataRequest` and the other messages of
+    /// `buffer`
+    ///
+    pub fn buffer(&self) -> &Addr<BufferSink> {
+        &self.buffer
+    }
+
+    ///
+    /// The state of the HTTP API, to serve it with `server`
+    ///
+    pub(crate) fn state(&self) -> Option<&State> {
+        self.state.as_ref()
+    }
+
+    ///
+    /// Fetches the symbols of the builder now, besides the scheduled fetches
+    ///
+    pub(crate) fn fetch_now(&self) -> Result<()> {
+        match &self.scheduler {
+            Some(scheduler) => scheduler.send(Fire { group: 0 }),
+            None => Ok(()),
+        }
+    }
+
+    ///
+    /// Waits until every symbol is fetched with `once`, otherwise until the scheduler stops (a
+    /// replay runs until it's interrupted)
+    ///
+    pub async fn wait(&self) {
+        match (&self.backfilled, &self.scheduler) {
+            (Some(backfilled), _) => backfilled.clone().wait_for_stop().await,
+            (None, Some(scheduler)) => scheduler.clone().wait_for_stop().await,
+            (None, None) => futures::future::pending().await,
+        }
+    }
+
+    ///
+    /// Waits like `wait`, or until the process is interrupted, and stops then
+    ///
+    pub async fn run(self) -> anyhow::Result<()> {
+        let signal = shutdown::on_signal()?;
+        self.run_until(async move {
+            signal.recv().await.ok();
+        })
+        .await
+    }
+
+    ///
+    /// Waits like `wait`, or until `interrupted` is ready, and stops then
+    ///
+    pub async fn run_until(self, interrupted: impl Future<Output = ()>) -> anyhow::Result<()> {
+        async_std::prelude::FutureExt::race(self.wait(), interrupted).await;
+        self.stop().await
+    }
+
+    ///
+    /// Stops fetching, lets the fetches in flight finish, flushes the sinks, and stops the
+    /// HTTP API
+    ///
+    pub async fn stop(mut self) -> anyhow::Result<()> {
+        // no new requests, and the ones in flight are answered
+        if let Some(scheduler) = &mut self.scheduler {
+            scheduler.stop(None).ok();
+        }
+        shutdown::barrier::<QuoteRequest>().await?;
+        for drain in self.router.call(DrainAllRequest).await? {
+            drain.call(Drain).await.ok();
+        }
+        // every stage handled what the one before published, up to the sinks
+        shutdown::barrier::<Quotes>().await?;
+        self.quality.call(Shutdown).await?;
+        shutdown::barrier::<CleanQuotes>().await?;
+        if let Some(derived) = &self.derived {
+            derived.call(Shutdown).await?;
+            shutdown::barrier::<CleanQuotes>().await?;
+        }
+        self.processor.call(Shutdown).await?;
+        shutdown::barrier::<PerformanceIndicators>().await?;
+        for sink in &self.sinks {
+            sink.call(Shutdown).await?;
+        }
+        if let Some(http_runtime) = self.http_runtime.take() {
+            async_std::task::spawn_blocking(move || http_runtime.stop()).await;
+        }
+        self.snapshot().await
+    }
+
+    ///
+    /// Writes the last snapshot, with `--snapshot`
+    ///
+    async fn snapshot(&self) -> anyhow::Result<()> {
+        if let Some(snapshotter) = &self.snapshotter {
+            snapshotter.call(TakeSnapshot).await??;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Serves the HTTP API on its own threads
+///
+pub(crate) fn serve(opts: &Opts, state: &State) -> anyhow::Result<HttpRuntime> {
+    let listener = Listener::open(&Endpoint::parse(&opts.listen))
+        .map_err(|e| anyhow::anyhow!("Could not listen on '{}': {}", opts.listen, e))?;
+    listen::notify_ready(&format!("Serving on {}", listener.describe()));
+    let app = server(state.clone());
+    let runtime = HttpRuntime::start(state.executor.clone(), opts.http_threads)?;
+    let executor = runtime.executor().clone();
+    runtime.spawn(async move {
+        if let Err(e) = listener.serve(app, executor).await {
+            tracing::error!("The server failed: {}", e);
+        }
+    });
+    Ok(runtime)
+}
+
+///
+/// Sets up the pipeline of the command line, see `PipelineBuilder::options`
+///
+async fn from_options(opts: Opts) -> anyhow::Result<Pipeline> {
+    let from: DateTime<Utc> = match &opts.from {
+        Some(from) => from.parse().expect("Couldn't parse 'from' date"),
+        None => Utc::now(),
+    };
+    let symbols: Vec<String> = match opts.synthetic {
+        Some(n) => synthetic::symbols(n),
+        None => opts
+            .symbols
+            .split(',')
+            .map(|s| s.trim().to_owned())
+            .collect(),
+    };
+
+    // Start actors. Supervisors also keep those actors alive
+    let clock = clock::system();
+    let complete_bars = opts.complete_bars;
+    let (breaker_failures, breaker_backoff) = (opts.breaker_failures, opts.breaker_backoff);
+    let concurrency = opts.max_concurrency;
+    let retry = RetryPolicy {
+        attempts: opts.retry_attempts.max(1),
+        base: Duration::from_millis(opts.retry_delay),
+        jitter: opts.retry_jitter,
+    };
+    let limiter = start_rate_limiter(opts.max_requests_per_minute).await?;
+    let downloader = start_downloaders(concurrency, move || {
+        StockDataDownloader::new(
+            YahooProvider::default(),
+            complete_bars,
+            CircuitBreaker::new(breaker_failures, breaker_backoff),
+            retry,
+        )
+        .rate_limited(limiter.clone())
+    })
+    .await?;
+    let synthetic = start_downloaders(concurrency, move || {
+        StockDataDownloader::new(
+            SyntheticProvider::default(),
+            complete_bars,
+            CircuitBreaker::new(breaker_failures, breaker_backoff),
+            retry,
+        )
+    })
+    .await?;
+    let quality = Supervisor::start(DataQuality::default).await?;
+    let backfill_clock = clock.clone();
+    let backfill = Supervisor::start(move || BackfillTracker::new(backfill_clock.clone())).await?;
+    let limits = opts
+        .quotas
+        .iter()
+        .map(|q| QuotaLimit::from_arg(q))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let quota_clock = clock.clone();
+    let quota_limits = limits.clone();
+    let quota =
+        Supervisor::start(move || QuotaTracker::new(&quota_limits, quota_clock.clone())).await?;
+    let mut config = load_config(&opts)?;
+    let aliases = load_aliases(&opts, &config)?;
+    config.resolve_aliases(&aliases);
+    let symbols: Vec<String> = symbols.iter().map(|s| aliases.resolve(s)).collect();
+    let assignments = Assignments {
+        default: match (&opts.provider, opts.synthetic) {
+            (Some(provider), _) => provider.clone(),
+            (None, Some(_)) => "synthetic".to_string(),
+            (None, None) => "yahoo".to_string(),
+        },
+        symbols: config
+            .symbols
+            .iter()
+            .filter_map(|(symbol, o)| o.provider.clone().map(|p| (symbol.clone(), p)))
+            .collect(),
+    };
+    if !PROVIDERS.contains(&assignments.default.as_str()) {
+        anyhow::bail!(
+            "Unknown provider '{}', expected one of {}",
+            assignments.default,
+            PROVIDERS.join(", ")
+        );
+    }
+    let assigned = |name: &str| {
+        std::iter::once(&assignments.default)
+            .chain(assignments.symbols.values())
+            .any(|p| p == name)
+    };
+    let alphavantage = match &opts.api_key {
+        Some(key) => {
+            let key = key.clone();
+            let limiter = start_rate_limiter(opts.max_requests_per_minute).await?;
+            Some(
+                start_downloaders(concurrency, move || {
+                    StockDataDownloader::new(
+                        AlphaVantageProvider::new(key.clone()),
+                        complete_bars,
+                        CircuitBreaker::new(breaker_failures, breaker_backoff),
+                        retry,
+                    )
+                    .rate_limited(limiter.clone())
+                })
+                .await?,
+            )
+        }
+        None if assigned("alphavantage") => {
+            anyhow::bail!("The provider 'alphavantage' needs --api-key")
+        }
+        None => None,
+    };
+    let file_replay = match &opts.replay_dir {
+        Some(dir) => {
+            let dir = dir.clone();
+            Some(
+                start_downloaders(concurrency, move || {
+                    StockDataDownloader::new(
+                        FileReplayProvider::new(dir.clone()),
+                        complete_bars,
+                        CircuitBreaker::new(breaker_failures, breaker_backoff),
+                        retry,
+                    )
+                })
+                .await?,
+            )
+        }
+        None if assigned("file") => anyhow::bail!("The provider 'file' needs --replay-dir"),
+        None => None,
+    };
+    let providers_of = assignments.clone();
+    let providers = Supervisor::start(move || {
+        let mut providers = BTreeMap::from([
+            ("yahoo".to_string(), Provider::pool(&downloader)),
+            ("synthetic".to_string(), Provider::pool(&synthetic)),
+        ]);
+        if let Some(alphavantage) = &alphavantage {
+            providers.insert("alphavantage".to_string(), Provider::pool(alphavantage));
+        }
+        if let Some(file_replay) = &file_replay {
+            providers.insert("file".to_string(), Provider::pool(file_replay));
+        }
+        ProviderRouter::new(providers, assignments.clone())
+    })
+    .await?;
+    let mut groups = if opts.synthetic.is_some() {
+        vec![ScheduleGroup {
+            name: "synthetic".to_string(),
+            symbols: symbols.clone(),
+            trigger: Trigger::Every(Duration::from_secs(opts.synthetic_interval.max(1))),
+            watchlist: None,
+        }]
+    } else if config.schedules.is_empty() {
+        vec![ScheduleGroup {
+            name: "default".to_string(),
+            symbols: symbols.clone(),
+            trigger: Trigger::from_config(opts.schedule.as_deref(), None, opts.interval)?,
+            watchlist: None,
+        }]
+    } else {
+        config
+            .schedules
+            .iter()
+            .map(|s| {
+                Ok(ScheduleGroup {
+                    name: s.name.clone(),
+                    symbols: s.symbols.clone(),
+                    trigger: Trigger::from_config(s.cron.as_deref(), s.interval, opts.interval)?,
+                    watchlist: None,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
+    for (name, watchlist) in &config.watchlists {
+        groups.push(ScheduleGroup {
+            name: name.clone(),
+            symbols: watchlist.symbols.clone(),
+            trigger: Trigger::from_config(
+                watchlist.cron.as_deref(),
+                watchlist.interval,
+                opts.interval,
+            )?,
+            watchlist: Some(name.clone()),
+        });
+    }
+    let intervals = config
+        .symbols
+        .iter()
+        .filter_map(|(symbol, o)| o.interval.map(|i| (symbol.clone(), i)))
+        .collect();
+    let groups = ScheduleGroup::split_intervals(groups, &intervals);
+    for group in &groups {
+        if let Trigger::Every(interval) = group.trigger {
+            let mut by_provider = BTreeMap::from([(providers_of.default.as_str(), 0)]);
+            for symbol in &group.symbols {
+                let provider = providers_of
+                    .symbols
+                    .get(symbol)
+                    .unwrap_or(&providers_of.default);
+                *by_provider.entry(provider.as_str()).or_default() += 1;
+            }
+            for (provider, count) in by_provider {
+                scheduler::check_interval(interval, count, provider, &limits)
+                    .map_err(|e| anyhow::anyhow!("Schedule '{}': {}", group.name, e))?;
+            }
+        }
+    }
+    let processor_config = ProcessorConfig::load(&opts, &config)?;
+    let warmup = opts
+        .history_window
+        .unwrap_or_else(|| processor_config.lookback());
+    let config_dir = config_dir(&opts);
+    let engine = script::engine();
+    let alert_rules = config
+        .alerts
+        .iter()
+        .filter(|c| c.rule.is_none())
+        .map(|c| Script::from_config(&engine, c, &config_dir))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let threshold_rules = ThresholdRules::new(RwLock::new(
+        config
+            .alerts
+            .iter()
+            .filter_map(|c| {
+                c.rule.clone().map(|rule| NamedRule {
+                    name: c.name.clone(),
+                    rule,
+                })
+            })
+            .collect(),
+    ));
+    let engine_rules = threshold_rules.clone();
+
+    let overrides = processor_config.overrides.clone();
+    let processor = Supervisor::start(move || processor_config.processor()).await?;
+    let derived = Derived::from_config(&config.derived)?;
+    let derived_symbols = match derived.is_empty() {
+        true => None,
+        false => Some(Supervisor::start(move || DerivedSymbols::new(derived.clone())).await?),
+    };
+    // Tags and sectors from the config file. Started before the directory, which publishes the
+    // cached sectors right away.
+    let tags: HashMap<String, BTreeSet<String>> = config
+        .symbols
+        .iter()
+        .map(|(symbol, c)| {
+            let groups = c.tags.iter().chain(c.sector.iter()).cloned().collect();
+            (symbol.clone(), groups)
+        })
+        .collect();
+    let group_csv = opts.group_csv.clone();
+    let group_aggregator =
+        Supervisor::start(move || GroupAggregator::new(tags.clone(), group_csv.clone())).await?;
+    let metadata_cache = opts.metadata_cache.clone();
+    let offline = opts.synthetic.is_some();
+    let directory_aliases = aliases.clone();
+    let symbol_directory = Supervisor::start(move || {
+        let directory = SymbolDirectory::new(metadata_cache.clone(), overrides.clone())
+            .with_aliases(directory_aliases.clone());
+        if offline {
+            directory.offline()
+        } else {
+            directory
+        }
+    })
+    .await?;
+    let quote_log = match opts.quote_log.clone() {
+        Some(path) => Some(Supervisor::start(move || QuoteLog::new(path.clone())).await?),
+        None => None,
+    };
+    let dead_letters = match opts.dead_letters.clone() {
+        Some(path) => Some(Supervisor::start(move || DeadLetterLog::new(path.clone())).await?),
+        None => None,
+    };
+    let recorder = match opts.record.clone() {
+        Some(path) => {
+            let clock = clock.clone();
+            Some(Supervisor::start(move || Recorder::new(path.clone(), clock.clone())).await?)
+        }
+        None => None,
+    };
+    let calendar = Calendar::load(opts.holidays.as_deref())?;
+    let repair_job = match opts.quote_log.clone() {
+        Some(path) if opts.repair_interval > 0 => {
+            let interval = Duration::from_secs(opts.repair_interval);
+            let calendar = calendar.clone();
+            Some(
+                Supervisor::start(move || RepairJob::new(path.clone(), interval, calendar.clone()))
+                    .await?,
+            )
+        }
+        _ => None,
+    };
+    let daily_summarizer = match opts.daily_summary.clone() {
+        Some(path) => {
+            let email = opts.summary_email.clone();
+            let clock = clock.clone();
+            let calendar = calendar.clone();
+            Some(
+                Supervisor::start(move || {
+                    DailySummarizer::new(
+                        path.clone(),
+                        email.clone(),
+                        clock.clone(),
+                        calendar.clone(),
+                    )
+                })
+                .await?,
+            )
+        }
+        None => None,
+    };
+    let reporter = match config.reports.clone() {
+        Some(reports) => {
+            let portfolio = config.portfolio.clone();
+            let clock = clock.clone();
+            Some(
+                Supervisor::start(move || {
+                    Reporter::new(reports.clone(), portfolio.clone(), clock.clone())
+                })
+                .await?,
+            )
+        }
+        None => None,
+    };
+    let mut notify_rules: HashSet<String> = config
+        .alerts
+        .iter()
+        .filter(|c| c.notify)
+        .map(|c| c.name.clone())
+        .collect();
+    if config.trailing_stops.values().any(|c| c.notify) {
+        notify_rules.insert(trailing_stop::RULE.to_string());
+    }
+    let stops = config.trailing_stops.clone();
+    let watch_start = clock.now();
+    let trailing_stops = Supervisor::start(move || TrailingStop::new(&stops, watch_start)).await?;
+    let default_thresholds = config.thresholds.clone();
+    let thresholds: HashMap<String, BTreeMap<String, f64>> = config
+        .symbols
+        .keys()
+        .map(|symbol| (symbol.clone(), config.thresholds(symbol)))
+        .collect();
+    let alerts = Supervisor::start(move || {
+        AlertEngine::new(
+            alert_rules.clone(),
+            engine_rules.clone(),
+            default_thresholds.clone(),
+            thresholds.clone(),
+        )
+    })
+    .await?;
+    let notifications = if notify_rules.is_empty() {
+        None
+    } else {
+        Some(Supervisor::start(move || DesktopNotifySink::new(notify_rules.clone())).await?)
+    };
+    let duplicates = opts.duplicate_rows;
+    let schema = csv_schema(&opts, &config)?;
+    let file_schema = schema.clone();
+    let sink_file = format!("{}.csv", Utc::now().timestamp()); // create a unique file name every time
+    let csv_file = CurrentFile::new(RwLock::new(sink_file.clone()));
+    let sink_current = csv_file.clone();
+    let rotation = Rotation {
+        daily: opts.csv_rotate_daily,
+        max_size: opts.csv_max_size,
+        gzip: opts.csv_gzip,
+    };
+    let file_rotation = rotation.clone();
+    let csv_files = SinkFiles {
+        base: sink_file.clone(),
+        rotation: rotation.clone(),
+        current: csv_file.clone(),
+    };
+    let sink_queue = opts.sink_queue;
+    let sink = if opts.no_csv {
+        None
+    } else {
+        Some(
+            Supervisor::start(move || RollingFileSink {
+                filename: sink_file.clone(),
+                writer: None,
+                watchlist: None,
+                schema: file_schema.clone(),
+                duplicates,
+                rotation: file_rotation.clone(),
+                current: sink_current.clone(),
+                queue: RetryQueue::new("file", sink_queue),
+            })
+            .await?,
+        )
+    };
+    let sqlite = match &opts.sqlite {
+        Some(path) => {
+            let path = path.clone();
+            Some(Supervisor::start(move || SqliteSink::new(path.clone(), sink_queue)).await?)
+        }
+        None => None,
+    };
+    let parquet = match &opts.parquet {
+        Some(path) => {
+            let (path, rows, flush) = (path.clone(), opts.parquet_rows, opts.parquet_flush);
+            Some(
+                Supervisor::start(move || ParquetSink::new(path.clone(), rows, flush, sink_queue))
+                    .await?,
+            )
+        }
+        None => None,
+    };
+
+    let webhook = match &opts.webhook_sink {
+        Some(url) => {
+            let webhook = WebhookSink::new(url.clone())?;
+            let wal = opts.webhook_wal.clone();
+            Some(Supervisor::start(move || WalSink::new(wal.clone(), webhook.clone())).await?)
+        }
+        None => None,
+    };
+    let influx = match &opts.influx_url {
+        Some(url) => {
+            let influx = InfluxSink::new(InfluxConfig {
+                url: url.clone(),
+                bucket: opts.influx_bucket.clone(),
+                org: opts.influx_org.clone(),
+                token: opts.influx_token.clone(),
+                measurement: opts.influx_measurement.clone(),
+            })?;
+            let wal = opts.influx_wal.clone();
+            Some(Supervisor::start(move || WalSink::new(wal.clone(), influx.clone())).await?)
+        }
+        None => None,
+    };
+
+    let (buffer_capacity, buffer_overflow) = (opts.buffer_capacity, opts.buffer_overflow);
+    let data_actor =
+        Supervisor::start(move || BufferSink::new(None, buffer_capacity, buffer_overflow)).await?;
+
+    // Every watchlist gets its own sinks
+    let mut watchlist_buffers = BTreeMap::new();
+    let mut watchlist_sinks = Vec::new();
+    for (name, watchlist) in &config.watchlists {
+        let filename = watchlist
+            .csv
+            .clone()
+            .unwrap_or_else(|| format!("{}-{}.csv", name, Utc::now().timestamp()));
+        let tag = Some(name.clone());
+        let file_tag = tag.clone();
+        let schema = schema.clone();
+        let queue_name = format!("file:{}", name);
+        let rotation = rotation.clone();
+        let sink = Supervisor::start(move || RollingFileSink {
+            filename: filename.clone(),
+            writer: None,
+            watchlist: file_tag.clone(),
+            schema: schema.clone(),
+            duplicates,
+            rotation: rotation.clone(),
+            current: CurrentFile::default(),
+            queue: RetryQueue::new(queue_name.clone(), sink_queue),
+        })
+        .await?;
+        watchlist_sinks.push(sink);
+        let buffer = Supervisor::start(move || {
+            BufferSink::new(tag.clone(), buffer_capacity, buffer_overflow)
+        })
+        .await?;
+        watchlist_buffers.insert(name.clone(), buffer);
+    }
+
+    let restored = match &opts.restore {
+        Some(path) => Some(AppState::load(path)?),
+        None => None,
+    };
+    let checkpoints = restored
+        .as_ref()
+        .map(|s| s.checkpoints.clone())
+        .unwrap_or_default();
+    let mut fetched = match &opts.checkpoints {
+        Some(path) => Checkpoints::load(path)?,
+        None => Checkpoints::default(),
+    };
+    for (key, timestamp) in &checkpoints {
+        fetched.advance(key.clone(), *timestamp);
+    }
+    // the snapshot brings the histories along, these aren't read again
+    let warmed: HashSet<String> = checkpoints.keys().cloned().collect();
+    if let Some(state) = restored {
+        tracing::info!(
+            "Restoring {} buffered records from '{}'",
+            state.buffer.len(),
+            opts.restore.as_deref().unwrap_or_default()
+        );
+        snapshot::restore(state, &data_actor, &watchlist_buffers, &processor, &alerts)?;
+    }
+    let snapshotter = match opts.snapshot.clone() {
+        Some(filename) => {
+            let buffer = data_actor.clone();
+            let watchlists = watchlist_buffers.clone();
+            let processor = processor.clone();
+            let alerts = alerts.clone();
+            // the checkpoint file's too, in case nothing is fetched before the next snapshot
+            let checkpoints = fetched.clone();
+            let interval = Duration::from_secs(opts.snapshot_interval);
+            Some(
+                Supervisor::start(move || Snapshotter {
+                    filename: filename.clone(),
+                    interval,
+                    buffer: buffer.clone(),
+                    watchlists: watchlists.clone(),
+                    processor: processor.clone(),
+                    alerts: alerts.clone(),
+                    checkpoints: checkpoints.clone(),
+                })
+                .await?,
+            )
+        }
+        None => None,
+    };
+
+    if let Some(path) = &opts.import {
+        let targets: Vec<Sender<PerformanceIndicators>> = std::iter::once(data_actor.sender())
+            .chain(watchlist_buffers.values().map(Addr::sender))
+            .chain(sink.iter().map(Addr::sender))
+            .chain(watchlist_sinks.iter().map(Addr::sender))
+            .chain(sqlite.iter().map(Addr::sender))
+            .chain(parquet.iter().map(Addr::sender))
+            .chain(influx.iter().map(Addr::sender))
+            .collect();
+        let count = export::import(path, &targets)?;
+        tracing::info!("Imported {} records from '{}'", count, path);
+    }
+
+    let soak_report = if opts.synthetic.is_some() && opts.soak_report > 0 {
+        let interval = Duration::from_secs(opts.soak_report);
+        let buffer = data_actor.clone();
+        Some(Supervisor::start(move || SoakReport::new(interval, buffer.clone())).await?)
+    } else {
+        None
+    };
+    let leaderboard = Supervisor::start(Leaderboard::default).await?;
+    let broadcaster = Supervisor::start(Broadcaster::default).await?;
+    let summary = Some(Duration::from_secs(opts.metrics_summary)).filter(|d| !d.is_zero());
+    let metrics = Supervisor::start(move || Metrics::new(summary)).await?;
+    let audit_log = opts.audit_log.clone();
+    let audit = Supervisor::start(move || AuditLog::new(audit_log.clone())).await?;
+    // also with `--no-csv`
+    let audit_log = opts.audit_log.clone();
+    let audit_sink =
+        Supervisor::start(move || AuditFileSink::new(audit_log.clone(), sink_queue)).await?;
+    let freshness = Freshness::new(clock.clone());
+    let tracked = freshness.clone();
+    let freshness_tracker = Supervisor::start(move || FreshnessTracker {
+        freshness: tracked.clone(),
+    })
+    .await?;
+    let cache = ResponseCache::new(Duration::from_millis(opts.cache_ttl), freshness.clone())
+        .with_aliases(aliases.clone());
+    let invalidated = cache.clone();
+    let cache_invalidator = Supervisor::start(move || CacheInvalidator {
+        cache: invalidated.clone(),
+    })
+    .await?;
+
+    let registry = Supervisor::start(SymbolRegistry::default).await?;
+    let cold = match &opts.cold_dir {
+        Some(dir) => Some(ColdStore::open(dir)?),
+        None => None,
+    };
+    let hot: Vec<_> = std::iter::once(data_actor.clone())
+        .chain(watchlist_buffers.values().cloned())
+        .collect();
+    let (cold_after, sqlite_path) = (opts.cold_after, opts.sqlite.clone());
+    let tiering = Supervisor::start(move || {
+        Tiering::new(cold.clone(), cold_after, hot.clone(), sqlite_path.clone())
+    })
+    .await?;
+
+    // Also keeps the actors alive without a server
+    let state = State {
+        buffer: data_actor.clone(),
+        metrics,
+        audit,
+        quality,
+        quota,
+        leaderboard,
+        symbols: symbol_directory,
+        groups: group_aggregator,
+        backfill,
+        providers,
+        broadcaster,
+        executor: Arc::new(Executor::new()),
+        registry: registry.clone(),
+        trailing_stops,
+        processor,
+        cache,
+        limit: ConcurrencyLimit::new(opts.max_concurrent_requests),
+        watchlists: Arc::new(watchlist_buffers),
+        csv_files,
+        alert_rules: threshold_rules,
+        numbers: Arc::new(NumberFormat::from_config(&config.format)?),
+        freshness,
+        buffer_calls: InFlight::default(),
+        aliases: Arc::new(aliases),
+        tiering,
+    };
+
+    let http_runtime = match opts.once {
+        true => None,
+        false => Some(serve(&opts, &state)?),
+    };
+    // Stops once every symbol is fetched
+    let (progress_bar, backfilled) = match opts.once {
+        true => (
+            Some(ProgressBar.start().await?),
+            Some(Backfilled.start().await?),
+        ),
+        false => (None, None),
+    };
+
+    // CSV header
+    println!("period start,symbol,price,change %,min,max,30d avg,ema,rsi,volatility");
+    // The scheduler stops when it can't publish requests anymore
+    let scheduler = match &opts.replay {
+        Some(path) => {
+            let entries = recording::read(path)?;
+            let speed = opts.replay_speed;
+            async_std::task::spawn(async move {
+                match recording::replay(entries, speed).await {
+                    Ok(count) => tracing::info!("Replayed {} messages", count),
+                    Err(e) => tracing::error!("The replay failed: {}", e),
+                }
+            });
+            None
+        }
+        None => Some(
+            Scheduler {
+                from,
+                groups,
+                checkpoints: fetched,
+                checkpoint_file: opts.checkpoints.clone(),
+                warmup,
+                warmed,
+                constituents: Constituents {
+                    url: opts.constituents_url.clone(),
+                    refresh: Some(Duration::from_secs(opts.constituents_refresh))
+                        .filter(|d| !d.is_zero()),
+                },
+                tickers: TickerResolver::new(
+                    opts.ticker_lookup,
+                    opts.openfigi_key.clone(),
+                    opts.ticker_cache.clone(),
+                ),
+                resolved: vec![],
+                unknown: HashSet::new(),
+                throttle: 1.0,
+                boost: Some(Boost {
+                    interval: Duration::from_secs(opts.anomaly_interval),
+                    period: Duration::from_secs(opts.anomaly_period),
+                })
+                .filter(|b| !b.interval.is_zero()),
+                boosted: HashMap::new(),
+                clock,
+                once: opts.once,
+                calendar,
+                registry,
+            }
+            .start()
+            .await?,
+        ),
+    };
+    let mut sinks: Vec<Caller<Shutdown>> = sink
+        .iter()
+        .chain(&watchlist_sinks)
+        .map(Addr::caller)
+        .collect();
+    sinks.extend(sqlite.iter().map(Addr::caller));
+    sinks.extend(parquet.iter().map(Addr::caller));
+    sinks.extend(webhook.iter().map(Addr::caller));
+    sinks.extend(influx.iter().map(Addr::caller));
+    sinks.extend(quote_log.iter().map(Addr::caller));
+    sinks.extend(recorder.iter().map(Addr::caller));
+    sinks.extend(daily_summarizer.iter().map(Addr::caller));
+    sinks.push(audit_sink.caller());
+    Ok(Pipeline {
+        buffer: state.buffer.clone(),
+        scheduler,
+        router: state.providers.clone(),
+        quality: state.quality.caller(),
+        derived: derived_symbols.as_ref().map(Addr::caller),
+        processor: state.processor.caller(),
+        sinks,
+        backfilled,
+        http_runtime,
+        snapshotter,
+        state: Some(state),
+        _actors: vec![
+            Box::new(sink),
+            Box::new(audit_sink),
+            Box::new(watchlist_sinks),
+            Box::new(sqlite),
+            Box::new(parquet),
+            Box::new(derived_symbols),
+            Box::new(quote_log),
+            Box::new(dead_letters),
+            Box::new(recorder),
+            Box::new(repair_job),
+            Box::new(daily_summarizer),
+            Box::new(reporter),
+            Box::new(alerts),
+            Box::new(notifications),
+            Box::new(webhook),
+            Box::new(influx),
+            Box::new(soak_report),
+            Box::new(cache_invalidator),
+            Box::new(freshness_tracker),
+            Box::new(progress_bar),
+        ],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditEntry;
+    use clap::Parser;
+
+    #[async_std::test]
+    async fn test_audit_log_without_csv() {
+        let dir = std::env::temp_dir().join("pipeline_audit_no_csv");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let audit_log = dir.join("audit.jsonl").to_str().unwrap().to_string();
+        // no symbols, so nothing is fetched
+        let opts = Opts::parse_from(vec![
+            "stocks",
+            "--synthetic",
+            "0",
+            "--once",
+            "--no-csv",
+            "--audit-log",
+            &audit_log,
+        ]);
+        let pipeline = Pipeline::builder().options(opts).build().await.unwrap();
+        let entry = AuditEntry {
+            timestamp: Utc.timestamp_opt(1593777609, 0).unwrap(),
+            remote: Some("127.0.0.1:4711".to_string()),
+            method: "DELETE".to_string(),
+            path: "/symbols/AAPL".to_string(),
+            status: 200,
+        };
+        Broker::from_registry()
+            .await
+            .unwrap()
+            .publish(entry.clone())
+            .unwrap();
+        pipeline.stop().await.unwrap();
+
+        let lines = std::fs::read_to_string(&audit_log).unwrap();
+        let written: Vec<AuditEntry> = lines
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert!(written.contains(&entry));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
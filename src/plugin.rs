@@ -0,0 +1,162 @@
+//!
+//! Host for user supplied signals compiled to WebAssembly.
+//!
+//! A plugin module has to export:
+//!
+//! - `memory`: its linear memory
+//! - `alloc(len: i32) -> i32`: returns a pointer to `len` bytes the host may write to
+//! - `calculate(input: i32, n: i32, output: i32) -> i32`: reads `n` little-endian `f64` values
+//!   at `input`, writes up to `n` result values to `output`, and returns how many it wrote
+//!   (or a negative number if there is no result for this series).
+//!
+//! The host calls `alloc` twice per calculation (input and output buffer), so a simple bump
+//! allocator that grows the memory as needed is enough.
+//!
+use anyhow::{anyhow, bail, Context as _};
+use async_trait::async_trait;
+use std::convert::TryInto;
+use wasmi::{Engine, Linker, Module, Store};
+
+use crate::signal::AsyncStockSignal;
+
+const F64_SIZE: usize = std::mem::size_of::<f64>();
+
+///
+/// A named signal implemented by a WASM module
+///
+#[derive(Clone)]
+pub struct SignalPlugin {
+    pub name: String,
+    engine: Engine,
+    module: Module,
+}
+
+impl SignalPlugin {
+    ///
+    /// Compiles a plugin from a `.wasm` (or `.wat`) file.
+    ///
+    pub fn load(name: &str, path: &str) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path).with_context(|| format!("Could not read '{}'", path))?;
+        Self::from_bytes(name, &bytes)
+    }
+
+    pub fn from_bytes(name: &str, wasm: &[u8]) -> anyhow::Result<Self> {
+        let engine = Engine::default();
+        let module =
+            Module::new(&engine, wasm).map_err(|e| anyhow!("Invalid plugin '{}': {}", name, e))?;
+        Ok(SignalPlugin {
+            name: name.to_string(),
+            engine,
+            module,
+        })
+    }
+
+    ///
+    /// Parses `name=path/to/plugin.wasm` and loads the plugin.
+    ///
+    pub fn from_arg(arg: &str) -> anyhow::Result<Self> {
+        match arg.split_once('=') {
+            Some((name, path)) if !name.is_empty() => Self::load(name.trim(), path.trim()),
+            _ => bail!("Expected a plugin as 'name=path.wasm', got '{}'", arg),
+        }
+    }
+
+    fn run(&self, series: &[f64]) -> anyhow::Result<Option<Vec<f64>>> {
+        let mut store = Store::new(&self.engine, ());
+        let instance = Linker::<()>::new(&self.engine)
+            .instantiate_and_start(&mut store, &self.module)
+            .map_err(|e| anyhow!(e))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| anyhow!("plugin '{}' does not export 'memory'", self.name))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|e| anyhow!(e))?;
+        let calculate = instance
+            .get_typed_func::<(i32, i32, i32), i32>(&store, "calculate")
+            .map_err(|e| anyhow!(e))?;
+
+        let len = (series.len() * F64_SIZE) as i32;
+        let input = alloc.call(&mut store, len).map_err(|e| anyhow!(e))?;
+        let output = alloc.call(&mut store, len).map_err(|e| anyhow!(e))?;
+        let bytes: Vec<u8> = series.iter().flat_map(|v| v.to_le_bytes()).collect();
+        memory
+            .write(&mut store, input as usize, &bytes)
+            .map_err(|e| anyhow!(e))?;
+
+        let written = calculate
+            .call(&mut store, (input, series.len() as i32, output))
+            .map_err(|e| anyhow!(e))?;
+        if written < 0 {
+            return Ok(None);
+        }
+        let written = (written as usize).min(series.len());
+        let mut out = vec![0u8; written * F64_SIZE];
+        memory
+            .read(&store, output as usize, &mut out)
+            .map_err(|e| anyhow!(e))?;
+        Ok(Some(
+            out.chunks_exact(F64_SIZE)
+                .map(|c| f64::from_le_bytes(c.try_into().unwrap()))
+                .collect(),
+        ))
+    }
+}
+
+#[async_trait]
+impl AsyncStockSignal for SignalPlugin {
+    type SignalType = Vec<f64>;
+
+    async fn calculate(&self, series: &[f64]) -> Option<Self::SignalType> {
+        match self.run(series) {
+            Ok(values) => values,
+            Err(e) => {
+                tracing::error!("Plugin '{}' failed: {}", self.name, e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(non_snake_case)]
+    use super::*;
+
+    /// Doubles every value, the output has the same length as the input
+    const DOUBLE: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 0))
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "calculate") (param $in i32) (param $n i32) (param $out i32) (result i32)
+            (local $i i32)
+            (block $done
+              (loop $loop
+                (br_if $done (i32.ge_u (local.get $i) (local.get $n)))
+                (f64.store
+                  (i32.add (local.get $out) (i32.mul (local.get $i) (i32.const 8)))
+                  (f64.mul
+                    (f64.load (i32.add (local.get $in) (i32.mul (local.get $i) (i32.const 8))))
+                    (f64.const 2)))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $loop)))
+            (local.get $n)))
+    "#;
+
+    #[async_std::test]
+    async fn test_SignalPlugin_calculate() {
+        let signal = SignalPlugin::from_bytes("double", DOUBLE.as_bytes()).unwrap();
+        assert_eq!(signal.calculate(&[]).await, Some(vec![]));
+        assert_eq!(
+            signal.calculate(&[1.0, 2.5, -3.0]).await,
+            Some(vec![2.0, 5.0, -6.0])
+        );
+        assert!(SignalPlugin::from_bytes("broken", b"(module").is_err());
+        assert!(SignalPlugin::from_arg("no-path").is_err());
+    }
+}
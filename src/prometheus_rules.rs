@@ -0,0 +1,467 @@
+//!
+//! The `prometheus-rules` command converts the alert conditions of the config file into a
+//! Prometheus alerting rules file over the `indicator_<name>` gauges of `/metrics`, so the
+//! alerts can be routed through an existing Alertmanager.
+//!
+//! Conditions are translated to PromQL as far as they only compare indicators, numbers, and
+//! `thresholds.<name>` with arithmetic, `&&` and `||`. Symbols with thresholds of their own get
+//! a rule of their own. Other conditions are listed as comments in the file.
+//!
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+
+use crate::config::Config;
+use crate::metrics::GAUGES;
+
+///
+/// Write the alert conditions of the config file as Prometheus alerting rules
+///
+#[derive(Args, Debug)]
+pub struct PrometheusRulesOpts {
+    /// The YAML file to write, stdout if not set
+    #[clap(short, long)]
+    pub output: Option<String>,
+    /// How long a condition must hold before the alert fires, e.g. `5m`
+    #[clap(long = "for")]
+    pub pending: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+}
+
+const OPERATORS: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "(", ")", ".",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut rest = source.trim().trim_end_matches(';');
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            let number = rest[..end].replace('_', "");
+            tokens.push(Token::Number(number.parse()?));
+            rest = &rest[end..];
+        } else if c.is_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| anyhow!("'{}' is not supported", c))?;
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    /// An indicator gauge
+    Gauge(String),
+    Neg(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn is_condition(&self) -> bool {
+        matches!(
+            self,
+            Expr::Binary("&&" | "||" | "==" | "!=" | "<" | "<=" | ">" | ">=", _, _)
+        )
+    }
+}
+
+///
+/// Recursive descent over the tokens, with the precedence of rhai (and PromQL): `||`, `&&`,
+/// comparisons, `+ -`, `* /`, unary minus
+///
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    thresholds: &'a BTreeMap<String, f64>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek_op(&self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[&'static str],
+        operand: fn(&mut Self) -> Result<Expr>,
+    ) -> Result<Expr> {
+        let mut left = operand(self)?;
+        while let Some(op) = self.peek_op(ops) {
+            self.pos += 1;
+            let right = operand(self)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        self.binary(&["||"], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        self.binary(&["&&"], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        self.binary(&["==", "!=", "<=", ">=", "<", ">"], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr> {
+        self.binary(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        self.binary(&["*", "/"], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.peek_op(&["-"]).is_some() {
+            self.pos += 1;
+            return Ok(match self.unary()? {
+                Expr::Number(n) => Expr::Number(-n),
+                e => Expr::Neg(Box::new(e)),
+            });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| anyhow!("unexpected end"))?;
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Op("(") => {
+                let inner = self.or()?;
+                match self.peek_op(&[")"]) {
+                    Some(_) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    None => bail!("missing ')'"),
+                }
+            }
+            Token::Ident(name) if name == "thresholds" => {
+                match (self.peek_op(&["."]), self.tokens.get(self.pos + 1)) {
+                    (Some(_), Some(Token::Ident(threshold))) => {
+                        self.pos += 2;
+                        self.thresholds
+                            .get(threshold)
+                            .map(|value| Expr::Number(*value))
+                            .ok_or_else(|| anyhow!("there is no threshold '{}'", threshold))
+                    }
+                    _ => bail!("'thresholds' without a name"),
+                }
+            }
+            Token::Ident(name) if GAUGES.iter().any(|(gauge, _)| *gauge == name) => {
+                Ok(Expr::Gauge(name))
+            }
+            Token::Ident(name) => bail!("'{}' is not an exported indicator", name),
+            Token::Op(op) => bail!("unexpected '{}'", op),
+        }
+    }
+}
+
+///
+/// Parses a condition, with the thresholds of a symbol
+///
+fn parse(source: &str, thresholds: &BTreeMap<String, f64>) -> Result<Expr> {
+    let tokens = tokenize(source)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        thresholds,
+    };
+    let expr = parser.or()?;
+    if let Some(token) = tokens.get(parser.pos) {
+        bail!("unexpected {:?}", token);
+    }
+    if !expr.is_condition() {
+        bail!("not a comparison");
+    }
+    Ok(expr)
+}
+
+///
+/// The names of the thresholds a condition uses
+///
+fn threshold_names(source: &str) -> BTreeSet<String> {
+    let tokens = tokenize(source).unwrap_or_default();
+    tokens
+        .windows(3)
+        .filter_map(|w| match w {
+            [Token::Ident(t), Token::Op("."), Token::Ident(name)] if t == "thresholds" => {
+                Some(name.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+///
+/// Renders the expression as PromQL, `selector` are the label matchers of every gauge
+///
+fn promql(expr: &Expr, selector: &str) -> Result<String> {
+    let operand = |e: &Expr| -> Result<String> {
+        Ok(match e {
+            Expr::Binary(..) => format!("({})", promql(e, selector)?),
+            _ => promql(e, selector)?,
+        })
+    };
+    Ok(match expr {
+        Expr::Number(n) => n.to_string(),
+        Expr::Gauge(name) if selector.is_empty() => format!("indicator_{}", name),
+        Expr::Gauge(name) => format!("indicator_{}{{{}}}", name, selector),
+        Expr::Neg(inner) => format!("-{}", operand(inner)?),
+        Expr::Binary(op, left, right) => {
+            if let (Expr::Number(_), Expr::Number(_)) = (left.as_ref(), right.as_ref()) {
+                bail!("compares two constants");
+            }
+            let op = match *op {
+                "&&" => "and",
+                "||" => "or",
+                op => op,
+            };
+            if matches!(op, "and" | "or") && !(left.is_condition() && right.is_condition()) {
+                bail!("'{}' needs comparisons on both sides", op);
+            }
+            format!("{} {} {}", operand(left)?, op, operand(right)?)
+        }
+    })
+}
+
+///
+/// A rule of the file, or the reason a condition couldn't be translated
+///
+#[derive(Debug, Clone, PartialEq)]
+enum Rule {
+    Alert { name: String, expr: String },
+    Skipped { name: String, reason: String },
+}
+
+fn label_value(symbol: &str) -> String {
+    symbol.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+///
+/// Translates a condition: one rule for the default thresholds, and one for every symbol that
+/// overrides a threshold the condition uses
+///
+fn rules(name: &str, source: &str, config: &Config) -> Vec<Rule> {
+    let used = threshold_names(source);
+    let own: Vec<&String> = config
+        .symbols
+        .keys()
+        .filter(|symbol| {
+            let thresholds = config.thresholds(symbol);
+            used.iter()
+                .any(|t| thresholds.get(t) != config.thresholds.get(t))
+        })
+        .collect();
+    let mut variants = vec![(
+        own.iter()
+            .map(|s| format!("symbol!=\"{}\"", label_value(s)))
+            .collect::<Vec<_>>()
+            .join(","),
+        config.thresholds.clone(),
+    )];
+    variants.extend(own.iter().map(|s| {
+        (
+            format!("symbol=\"{}\"", label_value(s)),
+            config.thresholds(s),
+        )
+    }));
+    let translated: Result<Vec<String>> = variants
+        .iter()
+        .map(|(selector, thresholds)| promql(&parse(source, thresholds)?, selector))
+        .collect();
+    match translated {
+        Ok(exprs) => exprs
+            .into_iter()
+            .map(|expr| Rule::Alert {
+                name: name.to_string(),
+                expr,
+            })
+            .collect(),
+        Err(e) => vec![Rule::Skipped {
+            name: name.to_string(),
+            reason: e.to_string(),
+        }],
+    }
+}
+
+///
+/// Writes the rules file, a YAML document. Strings are quoted as JSON, which YAML reads too.
+///
+fn write_rules(out: &mut impl Write, rules: &[Rule], pending: Option<&str>) -> io::Result<()> {
+    let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+    writeln!(
+        out,
+        "# Alert conditions of the config file, see `prometheus-rules`"
+    )?;
+    writeln!(out, "groups:")?;
+    writeln!(out, "  - name: stock-alerts")?;
+    let alerts = rules.iter().any(|r| matches!(r, Rule::Alert { .. }));
+    writeln!(out, "    rules:{}", if alerts { "" } else { " []" })?;
+    for rule in rules {
+        match rule {
+            Rule::Alert { name, expr } => {
+                writeln!(out, "      - alert: {}", quote(name))?;
+                writeln!(out, "        expr: {}", quote(expr))?;
+                if let Some(pending) = pending {
+                    writeln!(out, "        for: {}", pending)?;
+                }
+                writeln!(out, "        annotations:")?;
+                let summary = format!(
+                    "'{}' triggered for {{{{ $labels.symbol }}}} at {{{{ $value }}}}",
+                    name
+                );
+                writeln!(out, "          summary: {}", quote(&summary))?;
+            }
+            Rule::Skipped { name, reason } => {
+                writeln!(out, "      # '{}' is not translated: {}", name, reason)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+///
+/// Runs the `prometheus-rules` command
+///
+pub fn run(opts: &PrometheusRulesOpts, config: &Config, base: &Path) -> Result<()> {
+    let mut all = vec![];
+    for alert in &config.alerts {
+        let source = alert.source(base)?;
+        all.extend(rules(&alert.name, &source, config));
+    }
+    for rule in &all {
+        if let Rule::Skipped { name, reason } = rule {
+            tracing::warn!("Alert '{}' is not translated: {}", name, reason);
+        }
+    }
+    match &opts.output {
+        Some(path) => {
+            let mut out = BufWriter::new(File::create(path)?);
+            write_rules(&mut out, &all, opts.pending.as_deref())?;
+            out.flush()?;
+        }
+        None => write_rules(&mut io::stdout().lock(), &all, opts.pending.as_deref())?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_promql() {
+        let thresholds = BTreeMap::from([("drop".to_string(), -0.05)]);
+        let translate = |source: &str| promql(&parse(source, &thresholds)?, "");
+        assert_eq!(
+            translate("pct_change < thresholds.drop").unwrap(),
+            "indicator_pct_change < -0.05"
+        );
+        assert_eq!(
+            translate("rsi > 70 && (price >= high_52w || price > last_sma * 1.1)").unwrap(),
+            "(indicator_rsi > 70) and ((indicator_price >= indicator_high_52w) or \
+             (indicator_price > (indicator_last_sma * 1.1)))"
+        );
+        assert_eq!(
+            translate("gap_pct <= -thresholds.drop").unwrap(),
+            "indicator_gap_pct <= 0.05"
+        );
+        for unsupported in [
+            "symbol == \"AAPL\"",
+            "my_indicator > 1",
+            "price",
+            "price > 1 && rsi",
+            "thresholds.rise > 0",
+            "mean(closes) > 1",
+        ] {
+            assert!(translate(unsupported).is_err(), "{}", unsupported);
+        }
+    }
+
+    #[test]
+    fn test_rules_file() {
+        let config: Config = toml::from_str(
+            r#"
+            [[alerts]]
+            name = "drop"
+            script = "pct_change < thresholds.drop"
+
+            [[alerts]]
+            name = "high"
+            template = "new_52w_high"
+
+            [[alerts]]
+            name = "custom"
+            script = "my_indicator > 1"
+
+            [thresholds]
+            drop = -0.05
+
+            [symbols.BTC-USD]
+            thresholds = { drop = -0.15 }
+            "#,
+        )
+        .unwrap();
+        let mut all = vec![];
+        for alert in &config.alerts {
+            all.extend(rules(
+                &alert.name,
+                &alert.source(Path::new(".")).unwrap(),
+                &config,
+            ));
+        }
+        assert_eq!(all.len(), 4);
+
+        let mut out = vec![];
+        write_rules(&mut out, &all, Some("5m")).unwrap();
+        let yaml = String::from_utf8(out).unwrap();
+        assert!(yaml.contains(
+            "      - alert: \"drop\"\n        \
+             expr: \"indicator_pct_change{symbol!=\\\"BTC-USD\\\"} < -0.05\"\n        \
+             for: 5m\n"
+        ));
+        assert!(yaml.contains("expr: \"indicator_pct_change{symbol=\\\"BTC-USD\\\"} < -0.15\""));
+        assert!(yaml.contains("expr: \"indicator_price >= indicator_high_52w\""));
+        assert!(yaml.contains(
+            "      # 'custom' is not translated: 'my_indicator' is not an exported indicator\n"
+        ));
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use anyhow::bail;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+use yahoo_finance_api as yahoo;
+
+use crate::signal::{DataSourceError, TickerQuote};
+use crate::QuoteRequest;
+
+///
+/// Names of the data providers that can be assigned to symbols
+///
+pub const PROVIDERS: &[&str] = &["yahoo", "synthetic", "alphavantage", "file"];
+
+///
+/// A source of quotes. `StockDataDownloader` turns any provider into an actor that answers
+/// `QuoteRequest`s, so new providers don't need to know about the actors.
+///
+#[async_trait::async_trait]
+pub trait DataProvider: Send + 'static {
+    ///
+    /// Name of the provider in metrics, quotas, and fetch outcomes, e.g. `yahoo`
+    ///
+    fn name(&self) -> &str;
+
+    ///
+    /// The quotes of a symbol between `from` and `to`, sorted by time
+    ///
+    async fn fetch_quotes(
+        &mut self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> std::result::Result<Vec<TickerQuote>, DataSourceError>;
+}
+
+///
+/// Quotes from the Yahoo! Finance API
+///
+pub struct YahooProvider {
+    connector: yahoo::YahooConnector,
+}
+
+impl Default for YahooProvider {
+    fn default() -> Self {
+        YahooProvider {
+            connector: yahoo::YahooConnector::new(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl DataProvider for YahooProvider {
+    fn name(&self) -> &str {
+        "yahoo"
+    }
+
+    async fn fetch_quotes(
+        &mut self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> std::result::Result<Vec<TickerQuote>, DataSourceError> {
+        self.connector
+            .get_quote_history(symbol, to_offset(from), to_offset(to))
+            .await
+            .and_then(|response| response.quotes())
+    }
+}
+
+///
+/// Converts chrono's timestamps into what the yahoo API expects
+///
+fn to_offset(dt: DateTime<Utc>) -> time::OffsetDateTime {
+    time::OffsetDateTime::from_unix_timestamp(dt.timestamp()).expect("timestamp out of range")
+}
+
+///
+/// Answered by a provider once it has handled every request sent before, i.e. when the
+/// requests in flight are drained
+///
+#[message]
+pub struct Drain;
+
+///
+/// The provider actors the router forwards requests to. Each one fetches a symbol at a time,
+/// so a pool of them fetches that many symbols at once.
+///
+pub struct Provider {
+    requests: Vec<Sender<QuoteRequest>>,
+    drain: Vec<Arc<Caller<Drain>>>,
+}
+
+impl Provider {
+    pub fn pool<A: Handler<QuoteRequest> + Handler<Drain>>(addrs: &[Addr<A>]) -> Self {
+        Provider {
+            requests: addrs.iter().map(|addr| addr.sender()).collect(),
+            drain: addrs.iter().map(|addr| Arc::new(addr.caller())).collect(),
+        }
+    }
+
+    ///
+    /// The actor of the pool that fetches a symbol. It's always the same one, so what an
+    /// actor keeps about a symbol stays in one place and its fetches don't overtake each other.
+    ///
+    fn member(&self, symbol: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        symbol.hash(&mut hasher);
+        (hasher.finish() % self.requests.len() as u64) as usize
+    }
+}
+
+///
+/// Which provider fetches which symbols
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Assignments {
+    /// Fetches all symbols without an assignment of their own
+    pub default: String,
+    pub symbols: BTreeMap<String, String>,
+}
+
+///
+/// Switches the default provider, or the provider of a single symbol. Requests that were
+/// already sent to the old provider are still handled by it.
+///
+#[message(result = "anyhow::Result<SwitchedProvider>")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SwitchProvider {
+    pub provider: String,
+    #[serde(default)]
+    pub symbol: Option<String>,
+}
+
+///
+/// The assignments after a switch and a way to wait for the old provider
+///
+pub struct SwitchedProvider {
+    pub assignments: Assignments,
+    /// Drain the actors of the provider that was replaced, if it was a different one
+    pub previous: Vec<Arc<Caller<Drain>>>,
+}
+
+///
+/// Published after a switch, e.g. for the scheduler to try the symbols the old provider didn't
+/// know again
+///
+#[message]
+#[derive(Debug, Clone)]
+pub struct ProviderSwitched {
+    pub provider: String,
+    /// The symbol that was switched, `None` for the default provider
+    pub symbol: Option<String>,
+}
+
+///
+/// Request the current assignments
+///
+#[message(result = "Assignments")]
+pub struct AssignmentsRequest;
+
+///
+/// The drains of all providers, to wait for the requests in flight (e.g. at shutdown)
+///
+#[message(result = "Vec<Arc<Caller<Drain>>>")]
+pub struct DrainAllRequest;
+
+///
+/// Actor that forwards every `QuoteRequest` to the provider assigned to the symbol
+///
+pub struct ProviderRouter {
+    providers: BTreeMap<String, Provider>,
+    assignments: Assignments,
+}
+
+impl ProviderRouter {
+    pub fn new(providers: BTreeMap<String, Provider>, assignments: Assignments) -> Self {
+        ProviderRouter {
+            providers,
+            assignments,
+        }
+    }
+
+    fn provider(&self, symbol: &str) -> &str {
+        self.assignments
+            .symbols
+            .get(symbol)
+            .unwrap_or(&self.assignments.default)
+    }
+
+    fn switch(&mut self, msg: SwitchProvider) -> anyhow::Result<Option<String>> {
+        let SwitchProvider { provider, symbol } = msg;
+        if !self.providers.contains_key(&provider) {
+            bail!("Unknown provider '{}'", provider);
+        }
+        let previous = match symbol {
+            Some(symbol) => {
+                let previous = self.provider(&symbol).to_string();
+                if provider == self.assignments.default {
+                    self.assignments.symbols.remove(&symbol);
+                } else {
+                    self.assignments.symbols.insert(symbol, provider.clone());
+                }
+                previous
+            }
+            None => std::mem::replace(&mut self.assignments.default, provider.clone()),
+        };
+        Ok(Some(previous).filter(|p| p != &provider))
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for ProviderRouter {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<QuoteRequest>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<QuoteRequest> for ProviderRouter {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: QuoteRequest) {
+        let name = self.provider(&msg.symbol);
+        match self.providers.get(name) {
+            Some(provider) => {
+                let member = provider.member(&msg.symbol);
+                if let Err(e) = provider.requests[member].send(msg) {
+                    tracing::error!("Could not forward the request to '{}': {}", name, e);
+                }
+            }
+            None => tracing::error!("No provider '{}' for {}", name, msg.symbol),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<SwitchProvider> for ProviderRouter {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: SwitchProvider,
+    ) -> anyhow::Result<SwitchedProvider> {
+        let switched = ProviderSwitched {
+            provider: msg.provider.clone(),
+            symbol: msg.symbol.clone(),
+        };
+        let previous = self.switch(msg)?;
+        match &switched.symbol {
+            Some(symbol) => tracing::info!(
+                provider = %switched.provider,
+                symbol = %symbol,
+                previous = ?previous,
+                "{} is now fetched from '{}'",
+                symbol,
+                switched.provider
+            ),
+            None => tracing::info!(
+                provider = %switched.provider,
+                previous = ?previous,
+                "'{}' is now the default provider",
+                switched.provider
+            ),
+        }
+        Broker::from_registry().await?.publish(switched)?;
+        Ok(SwitchedProvider {
+            assignments: self.assignments.clone(),
+            previous: previous
+                .map(|name| self.providers[&name].drain.clone())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<AssignmentsRequest> for ProviderRouter {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: AssignmentsRequest) -> Assignments {
+        self.assignments.clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<DrainAllRequest> for ProviderRouter {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: DrainAllRequest,
+    ) -> Vec<Arc<Caller<Drain>>> {
+        self.providers
+            .values()
+            .flat_map(|p| p.drain.iter().cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Nothing;
+
+    impl Actor for Nothing {}
+
+    #[async_trait::async_trait]
+    impl Handler<QuoteRequest> for Nothing {
+        async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: QuoteRequest) {}
+    }
+
+    #[async_trait::async_trait]
+    impl Handler<Drain> for Nothing {
+        async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Drain) {}
+    }
+
+    #[async_std::test]
+    async fn test_switch() {
+        let addr = Nothing.start().await.unwrap();
+        let providers = PROVIDERS
+            .iter()
+            .map(|name| {
+                (
+                    name.to_string(),
+                    Provider::pool(std::slice::from_ref(&addr)),
+                )
+            })
+            .collect();
+        let mut router = ProviderRouter::new(
+            providers,
+            Assignments {
+                default: "yahoo".to_string(),
+                symbols: BTreeMap::from([("BTC-USD".to_string(), "synthetic".to_string())]),
+            },
+        );
+        let switch = |provider: &str, symbol: Option<&str>| SwitchProvider {
+            provider: provider.to_string(),
+            symbol: symbol.map(|s| s.to_string()),
+        };
+        assert_eq!(router.provider("AAPL"), "yahoo");
+        assert_eq!(router.provider("BTC-USD"), "synthetic");
+        assert!(router.switch(switch("unknown", None)).is_err());
+
+        assert_eq!(
+            router.switch(switch("synthetic", None)).unwrap().as_deref(),
+            Some("yahoo")
+        );
+        assert_eq!(router.provider("AAPL"), "synthetic");
+        // the same provider again, nothing to drain
+        assert_eq!(router.switch(switch("synthetic", None)).unwrap(), None);
+
+        assert_eq!(
+            router
+                .switch(switch("yahoo", Some("AAPL")))
+                .unwrap()
+                .as_deref(),
+            Some("synthetic")
+        );
+        assert_eq!(router.provider("AAPL"), "yahoo");
+        // back to the default, the assignment is removed
+        router.switch(switch("synthetic", Some("AAPL"))).unwrap();
+        assert_eq!(router.assignments.symbols.len(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_pool() {
+        let mut addrs = vec![];
+        for _ in 0..4 {
+            addrs.push(Nothing.start().await.unwrap());
+        }
+        let pool = Provider::pool(&addrs);
+        let symbols: Vec<String> = (0..100).map(|i| format!("S{}", i)).collect();
+        let members: Vec<usize> = symbols.iter().map(|s| pool.member(s)).collect();
+        // a symbol always goes to the same actor
+        assert_eq!(
+            symbols.iter().map(|s| pool.member(s)).collect::<Vec<_>>(),
+            members
+        );
+        // and every actor gets some
+        for member in 0..4 {
+            assert!(members.contains(&member));
+        }
+        assert_eq!(Provider::pool(&addrs[..1]).member("AAPL"), 0);
+    }
+}
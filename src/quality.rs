@@ -0,0 +1,271 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::shutdown::Shutdown;
+use crate::signal::TickerQuote;
+use crate::Quotes;
+
+///
+/// Bars that change the close price by more than this (relative) are suspect
+///
+const MAX_JUMP: f64 = 0.5;
+
+///
+/// How many quarantined bars are kept for `/quality`
+///
+const RECENT_QUARANTINED: usize = 100;
+
+///
+/// `Quotes` without the bars that failed the quality checks
+///
+#[message]
+#[derive(Debug, Default, Clone)]
+pub struct CleanQuotes(pub Quotes);
+
+///
+/// A bar that was held back
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QuarantinedBar {
+    pub symbol: String,
+    pub timestamp: DateTime<Utc>,
+    pub close: f64,
+    pub reason: String,
+}
+
+///
+/// Published whenever a response contained suspect bars
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QualityReport {
+    pub symbol: String,
+    pub checked: usize,
+    pub quarantined: Vec<QuarantinedBar>,
+}
+
+///
+/// Quality statistics of a symbol
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SymbolQuality {
+    pub checked: usize,
+    pub quarantined: usize,
+}
+
+///
+/// Everything served by `/quality`
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct QualitySummary {
+    pub symbols: BTreeMap<String, SymbolQuality>,
+    pub recent: Vec<QuarantinedBar>,
+}
+
+#[message(result = "QualitySummary")]
+pub struct QualityRequest;
+
+///
+/// What the checks remember about a symbol between responses
+///
+#[derive(Debug, Clone, Default)]
+struct SymbolState {
+    /// Close of the last accepted bar
+    last: Option<f64>,
+    /// Close of the last bar that was quarantined because of a jump
+    suspect: Option<f64>,
+}
+
+fn is_jump(from: f64, to: f64) -> bool {
+    ((to - from) / from).abs() > MAX_JUMP
+}
+
+///
+/// Splits the (sorted) bars into the ones that pass the checks and the quarantined ones.
+/// A jump is accepted if the bar after it confirms the new price level (e.g. a stock split).
+///
+fn check(
+    state: &mut SymbolState,
+    symbol: &str,
+    quotes: Vec<TickerQuote>,
+) -> (Vec<TickerQuote>, Vec<QuarantinedBar>) {
+    let mut seen = HashSet::new();
+    let mut passed = vec![];
+    let mut quarantined = vec![];
+    for quote in quotes {
+        let reason = if ![quote.open, quote.high, quote.low, quote.close]
+            .iter()
+            .all(|p| *p > 0.0)
+        {
+            Some("non-positive price")
+        } else if !seen.insert(quote.timestamp) {
+            Some("duplicate timestamp")
+        } else if state.last.is_some_and(|last| is_jump(last, quote.close))
+            && state.suspect.is_none_or(|s| is_jump(s, quote.close))
+        {
+            state.suspect = Some(quote.close);
+            Some("price jump")
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => quarantined.push(QuarantinedBar {
+                symbol: symbol.to_string(),
+                timestamp: Utc
+                    .timestamp_opt(quote.timestamp as i64, 0)
+                    .single()
+                    .unwrap_or_default(),
+                close: quote.close,
+                reason: reason.to_string(),
+            }),
+            None => {
+                state.last = Some(quote.close);
+                state.suspect = None;
+                passed.push(quote);
+            }
+        }
+    }
+    (passed, quarantined)
+}
+
+///
+/// Actor that checks incoming `Quotes` for bad ticks and forwards the rest as `CleanQuotes`
+///
+#[derive(Default)]
+pub struct DataQuality {
+    states: HashMap<String, SymbolState>,
+    stats: BTreeMap<String, SymbolQuality>,
+    recent: VecDeque<QuarantinedBar>,
+}
+
+#[async_trait::async_trait]
+impl Actor for DataQuality {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<Quotes>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Quotes> for DataQuality {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, mut msg: Quotes) {
+        msg.quotes.sort_by_cached_key(|k| k.timestamp);
+        let checked = msg.quotes.len();
+        let state = self.states.entry(msg.symbol.clone()).or_default();
+        let (passed, quarantined) = check(state, &msg.symbol, std::mem::take(&mut msg.quotes));
+        msg.quotes = passed;
+
+        let stats = self.stats.entry(msg.symbol.clone()).or_default();
+        stats.checked += checked;
+        stats.quarantined += quarantined.len();
+        if !quarantined.is_empty() {
+            for bar in &quarantined {
+                tracing::warn!(
+                    "Quarantined {} bar at {} (${:.2}): {}",
+                    bar.symbol,
+                    bar.timestamp.to_rfc3339(),
+                    bar.close,
+                    bar.reason
+                );
+                if self.recent.len() == RECENT_QUARANTINED {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(bar.clone());
+            }
+            let report = QualityReport {
+                symbol: msg.symbol.clone(),
+                checked,
+                quarantined,
+            };
+            if let Err(e) = Broker::from_registry().await.unwrap().publish(report) {
+                tracing::error!("{}", e);
+            }
+        }
+        if let Err(e) = Broker::from_registry()
+            .await
+            .unwrap()
+            .publish(CleanQuotes(msg))
+        {
+            tracing::error!("{}", e);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<QualityRequest> for DataQuality {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: QualityRequest) -> QualitySummary {
+        QualitySummary {
+            symbols: self.stats.clone(),
+            recent: self.recent.iter().cloned().collect(),
+        }
+    }
+}
+
+///
+/// Answered once the quotes that arrived before are checked and published
+///
+#[async_trait::async_trait]
+impl Handler<Shutdown> for DataQuality {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(timestamp: u64, close: f64) -> TickerQuote {
+        TickerQuote {
+            timestamp,
+            open: close,
+            high: close,
+            low: close,
+            volume: 100,
+            close,
+            adjclose: close,
+        }
+    }
+
+    fn reasons(bars: &[QuarantinedBar]) -> Vec<(DateTime<Utc>, &str)> {
+        bars.iter()
+            .map(|b| (b.timestamp, b.reason.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let mut state = SymbolState::default();
+        let quotes = vec![
+            quote(1, 100.0),
+            quote(2, 0.0),
+            quote(2, 101.0),
+            quote(2, 102.0),
+            quote(3, 250.0), // spike
+            quote(4, 103.0),
+            quote(5, -1.0),
+        ];
+        let (passed, quarantined) = check(&mut state, "A", quotes);
+        assert_eq!(
+            passed.iter().map(|q| q.close).collect::<Vec<_>>(),
+            vec![100.0, 101.0, 103.0]
+        );
+        let ts = |s| Utc.timestamp_opt(s, 0).unwrap();
+        assert_eq!(
+            reasons(&quarantined),
+            vec![
+                (ts(2), "non-positive price"),
+                (ts(2), "duplicate timestamp"),
+                (ts(3), "price jump"),
+                (ts(5), "non-positive price"),
+            ]
+        );
+
+        // the next response is compared to the last accepted bar, a confirmed level holds
+        let (passed, quarantined) = check(&mut state, "A", vec![quote(6, 34.0), quote(7, 34.5)]);
+        assert_eq!(passed.len(), 1);
+        assert_eq!(passed[0].close, 34.5);
+        assert_eq!(reasons(&quarantined), vec![(ts(6), "price jump")]);
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use chrono::prelude::*;
+use serde::Serialize;
+use xactor::*;
+
+use crate::clock::SharedClock;
+
+///
+/// Throttling starts when less than this share of the quota is left
+///
+const THROTTLE_BELOW: f64 = 0.2;
+
+///
+/// Intervals are stretched by at most this factor
+///
+const MAX_THROTTLE: f64 = 20.0;
+
+///
+/// A request limit of a provider, e.g. 2000 requests per hour
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuotaLimit {
+    pub provider: String,
+    pub requests: u64,
+    pub window: Duration,
+}
+
+impl QuotaLimit {
+    ///
+    /// Parses `provider=requests/seconds`, e.g. `yahoo=2000/3600`.
+    ///
+    pub fn from_arg(arg: &str) -> anyhow::Result<Self> {
+        let parse = || -> Option<QuotaLimit> {
+            let (provider, limit) = arg.split_once('=')?;
+            let (requests, seconds) = limit.split_once('/')?;
+            Some(QuotaLimit {
+                provider: provider.trim().to_string(),
+                requests: requests.trim().parse().ok()?,
+                window: Duration::from_secs(seconds.trim().parse().ok()?),
+            })
+        };
+        match parse() {
+            Some(limit) if limit.requests > 0 && !limit.window.is_zero() => Ok(limit),
+            Some(_) => bail!("Quota '{}' needs a positive limit and window", arg),
+            None => Err(anyhow!(
+                "Expected a quota as 'provider=requests/seconds', got '{}'",
+                arg
+            )),
+        }
+    }
+
+    ///
+    /// The shortest interval to fetch the symbols at without exceeding the limit
+    ///
+    pub fn shortest_interval(&self, symbols: usize) -> Duration {
+        self.window.mul_f64(symbols as f64 / self.requests as f64)
+    }
+}
+
+///
+/// Sent by a provider after every request
+///
+#[message]
+#[derive(Debug, Clone, Default)]
+pub struct QuotaUsage {
+    pub provider: String,
+    /// Remaining requests as reported by the provider (e.g. in response headers)
+    pub remaining: Option<u64>,
+    /// The provider refused the request because of its rate limit
+    pub rate_limited: bool,
+}
+
+///
+/// Publishes a usage record, ignoring errors like `metrics::record`.
+///
+pub async fn record(usage: QuotaUsage) {
+    if let Ok(mut broker) = Broker::from_registry().await {
+        let _ = broker.publish(usage);
+    }
+}
+
+///
+/// How much the scheduler should stretch its intervals (1.0 is no throttling)
+///
+#[message]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throttle {
+    pub factor: f64,
+}
+
+///
+/// The usage of a single provider as served by `/quota`
+///
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ProviderQuota {
+    pub requests: u64,
+    pub rate_limited: u64,
+    pub limit: Option<u64>,
+    pub window_secs: Option<u64>,
+    pub used_in_window: u64,
+    pub remaining: Option<u64>,
+    pub throttle: f64,
+    #[serde(skip)]
+    recent: VecDeque<DateTime<Utc>>,
+    #[serde(skip)]
+    reported_remaining: Option<u64>,
+    #[serde(skip)]
+    last_rate_limited: Option<DateTime<Utc>>,
+}
+
+impl ProviderQuota {
+    fn record(&mut self, usage: &QuotaUsage, now: DateTime<Utc>) {
+        self.requests += 1;
+        // without a window, there is no limit to compare against
+        if self.window_secs.is_some() {
+            self.recent.push_back(now);
+        }
+        if usage.remaining.is_some() {
+            self.reported_remaining = usage.remaining;
+        }
+        if usage.rate_limited {
+            self.rate_limited += 1;
+            self.last_rate_limited = Some(now);
+        }
+        self.update(now);
+    }
+
+    ///
+    /// Recalculates the remaining requests and the throttle factor
+    ///
+    fn update(&mut self, now: DateTime<Utc>) {
+        if let Some(window) = self.window_secs {
+            let start = now - chrono::Duration::seconds(window as i64);
+            while self.recent.front().is_some_and(|t| *t <= start) {
+                self.recent.pop_front();
+            }
+        }
+        self.used_in_window = self.recent.len() as u64;
+        let counted = self
+            .limit
+            .map(|limit| limit.saturating_sub(self.used_in_window));
+        self.remaining = match (self.reported_remaining, counted) {
+            (Some(reported), Some(counted)) => Some(reported.min(counted)),
+            (reported, counted) => reported.or(counted),
+        };
+
+        let window = self.window_secs.unwrap_or(60) as i64;
+        let recently_limited = self
+            .last_rate_limited
+            .is_some_and(|t| now - t < chrono::Duration::seconds(window));
+        self.throttle = if recently_limited {
+            MAX_THROTTLE
+        } else {
+            match (self.remaining, self.limit) {
+                (Some(remaining), Some(limit)) => {
+                    let share = remaining as f64 / limit as f64;
+                    if share >= THROTTLE_BELOW {
+                        1.0
+                    } else {
+                        (THROTTLE_BELOW / share.max(f64::EPSILON)).min(MAX_THROTTLE)
+                    }
+                }
+                _ => 1.0,
+            }
+        };
+    }
+}
+
+#[message(result = "BTreeMap<String, ProviderQuota>")]
+pub struct QuotaRequest;
+
+#[message]
+#[derive(Clone)]
+struct Tick;
+
+///
+/// Actor that tracks the API usage per provider and publishes a `Throttle` whenever the
+/// scheduler should slow down (or may speed up again)
+///
+pub struct QuotaTracker {
+    providers: BTreeMap<String, ProviderQuota>,
+    throttle: f64,
+    clock: SharedClock,
+}
+
+impl QuotaTracker {
+    pub fn new(limits: &[QuotaLimit], clock: SharedClock) -> Self {
+        let providers = limits
+            .iter()
+            .map(|l| {
+                let quota = ProviderQuota {
+                    limit: Some(l.requests),
+                    remaining: Some(l.requests),
+                    window_secs: Some(l.window.as_secs()),
+                    throttle: 1.0,
+                    ..Default::default()
+                };
+                (l.provider.clone(), quota)
+            })
+            .collect();
+        QuotaTracker {
+            providers,
+            throttle: 1.0,
+            clock,
+        }
+    }
+
+    async fn publish_throttle(&mut self) {
+        let factor = self
+            .providers
+            .values()
+            .map(|p| p.throttle)
+            .fold(1.0, f64::max);
+        if factor != self.throttle {
+            self.throttle = factor;
+            tracing::info!(
+                "Provider quota: fetch intervals are stretched by {:.1}x",
+                factor
+            );
+            if let Ok(mut broker) = Broker::from_registry().await {
+                let _ = broker.publish(Throttle { factor });
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for QuotaTracker {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.send_interval(Tick, Duration::from_secs(10));
+        ctx.subscribe::<QuotaUsage>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<QuotaUsage> for QuotaTracker {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: QuotaUsage) {
+        self.providers
+            .entry(msg.provider.clone())
+            .or_insert_with(|| ProviderQuota {
+                throttle: 1.0,
+                ..Default::default()
+            })
+            .record(&msg, self.clock.now());
+        self.publish_throttle().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Tick> for QuotaTracker {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Tick) {
+        let now = self.clock.now();
+        for provider in self.providers.values_mut() {
+            provider.update(now);
+        }
+        self.publish_throttle().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<QuotaRequest> for QuotaTracker {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: QuotaRequest,
+    ) -> BTreeMap<String, ProviderQuota> {
+        self.providers.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_limit_from_arg() {
+        assert_eq!(
+            QuotaLimit::from_arg("yahoo=2000/3600").unwrap(),
+            QuotaLimit {
+                provider: "yahoo".to_string(),
+                requests: 2000,
+                window: Duration::from_secs(3600),
+            }
+        );
+        assert!(QuotaLimit::from_arg("yahoo=2000").is_err());
+        assert!(QuotaLimit::from_arg("yahoo=0/60").is_err());
+    }
+
+    #[test]
+    fn test_provider_throttle() {
+        let mut tracker = QuotaTracker::new(
+            &[QuotaLimit::from_arg("yahoo=10/60").unwrap()],
+            crate::clock::system(),
+        );
+        let quota = tracker.providers.get_mut("yahoo").unwrap();
+        let start = Utc.timestamp_opt(0, 0).unwrap();
+        let usage = QuotaUsage {
+            provider: "yahoo".to_string(),
+            ..Default::default()
+        };
+        for i in 0..8 {
+            quota.record(&usage, start + chrono::Duration::seconds(i));
+        }
+        assert_eq!(quota.remaining, Some(2));
+        assert_eq!(quota.throttle, 1.0);
+        quota.record(&usage, start + chrono::Duration::seconds(8));
+        assert_eq!(quota.throttle, 2.0);
+
+        // the provider knows better
+        let reported = QuotaUsage {
+            remaining: Some(0),
+            ..usage.clone()
+        };
+        quota.record(&reported, start + chrono::Duration::seconds(9));
+        assert_eq!(quota.remaining, Some(0));
+        assert_eq!(quota.throttle, MAX_THROTTLE);
+
+        // once the window has passed, everything is back to normal
+        quota.reported_remaining = None;
+        quota.update(start + chrono::Duration::seconds(100));
+        assert_eq!(quota.used_in_window, 0);
+        assert_eq!(quota.throttle, 1.0);
+
+        let limited = QuotaUsage {
+            rate_limited: true,
+            ..usage
+        };
+        quota.record(&limited, start + chrono::Duration::seconds(101));
+        assert_eq!(quota.throttle, MAX_THROTTLE);
+    }
+}
@@ -0,0 +1,199 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::quality::CleanQuotes;
+use crate::shutdown::Shutdown;
+use crate::signal::TickerQuote;
+use crate::Quotes;
+
+///
+/// A quote as written to the log (the provider's type can't be serialized)
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct LoggedQuote {
+    timestamp: u64,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    adjclose: f64,
+    volume: u64,
+}
+
+impl From<&TickerQuote> for LoggedQuote {
+    fn from(q: &TickerQuote) -> Self {
+        LoggedQuote {
+            timestamp: q.timestamp,
+            open: q.open,
+            high: q.high,
+            low: q.low,
+            close: q.close,
+            adjclose: q.adjclose,
+            volume: q.volume,
+        }
+    }
+}
+
+impl From<LoggedQuote> for TickerQuote {
+    fn from(q: LoggedQuote) -> Self {
+        TickerQuote {
+            timestamp: q.timestamp,
+            open: q.open,
+            high: q.high,
+            low: q.low,
+            volume: q.volume,
+            close: q.close,
+            adjclose: q.adjclose,
+        }
+    }
+}
+
+///
+/// One line of the log: the quotes of a single fetch
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LoggedBatch {
+    symbol: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    watchlist: Option<String>,
+    quotes: Vec<LoggedQuote>,
+}
+
+impl From<&Quotes> for LoggedBatch {
+    fn from(q: &Quotes) -> Self {
+        LoggedBatch {
+            symbol: q.symbol.clone(),
+            watchlist: q.watchlist.clone(),
+            quotes: q.quotes.iter().map(LoggedQuote::from).collect(),
+        }
+    }
+}
+
+impl From<LoggedBatch> for Quotes {
+    fn from(batch: LoggedBatch) -> Self {
+        Quotes {
+            symbol: batch.symbol,
+            quotes: batch.quotes.into_iter().map(TickerQuote::from).collect(),
+            watchlist: batch.watchlist,
+        }
+    }
+}
+
+///
+/// Reads all batches of a quote log in the order they were fetched
+///
+pub fn read(path: &str) -> anyhow::Result<Vec<Quotes>> {
+    let file = File::open(path).with_context(|| format!("Could not open '{}'", path))?;
+    let mut batches = vec![];
+    for (no, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let batch: LoggedBatch = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: invalid batch", path, no + 1))?;
+        batches.push(Quotes::from(batch));
+    }
+    Ok(batches)
+}
+
+///
+/// Actor that appends the checked quotes of every fetch to a file (JSON lines), so the
+/// indicators can be recomputed later without fetching again
+///
+#[derive(Default, Debug)]
+pub struct QuoteLog {
+    pub filename: String,
+    writer: Option<File>,
+}
+
+impl QuoteLog {
+    pub fn new(filename: String) -> Self {
+        QuoteLog {
+            filename,
+            writer: None,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for QuoteLog {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.filename)?;
+        self.writer = Some(file);
+        ctx.subscribe::<CleanQuotes>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<CleanQuotes> for QuoteLog {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: CleanQuotes) {
+        let msg = msg.0;
+        if msg.quotes.is_empty() {
+            return;
+        }
+        let batch = LoggedBatch::from(&msg);
+        if let Some(file) = &mut self.writer {
+            match serde_json::to_string(&batch) {
+                Ok(line) => {
+                    if let Err(e) = writeln!(file, "{}", line) {
+                        tracing::error!("Could not write quotes to '{}': {}", self.filename, e);
+                    }
+                }
+                Err(e) => tracing::error!("Could not serialize quotes: {}", e),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Shutdown> for QuoteLog {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {
+        if let Some(file) = &mut self.writer {
+            if let Err(e) = file.sync_data() {
+                tracing::error!("Could not sync '{}': {}", self.filename, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let path = std::env::temp_dir().join("quote_log_read.jsonl");
+        let path = path.to_str().unwrap().to_string();
+        let quote = TickerQuote {
+            timestamp: 1593777609,
+            open: 1.0,
+            high: 2.0,
+            low: 0.5,
+            volume: 100,
+            close: 1.5,
+            adjclose: 1.4,
+        };
+        let batch = LoggedBatch {
+            symbol: "AAPL".to_string(),
+            watchlist: Some("tech".to_string()),
+            quotes: vec![LoggedQuote::from(&quote)],
+        };
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "{}\n", serde_json::to_string(&batch).unwrap()).unwrap();
+
+        let batches = read(&path).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].symbol, "AAPL");
+        assert_eq!(batches[0].watchlist.as_deref(), Some("tech"));
+        assert_eq!(batches[0].quotes, vec![quote]);
+    }
+}
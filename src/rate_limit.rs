@@ -0,0 +1,133 @@
+//!
+//! A limit on the requests sent to a provider. `--max-requests-per-minute` gives every provider
+//! a token bucket that its downloaders take a token from before each request. Requests over the
+//! budget wait for their turn instead of being dropped.
+//!
+use std::time::{Duration, Instant};
+
+use xactor::*;
+
+///
+/// A token bucket that lets requests go into debt: a request that finds no token reserves the
+/// next one and waits until it's refilled
+///
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    /// Tokens per second
+    rate: f64,
+    /// Tokens the bucket holds at most, i.e. the requests of a burst
+    capacity: f64,
+    /// Below zero, the tokens already reserved by waiting requests
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    ///
+    /// A bucket for `per_minute` requests, with a burst of up to a second's worth
+    ///
+    pub fn new(per_minute: u32, now: Instant) -> Self {
+        let rate = f64::from(per_minute.max(1)) / 60.0;
+        let capacity = rate.max(1.0);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            refilled: now,
+        }
+    }
+
+    ///
+    /// Takes a token and returns how long to wait until it's available
+    ///
+    pub fn reserve(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.refilled = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+///
+/// Reserve a request, answered with how long to wait before sending it
+///
+#[message(result = "Duration")]
+pub struct Acquire;
+
+///
+/// Actor that holds the token bucket of a provider, shared by its downloaders
+///
+pub struct RateLimiter {
+    bucket: TokenBucket,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        RateLimiter {
+            bucket: TokenBucket::new(per_minute, Instant::now()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for RateLimiter {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Acquire> for RateLimiter {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Acquire) -> Duration {
+        self.bucket.reserve(Instant::now())
+    }
+}
+
+///
+/// Waits for the limiter's go, if there is one
+///
+pub async fn acquire(limiter: &Option<Addr<RateLimiter>>) {
+    if let Some(limiter) = limiter {
+        match limiter.call(Acquire).await {
+            Ok(wait) if !wait.is_zero() => async_std::task::sleep(wait).await,
+            Ok(_) => {}
+            Err(e) => tracing::error!("Rate limiter not available: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let start = Instant::now();
+        // one request every 2 seconds
+        let mut bucket = TokenBucket::new(30, start);
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        // the next ones queue up behind each other
+        assert_eq!(bucket.reserve(start), Duration::from_secs(2));
+        assert_eq!(bucket.reserve(start), Duration::from_secs(4));
+        // time pays off the debt
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.reserve(later), Duration::ZERO);
+        // but doesn't save up more than the burst
+        let much_later = later + Duration::from_secs(600);
+        assert_eq!(bucket.reserve(much_later), Duration::ZERO);
+        assert_eq!(bucket.reserve(much_later), Duration::from_secs(2));
+
+        // a second's worth at once
+        let mut bucket = TokenBucket::new(600, start);
+        for _ in 0..10 {
+            assert_eq!(bucket.reserve(start), Duration::ZERO);
+        }
+        assert!(bucket.reserve(start) > Duration::ZERO);
+    }
+}
@@ -0,0 +1,264 @@
+//!
+//! Recordings of the broker traffic, to reproduce a run without network access. `--record`
+//! writes the messages that come into the pipeline (fetched quotes and symbol metadata) and
+//! the ones it produces (indicators, alerts, gaps, and anomalies) to a file, one JSON line
+//! each with the time it was published. `--replay` feeds the incoming ones back through the
+//! actors instead of fetching, so the output can be compared with the recorded one.
+//!
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+
+use anyhow::Context as _;
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::alert::Alert;
+use crate::anomaly::Anomaly;
+use crate::clock::SharedClock;
+use crate::gap::GapEvent;
+use crate::metadata::SymbolMetadata;
+use crate::quote_log::LoggedBatch;
+use crate::shutdown::Shutdown;
+use crate::{PerformanceIndicators, Quotes};
+
+///
+/// A recorded message
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Recorded {
+    Quotes(LoggedBatch),
+    Metadata(SymbolMetadata),
+    Indicators(Box<PerformanceIndicators>),
+    Alert(Alert),
+    Gap(GapEvent),
+    Anomaly(Anomaly),
+}
+
+impl Recorded {
+    ///
+    /// Whether the message comes into the pipeline from outside, i.e. is replayed. The others
+    /// are calculated again.
+    ///
+    pub fn is_input(&self) -> bool {
+        matches!(self, Recorded::Quotes(_) | Recorded::Metadata(_))
+    }
+}
+
+///
+/// One line of a recording
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    pub at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub message: Recorded,
+}
+
+///
+/// Reads all entries of a recording in the order they were published
+///
+pub fn read(path: &str) -> anyhow::Result<Vec<Entry>> {
+    let file = File::open(path).with_context(|| format!("Could not open '{}'", path))?;
+    let mut entries = vec![];
+    for (no, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: invalid message", path, no + 1))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+///
+/// Publishes the incoming messages of a recording in order and returns how many there were.
+/// With a `speed` they keep their recorded pace, e.g. `10.0` for ten times as fast, otherwise
+/// they follow each other right away.
+///
+pub async fn replay(entries: Vec<Entry>, speed: Option<f64>) -> anyhow::Result<usize> {
+    let mut previous: Option<DateTime<Utc>> = None;
+    let mut count = 0;
+    for entry in entries.into_iter().filter(|e| e.message.is_input()) {
+        if let (Some(speed), Some(previous)) = (speed.filter(|s| *s > 0.0), previous) {
+            let pause = (entry.at - previous).to_std().unwrap_or_default();
+            async_std::task::sleep(Duration::from_secs_f64(pause.as_secs_f64() / speed)).await;
+        }
+        previous = Some(entry.at);
+        match entry.message {
+            Recorded::Quotes(batch) => Broker::from_registry()
+                .await?
+                .publish(Quotes::from(batch))?,
+            Recorded::Metadata(metadata) => Broker::from_registry().await?.publish(metadata)?,
+            _ => unreachable!("only inputs are replayed"),
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+///
+/// Actor that appends the messages in and out of the pipeline to a file (JSON lines)
+///
+pub struct Recorder {
+    filename: String,
+    writer: Option<File>,
+    clock: SharedClock,
+}
+
+impl Recorder {
+    pub fn new(filename: String, clock: SharedClock) -> Self {
+        Recorder {
+            filename,
+            writer: None,
+            clock,
+        }
+    }
+
+    fn record(&mut self, message: Recorded) {
+        let entry = Entry {
+            at: self.clock.now(),
+            message,
+        };
+        if let Some(file) = &mut self.writer {
+            match serde_json::to_string(&entry) {
+                Ok(line) => {
+                    if let Err(e) = writeln!(file, "{}", line) {
+                        tracing::error!(
+                            "Could not write to the recording '{}': {}",
+                            self.filename,
+                            e
+                        );
+                    }
+                }
+                Err(e) => tracing::error!("Could not serialize a recorded message: {}", e),
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for Recorder {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.filename)?;
+        self.writer = Some(file);
+        ctx.subscribe::<Quotes>().await?;
+        ctx.subscribe::<SymbolMetadata>().await?;
+        ctx.subscribe::<PerformanceIndicators>().await?;
+        ctx.subscribe::<Alert>().await?;
+        ctx.subscribe::<GapEvent>().await?;
+        ctx.subscribe::<Anomaly>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Quotes> for Recorder {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Quotes) {
+        self.record(Recorded::Quotes(LoggedBatch::from(&msg)));
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<SymbolMetadata> for Recorder {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: SymbolMetadata) {
+        self.record(Recorded::Metadata(msg));
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for Recorder {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        self.record(Recorded::Indicators(Box::new(msg)));
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Alert> for Recorder {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Alert) {
+        self.record(Recorded::Alert(msg));
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<GapEvent> for Recorder {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: GapEvent) {
+        self.record(Recorded::Gap(msg));
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Anomaly> for Recorder {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Anomaly) {
+        self.record(Recorded::Anomaly(msg));
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Shutdown> for Recorder {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {
+        if let Some(file) = &mut self.writer {
+            if let Err(e) = file.sync_data() {
+                tracing::error!("Could not sync the recording '{}': {}", self.filename, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::TickerQuote;
+
+    #[test]
+    fn test_recording_roundtrip() {
+        let path = std::env::temp_dir().join("recording_roundtrip.jsonl");
+        let path = path.to_str().unwrap().to_string();
+        std::fs::remove_file(&path).ok();
+        let at = Utc.with_ymd_and_hms(2024, 1, 2, 14, 30, 0).unwrap();
+        let quotes = Quotes {
+            symbol: "AAPL".to_string(),
+            quotes: vec![TickerQuote {
+                timestamp: 1704205800,
+                open: 1.0,
+                high: 2.0,
+                low: 0.5,
+                volume: 100,
+                close: 1.5,
+                adjclose: 1.4,
+            }],
+            watchlist: None,
+        };
+        let indicators = PerformanceIndicators {
+            symbol: "AAPL".to_string(),
+            timestamp: at,
+            price: 1.5,
+            rsi: Some(55.0),
+            ..Default::default()
+        };
+        let mut recorder = Recorder::new(path.clone(), crate::clock::system());
+        recorder.writer = Some(File::create(&path).unwrap());
+        recorder.record(Recorded::Quotes(LoggedBatch::from(&quotes)));
+        recorder.record(Recorded::Indicators(Box::new(indicators)));
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].message.is_input());
+        match &entries[0].message {
+            Recorded::Quotes(batch) => {
+                assert_eq!(Quotes::from(batch.clone()).quotes, quotes.quotes)
+            }
+            other => panic!("expected quotes, got {:?}", other),
+        }
+        assert!(!entries[1].message.is_input());
+        assert!(matches!(&entries[1].message, Recorded::Indicators(i) if i.rsi == Some(55.0)));
+        assert!(entries[0].at <= entries[1].at);
+    }
+}
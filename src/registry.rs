@@ -0,0 +1,170 @@
+//!
+//! Symbols can be added to and removed from the default pipeline while it's running, with
+//! `POST /symbols` and `DELETE /symbols/:symbol`. The changes last until the next restart.
+//!
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::fetch::{FetchOutcome, FetchStatus};
+
+///
+/// How the symbols of the default pipeline differ from the configured ones
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SymbolChanges {
+    /// Fetched in addition to the configured symbols
+    pub added: BTreeSet<String>,
+    /// Not fetched anymore, even if configured
+    pub removed: BTreeSet<String>,
+}
+
+impl SymbolChanges {
+    ///
+    /// The symbols of the default pipeline's groups after the changes. The added symbols that
+    /// aren't in any group yet join the first one.
+    ///
+    pub fn apply(&self, groups: &[Vec<String>]) -> Vec<Vec<String>> {
+        let mut applied: Vec<Vec<String>> = groups
+            .iter()
+            .map(|symbols| {
+                symbols
+                    .iter()
+                    .filter(|s| !self.removed.contains(*s))
+                    .cloned()
+                    .collect()
+            })
+            .collect();
+        if let Some(first) = applied.first_mut() {
+            let added = self
+                .added
+                .iter()
+                .filter(|s| !groups.iter().any(|symbols| symbols.contains(s)));
+            first.extend(added.cloned());
+        }
+        applied
+    }
+}
+
+///
+/// Start fetching a symbol
+///
+#[message(result = "SymbolChanges")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AddSymbol {
+    pub symbol: String,
+}
+
+///
+/// Stop fetching a symbol
+///
+#[message(result = "SymbolChanges")]
+#[derive(Debug, Clone)]
+pub struct RemoveSymbol {
+    pub symbol: String,
+}
+
+///
+/// Request the current changes, the scheduler does with every fetch
+///
+#[message(result = "SymbolChanges")]
+pub struct SymbolChangesRequest;
+
+///
+/// Actor that keeps the symbols added and removed at runtime. Added symbols the provider
+/// doesn't know are dropped again.
+///
+#[derive(Default)]
+pub struct SymbolRegistry {
+    changes: SymbolChanges,
+}
+
+#[async_trait::async_trait]
+impl Actor for SymbolRegistry {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<FetchOutcome>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<AddSymbol> for SymbolRegistry {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: AddSymbol) -> SymbolChanges {
+        self.changes.removed.remove(&msg.symbol);
+        if self.changes.added.insert(msg.symbol.clone()) {
+            tracing::info!("{} was added", msg.symbol);
+        }
+        self.changes.clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<RemoveSymbol> for SymbolRegistry {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: RemoveSymbol) -> SymbolChanges {
+        self.changes.added.remove(&msg.symbol);
+        if self.changes.removed.insert(msg.symbol.clone()) {
+            tracing::info!("{} was removed", msg.symbol);
+        }
+        self.changes.clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<SymbolChangesRequest> for SymbolRegistry {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: SymbolChangesRequest,
+    ) -> SymbolChanges {
+        self.changes.clone()
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<FetchOutcome> for SymbolRegistry {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: FetchOutcome) {
+        if msg.status == FetchStatus::NotFound && msg.watchlist.is_none() {
+            self.changes.added.remove(&msg.symbol);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[async_std::test]
+    async fn test_symbol_registry() {
+        let registry = SymbolRegistry::default().start().await.unwrap();
+        let add = |symbol: &str| AddSymbol {
+            symbol: symbol.to_string(),
+        };
+        registry.call(add("NVDA")).await.unwrap();
+        registry.call(add("MSFT")).await.unwrap();
+        let changes = registry
+            .call(RemoveSymbol {
+                symbol: "AAPL".to_string(),
+            })
+            .await
+            .unwrap();
+        let groups = vec![symbols(&["AAPL", "GOOG"]), symbols(&["MSFT"])];
+        assert_eq!(
+            changes.apply(&groups),
+            vec![symbols(&["GOOG", "NVDA"]), symbols(&["MSFT"])]
+        );
+
+        // adding a removed symbol again restores it
+        let changes = registry.call(add("AAPL")).await.unwrap();
+        assert!(changes.removed.is_empty());
+        assert_eq!(
+            changes.apply(&groups)[0],
+            symbols(&["AAPL", "GOOG", "NVDA"])
+        );
+        assert_eq!(registry.call(SymbolChangesRequest).await.unwrap(), changes);
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use chrono::prelude::*;
+use xactor::*;
+
+use crate::calendar::Calendar;
+use crate::gap::SESSION;
+use crate::quote_log;
+use crate::QuoteRequest;
+
+///
+/// At most this many gaps are requested per scan, so a long outage doesn't flood the provider
+///
+const MAX_REQUESTS_PER_SCAN: usize = 20;
+
+///
+/// A stretch of missing bars between two stored ones
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MissingBars {
+    /// The stored bars before and after the gap
+    pub after: u64,
+    pub before: u64,
+    /// How many bars are expected in between
+    pub expected: u64,
+}
+
+///
+/// The regular spacing of a series: the most common distance between two bars
+///
+pub fn bar_interval(timestamps: &[u64]) -> Option<u64> {
+    let mut counts: BTreeMap<u64, usize> = BTreeMap::new();
+    for pair in timestamps.windows(2) {
+        if pair[1] > pair[0] {
+            *counts.entry(pair[1] - pair[0]).or_default() += 1;
+        }
+    }
+    // ties go to the shorter interval
+    counts
+        .into_iter()
+        .max_by_key(|(interval, count)| (*count, std::cmp::Reverse(*interval)))
+        .map(|(interval, _)| interval)
+}
+
+fn date(timestamp: u64) -> NaiveDate {
+    Utc.timestamp_opt(timestamp as i64, 0).unwrap().date_naive()
+}
+
+///
+/// Finds the bars missing from sorted timestamps. Markets are expected to be open on every
+/// trading day of the calendar: intraday series should have a bar every interval within a
+/// session (UTC day) until the close of a half-day, daily series a bar on every trading day.
+/// Nights, weekends, and holidays are never missing.
+///
+pub fn missing_bars(timestamps: &[u64], calendar: &Calendar) -> Vec<MissingBars> {
+    let interval = match bar_interval(timestamps) {
+        Some(interval) => interval,
+        None => return vec![],
+    };
+    timestamps
+        .windows(2)
+        .filter_map(|pair| {
+            let (after, before) = (pair[0], pair[1]);
+            let expected = if interval >= SESSION {
+                (after / SESSION + 1..before / SESSION)
+                    .filter(|day| calendar.is_trading_day(date(day * SESSION)))
+                    .count() as u64
+            } else if after / SESSION == before / SESSION {
+                let end = match calendar.early_close(date(after)) {
+                    Some(close) => {
+                        let close =
+                            after / SESSION * SESSION + close.num_seconds_from_midnight() as u64;
+                        before.min(close)
+                    }
+                    None => before,
+                };
+                end.saturating_sub(after).saturating_sub(1) / interval
+            } else {
+                0
+            };
+            (expected > 0).then_some(MissingBars {
+                after,
+                before,
+                expected,
+            })
+        })
+        .collect()
+}
+
+#[message]
+#[derive(Clone)]
+struct Scan;
+
+///
+/// Actor that periodically scans the quote log for missing bars and requests them again
+///
+pub struct RepairJob {
+    pub quote_log: String,
+    pub interval: Duration,
+    pub calendar: Calendar,
+    /// Gaps (symbol, watchlist, gap) that were requested already; the provider may not have
+    /// the bars at all, e.g. on holidays
+    requested: HashSet<(String, Option<String>, MissingBars)>,
+}
+
+impl RepairJob {
+    pub fn new(quote_log: String, interval: Duration, calendar: Calendar) -> Self {
+        RepairJob {
+            quote_log,
+            interval,
+            calendar,
+            requested: HashSet::new(),
+        }
+    }
+
+    ///
+    /// Requests for the gaps that weren't requested before
+    ///
+    fn requests(&mut self) -> anyhow::Result<Vec<QuoteRequest>> {
+        let mut series: BTreeMap<(String, Option<String>), Vec<u64>> = BTreeMap::new();
+        for batch in quote_log::read(&self.quote_log)? {
+            series
+                .entry((batch.symbol, batch.watchlist))
+                .or_default()
+                .extend(batch.quotes.iter().map(|q| q.timestamp));
+        }
+        let mut requests = vec![];
+        for ((symbol, watchlist), mut timestamps) in series {
+            timestamps.sort_unstable();
+            timestamps.dedup();
+            for gap in missing_bars(&timestamps, &self.calendar) {
+                if !self
+                    .requested
+                    .insert((symbol.clone(), watchlist.clone(), gap))
+                {
+                    continue;
+                }
+                requests.push(QuoteRequest {
+                    symbol: symbol.clone(),
+                    from: Utc.timestamp_opt(gap.after as i64 + 1, 0).unwrap(),
+                    to: Utc.timestamp_opt(gap.before as i64, 0).unwrap(),
+                    watchlist: watchlist.clone(),
+                });
+            }
+        }
+        Ok(requests)
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for RepairJob {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.send_interval(Scan, self.interval);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Scan> for RepairJob {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Scan) {
+        let requests = match self.requests() {
+            Ok(requests) => requests,
+            Err(e) => {
+                tracing::error!("Could not scan '{}' for gaps: {}", self.quote_log, e);
+                return;
+            }
+        };
+        if requests.is_empty() {
+            return;
+        }
+        tracing::info!(
+            "Requesting {} gaps in '{}' again",
+            requests.len().min(MAX_REQUESTS_PER_SCAN),
+            self.quote_log
+        );
+        let mut broker = Broker::from_registry().await.unwrap();
+        for request in requests.into_iter().take(MAX_REQUESTS_PER_SCAN) {
+            if let Err(e) = broker.publish(request) {
+                tracing::error!("{}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_bars() {
+        assert_eq!(bar_interval(&[]), None);
+        assert_eq!(bar_interval(&[0, 60, 120, 300, 360]), Some(60));
+
+        // Friday 2022-12-02, minute bars from 14:30 with 10:00 missing and a few more
+        let friday = Utc
+            .with_ymd_and_hms(2022, 12, 2, 14, 30, 0)
+            .unwrap()
+            .timestamp() as u64;
+        let mut timestamps: Vec<u64> = (0..10).map(|i| friday + i * 60).collect();
+        timestamps.extend((14..20).map(|i| friday + i * 60));
+        // Monday after the weekend
+        timestamps.push(friday + 3 * SESSION);
+        timestamps.push(friday + 3 * SESSION + 60);
+        assert_eq!(
+            missing_bars(&timestamps, &Calendar::default()),
+            vec![MissingBars {
+                after: friday + 9 * 60,
+                before: friday + 14 * 60,
+                expected: 4,
+            }]
+        );
+
+        // daily bars: Thursday and Friday, then Tuesday, so Monday is missing
+        let thursday = friday - SESSION;
+        let days = [thursday, friday, friday + 4 * SESSION, friday + 5 * SESSION];
+        assert_eq!(
+            missing_bars(&days, &Calendar::default()),
+            vec![MissingBars {
+                after: friday,
+                before: friday + 4 * SESSION,
+                expected: 1,
+            }]
+        );
+
+        // Thanksgiving is a holiday, and the day after closes at 18:00
+        let nyse = Calendar::nyse();
+        let day = |d| {
+            Utc.with_ymd_and_hms(2024, 11, d, 21, 0, 0)
+                .unwrap()
+                .timestamp() as u64
+        };
+        assert!(missing_bars(&[day(25), day(26), day(27), day(29)], &nyse).is_empty());
+        assert_eq!(
+            missing_bars(&[day(25), day(26), day(27), day(29)], &Calendar::default()).len(),
+            1
+        );
+        let close = day(29) - 3 * 60 * 60;
+        let minutes = [close - 180, close - 120, close - 60, close + 1800];
+        assert!(missing_bars(&minutes, &nyse).is_empty());
+        assert_eq!(missing_bars(&minutes, &Calendar::default())[0].expected, 30);
+    }
+}
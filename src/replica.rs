@@ -0,0 +1,142 @@
+//!
+//! A read-only replica serves the HTTP API from the SQLite database another instance (the
+//! collector) writes with `--sqlite`. It doesn't fetch anything, so the query side can be
+//! scaled and restarted on its own.
+//!
+use std::time::Duration;
+
+use clap::Parser;
+use xactor::*;
+
+use crate::scheduler;
+use crate::sqlite_sink::SqliteStore;
+use crate::PerformanceIndicators;
+
+///
+/// Rows read from the database at once
+///
+const BATCH_SIZE: usize = 1000;
+
+#[derive(Parser, Debug)]
+pub struct ReplicaOpts {
+    /// The SQLite database the collector writes with `--sqlite`
+    pub store: String,
+    /// How often the database is checked for new rows
+    #[clap(long, default_value = "1s", parse(try_from_str = scheduler::parse_interval))]
+    pub poll: Duration,
+}
+
+#[message]
+#[derive(Clone)]
+struct Poll;
+
+///
+/// Actor that reads the rows the collector writes and publishes them as if they were just
+/// calculated, for the buffers and the other actors behind the API
+///
+pub struct StoreReader {
+    path: String,
+    poll: Duration,
+    store: Option<SqliteStore>,
+    /// Of the last row read
+    version: i64,
+}
+
+impl StoreReader {
+    pub fn new(path: String, poll: Duration) -> Self {
+        StoreReader {
+            path,
+            poll,
+            store: None,
+            version: 0,
+        }
+    }
+
+    ///
+    /// Publishes the rows written since the last call, in the order they were written
+    ///
+    async fn read(&mut self) -> anyhow::Result<usize> {
+        let mut broker = Broker::<PerformanceIndicators>::from_registry().await?;
+        let store = match &self.store {
+            Some(store) => store,
+            None => return Ok(0),
+        };
+        let mut count = 0;
+        loop {
+            let rows = store.changes(self.version, BATCH_SIZE)?;
+            let done = rows.len() < BATCH_SIZE;
+            for (version, row) in rows {
+                self.version = version;
+                broker.publish(row)?;
+                count += 1;
+            }
+            if done {
+                return Ok(count);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for StoreReader {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        self.store = Some(SqliteStore::open_read_only(&self.path)?);
+        ctx.address().send(Poll)?;
+        ctx.send_interval(Poll, self.poll);
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Poll> for StoreReader {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Poll) {
+        match self.read().await {
+            Ok(0) => {}
+            Ok(count) => tracing::debug!("Read {} rows from '{}'", count, self.path),
+            // e.g. the collector didn't create the table yet
+            Err(e) => tracing::warn!("Could not read '{}': {}", self.path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    #[async_std::test]
+    async fn test_store_reader() {
+        let path = std::env::temp_dir().join("replica_store_reader.db");
+        let path = path.to_str().unwrap().to_string();
+        std::fs::remove_file(&path).ok();
+        let row = |ts: i64, price: f64| PerformanceIndicators {
+            timestamp: Utc.timestamp_opt(ts, 0).unwrap(),
+            symbol: "AAPL".to_string(),
+            price,
+            rsi: Some(55.0),
+            watchlist: Some("tech".to_string()),
+            ..Default::default()
+        };
+        let mut writer = SqliteStore::open(&path).unwrap();
+        writer.insert(&[row(60, 1.0), row(120, 2.0)]).unwrap();
+
+        let mut reader = StoreReader::new(path.clone(), Duration::from_secs(1));
+        reader.store = Some(SqliteStore::open_read_only(&path).unwrap());
+        assert_eq!(reader.read().await.unwrap(), 2);
+        let store = reader.store.as_ref().unwrap();
+        let rows = store.changes(0, 10).unwrap();
+        let (version, first) = &rows[0];
+        assert_eq!(*version, 1);
+        assert_eq!(first.timestamp, row(60, 1.0).timestamp);
+        assert_eq!(first.rsi, Some(55.0));
+        assert_eq!(first.watchlist.as_deref(), Some("tech"));
+        assert_eq!(first.resolution, None);
+
+        // the forming bar is updated, and read again
+        writer.insert(&[row(120, 2.5)]).unwrap();
+        assert_eq!(reader.read().await.unwrap(), 1);
+        assert_eq!(reader.read().await.unwrap(), 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,671 @@
+//!
+//! Scheduled reports: when a day (or week) is over, the top movers, the P&L of the portfolio
+//! in the config file, the alerts that fired, and a price chart per symbol are rendered to
+//! HTML or Markdown. Reports are written to a directory, mailed, or announced on the desktop.
+//!
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::bail;
+use chrono::prelude::*;
+use serde::Deserialize;
+use xactor::*;
+
+use crate::alert::Alert;
+use crate::clock::SharedClock;
+use crate::PerformanceIndicators;
+
+///
+/// Prices kept per symbol and period for the charts
+///
+const CHART_POINTS: usize = 200;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportPeriod {
+    /// A report per UTC day
+    #[default]
+    Daily,
+    /// A report per week, from Monday to Sunday
+    Weekly,
+}
+
+impl ReportPeriod {
+    ///
+    /// The first day of the period `day` is in
+    ///
+    pub fn start(&self, day: NaiveDate) -> NaiveDate {
+        match self {
+            ReportPeriod::Daily => day,
+            ReportPeriod::Weekly => {
+                day - chrono::Duration::days(day.weekday().num_days_from_monday() as i64)
+            }
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ReportPeriod::Daily => "daily",
+            ReportPeriod::Weekly => "weekly",
+        }
+    }
+
+    fn title(&self, start: NaiveDate) -> String {
+        match self {
+            ReportPeriod::Daily => format!("Daily report {}", start),
+            ReportPeriod::Weekly => format!("Weekly report, week of {}", start),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Html,
+    Markdown,
+}
+
+impl ReportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ReportFormat::Html => "html",
+            ReportFormat::Markdown => "md",
+        }
+    }
+}
+
+fn default_top() -> usize {
+    5
+}
+
+///
+/// The `[reports]` section of the config file:
+///
+/// ```toml
+/// [reports]
+/// period = "weekly"          # or "daily" (the default)
+/// format = "markdown"        # or "html" (the default)
+/// dir = "reports"            # write every report to a file in here
+/// email = "me@example.com"   # mail it (via the local `sendmail`)
+/// notify = true              # raise a desktop notification
+/// top = 5                    # movers listed
+/// ```
+///
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ReportConfig {
+    #[serde(default)]
+    pub period: ReportPeriod,
+    #[serde(default)]
+    pub format: ReportFormat,
+    #[serde(default)]
+    pub dir: Option<String>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub notify: bool,
+    #[serde(default = "default_top")]
+    pub top: usize,
+}
+
+impl ReportConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.dir.is_none() && self.email.is_none() && !self.notify {
+            bail!("[reports] needs a dir, an email, or notify = true");
+        }
+        Ok(())
+    }
+}
+
+///
+/// A position of the `[portfolio]` section, e.g. `AAPL = { quantity = 10, cost = 150.0 }`
+///
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub quantity: f64,
+    /// Price paid per share
+    pub cost: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mover {
+    pub symbol: String,
+    /// The price at the start of the period (the previous period's last)
+    pub first: f64,
+    pub last: f64,
+    pub pct_change: f64,
+    /// The prices of the period, oldest first
+    pub prices: Vec<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Holding {
+    pub symbol: String,
+    pub quantity: f64,
+    pub cost: f64,
+    /// The latest price, unknown before the first quote of the symbol
+    pub price: Option<f64>,
+}
+
+impl Holding {
+    pub fn value(&self) -> Option<f64> {
+        self.price.map(|p| p * self.quantity)
+    }
+
+    ///
+    /// Gain or loss against the cost
+    ///
+    pub fn pnl(&self) -> Option<f64> {
+        self.price.map(|p| (p - self.cost) * self.quantity)
+    }
+}
+
+///
+/// Everything a report shows
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub period: ReportPeriod,
+    pub start: NaiveDate,
+    /// By the size of the move, the largest first
+    pub movers: Vec<Mover>,
+    pub holdings: Vec<Holding>,
+    pub alerts: Vec<Alert>,
+}
+
+impl Report {
+    pub fn title(&self) -> String {
+        self.period.title(self.start)
+    }
+
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Html => self.html(),
+            ReportFormat::Markdown => self.markdown(),
+        }
+    }
+
+    ///
+    /// The P&L of the positions with a price
+    ///
+    pub fn total_pnl(&self) -> f64 {
+        self.holdings.iter().filter_map(Holding::pnl).sum()
+    }
+
+    fn markdown(&self) -> String {
+        let number = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.2}", v));
+        let mut out = format!("# {}\n\n## Top movers\n\n", self.title());
+        if self.movers.is_empty() {
+            out.push_str("No quotes.\n");
+        } else {
+            out.push_str("| Symbol | Open | Last | Change | Chart |\n|---|---:|---:|---:|---|\n");
+            for m in &self.movers {
+                out.push_str(&format!(
+                    "| {} | {:.2} | {:.2} | {:+.2}% | {} |\n",
+                    m.symbol,
+                    m.first,
+                    m.last,
+                    m.pct_change * 100.0,
+                    sparkline(&m.prices)
+                ));
+            }
+        }
+        if !self.holdings.is_empty() {
+            out.push_str("\n## Portfolio\n\n| Symbol | Quantity | Cost | Price | Value | P&L |\n|---|---:|---:|---:|---:|---:|\n");
+            for h in &self.holdings {
+                out.push_str(&format!(
+                    "| {} | {} | {:.2} | {} | {} | {} |\n",
+                    h.symbol,
+                    h.quantity,
+                    h.cost,
+                    number(h.price),
+                    number(h.value()),
+                    number(h.pnl())
+                ));
+            }
+            out.push_str(&format!(
+                "| **Total** | | | | | **{:.2}** |\n",
+                self.total_pnl()
+            ));
+        }
+        out.push_str("\n## Alerts\n\n");
+        if self.alerts.is_empty() {
+            out.push_str("None fired.\n");
+        }
+        for a in &self.alerts {
+            out.push_str(&format!(
+                "- {} {} `{}`: {}\n",
+                a.timestamp.format("%Y-%m-%d %H:%M"),
+                a.symbol,
+                a.rule,
+                a.message
+            ));
+        }
+        out
+    }
+
+    fn html(&self) -> String {
+        let number = |value: Option<f64>| value.map_or("-".to_string(), |v| format!("{:.2}", v));
+        let title = escape(&self.title());
+        let mut out = format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{0}</title></head>\n<body>\n<h1>{0}</h1>\n<h2>Top movers</h2>\n",
+            title
+        );
+        if self.movers.is_empty() {
+            out.push_str("<p>No quotes.</p>\n");
+        } else {
+            out.push_str("<table>\n<tr><th>Symbol</th><th>Open</th><th>Last</th><th>Change</th><th>Chart</th></tr>\n");
+            for m in &self.movers {
+                out.push_str(&format!(
+                    "<tr><td>{}</td><td>{:.2}</td><td>{:.2}</td><td>{:+.2}%</td><td>{}</td></tr>\n",
+                    escape(&m.symbol),
+                    m.first,
+                    m.last,
+                    m.pct_change * 100.0,
+                    svg_chart(&m.prices)
+                ));
+            }
+            out.push_str("</table>\n");
+        }
+        if !self.holdings.is_empty() {
+            out.push_str("<h2>Portfolio</h2>\n<table>\n<tr><th>Symbol</th><th>Quantity</th><th>Cost</th><th>Price</th><th>Value</th><th>P&amp;L</th></tr>\n");
+            for h in &self.holdings {
+                out.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td>{:.2}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                    escape(&h.symbol),
+                    h.quantity,
+                    h.cost,
+                    number(h.price),
+                    number(h.value()),
+                    number(h.pnl())
+                ));
+            }
+            out.push_str(&format!(
+                "<tr><th>Total</th><td></td><td></td><td></td><td></td><th>{:.2}</th></tr>\n</table>\n",
+                self.total_pnl()
+            ));
+        }
+        out.push_str("<h2>Alerts</h2>\n");
+        if self.alerts.is_empty() {
+            out.push_str("<p>None fired.</p>\n");
+        } else {
+            out.push_str("<ul>\n");
+            for a in &self.alerts {
+                out.push_str(&format!(
+                    "<li>{} {} <code>{}</code>: {}</li>\n",
+                    a.timestamp.format("%Y-%m-%d %H:%M"),
+                    escape(&a.symbol),
+                    escape(&a.rule),
+                    escape(&a.message)
+                ));
+            }
+            out.push_str("</ul>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+///
+/// The prices scaled to `0..=1` between their low and high (a flat line in the middle)
+///
+fn scaled(prices: &[f64]) -> Vec<f64> {
+    let low = prices.iter().cloned().fold(f64::INFINITY, f64::min);
+    let high = prices.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    prices
+        .iter()
+        .map(|p| {
+            if high > low {
+                (p - low) / (high - low)
+            } else {
+                0.5
+            }
+        })
+        .collect()
+}
+
+///
+/// A chart of the prices in block characters, e.g. `▁▃▅▇`
+///
+pub fn sparkline(prices: &[f64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    scaled(prices)
+        .iter()
+        .map(|v| BARS[(v * 7.0).round() as usize])
+        .collect()
+}
+
+///
+/// A chart of the prices as an inline SVG line
+///
+fn svg_chart(prices: &[f64]) -> String {
+    const WIDTH: f64 = 120.0;
+    const HEIGHT: f64 = 30.0;
+    let step = WIDTH / (prices.len().max(2) - 1) as f64;
+    let points: Vec<String> = scaled(prices)
+        .iter()
+        .enumerate()
+        .map(|(i, v)| format!("{:.1},{:.1}", i as f64 * step, HEIGHT - v * HEIGHT))
+        .collect();
+    format!(
+        "<svg width=\"{}\" height=\"{}\"><polyline fill=\"none\" stroke=\"black\" points=\"{}\"/></svg>",
+        WIDTH,
+        HEIGHT,
+        points.join(" ")
+    )
+}
+
+///
+/// The prices of a symbol in the current period
+///
+#[derive(Debug, Clone)]
+struct Track {
+    first: f64,
+    prices: Vec<f64>,
+}
+
+///
+/// Collects the prices and alerts of a period and turns them into a `Report` once it's over
+///
+#[derive(Debug)]
+pub struct ReportBook {
+    period: ReportPeriod,
+    top: usize,
+    portfolio: BTreeMap<String, Position>,
+    /// The first day of the period being collected
+    start: Option<NaiveDate>,
+    tracks: BTreeMap<String, Track>,
+    alerts: Vec<Alert>,
+}
+
+impl ReportBook {
+    pub fn new(period: ReportPeriod, top: usize, portfolio: BTreeMap<String, Position>) -> Self {
+        ReportBook {
+            period,
+            top,
+            portfolio,
+            start: None,
+            tracks: BTreeMap::new(),
+            alerts: vec![],
+        }
+    }
+
+    pub fn record(&mut self, indicators: &PerformanceIndicators, now: DateTime<Utc>) {
+        self.start
+            .get_or_insert(self.period.start(now.date_naive()));
+        let track = self
+            .tracks
+            .entry(indicators.symbol.clone())
+            .or_insert_with(|| Track {
+                first: indicators.price,
+                prices: vec![],
+            });
+        track.prices.push(indicators.price);
+        if track.prices.len() > CHART_POINTS {
+            track.prices.remove(0);
+        }
+    }
+
+    pub fn alert(&mut self, alert: Alert, now: DateTime<Utc>) {
+        self.start
+            .get_or_insert(self.period.start(now.date_naive()));
+        self.alerts.push(alert);
+    }
+
+    ///
+    /// The report of the period before `now`'s, once `now` is in the next one. The next
+    /// period starts at the last prices of this one.
+    ///
+    pub fn close(&mut self, now: DateTime<Utc>) -> Option<Report> {
+        let current = self.period.start(now.date_naive());
+        let start = self.start.filter(|start| *start < current)?;
+        self.start = Some(current);
+        let mut movers: Vec<Mover> = self
+            .tracks
+            .iter()
+            .filter_map(|(symbol, track)| {
+                let last = *track.prices.last()?;
+                Some(Mover {
+                    symbol: symbol.clone(),
+                    first: track.first,
+                    last,
+                    pct_change: if track.first != 0.0 {
+                        last / track.first - 1.0
+                    } else {
+                        0.0
+                    },
+                    prices: track.prices.clone(),
+                })
+            })
+            .collect();
+        movers.sort_by(|a, b| b.pct_change.abs().total_cmp(&a.pct_change.abs()));
+        let holdings = self
+            .portfolio
+            .iter()
+            .map(|(symbol, position)| Holding {
+                symbol: symbol.clone(),
+                quantity: position.quantity,
+                cost: position.cost,
+                price: self
+                    .tracks
+                    .get(symbol)
+                    .and_then(|t| t.prices.last().copied()),
+            })
+            .collect();
+        movers.truncate(self.top);
+        for track in self.tracks.values_mut() {
+            if let Some(last) = track.prices.last().copied() {
+                track.first = last;
+                track.prices = vec![last];
+            }
+        }
+        Some(Report {
+            period: self.period,
+            start,
+            movers,
+            holdings,
+            alerts: std::mem::take(&mut self.alerts),
+        })
+    }
+}
+
+#[message]
+#[derive(Clone)]
+struct CloseReport;
+
+///
+/// Actor that renders a report whenever a period is over, and writes, mails, or announces it
+///
+pub struct Reporter {
+    config: ReportConfig,
+    book: ReportBook,
+    clock: SharedClock,
+}
+
+impl Reporter {
+    pub fn new(
+        config: ReportConfig,
+        portfolio: BTreeMap<String, Position>,
+        clock: SharedClock,
+    ) -> Self {
+        Reporter {
+            book: ReportBook::new(config.period, config.top, portfolio),
+            config,
+            clock,
+        }
+    }
+
+    async fn deliver(&self, report: Report) {
+        let format = self.config.format;
+        let text = report.render(format);
+        if let Some(dir) = &self.config.dir {
+            let path = Path::new(dir).join(format!(
+                "{}-{}.{}",
+                report.period.name(),
+                report.start,
+                format.extension()
+            ));
+            let written = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(&path, &text));
+            match written {
+                Ok(()) => tracing::info!("Wrote {}", path.display()),
+                Err(e) => tracing::error!("Could not write '{}': {}", path.display(), e),
+            }
+        }
+        if let Some(to) = &self.config.email {
+            let content_type = match format {
+                ReportFormat::Html => "text/html",
+                ReportFormat::Markdown => "text/plain",
+            };
+            let mail = format!(
+                "To: {}\nSubject: {}\nContent-Type: {}; charset=utf-8\n\n{}",
+                to,
+                report.title(),
+                content_type,
+                text
+            );
+            if let Err(e) = crate::daily::sendmail(mail).await {
+                tracing::error!("Could not send the report to {}: {}", to, e);
+            }
+        }
+        if self.config.notify {
+            let mut body = match report.movers.first() {
+                Some(m) => format!("Top mover {} {:+.2}%", m.symbol, m.pct_change * 100.0),
+                None => "No quotes".to_string(),
+            };
+            if !report.holdings.is_empty() {
+                body.push_str(&format!(", P&L {:.2}", report.total_pnl()));
+            }
+            body.push_str(&format!(", {} alerts", report.alerts.len()));
+            if let Err(e) = crate::notify::show(report.title(), body).await {
+                tracing::error!("Could not show desktop notification: {}", e);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for Reporter {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.send_interval(CloseReport, Duration::from_secs(60));
+        ctx.subscribe::<PerformanceIndicators>().await?;
+        ctx.subscribe::<Alert>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for Reporter {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        // the charts are of the fetched bars
+        if msg.resolution.is_none() {
+            self.book.record(&msg, self.clock.now());
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Alert> for Reporter {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Alert) {
+        self.book.alert(msg, self.clock.now());
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<CloseReport> for Reporter {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: CloseReport) {
+        if let Some(report) = self.book.close(self.clock.now()) {
+            self.deliver(report).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        // a Friday
+        let friday = Utc.with_ymd_and_hms(2024, 1, 5, 15, 0, 0).unwrap();
+        let indicators = |symbol: &str, price: f64| PerformanceIndicators {
+            symbol: symbol.to_string(),
+            price,
+            ..Default::default()
+        };
+        let portfolio = BTreeMap::from([
+            (
+                "AAPL".to_string(),
+                Position {
+                    quantity: 10.0,
+                    cost: 100.0,
+                },
+            ),
+            (
+                "TSLA".to_string(),
+                Position {
+                    quantity: 1.0,
+                    cost: 200.0,
+                },
+            ),
+        ]);
+        let mut book = ReportBook::new(ReportPeriod::Weekly, 1, portfolio);
+        for (symbol, price) in [
+            ("AAPL", 100.0),
+            ("MSFT", 50.0),
+            ("AAPL", 110.0),
+            ("MSFT", 40.0),
+        ] {
+            book.record(&indicators(symbol, price), friday);
+        }
+        book.alert(
+            Alert {
+                rule: "drop".to_string(),
+                symbol: "MSFT".to_string(),
+                timestamp: friday,
+                message: "MSFT <dropped>".to_string(),
+            },
+            friday,
+        );
+        // the week isn't over on Sunday
+        assert_eq!(book.close(friday + chrono::Duration::days(2)), None);
+        let report = book.close(friday + chrono::Duration::days(3)).unwrap();
+        assert_eq!(report.start, NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
+        // down 20% is the bigger move
+        assert_eq!(report.movers.len(), 1);
+        assert_eq!(report.movers[0].symbol, "MSFT");
+        assert_eq!(report.holdings[0].pnl(), Some(100.0));
+        assert_eq!(report.holdings[1].price, None);
+        assert_eq!(report.total_pnl(), 100.0);
+
+        let markdown = report.render(ReportFormat::Markdown);
+        assert!(markdown.starts_with("# Weekly report, week of 2024-01-01\n"));
+        assert!(markdown.contains("| MSFT | 50.00 | 40.00 | -20.00% | █▁ |"));
+        assert!(markdown.contains("| TSLA | 1 | 200.00 | - | - | - |"));
+        assert!(markdown.contains("**100.00**"));
+        let html = report.render(ReportFormat::Html);
+        assert!(html.contains("MSFT &lt;dropped&gt;"));
+        assert!(html.contains("<polyline"));
+
+        // the next week starts at the last prices
+        assert_eq!(book.close(friday + chrono::Duration::days(4)), None);
+        book.record(
+            &indicators("AAPL", 121.0),
+            friday + chrono::Duration::days(10),
+        );
+        let report = book.close(friday + chrono::Duration::days(10)).unwrap();
+        assert_eq!(report.movers[0].symbol, "AAPL");
+        assert_eq!(report.movers[0].first, 110.0);
+        assert!(report.alerts.is_empty());
+
+        assert_eq!(sparkline(&[1.0, 2.0, 3.0]), "▁▅█");
+        assert_eq!(sparkline(&[2.0, 2.0]), "▅▅");
+    }
+}
@@ -0,0 +1,128 @@
+//!
+//! Aggregation of fine-grained quotes into coarser OHLCV bars (e.g. 1m → 5m → 1h → 1d).
+//!
+//! Bars are aligned to the epoch in UTC, so a `1h` bar always starts at a full hour and a `1d`
+//! bar at midnight UTC. A bar takes the open of its first quote, the highest high, the lowest
+//! low, the close (and adjusted close) of its last quote, and the sum of all volumes.
+//!
+use crate::signal::{Resolution, TickerQuote};
+
+///
+/// Start of the bar of `seconds` length that contains `timestamp`
+///
+pub fn bucket_start(timestamp: u64, seconds: u64) -> u64 {
+    timestamp - timestamp % seconds
+}
+
+///
+/// Aggregates quotes (sorted by time) into bars of the given resolution.
+///
+pub fn resample(quotes: &[TickerQuote], resolution: &Resolution) -> Vec<TickerQuote> {
+    let mut bars: Vec<TickerQuote> = vec![];
+    for quote in quotes {
+        let start = bucket_start(quote.timestamp, resolution.seconds);
+        match bars.last_mut() {
+            Some(bar) if bar.timestamp == start => {
+                bar.high = bar.high.max(quote.high);
+                bar.low = bar.low.min(quote.low);
+                bar.close = quote.close;
+                bar.adjclose = quote.adjclose;
+                bar.volume += quote.volume;
+            }
+            _ => bars.push(TickerQuote {
+                timestamp: start,
+                ..quote.clone()
+            }),
+        }
+    }
+    bars
+}
+
+///
+/// Resamples the quotes into every resolution (in the given order). Each resolution is built
+/// from the finest previous one that divides it, so `1m,5m,1h,1d` only walks over the quotes
+/// once and the bars after that.
+///
+pub fn cascade(quotes: &[TickerQuote], resolutions: &[Resolution]) -> Vec<Vec<TickerQuote>> {
+    let mut results: Vec<Vec<TickerQuote>> = Vec::with_capacity(resolutions.len());
+    for (i, resolution) in resolutions.iter().enumerate() {
+        let source = (0..i)
+            .filter(|j| {
+                let finer = resolutions[*j].seconds;
+                finer < resolution.seconds && resolution.seconds % finer == 0
+            })
+            .max_by_key(|j| resolutions[*j].seconds)
+            .map(|j| results[j].as_slice())
+            .unwrap_or(quotes);
+        let bars = resample(source, resolution);
+        results.push(bars);
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quote(timestamp: u64, open: f64, high: f64, low: f64, close: f64) -> TickerQuote {
+        TickerQuote {
+            timestamp,
+            open,
+            high,
+            low,
+            volume: 10,
+            close,
+            adjclose: close,
+        }
+    }
+
+    fn minutes() -> Vec<TickerQuote> {
+        (0..120)
+            .map(|i| {
+                let price = 100.0 + i as f64;
+                quote(3600 + i * 60, price, price + 0.5, price - 0.5, price + 0.25)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_resample() {
+        let five: Resolution = "5m".parse().unwrap();
+        let bars = resample(&minutes(), &five);
+        assert_eq!(bars.len(), 24);
+        assert_eq!(bars[0], {
+            let mut bar = quote(3600, 100.0, 104.5, 99.5, 104.25);
+            bar.volume = 50;
+            bar
+        });
+        assert_eq!(bars[1].timestamp, 3900);
+
+        // unaligned quotes end up in the bar they belong to
+        let hour: Resolution = "1h".parse().unwrap();
+        let bars = resample(
+            &[
+                quote(3599, 1.0, 2.0, 0.5, 1.5),
+                quote(3601, 1.5, 1.6, 1.4, 1.45),
+            ],
+            &hour,
+        );
+        assert_eq!(
+            bars.iter().map(|b| b.timestamp).collect::<Vec<_>>(),
+            vec![0, 3600]
+        );
+        assert!(resample(&[], &hour).is_empty());
+    }
+
+    #[test]
+    fn test_cascade() {
+        let resolutions = Resolution::parse_list("5m,1h,7m,1d").unwrap();
+        let quotes = minutes();
+        let cascaded = cascade(&quotes, &resolutions);
+        for (bars, resolution) in cascaded.iter().zip(&resolutions) {
+            assert_eq!(bars, &resample(&quotes, resolution));
+        }
+        assert_eq!(cascaded[1].len(), 2);
+        assert_eq!(cascaded[3].len(), 1);
+        assert_eq!(cascaded[3][0].volume, 1200);
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tide::http::Mime;
+use tide::{Body, Middleware, Next, Request, Response, StatusCode};
+use xactor::*;
+
+use crate::alias::Aliases;
+use crate::envelope::{Envelope, Freshness, CALL_HEADER};
+use crate::PerformanceIndicators;
+
+///
+/// A cached response body
+///
+#[derive(Debug, Clone)]
+struct Entry {
+    body: String,
+    content_type: Option<Mime>,
+    /// The response had the trace headers of a buffer call, see `Freshness::trace`
+    traced: bool,
+    stored: Instant,
+    /// The ticker the response is about, `None` if it covers all symbols
+    symbol: Option<String>,
+}
+
+///
+/// Short-lived cache of serialized API responses, keyed by path and query. Entries expire
+/// after the TTL or as soon as new indicators of their symbol arrive. The freshness of a hit
+/// (the envelope's time and lag, and the trace headers) is the one when it's served.
+///
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    ttl: Duration,
+    freshness: Freshness,
+    /// Resolves the symbols in the paths, so invalidating a ticker drops its aliases' entries
+    aliases: Arc<Aliases>,
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl ResponseCache {
+    pub fn new(ttl: Duration, freshness: Freshness) -> Self {
+        ResponseCache {
+            ttl,
+            freshness,
+            aliases: Default::default(),
+            entries: Default::default(),
+        }
+    }
+
+    ///
+    /// Resolves the aliases in the paths with `aliases`
+    ///
+    pub fn with_aliases(mut self, aliases: Aliases) -> Self {
+        self.aliases = Arc::new(aliases);
+        self
+    }
+
+    fn get(&self, key: &str, now: Instant) -> Option<Entry> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|entry| now.duration_since(entry.stored) < self.ttl)
+            .cloned()
+    }
+
+    fn insert(&self, key: String, entry: Entry) {
+        let mut entries = self.entries.lock().unwrap();
+        // expired entries are dropped here, so polling many distinct URLs doesn't pile up
+        let ttl = self.ttl;
+        entries.retain(|_, e| entry.stored.duration_since(e.stored) < ttl);
+        entries.insert(key, entry);
+    }
+
+    ///
+    /// Drops the responses about `symbol` and those that cover all symbols
+    ///
+    pub fn invalidate(&self, symbol: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, e| matches!(&e.symbol, Some(s) if s != symbol));
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    ///
+    /// The response of a cache hit, with the current freshness
+    ///
+    fn replay(&self, entry: Entry, started: Instant) -> tide::Result {
+        let body = match serde_json::from_str::<Envelope<serde_json::Value>>(&entry.body) {
+            Ok(mut envelope) => {
+                self.freshness.refresh(&mut envelope);
+                serde_json::to_string(&envelope)?
+            }
+            Err(_) => entry.body,
+        };
+        let mut response = Response::new(StatusCode::Ok);
+        response.set_body(Body::from_string(body));
+        if let Some(content_type) = entry.content_type {
+            response.set_content_type(content_type);
+        }
+        if entry.traced {
+            // no call to a buffer, and none waited for
+            self.freshness.trace(&mut response, started.elapsed(), 0);
+        }
+        Ok(response)
+    }
+}
+
+#[tide::utils::async_trait]
+impl<State: Clone + Send + Sync + 'static> Middleware<State> for ResponseCache {
+    async fn handle(&self, req: Request<State>, next: Next<'_, State>) -> tide::Result {
+        if !self.is_enabled() || req.method() != tide::http::Method::Get {
+            return Ok(next.run(req).await);
+        }
+        let key = match req.url().query() {
+            Some(query) => format!("{}?{}", req.url().path(), query),
+            None => req.url().path().to_string(),
+        };
+        let started = Instant::now();
+        if let Some(entry) = self.get(&key, started) {
+            return self.replay(entry, started);
+        }
+        let symbol = req.param("symbol").ok().map(|s| self.aliases.resolve(s));
+        let mut response = next.run(req).await;
+        if response.status() == StatusCode::Ok {
+            let content_type = response.content_type();
+            let body = response.take_body().into_string().await?;
+            self.insert(
+                key,
+                Entry {
+                    body: body.clone(),
+                    content_type,
+                    traced: response.header(CALL_HEADER).is_some(),
+                    stored: Instant::now(),
+                    symbol,
+                },
+            );
+            response.set_body(Body::from_string(body));
+        }
+        Ok(response)
+    }
+}
+
+///
+/// Actor that invalidates cached responses when new indicators arrive
+///
+pub struct CacheInvalidator {
+    pub cache: ResponseCache,
+}
+
+#[async_trait::async_trait]
+impl Actor for CacheInvalidator {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for CacheInvalidator {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        self.cache.invalidate(&msg.symbol);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TestClock;
+    use crate::envelope::{LAG_HEADER, QUEUE_HEADER};
+    use chrono::prelude::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tide::http::{Method, Url};
+
+    fn freshness(start: DateTime<Utc>) -> (TestClock, Freshness) {
+        let clock = TestClock::new(start);
+        let freshness = Freshness::new(clock.shared());
+        (clock, freshness)
+    }
+
+    fn entry(stored: Instant, symbol: Option<&str>) -> Entry {
+        Entry {
+            body: "[]".to_string(),
+            content_type: None,
+            traced: false,
+            stored,
+            symbol: symbol.map(|s| s.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_response_cache() {
+        let (_, freshness) = freshness(Utc::now());
+        let cache = ResponseCache::new(Duration::from_secs(1), freshness);
+        let now = Instant::now();
+        cache.insert("/tail/10".to_string(), entry(now, None));
+        cache.insert("/latest/AAPL".to_string(), entry(now, Some("AAPL")));
+        cache.insert("/latest/MSFT".to_string(), entry(now, Some("MSFT")));
+        assert!(cache.get("/tail/10", now).is_some());
+        assert!(cache.get("/tail/5", now).is_none());
+        assert!(cache
+            .get("/tail/10", now + Duration::from_millis(1500))
+            .is_none());
+
+        cache.invalidate("AAPL");
+        assert!(cache.get("/tail/10", now).is_none());
+        assert!(cache.get("/latest/AAPL", now).is_none());
+        assert!(cache.get("/latest/MSFT", now).is_some());
+
+        // expired entries are dropped with the next insert
+        cache.insert(
+            "/tail/10".to_string(),
+            entry(now + Duration::from_secs(2), None),
+        );
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_cache_hit_is_fresh() {
+        let start = Utc.with_ymd_and_hms(2022, 12, 2, 15, 0, 0).unwrap();
+        let (clock, freshness) = freshness(start);
+        freshness.fetched();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mut app = tide::with_state(freshness.clone());
+        app.at("/tail/:n")
+            .with(ResponseCache::new(Duration::from_secs(60), freshness))
+            .get(move |req: Request<Freshness>| {
+                let counted = counted.clone();
+                async move {
+                    counted.fetch_add(1, Ordering::SeqCst);
+                    let mut response = req.state().json(vec![1, 2, 3])?;
+                    req.state()
+                        .trace(&mut response, Duration::from_millis(2), 1);
+                    Ok(response)
+                }
+            });
+        let tail = || {
+            let request = tide::http::Request::new(
+                Method::Get,
+                Url::parse("http://localhost/tail/3").unwrap(),
+            );
+            app.respond::<_, tide::http::Response>(request)
+        };
+
+        let mut response = tail().await.unwrap();
+        assert_eq!(response[LAG_HEADER], "0");
+        assert_eq!(response[CALL_HEADER], "2.000");
+        assert_eq!(response[QUEUE_HEADER], "1");
+        let envelope: Envelope<Vec<u32>> = response.body_json().await.unwrap();
+        assert_eq!(envelope.lag_ms, Some(0));
+
+        // served from the cache, with the freshness of now
+        clock.advance(Duration::from_millis(1500));
+        let mut response = tail().await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(response[LAG_HEADER], "1500");
+        assert!(response.header(CALL_HEADER).is_some());
+        assert_eq!(response[QUEUE_HEADER], "0");
+        let envelope: Envelope<Vec<u32>> = response.body_json().await.unwrap();
+        assert_eq!(envelope.lag_ms, Some(1500));
+        assert_eq!(
+            envelope.generated_at,
+            start + chrono::Duration::milliseconds(1500)
+        );
+        assert_eq!(envelope.data, vec![1, 2, 3]);
+    }
+
+    #[async_std::test]
+    async fn test_invalidate_alias() {
+        let (_, freshness) = freshness(Utc::now());
+        let aliases = Aliases::new(vec![("apple".to_string(), "AAPL".to_string())]).unwrap();
+        let cache =
+            ResponseCache::new(Duration::from_secs(60), freshness.clone()).with_aliases(aliases);
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let mut app = tide::with_state(freshness);
+        app.at("/symbols/:symbol/latest").with(cache.clone()).get(
+            move |req: Request<Freshness>| {
+                let counted = counted.clone();
+                async move {
+                    let calls = counted.fetch_add(1, Ordering::SeqCst) + 1;
+                    req.state().json(calls)
+                }
+            },
+        );
+        let latest = |symbol: &str| {
+            let url = format!("http://localhost/symbols/{}/latest", symbol);
+            let request = tide::http::Request::new(Method::Get, Url::parse(&url).unwrap());
+            app.respond::<_, tide::http::Response>(request)
+        };
+
+        latest("apple").await.unwrap();
+        latest("apple").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        // new indicators of the ticker drop the entry of its alias
+        cache.invalidate("AAPL");
+        let mut response = latest("apple").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        let envelope: Envelope<usize> = response.body_json().await.unwrap();
+        assert_eq!(envelope.data, 2);
+    }
+}
@@ -0,0 +1,161 @@
+//!
+//! Retries of failed fetches. Network errors are retried with an exponential backoff and
+//! random jitter, so many symbols failing at once don't retry in lockstep. A fetch that fails
+//! every attempt ends up as a `DeadLetter`, which `--dead-letters` appends to a file.
+//!
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+///
+/// The longest pause between two attempts
+///
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+///
+/// How often and when a failed fetch is tried again
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one
+    pub attempts: u32,
+    /// Pause before the first retry, doubling with every further one
+    pub base: Duration,
+    /// Share of the pause that is random, from 0 (none) to 1 (anything up to the pause)
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    ///
+    /// The pause after the failed `attempt` (starting at 1), with `random` between 0 and 1
+    ///
+    pub fn delay(&self, attempt: u32, random: f64) -> Duration {
+        let backoff = self
+            .base
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(MAX_DELAY);
+        backoff.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random)
+    }
+}
+
+///
+/// Random numbers for the jitter (xorshift), seeded from the time
+///
+#[derive(Debug, Clone)]
+pub struct Jitter(u64);
+
+impl Default for Jitter {
+    fn default() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        // xorshift gets stuck at 0
+        Jitter(nanos | 1)
+    }
+}
+
+impl Jitter {
+    ///
+    /// A number between 0 and 1
+    ///
+    pub fn sample(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+///
+/// Published for a fetch that failed every attempt
+///
+#[message]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    pub symbol: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watchlist: Option<String>,
+    pub provider: String,
+    /// The range that wasn't fetched
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub attempts: u32,
+    /// The error of the last attempt
+    pub error: String,
+}
+
+///
+/// Actor that appends every `DeadLetter` to a file (JSON lines)
+///
+pub struct DeadLetterLog {
+    filename: String,
+    writer: Option<File>,
+}
+
+impl DeadLetterLog {
+    pub fn new(filename: String) -> Self {
+        DeadLetterLog {
+            filename,
+            writer: None,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for DeadLetterLog {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.filename)?;
+        self.writer = Some(file);
+        ctx.subscribe::<DeadLetter>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<DeadLetter> for DeadLetterLog {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: DeadLetter) {
+        if let Some(file) = &mut self.writer {
+            match serde_json::to_string(&msg) {
+                Ok(line) => {
+                    if let Err(e) = writeln!(file, "{}", line) {
+                        tracing::error!("Could not write to '{}': {}", self.filename, e);
+                    }
+                }
+                Err(e) => tracing::error!("Could not serialize a dead letter: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            attempts: 5,
+            base: Duration::from_millis(500),
+            jitter: 0.5,
+        };
+        assert_eq!(policy.delay(1, 0.0), Duration::from_millis(500));
+        assert_eq!(policy.delay(3, 0.0), Duration::from_secs(2));
+        assert_eq!(policy.delay(3, 1.0), Duration::from_secs(1));
+        assert_eq!(policy.delay(20, 0.0), MAX_DELAY);
+
+        let mut jitter = Jitter::default();
+        for _ in 0..1000 {
+            let random = jitter.sample();
+            assert!((0.0..1.0).contains(&random));
+            let delay = policy.delay(2, random);
+            assert!(delay > Duration::from_millis(500) && delay <= Duration::from_secs(1));
+        }
+    }
+}
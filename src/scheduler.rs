@@ -0,0 +1,755 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use chrono::prelude::*;
+use cron::Schedule;
+use xactor::*;
+
+use crate::anomaly::{Anomaly, Boost};
+use crate::calendar::Calendar;
+use crate::checkpoint::Checkpoints;
+use crate::clock::SharedClock;
+use crate::fetch::{FetchOutcome, FetchStatus};
+use crate::identifier::TickerResolver;
+use crate::index::{self, Constituents};
+use crate::provider::ProviderSwitched;
+use crate::quota::{QuotaLimit, Throttle};
+use crate::registry::{SymbolChangesRequest, SymbolRegistry};
+use crate::{QuoteRequest, Quotes};
+
+///
+/// Shortest interval between two fetches of a group
+///
+pub const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+///
+/// Parses an interval as seconds (`30`) or with units (`15s`, `1m`, `1h30m`, `1d`)
+///
+pub fn parse_interval(s: &str) -> anyhow::Result<Duration> {
+    let s = s.trim();
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    let invalid = || {
+        anyhow!(
+            "Invalid interval '{}', expected e.g. 30, 15s, 1m, or 1h30m",
+            s
+        )
+    };
+    if s.is_empty() {
+        return Err(invalid());
+    }
+    let mut total = 0u64;
+    let mut rest = s;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let amount: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit_end = rest[digits..]
+            .find(|c: char| c.is_ascii_digit())
+            .map_or(rest.len(), |i| digits + i);
+        let unit = match &rest[digits..unit_end] {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(invalid()),
+        };
+        total = amount
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(|| anyhow!("The interval '{}' is too long", s))?;
+        rest = &rest[unit_end..];
+    }
+    Ok(Duration::from_secs(total))
+}
+
+///
+/// Fails if the interval is too short to fetch the symbols within the provider's quotas
+///
+pub fn check_interval(
+    interval: Duration,
+    symbols: usize,
+    provider: &str,
+    limits: &[QuotaLimit],
+) -> anyhow::Result<()> {
+    if interval < MIN_INTERVAL {
+        bail!(
+            "The interval needs to be at least {}s",
+            MIN_INTERVAL.as_secs()
+        );
+    }
+    for limit in limits.iter().filter(|l| l.provider == provider) {
+        let shortest = limit.shortest_interval(symbols);
+        if interval < shortest {
+            bail!(
+                "Fetching {} symbols every {}s exceeds the quota of {} requests per {}s of \
+                 '{}', the interval needs to be at least {}s",
+                symbols,
+                interval.as_secs(),
+                limit.requests,
+                limit.window.as_secs(),
+                provider,
+                shortest.as_secs_f64().ceil()
+            );
+        }
+    }
+    Ok(())
+}
+
+///
+/// When a group of symbols is fetched
+///
+#[derive(Debug, Clone)]
+pub enum Trigger {
+    /// At a fixed interval
+    Every(Duration),
+    /// Whenever the cron expression matches (evaluated in UTC), except on holidays and after
+    /// the close of a half-day
+    Cron(Box<Schedule>),
+}
+
+impl Trigger {
+    ///
+    /// Parses a cron expression. Classic 5-field expressions (`*/5 9-16 * * MON-FRI`) are
+    /// accepted as well as the 6/7-field variants that start with seconds.
+    ///
+    pub fn cron(expression: &str) -> anyhow::Result<Self> {
+        let expression = expression.trim();
+        let full = if expression.split_whitespace().count() == 5 {
+            format!("0 {}", expression)
+        } else {
+            expression.to_string()
+        };
+        let schedule = Schedule::from_str(&full)
+            .map_err(|e| anyhow!("Invalid cron expression '{}': {}", expression, e))?;
+        Ok(Trigger::Cron(Box::new(schedule)))
+    }
+
+    ///
+    /// The trigger for a configured group: the cron expression if there is one, otherwise the
+    /// interval in seconds (`default` if not set), which can't be shorter than `MIN_INTERVAL`.
+    ///
+    pub fn from_config(
+        cron: Option<&str>,
+        interval: Option<u64>,
+        default: Duration,
+    ) -> anyhow::Result<Self> {
+        match (cron, interval) {
+            (Some(expression), _) => Trigger::cron(expression),
+            (None, secs) => {
+                let interval = secs.map(Duration::from_secs).unwrap_or(default);
+                check_interval(interval, 0, "", &[])?;
+                Ok(Trigger::Every(interval))
+            }
+        }
+    }
+
+    ///
+    /// Time until the next fetch, or `None` if the trigger never fires again.
+    ///
+    pub fn next_delay(&self, now: DateTime<Utc>, calendar: &Calendar) -> Option<Duration> {
+        match self {
+            Trigger::Every(interval) => Some(*interval),
+            Trigger::Cron(schedule) => schedule
+                .after(&now)
+                .find(|next| !calendar.is_closed(*next))
+                .map(|next| (next - now).to_std().unwrap_or_default()),
+        }
+    }
+}
+
+///
+/// A set of symbols fetched together
+///
+#[derive(Debug, Clone)]
+pub struct ScheduleGroup {
+    pub name: String,
+    /// Symbols and indices (`index:sp500`) to fetch
+    pub symbols: Vec<String>,
+    pub trigger: Trigger,
+    /// The watchlist the fetched data belongs to, `None` for the default pipeline
+    pub watchlist: Option<String>,
+}
+
+impl ScheduleGroup {
+    ///
+    /// Moves every symbol with its own fetch interval into a separate group with that interval
+    ///
+    pub fn split_intervals(
+        groups: Vec<ScheduleGroup>,
+        intervals: &BTreeMap<String, u64>,
+    ) -> Vec<ScheduleGroup> {
+        let mut split = vec![];
+        for mut group in groups {
+            let (own, shared): (Vec<String>, Vec<String>) = group
+                .symbols
+                .into_iter()
+                .partition(|s| intervals.contains_key(s));
+            group.symbols = shared;
+            for symbol in own {
+                split.push(ScheduleGroup {
+                    name: format!("{}/{}", group.name, symbol),
+                    trigger: Trigger::Every(Duration::from_secs(intervals[&symbol])),
+                    symbols: vec![symbol],
+                    watchlist: group.watchlist.clone(),
+                });
+            }
+            if !group.symbols.is_empty() {
+                split.push(group);
+            }
+        }
+        split
+    }
+}
+
+///
+/// Fetch the symbols of a group, sent whenever its trigger fires
+///
+#[message]
+#[derive(Clone)]
+pub struct Fire {
+    pub group: usize,
+}
+
+#[message]
+#[derive(Clone)]
+struct RefreshConstituents;
+
+///
+/// Fetch a single boosted symbol, identified by its checkpoint key
+///
+#[message]
+#[derive(Clone)]
+struct FireSymbol {
+    key: String,
+}
+
+///
+/// A symbol that is fetched more often for a while
+///
+#[derive(Debug, Clone)]
+pub struct Boosted {
+    symbol: String,
+    watchlist: Option<String>,
+    until: DateTime<Utc>,
+}
+
+///
+/// Actor that publishes `QuoteRequest`s for every group whenever its trigger fires. Only quotes
+/// newer than the last fetched one are requested.
+///
+pub struct Scheduler {
+    pub from: DateTime<Utc>,
+    pub groups: Vec<ScheduleGroup>,
+    pub checkpoints: Checkpoints,
+    /// Where to persist the checkpoints, if at all
+    pub checkpoint_file: Option<String>,
+    /// How far before its checkpoint the first fetch of a symbol after a restart starts: the
+    /// history window, or the history the longest signal window needs
+    pub warmup: Duration,
+    /// Checkpoint keys fetched since the start. The first fetch of the others reads the
+    /// history again, which the processor lost with the restart.
+    pub warmed: HashSet<String>,
+    pub constituents: Constituents,
+    /// Replaces ISINs and CUSIPs with tickers
+    pub tickers: TickerResolver,
+    /// The symbols of every group with all indices and identifiers resolved
+    pub resolved: Vec<Vec<String>>,
+    /// Symbols (and their watchlist) the provider didn't know. They aren't requested again
+    /// until the provider is switched.
+    pub unknown: HashSet<(String, Option<String>)>,
+    /// Stretches all intervals when the provider quota runs low
+    pub throttle: f64,
+    /// Fetch symbols with anomalies more often, if set
+    pub boost: Option<Boost>,
+    pub boosted: HashMap<String, Boosted>,
+    /// Source of the current time, the end of every requested period
+    pub clock: SharedClock,
+    /// Fetch every group right away and only once (the backfill), ignoring the triggers
+    pub once: bool,
+    /// Holidays and half-days the cron schedules skip
+    pub calendar: Calendar,
+    /// Symbols added to and removed from the default pipeline at runtime
+    pub registry: Addr<SymbolRegistry>,
+}
+
+impl Scheduler {
+    fn request(&self, symbol: &str, watchlist: Option<&str>, now: DateTime<Utc>) -> QuoteRequest {
+        let key = Checkpoints::key(symbol, watchlist);
+        let from = match self.checkpoints.last_fetch.get(&key) {
+            Some(last) if !self.warmed.contains(&key) => {
+                let warmup = chrono::Duration::from_std(self.warmup).unwrap_or_default();
+                (*last - warmup).max(self.from)
+            }
+            _ => self.checkpoints.next_from(&key, self.from),
+        };
+        QuoteRequest {
+            symbol: symbol.to_string(),
+            from,
+            to: now,
+            watchlist: watchlist.map(|w| w.to_string()),
+        }
+    }
+
+    ///
+    /// The symbols of a group to fetch now, with the changes made at runtime
+    ///
+    async fn symbols(&self, group: usize) -> Vec<String> {
+        if self.groups[group].watchlist.is_some() {
+            return self.resolved[group].clone();
+        }
+        let changes = match self.registry.call(SymbolChangesRequest).await {
+            Ok(changes) => changes,
+            Err(_) => return self.resolved[group].clone(),
+        };
+        let defaults: Vec<usize> = (0..self.groups.len())
+            .filter(|g| self.groups[*g].watchlist.is_none())
+            .collect();
+        let resolved: Vec<Vec<String>> =
+            defaults.iter().map(|g| self.resolved[*g].clone()).collect();
+        let position = defaults.iter().position(|g| *g == group).unwrap_or(0);
+        changes.apply(&resolved).swap_remove(position)
+    }
+
+    ///
+    /// Stops requesting a symbol the provider didn't know, which only uses up the quota
+    ///
+    fn forget(&mut self, msg: FetchOutcome) {
+        if msg.status != FetchStatus::NotFound {
+            return;
+        }
+        let group = self
+            .groups
+            .iter()
+            .zip(&self.resolved)
+            .find(|(group, resolved)| {
+                group.watchlist == msg.watchlist && resolved.contains(&msg.symbol)
+            });
+        let name = match group {
+            Some((group, _)) => group.name.clone(),
+            None => return,
+        };
+        if self.unknown.insert((msg.symbol.clone(), msg.watchlist)) {
+            tracing::warn!(
+                "{} is unknown at {}, schedule '{}' no longer fetches it",
+                msg.symbol,
+                msg.provider,
+                name
+            );
+        }
+    }
+
+    ///
+    /// Requests the unknown symbols again that are now fetched from another provider
+    ///
+    fn retry_unknown(&mut self, msg: &ProviderSwitched) {
+        let before = self.unknown.len();
+        self.unknown.retain(|(symbol, _)| match &msg.symbol {
+            Some(switched) => symbol != switched,
+            None => false,
+        });
+        let retried = before - self.unknown.len();
+        if retried > 0 {
+            tracing::info!(
+                "Fetching {} unknown symbols again from '{}'",
+                retried,
+                msg.provider
+            );
+        }
+    }
+
+    fn schedule(&self, ctx: &mut Context<Self>, group: usize) {
+        match self.groups[group]
+            .trigger
+            .next_delay(self.clock.now(), &self.calendar)
+        {
+            Some(delay) => ctx.send_later(Fire { group }, delay.mul_f64(self.throttle)),
+            None => tracing::warn!("Schedule '{}' will not fire again", self.groups[group].name),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for Scheduler {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        self.resolved = vec![];
+        for group in &self.groups {
+            let symbols = self.constituents.resolve(&group.symbols).await?;
+            self.resolved.push(self.tickers.resolve(symbols).await);
+        }
+        if let Some(refresh) = self.constituents.refresh {
+            if self.groups.iter().any(|g| index::has_index(&g.symbols)) {
+                ctx.send_interval(RefreshConstituents, refresh);
+            }
+        }
+        for group in 0..self.groups.len() {
+            if self.once {
+                ctx.address().send(Fire { group })?;
+            } else {
+                self.schedule(ctx, group);
+            }
+        }
+        ctx.subscribe::<Throttle>().await?;
+        ctx.subscribe::<Anomaly>().await?;
+        ctx.subscribe::<FetchOutcome>().await?;
+        ctx.subscribe::<ProviderSwitched>().await?;
+        ctx.subscribe::<Quotes>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Fire> for Scheduler {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: Fire) {
+        let now = self.clock.now(); // Period end for this fetch
+        let mut broker = Broker::from_registry().await.unwrap();
+        let symbols = self.symbols(msg.group).await;
+        let group = &self.groups[msg.group];
+        for symbol in &symbols {
+            if self
+                .unknown
+                .contains(&(symbol.clone(), group.watchlist.clone()))
+            {
+                continue;
+            }
+            let request = self.request(symbol, group.watchlist.as_deref(), now);
+            if let Err(e) = broker.publish(request) {
+                tracing::error!("{}", e);
+                ctx.stop(None);
+                return;
+            }
+        }
+        if !self.once {
+            self.schedule(ctx, msg.group);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<RefreshConstituents> for Scheduler {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: RefreshConstituents) {
+        for (group, resolved) in self.groups.iter().zip(self.resolved.iter_mut()) {
+            if !index::has_index(&group.symbols) {
+                continue;
+            }
+            match self.constituents.resolve(&group.symbols).await {
+                Ok(symbols) => {
+                    let symbols = self.tickers.resolve(symbols).await;
+                    let added = symbols.iter().filter(|s| !resolved.contains(s)).count();
+                    let removed = resolved.iter().filter(|s| !symbols.contains(s)).count();
+                    if added + removed > 0 {
+                        tracing::info!(
+                            "Schedule '{}' now tracks {} symbols (+{} -{})",
+                            group.name,
+                            symbols.len(),
+                            added,
+                            removed
+                        );
+                    }
+                    *resolved = symbols;
+                }
+                Err(e) => {
+                    tracing::error!("Could not refresh the symbols of '{}': {}", group.name, e)
+                }
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Anomaly> for Scheduler {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: Anomaly) {
+        let boost = match self.boost {
+            Some(boost) => boost,
+            None => return,
+        };
+        let key = Checkpoints::key(&msg.symbol, msg.watchlist.as_deref());
+        let until = self.clock.now() + chrono::Duration::from_std(boost.period).unwrap_or_default();
+        if let Some(boosted) = self.boosted.get_mut(&key) {
+            boosted.until = until;
+            return;
+        }
+        tracing::info!(
+            "Anomaly for {} (z = {:.2}), fetching it every {}s",
+            msg.symbol,
+            msg.zscore,
+            boost.interval.as_secs()
+        );
+        self.boosted.insert(
+            key.clone(),
+            Boosted {
+                symbol: msg.symbol,
+                watchlist: msg.watchlist,
+                until,
+            },
+        );
+        ctx.send_later(FireSymbol { key }, boost.interval);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<FireSymbol> for Scheduler {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: FireSymbol) {
+        let now = self.clock.now();
+        let boosted = match self.boosted.get(&msg.key) {
+            Some(boosted) if boosted.until > now => boosted,
+            _ => {
+                if let Some(boosted) = self.boosted.remove(&msg.key) {
+                    tracing::info!("{} is back on its regular schedule", boosted.symbol);
+                }
+                return;
+            }
+        };
+        let request = self.request(&boosted.symbol, boosted.watchlist.as_deref(), now);
+        if let Err(e) = Broker::from_registry().await.unwrap().publish(request) {
+            tracing::error!("{}", e);
+            ctx.stop(None);
+            return;
+        }
+        if let Some(boost) = self.boost {
+            ctx.send_later(msg, boost.interval);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<FetchOutcome> for Scheduler {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: FetchOutcome) {
+        self.forget(msg);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<ProviderSwitched> for Scheduler {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: ProviderSwitched) {
+        self.retry_unknown(&msg);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Throttle> for Scheduler {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Throttle) {
+        self.throttle = msg.factor.max(1.0);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Quotes> for Scheduler {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Quotes) {
+        self.warmed
+            .insert(Checkpoints::key(&msg.symbol, msg.watchlist.as_deref()));
+        if self.checkpoints.update(&msg) {
+            if let Some(path) = &self.checkpoint_file {
+                if let Err(e) = self.checkpoints.save(path) {
+                    tracing::error!("Could not write checkpoints '{}': {}", path, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signal::SignalSet;
+
+    #[test]
+    fn test_trigger_next_delay() {
+        let now = Utc.with_ymd_and_hms(2022, 12, 2, 15, 57, 30).unwrap(); // a Friday
+        let none = Calendar::default();
+        let every = Trigger::Every(Duration::from_secs(30));
+        assert_eq!(every.next_delay(now, &none), Some(Duration::from_secs(30)));
+
+        let cron = Trigger::cron("*/5 9-16 * * MON-FRI").unwrap();
+        assert_eq!(cron.next_delay(now, &none), Some(Duration::from_secs(150)));
+
+        // after hours on Friday, the next run is on Monday at 9:00
+        let evening = Utc.with_ymd_and_hms(2022, 12, 2, 17, 0, 0).unwrap();
+        let monday = Utc.with_ymd_and_hms(2022, 12, 5, 9, 0, 0).unwrap();
+        assert_eq!(
+            cron.next_delay(evening, &none),
+            Some((monday - evening).to_std().unwrap())
+        );
+
+        // Thanksgiving is skipped, and the half-day after closes at 18:00
+        let nyse = Calendar::nyse();
+        let wednesday = Utc.with_ymd_and_hms(2024, 11, 27, 17, 0, 0).unwrap();
+        let friday = Utc.with_ymd_and_hms(2024, 11, 29, 9, 0, 0).unwrap();
+        assert_eq!(
+            cron.next_delay(wednesday, &nyse),
+            Some((friday - wednesday).to_std().unwrap())
+        );
+        let market_hours = Trigger::cron("*/5 14-20 * * MON-FRI").unwrap();
+        let afternoon = Utc.with_ymd_and_hms(2024, 11, 29, 17, 58, 0).unwrap();
+        let monday = Utc.with_ymd_and_hms(2024, 12, 2, 14, 0, 0).unwrap();
+        assert_eq!(
+            market_hours.next_delay(afternoon, &nyse),
+            Some((monday - afternoon).to_std().unwrap())
+        );
+
+        assert!(Trigger::cron("0 */10 * * * *").is_ok());
+        assert!(Trigger::cron("every minute").is_err());
+
+        let thirty = Duration::from_secs(30);
+        let default = Trigger::from_config(None, None, thirty).unwrap();
+        assert_eq!(default.next_delay(now, &none), Some(thirty));
+        let interval = Trigger::from_config(None, Some(10), thirty).unwrap();
+        assert_eq!(
+            interval.next_delay(now, &none),
+            Some(Duration::from_secs(10))
+        );
+        let cron = Trigger::from_config(Some("*/5 9-16 * * MON-FRI"), Some(10), thirty).unwrap();
+        assert_eq!(cron.next_delay(now, &none), Some(Duration::from_secs(150)));
+        assert!(Trigger::from_config(None, Some(0), thirty).is_err());
+        assert!(Trigger::from_config(None, None, Duration::ZERO).is_err());
+        assert!(Trigger::from_config(Some("*/5 * * * *"), None, Duration::ZERO).is_ok());
+    }
+
+    #[test]
+    fn test_interval() {
+        let secs = |s: &str| parse_interval(s).unwrap().as_secs();
+        assert_eq!(secs("30"), 30);
+        assert_eq!(secs("15s"), 15);
+        assert_eq!(secs("1m"), 60);
+        assert_eq!(secs("1h30m"), 5400);
+        assert!(parse_interval("1x").is_err());
+        assert!(parse_interval("m").is_err());
+        assert!(parse_interval("").is_err());
+        assert!(parse_interval("0").is_ok_and(|d| d.is_zero()));
+        // too long for a u64 of seconds
+        assert!(parse_interval("300000000000000000d").is_err());
+        assert!(parse_interval("18446744073709551615s1s").is_err());
+
+        let limits = [QuotaLimit::from_arg("yahoo=2000/3600").unwrap()];
+        let minute = Duration::from_secs(60);
+        assert!(check_interval(minute, 30, "yahoo", &limits).is_ok());
+        // 40 symbols per minute are 2400 requests per hour
+        assert!(check_interval(minute, 40, "yahoo", &limits).is_err());
+        assert!(check_interval(minute, 40, "synthetic", &limits).is_ok());
+        assert!(check_interval(Duration::ZERO, 1, "yahoo", &[]).is_err());
+    }
+
+    async fn scheduler(
+        from: DateTime<Utc>,
+        checkpoints: Checkpoints,
+        warmup: Duration,
+    ) -> Scheduler {
+        Scheduler {
+            from,
+            groups: vec![],
+            checkpoints,
+            checkpoint_file: None,
+            warmup,
+            warmed: HashSet::new(),
+            constituents: Constituents {
+                url: None,
+                refresh: None,
+            },
+            tickers: TickerResolver::default(),
+            resolved: vec![],
+            unknown: HashSet::new(),
+            throttle: 1.0,
+            boost: None,
+            boosted: HashMap::new(),
+            clock: crate::clock::system(),
+            once: false,
+            calendar: Calendar::default(),
+            registry: SymbolRegistry::default().start().await.unwrap(),
+        }
+    }
+
+    #[async_std::test]
+    async fn test_restart_reads_warmup() {
+        let from = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let last = Utc.with_ymd_and_hms(2024, 6, 3, 0, 0, 0).unwrap();
+        let mut checkpoints = Checkpoints::default();
+        checkpoints.advance("AAPL".to_string(), last);
+        let warmup = SignalSet::default().lookback();
+        // 30 bars of the moving average over six weeks of trading days, and a week
+        assert_eq!(warmup, Duration::from_secs(49 * 24 * 3600));
+        let mut scheduler = scheduler(from, checkpoints, warmup).await;
+        let now = last + chrono::Duration::days(1);
+        // the processor lost the history with the restart, the first fetch reads it again
+        let request = scheduler.request("AAPL", None, now);
+        assert_eq!(request.from, last - chrono::Duration::days(49));
+        // but not all of it since `from`
+        assert!(request.from > from);
+        // never a symbol that wasn't fetched before
+        assert_eq!(scheduler.request("MSFT", None, now).from, from);
+
+        scheduler.warmed.insert("AAPL".to_string());
+        let request = scheduler.request("AAPL", None, now);
+        assert_eq!(request.from, last + chrono::Duration::seconds(1));
+    }
+
+    #[async_std::test]
+    async fn test_unknown_symbols_after_switch() {
+        let from = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        let mut scheduler = scheduler(from, Checkpoints::default(), Duration::ZERO).await;
+        scheduler.groups = vec![ScheduleGroup {
+            name: "default".to_string(),
+            symbols: vec!["AAPL".to_string(), "ZZZZ".to_string()],
+            trigger: Trigger::Every(Duration::from_secs(60)),
+            watchlist: None,
+        }];
+        scheduler.resolved = vec![vec!["AAPL".to_string(), "ZZZZ".to_string()]];
+        let outcome = |symbol: &str, status: FetchStatus| FetchOutcome {
+            symbol: symbol.to_string(),
+            watchlist: None,
+            provider: "yahoo".to_string(),
+            status,
+        };
+        let unknown = |symbol: &str| (symbol.to_string(), None);
+        scheduler.forget(outcome("AAPL", FetchStatus::RateLimited));
+        scheduler.forget(outcome("ZZZZ", FetchStatus::NotFound));
+        assert_eq!(scheduler.unknown, HashSet::from([unknown("ZZZZ")]));
+        // the symbol stays in its group, only the requests are skipped
+        assert_eq!(scheduler.resolved[0].len(), 2);
+
+        // switching another symbol doesn't help
+        scheduler.retry_unknown(&ProviderSwitched {
+            provider: "alphavantage".to_string(),
+            symbol: Some("AAPL".to_string()),
+        });
+        assert!(scheduler.unknown.contains(&unknown("ZZZZ")));
+        scheduler.retry_unknown(&ProviderSwitched {
+            provider: "alphavantage".to_string(),
+            symbol: Some("ZZZZ".to_string()),
+        });
+        assert!(scheduler.unknown.is_empty());
+
+        // the new provider doesn't know it either, until the next switch
+        scheduler.forget(outcome("ZZZZ", FetchStatus::NotFound));
+        assert!(scheduler.unknown.contains(&unknown("ZZZZ")));
+        scheduler.retry_unknown(&ProviderSwitched {
+            provider: "synthetic".to_string(),
+            symbol: None,
+        });
+        assert!(scheduler.unknown.is_empty());
+    }
+
+    #[test]
+    fn test_split_intervals() {
+        let group = ScheduleGroup {
+            name: "crypto".to_string(),
+            symbols: vec!["BTC-USD".to_string(), "ETH-USD".to_string()],
+            trigger: Trigger::Every(Duration::from_secs(60)),
+            watchlist: Some("crypto".to_string()),
+        };
+        let intervals = BTreeMap::from([("BTC-USD".to_string(), 10)]);
+        let groups = ScheduleGroup::split_intervals(vec![group], &intervals);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, "crypto/BTC-USD");
+        assert_eq!(groups[0].symbols, vec!["BTC-USD"]);
+        assert_eq!(groups[0].watchlist.as_deref(), Some("crypto"));
+        assert!(matches!(groups[0].trigger, Trigger::Every(d) if d.as_secs() == 10));
+        assert_eq!(groups[1].symbols, vec!["ETH-USD"]);
+
+        let only = ScheduleGroup::split_intervals(groups[..1].to_vec(), &intervals);
+        assert_eq!(only.len(), 1);
+    }
+}
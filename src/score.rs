@@ -0,0 +1,180 @@
+use serde::Deserialize;
+
+use crate::PerformanceIndicators;
+
+///
+/// Number of returns the volatility component is calculated over
+///
+const VOLATILITY_WINDOW: usize = 30;
+
+///
+/// Standard deviation of the returns that scores 50 on calmness
+///
+const VOLATILITY_SCALE: f64 = 0.02;
+
+///
+/// Distance of the price from its moving average that counts as a strong trend
+///
+const TREND_SCALE: f64 = 0.05;
+
+///
+/// Change over the full range that counts as strong momentum, if there is no RSI yet
+///
+const MOMENTUM_SCALE: f64 = 0.1;
+
+///
+/// How much each normalized indicator counts towards the composite score. The weights are
+/// relative to each other; a weight of 0 leaves the indicator out.
+///
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct ScoreWeights {
+    /// Strength of the recent moves (the RSI)
+    pub momentum: f64,
+    /// Calmness: low volatility of the latest returns scores high
+    pub volatility: f64,
+    /// Distance of the price above its moving average
+    pub trend: f64,
+}
+
+impl Default for ScoreWeights {
+    fn default() -> Self {
+        ScoreWeights {
+            momentum: 1.0,
+            volatility: 1.0,
+            trend: 1.0,
+        }
+    }
+}
+
+impl ScoreWeights {
+    ///
+    /// Weights need to be non-negative and not all 0
+    ///
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let weights = [self.momentum, self.volatility, self.trend];
+        if weights.iter().any(|w| !w.is_finite() || *w < 0.0) || weights.iter().sum::<f64>() == 0.0
+        {
+            anyhow::bail!("The score weights need to be non-negative and not all 0");
+        }
+        Ok(())
+    }
+}
+
+///
+/// Standard deviation of the latest returns of a series of closes, `None` with less than two
+/// returns
+///
+pub fn volatility(closes: &[f64]) -> Option<f64> {
+    let start = closes.len().saturating_sub(VOLATILITY_WINDOW + 1);
+    let returns: Vec<f64> = closes[start..]
+        .windows(2)
+        .filter(|w| w[0] != 0.0)
+        .map(|w| w[1] / w[0] - 1.0)
+        .collect();
+    if returns.len() < 2 {
+        return None;
+    }
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance =
+        returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+    Some(variance.sqrt())
+}
+
+///
+/// The indicators, each normalized to 0..1 (0.5 is neutral), as (momentum, calmness, trend)
+///
+fn components(data: &PerformanceIndicators, volatility: Option<f64>) -> (f64, f64, f64) {
+    let momentum = match data.rsi {
+        Some(rsi) => rsi / 100.0,
+        None => 0.5 + 0.5 * (data.pct_change / MOMENTUM_SCALE).tanh(),
+    };
+    let calmness = volatility.map_or(0.5, |v| 1.0 / (1.0 + v / VOLATILITY_SCALE));
+    let trend = if data.last_sma > 0.0 {
+        0.5 + 0.5 * ((data.price / data.last_sma - 1.0) / TREND_SCALE).tanh()
+    } else {
+        0.5
+    };
+    (momentum, calmness, trend)
+}
+
+///
+/// The weighted average of the normalized indicators, from 0 to 100
+///
+pub fn composite(
+    weights: &ScoreWeights,
+    data: &PerformanceIndicators,
+    volatility: Option<f64>,
+) -> Option<f64> {
+    let (momentum, calmness, trend) = components(data, volatility);
+    let total = weights.momentum + weights.volatility + weights.trend;
+    if total <= 0.0 {
+        return None;
+    }
+    let score =
+        (weights.momentum * momentum + weights.volatility * calmness + weights.trend * trend)
+            / total
+            * 100.0;
+    Some(score.clamp(0.0, 100.0)).filter(|s| s.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volatility() {
+        assert_eq!(volatility(&[100.0, 101.0]), None);
+        assert_eq!(volatility(&[100.0, 100.0, 100.0]), Some(0.0));
+        let zigzag: Vec<f64> = (0..100)
+            .map(|i| if i % 2 == 0 { 110.0 } else { 100.0 })
+            .collect();
+        assert!(volatility(&zigzag).unwrap() > 0.09);
+    }
+
+    #[test]
+    fn test_composite() {
+        let weights = ScoreWeights::default();
+        let neutral = PerformanceIndicators {
+            price: 10.0,
+            ..Default::default()
+        };
+        assert_eq!(composite(&weights, &neutral, None), Some(50.0));
+
+        let strong = PerformanceIndicators {
+            price: 12.0,
+            last_sma: 10.0,
+            rsi: Some(90.0),
+            ..Default::default()
+        };
+        let weak = PerformanceIndicators {
+            price: 8.0,
+            last_sma: 10.0,
+            rsi: Some(10.0),
+            ..Default::default()
+        };
+        let strong = composite(&weights, &strong, Some(0.01)).unwrap();
+        let weak = composite(&weights, &weak, Some(0.05)).unwrap();
+        assert!(strong > 75.0, "{}", strong);
+        assert!(weak < 25.0, "{}", weak);
+
+        // only the momentum counts
+        let momentum = ScoreWeights {
+            volatility: 0.0,
+            trend: 0.0,
+            ..Default::default()
+        };
+        let data = PerformanceIndicators {
+            rsi: Some(70.0),
+            ..Default::default()
+        };
+        assert_eq!(composite(&momentum, &data, Some(0.5)), Some(70.0));
+        assert!(ScoreWeights {
+            momentum: 0.0,
+            ..momentum
+        }
+        .validate()
+        .is_err());
+        assert!(weights.validate().is_ok());
+    }
+}
@@ -0,0 +1,309 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::anyhow;
+use rhai::{Array, Dynamic, Engine, ImmutableString, Map, Scope, AST};
+
+use crate::config::ScriptConfig;
+use crate::signal::TickerQuote;
+use crate::PerformanceIndicators;
+
+///
+/// Creates a rhai engine with the helper functions available to all scripts:
+/// `sum`, `mean`, `min`, and `max` over arrays of numbers.
+///
+pub fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .register_type_with_name::<Timeframes>("Timeframes")
+        .register_indexer_get(|t: &mut Timeframes, resolution: ImmutableString| {
+            t.0.get(resolution.as_str()).cloned().unwrap_or_default()
+        });
+    engine.register_fn("sum", |a: Array| numbers(&a).sum::<f64>());
+    engine.register_fn("mean", |a: Array| {
+        if a.is_empty() {
+            0.0
+        } else {
+            numbers(&a).sum::<f64>() / a.len() as f64
+        }
+    });
+    engine.register_fn("min", |a: Array| numbers(&a).fold(f64::MAX, f64::min));
+    engine.register_fn("max", |a: Array| numbers(&a).fold(f64::MIN, f64::max));
+    engine
+}
+
+fn numbers(a: &Array) -> impl Iterator<Item = f64> + '_ {
+    a.iter().filter_map(as_f64)
+}
+
+fn as_f64(d: &Dynamic) -> Option<f64> {
+    d.as_float()
+        .ok()
+        .or_else(|| d.as_int().ok().map(|i| i as f64))
+}
+
+fn series(values: impl Iterator<Item = f64>) -> Array {
+    values.map(Dynamic::from_float).collect()
+}
+
+///
+/// Ready-made alert conditions that can be used with `template = "<name>"` in the config file
+///
+pub fn template(name: &str) -> Option<&'static str> {
+    match name {
+        "new_52w_high" => Some("price >= high_52w"),
+        "new_52w_low" => Some("price <= low_52w"),
+        "gap_up" => Some("gap_pct >= thresholds.gap"),
+        "gap_down" => Some("gap_pct <= -thresholds.gap"),
+        _ => None,
+    }
+}
+
+///
+/// A compiled script
+///
+#[derive(Clone, Debug)]
+pub struct Script {
+    pub name: String,
+    ast: AST,
+}
+
+impl Script {
+    pub fn compile(engine: &Engine, name: &str, source: &str) -> anyhow::Result<Self> {
+        let ast = engine
+            .compile(source)
+            .map_err(|e| anyhow!("Script '{}' does not compile: {}", name, e))?;
+        Ok(Script {
+            name: name.to_string(),
+            ast,
+        })
+    }
+
+    pub fn from_config(
+        engine: &Engine,
+        config: &ScriptConfig,
+        base: &Path,
+    ) -> anyhow::Result<Self> {
+        Self::compile(engine, &config.name, &config.source(base)?)
+    }
+
+    ///
+    /// Evaluates a custom indicator. The scope contains `symbol` and the candle series
+    /// `opens`, `highs`, `lows`, `closes`, and `volumes`.
+    ///
+    pub fn indicator(
+        &self,
+        engine: &Engine,
+        symbol: &str,
+        quotes: &[TickerQuote],
+    ) -> anyhow::Result<f64> {
+        let mut scope = Scope::new();
+        scope.push("symbol", symbol.to_string());
+        scope.push("opens", series(quotes.iter().map(|q| q.open)));
+        scope.push("highs", series(quotes.iter().map(|q| q.high)));
+        scope.push("lows", series(quotes.iter().map(|q| q.low)));
+        scope.push("closes", series(quotes.iter().map(|q| q.close)));
+        scope.push("volumes", series(quotes.iter().map(|q| q.volume as f64)));
+        let result: Dynamic = engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| anyhow!("{}", e))?;
+        as_f64(&result).ok_or_else(|| anyhow!("expected a number, got '{}'", result.type_name()))
+    }
+
+    ///
+    /// Evaluates an alert condition. The scope contains all fields of the indicators, plus the
+    /// custom indicators by name. `resolution` is empty for indicators of the fetched quotes.
+    /// The symbol's alert thresholds are available as `thresholds.<name>`, and its latest
+    /// indicators at other resolutions as `timeframes["1d"].<name>`.
+    ///
+    pub fn condition(
+        &self,
+        engine: &Engine,
+        data: &PerformanceIndicators,
+        timeframes: &Timeframes,
+        thresholds: &BTreeMap<String, f64>,
+    ) -> anyhow::Result<bool> {
+        let mut scope = Scope::new();
+        for (name, value) in variables(data) {
+            scope.push_dynamic(name, value);
+        }
+        scope.push("timeframes", timeframes.clone());
+        let thresholds: Map = thresholds
+            .iter()
+            .map(|(name, value)| (name.as_str().into(), Dynamic::from_float(*value)))
+            .collect();
+        scope.push("thresholds", thresholds);
+        engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|e| anyhow!("{}", e))
+    }
+}
+
+///
+/// The fields of the indicators and the custom indicators by name, as the variables of an
+/// alert condition
+///
+fn variables(data: &PerformanceIndicators) -> Map {
+    let mut variables: Map = data
+        .custom
+        .iter()
+        .map(|(name, value)| (name.as_str().into(), Dynamic::from_float(*value)))
+        .collect();
+    let mut set = |name: &str, value: Dynamic| {
+        variables.insert(name.into(), value);
+    };
+    set("symbol", data.symbol.clone().into());
+    set("price", data.price.into());
+    set("pct_change", data.pct_change.into());
+    set("period_min", data.period_min.into());
+    set("period_max", data.period_max.into());
+    set("last_sma", data.last_sma.into());
+    set("last_ema", data.last_ema.into());
+    set(
+        "resolution",
+        data.resolution.clone().unwrap_or_default().into(),
+    );
+    // missing values are `()`, which never compares true
+    let optional = |value: Option<f64>| value.map_or(Dynamic::UNIT, Dynamic::from_float);
+    set("high_52w", optional(data.high_52w));
+    set("low_52w", optional(data.low_52w));
+    set("pct_from_high_52w", optional(data.pct_from_high_52w));
+    set("pct_from_low_52w", optional(data.pct_from_low_52w));
+    set("session_open", optional(data.session_open));
+    set("vwap_session", optional(data.vwap_session));
+    set("rsi", optional(data.rsi));
+    set("volatility", optional(data.volatility));
+    set("score", optional(data.score));
+    set("gap_pct", optional(data.gap_pct));
+    set(
+        "change_from_prev_close",
+        optional(data.change_from_prev_close),
+    );
+    variables
+}
+
+///
+/// The latest indicators of a symbol at every resolution, for conditions that combine them.
+/// A resolution without indicators yet has no fields, so its values never compare true.
+///
+#[derive(Debug, Clone, Default)]
+pub struct Timeframes(BTreeMap<String, Map>);
+
+impl Timeframes {
+    ///
+    /// Keeps the indicators if they are of a resolution
+    ///
+    pub fn update(&mut self, data: &PerformanceIndicators) {
+        if let Some(resolution) = &data.resolution {
+            self.0.insert(resolution.clone(), variables(data));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    fn quote(close: f64) -> TickerQuote {
+        TickerQuote {
+            timestamp: 0,
+            open: close,
+            high: close + 1.0,
+            low: close - 1.0,
+            volume: 100,
+            close,
+            adjclose: close,
+        }
+    }
+
+    #[test]
+    fn test_indicator_script() {
+        let engine = engine();
+        let quotes = vec![quote(1.0), quote(2.0), quote(6.0)];
+        let range = Script::compile(&engine, "range", "max(highs) - min(lows)").unwrap();
+        assert_eq!(range.indicator(&engine, "A", &quotes).unwrap(), 7.0);
+        let avg = Script::compile(&engine, "avg", "mean(closes)").unwrap();
+        assert_eq!(avg.indicator(&engine, "A", &quotes).unwrap(), 3.0);
+        let int = Script::compile(&engine, "int", "closes.len()").unwrap();
+        assert_eq!(int.indicator(&engine, "A", &quotes).unwrap(), 3.0);
+        let text = Script::compile(&engine, "text", "symbol").unwrap();
+        assert!(text.indicator(&engine, "A", &quotes).is_err());
+        assert!(Script::compile(&engine, "broken", "1 +").is_err());
+    }
+
+    #[test]
+    fn test_condition_script() {
+        let engine = engine();
+        let mut data = PerformanceIndicators {
+            timestamp: Utc::now(),
+            symbol: "AAPL".to_string(),
+            price: 210.0,
+            pct_change: -0.06,
+            period_min: 0.0,
+            period_max: 0.0,
+            last_sma: 0.0,
+            custom: Default::default(),
+            ..Default::default()
+        };
+        data.custom.insert("range".to_string(), 7.0);
+        let rule = Script::compile(
+            &engine,
+            "drop",
+            r#"symbol == "AAPL" && pct_change < -0.05 && range > 5.0"#,
+        )
+        .unwrap();
+        let none = BTreeMap::new();
+        let frames = Timeframes::default();
+        assert!(rule.condition(&engine, &data, &frames, &none).unwrap());
+        data.pct_change = 0.0;
+        assert!(!rule.condition(&engine, &data, &frames, &none).unwrap());
+
+        let rule = Script::compile(&engine, "drop", "pct_change < thresholds.drop").unwrap();
+        let thresholds = BTreeMap::from([("drop".to_string(), 0.01)]);
+        assert!(rule
+            .condition(&engine, &data, &frames, &thresholds)
+            .unwrap());
+        // a missing threshold never matches
+        assert!(!rule.condition(&engine, &data, &frames, &none).unwrap());
+
+        let rule = Script::compile(&engine, "high", template("new_52w_high").unwrap()).unwrap();
+        assert!(!rule.condition(&engine, &data, &frames, &none).unwrap());
+        data.high_52w = Some(210.0);
+        assert!(rule.condition(&engine, &data, &frames, &none).unwrap());
+        data.high_52w = Some(215.0);
+        assert!(!rule.condition(&engine, &data, &frames, &none).unwrap());
+    }
+
+    #[test]
+    fn test_timeframes() {
+        let engine = engine();
+        let row = |resolution: Option<&str>, rsi: f64, last_ema: f64| PerformanceIndicators {
+            symbol: "AAPL".to_string(),
+            rsi: Some(rsi),
+            last_ema,
+            last_sma: 100.0,
+            resolution: resolution.map(String::from),
+            ..Default::default()
+        };
+        let rule = Script::compile(
+            &engine,
+            "confluence",
+            r#"timeframes["1h"].rsi < 30 && timeframes["1d"].last_ema > timeframes["1d"].last_sma"#,
+        )
+        .unwrap();
+        let none = BTreeMap::new();
+        let mut frames = Timeframes::default();
+        let data = row(None, 50.0, 100.0);
+        // no indicators of the resolutions yet
+        assert!(!rule.condition(&engine, &data, &frames, &none).unwrap());
+
+        frames.update(&row(Some("1h"), 25.0, 100.0));
+        frames.update(&data);
+        assert!(!rule.condition(&engine, &data, &frames, &none).unwrap());
+        frames.update(&row(Some("1d"), 60.0, 110.0));
+        assert!(rule.condition(&engine, &data, &frames, &none).unwrap());
+        frames.update(&row(Some("1h"), 35.0, 100.0));
+        assert!(!rule.condition(&engine, &data, &frames, &none).unwrap());
+    }
+}
@@ -0,0 +1,50 @@
+use async_std::channel::Receiver;
+use xactor::*;
+
+///
+/// Asks a sink to write out everything it holds. Sent with `call`, so the answer means the
+/// data is on disk. The stages in front of the sinks answer it once they handled the messages
+/// that arrived before it.
+///
+#[message]
+#[derive(Clone, Copy, Debug)]
+pub struct Shutdown;
+
+///
+/// Answered by a `Broker` once it forwarded everything published before it
+///
+#[message]
+#[derive(Clone, Copy, Debug)]
+pub struct Barrier;
+
+#[async_trait::async_trait]
+impl<T: Message<Result = ()>> Handler<Barrier> for Broker<T> {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Barrier) {}
+}
+
+///
+/// Waits until the messages of type `T` published so far are in the mailboxes of their
+/// subscribers. A `Shutdown` sent to a subscriber after that is answered once it handled them.
+///
+pub async fn barrier<T: Message<Result = ()>>() -> Result<()> {
+    Broker::<T>::from_registry().await?.call(Barrier).await
+}
+
+///
+/// Receives a message on the first SIGINT (Ctrl-C) or SIGTERM. A second signal exits right
+/// away.
+///
+pub fn on_signal() -> anyhow::Result<Receiver<()>> {
+    let (tx, rx) = async_std::channel::bounded(1);
+    let mut received = false;
+    ctrlc::set_handler(move || {
+        if received {
+            tracing::warn!("Exiting without flushing");
+            std::process::exit(130);
+        }
+        received = true;
+        tracing::info!("Shutting down, send the signal again to exit right away");
+        let _ = tx.try_send(());
+    })?;
+    Ok(rx)
+}
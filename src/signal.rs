@@ -1,4 +1,9 @@
 use async_trait::async_trait;
+
+use std::time::Duration;
+
+use crate::candles::Candles;
+use crate::config::SymbolConfig;
 pub use yahoo::Quote as TickerQuote;
 pub use yahoo::YahooError as DataSourceError;
 use yahoo_finance_api as yahoo;
@@ -75,6 +80,148 @@ impl AsyncStockSignal for WindowedSMA {
     }
 }
 
+///
+/// Exponential moving average over `period` values. Each value is weighted with
+/// `smoothing / (period + 1)` against the previous average, which starts as the simple average
+/// of the first `period` values.
+///
+pub struct ExponentialMovingAverage {
+    pub period: usize,
+    pub smoothing: f64,
+}
+
+impl ExponentialMovingAverage {
+    fn alpha(&self) -> f64 {
+        self.smoothing / (self.period + 1) as f64
+    }
+}
+
+#[async_trait]
+impl AsyncStockSignal for ExponentialMovingAverage {
+    type SignalType = Vec<f64>;
+
+    async fn calculate(&self, series: &[f64]) -> Option<Self::SignalType> {
+        let alpha = self.alpha();
+        if series.is_empty() || self.period == 0 || !(alpha > 0.0 && alpha <= 1.0) {
+            return None;
+        }
+        if series.len() < self.period {
+            return Some(vec![]);
+        }
+        let (seed, rest) = series.split_at(self.period);
+        let first = seed.iter().sum::<f64>() / seed.len() as f64;
+        Some(
+            std::iter::once(first)
+                .chain(rest.iter().scan(first, |ema, price| {
+                    *ema += alpha * (price - *ema);
+                    Some(*ema)
+                }))
+                .collect(),
+        )
+    }
+}
+
+///
+/// Wilder's relative strength index over `period` price changes: the share of the average
+/// gain in the average absolute change, from 0 to 100
+///
+pub struct RelativeStrengthIndex {
+    pub period: usize,
+}
+
+impl Default for RelativeStrengthIndex {
+    fn default() -> Self {
+        RelativeStrengthIndex { period: 14 }
+    }
+}
+
+#[async_trait]
+impl AsyncStockSignal for RelativeStrengthIndex {
+    type SignalType = Vec<f64>;
+
+    async fn calculate(&self, series: &[f64]) -> Option<Self::SignalType> {
+        if series.is_empty() || self.period == 0 {
+            return None;
+        }
+        let changes: Vec<(f64, f64)> = series
+            .windows(2)
+            .map(|w| {
+                let change = w[1] - w[0];
+                (change.max(0.0), (-change).max(0.0))
+            })
+            .collect();
+        if changes.len() < self.period {
+            return Some(vec![]);
+        }
+        let rsi = |gain: f64, loss: f64| {
+            if gain + loss == 0.0 {
+                50.0
+            } else {
+                // rounding can overshoot by an ulp
+                (100.0 * gain / (gain + loss)).min(100.0)
+            }
+        };
+        let (seed, rest) = changes.split_at(self.period);
+        let n = self.period as f64;
+        let mut gain = seed.iter().map(|c| c.0).sum::<f64>() / n;
+        let mut loss = seed.iter().map(|c| c.1).sum::<f64>() / n;
+        let mut values = vec![rsi(gain, loss)];
+        for (up, down) in rest {
+            gain = (gain * (n - 1.0) + up) / n;
+            loss = (loss * (n - 1.0) + down) / n;
+            values.push(rsi(gain, loss));
+        }
+        Some(values)
+    }
+}
+
+///
+/// Annualized standard deviation of the log returns over a rolling window of `window` returns
+///
+pub struct RollingVolatility {
+    pub window: usize,
+    /// Bars per year the deviation is scaled with, 252 trading days for daily bars
+    pub periods_per_year: f64,
+}
+
+impl Default for RollingVolatility {
+    fn default() -> Self {
+        RollingVolatility {
+            window: 20,
+            periods_per_year: 252.0,
+        }
+    }
+}
+
+#[async_trait]
+impl AsyncStockSignal for RollingVolatility {
+    type SignalType = Vec<f64>;
+
+    async fn calculate(&self, series: &[f64]) -> Option<Self::SignalType> {
+        let periods = self.periods_per_year;
+        if series.is_empty() || self.window < 2 || !(periods > 0.0 && periods.is_finite()) {
+            return None;
+        }
+        // prices of 0 or below have no log return
+        let returns: Vec<f64> = series
+            .windows(2)
+            .filter(|w| w[0] > 0.0 && w[1] > 0.0)
+            .map(|w| (w[1] / w[0]).ln())
+            .collect();
+        let n = self.window as f64;
+        Some(
+            returns
+                .windows(self.window)
+                .map(|w| {
+                    let mean = w.iter().sum::<f64>() / n;
+                    let variance = w.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0);
+                    (variance * self.periods_per_year).sqrt()
+                })
+                .collect(),
+        )
+    }
+}
+
 ///
 /// Find the maximum in a series of f64
 ///
@@ -111,10 +258,253 @@ impl AsyncStockSignal for MinPrice {
     }
 }
 
+///
+/// How many standard deviations the last value is away from the mean of the `window_size`
+/// values before it
+///
+pub struct ZScore {
+    pub window_size: usize,
+}
+
+#[async_trait]
+impl AsyncStockSignal for ZScore {
+    type SignalType = f64;
+
+    async fn calculate(&self, series: &[f64]) -> Option<Self::SignalType> {
+        if self.window_size < 2 || series.len() <= self.window_size {
+            return None;
+        }
+        let (last, rest) = series.split_last()?;
+        let window = &rest[rest.len() - self.window_size..];
+        let mean = window.iter().sum::<f64>() / window.len() as f64;
+        let variance =
+            window.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (window.len() - 1) as f64;
+        let std_dev = variance.sqrt();
+        if std_dev > 0.0 {
+            Some((last - mean) / std_dev)
+        } else {
+            Some(0.0)
+        }
+    }
+}
+
+///
+/// A bar size like `5m`, `1h`, or `1d`
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub label: String,
+    pub seconds: u64,
+}
+
+impl Resolution {
+    ///
+    /// Parses a comma separated list, e.g. `1h,1d`. An empty string is an empty list.
+    ///
+    pub fn parse_list(list: &str) -> anyhow::Result<Vec<Self>> {
+        list.split(',')
+            .map(|r| r.trim())
+            .filter(|r| !r.is_empty())
+            .map(|r| r.parse())
+            .collect()
+    }
+}
+
+impl std::str::FromStr for Resolution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (amount, unit) = s.split_at(split);
+        let unit_seconds = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => anyhow::bail!("Unknown resolution '{}', expected e.g. 5m, 1h, or 1d", s),
+        };
+        match amount.parse::<u64>() {
+            Ok(amount) if amount > 0 => Ok(Resolution {
+                label: s.to_string(),
+                seconds: amount * unit_seconds,
+            }),
+            _ => anyhow::bail!("Invalid resolution '{}', expected e.g. 5m, 1h, or 1d", s),
+        }
+    }
+}
+
+///
+/// The signals calculated for a watchlist
+///
+#[derive(Clone, Debug)]
+pub struct SignalSet {
+    pub sma_window: usize,
+    pub ema_period: usize,
+    pub ema_smoothing: f64,
+    pub rsi_period: usize,
+    pub volatility_window: usize,
+    /// Names of the custom signals (plugins and scripts) to calculate, `None` for all
+    pub custom: Option<Vec<String>>,
+    /// Additionally calculate the signals over bars of these sizes
+    pub resolutions: Vec<Resolution>,
+    /// The candles the signals are calculated over
+    pub candles: Candles,
+}
+
+impl Default for SignalSet {
+    fn default() -> Self {
+        SignalSet {
+            sma_window: 30,
+            ema_period: 12,
+            ema_smoothing: 2.0,
+            rsi_period: 14,
+            volatility_window: 20,
+            custom: None,
+            resolutions: vec![],
+            candles: Candles::Raw,
+        }
+    }
+}
+
+impl SignalSet {
+    pub fn includes(&self, name: &str) -> bool {
+        self.custom
+            .as_ref()
+            .is_none_or(|names| names.iter().any(|n| n == name))
+    }
+
+    ///
+    /// The signals with the windows and candles of a symbol's settings
+    ///
+    pub fn with_overrides(mut self, overrides: &SymbolConfig) -> Self {
+        if let Some(sma_window) = overrides.sma_window {
+            self.sma_window = sma_window;
+        }
+        if let Some(ema_period) = overrides.ema_period {
+            self.ema_period = ema_period;
+        }
+        if let Some(rsi_period) = overrides.rsi_period {
+            self.rsi_period = rsi_period;
+        }
+        if let Some(volatility_window) = overrides.volatility_window {
+            self.volatility_window = volatility_window;
+        }
+        if let Some(candles) = overrides.candles {
+            self.candles = candles;
+        }
+        self
+    }
+
+    ///
+    /// How much history the longest window needs: daily bars (or bars of the largest
+    /// resolution) over five trading days a week, plus a week for the holidays
+    ///
+    pub fn lookback(&self) -> Duration {
+        const DAY: u64 = 24 * 60 * 60;
+        let bars = self
+            .sma_window
+            .max(self.ema_period)
+            .max(self.rsi_period + 1)
+            .max(self.volatility_window + 1) as u64;
+        let bar = self
+            .resolutions
+            .iter()
+            .map(|r| r.seconds)
+            .fold(DAY, u64::max);
+        Duration::from_secs((bars * bar).div_ceil(5) * 7 + 7 * DAY)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(non_snake_case)]
     use super::*;
+    use async_std::task::block_on;
+    use proptest::prelude::*;
+
+    ///
+    /// Price series as random walks, from pennies to large values, with flat stretches
+    ///
+    fn price_series() -> impl Strategy<Value = Vec<f64>> {
+        (
+            0.01f64..100_000.0,
+            prop::collection::vec(prop_oneof![Just(0.0), -0.5f64..0.5], 1..300),
+        )
+            .prop_map(|(start, returns)| {
+                returns
+                    .into_iter()
+                    .scan(start, |price, r| {
+                        *price *= 1.0 + r;
+                        Some(*price)
+                    })
+                    .collect()
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn test_min_max_bound_series(series in price_series()) {
+            let min = block_on(MinPrice {}.calculate(&series)).unwrap();
+            let max = block_on(MaxPrice {}.calculate(&series)).unwrap();
+            prop_assert!(min <= max);
+            prop_assert!(series.iter().all(|p| min <= *p && *p <= max));
+            let (abs, rel) = block_on(PriceDifference {}.calculate(&series)).unwrap();
+            prop_assert!(abs.is_finite() && rel.is_finite());
+        }
+
+        #[test]
+        fn test_sma_bounded_by_min_max(series in price_series(), window in 2usize..50) {
+            let min = block_on(MinPrice {}.calculate(&series)).unwrap();
+            let max = block_on(MaxPrice {}.calculate(&series)).unwrap();
+            let sma = block_on(WindowedSMA { window_size: window }.calculate(&series)).unwrap();
+            prop_assert_eq!(sma.len(), (series.len() + 1).saturating_sub(window));
+            for value in sma {
+                let tolerance = 1e-9 * max.abs().max(1.0);
+                prop_assert!(min - tolerance <= value && value <= max + tolerance);
+            }
+        }
+
+        #[test]
+        fn test_ema_bounded_by_min_max(series in price_series(), period in 1usize..50) {
+            let min = block_on(MinPrice {}.calculate(&series)).unwrap();
+            let max = block_on(MaxPrice {}.calculate(&series)).unwrap();
+            let signal = ExponentialMovingAverage { period, smoothing: 2.0 };
+            let ema = block_on(signal.calculate(&series)).unwrap();
+            prop_assert_eq!(ema.len(), (series.len() + 1).saturating_sub(period));
+            for value in ema {
+                let tolerance = 1e-9 * max.abs().max(1.0);
+                prop_assert!(min - tolerance <= value && value <= max + tolerance);
+            }
+        }
+
+        #[test]
+        fn test_rsi_within_range(series in price_series(), period in 1usize..30) {
+            let rsi = block_on(RelativeStrengthIndex { period }.calculate(&series)).unwrap();
+            prop_assert_eq!(rsi.len(), series.len().saturating_sub(period));
+            for value in rsi {
+                prop_assert!((0.0..=100.0).contains(&value));
+            }
+        }
+
+        #[test]
+        fn test_volatility_not_negative(series in price_series(), window in 2usize..30) {
+            let signal = RollingVolatility { window, ..Default::default() };
+            let volatility = block_on(signal.calculate(&series)).unwrap();
+            prop_assert_eq!(volatility.len(), series.len().saturating_sub(window));
+            for value in volatility {
+                prop_assert!(value.is_finite() && value >= 0.0);
+            }
+        }
+
+        #[test]
+        fn test_zscore_finite(series in price_series(), window in 2usize..50) {
+            if let Some(z) = block_on(ZScore { window_size: window }.calculate(&series)) {
+                prop_assert!(z.is_finite());
+            }
+        }
+    }
 
     #[async_std::test]
     async fn test_PriceDifference_calculate() {
@@ -186,4 +576,122 @@ mod tests {
         let signal = WindowedSMA { window_size: 10 };
         assert_eq!(signal.calculate(&series).await, Some(vec![]));
     }
+
+    #[async_std::test]
+    async fn test_ExponentialMovingAverage_calculate() {
+        let series = vec![2.0, 4.0, 6.0, 8.0, 4.0];
+
+        // alpha = 2 / 4 = 0.5, seeded with the average of the first 3 values
+        let signal = ExponentialMovingAverage {
+            period: 3,
+            smoothing: 2.0,
+        };
+        assert_eq!(signal.calculate(&series).await, Some(vec![4.0, 6.0, 5.0]));
+
+        // a smoothing of period + 1 just follows the price
+        let signal = ExponentialMovingAverage {
+            period: 1,
+            smoothing: 2.0,
+        };
+        assert_eq!(signal.calculate(&series).await, Some(series.clone()));
+
+        let signal = ExponentialMovingAverage {
+            period: 10,
+            smoothing: 2.0,
+        };
+        assert_eq!(signal.calculate(&series).await, Some(vec![]));
+        assert_eq!(signal.calculate(&[]).await, None);
+
+        // weights above 1 would overshoot
+        let signal = ExponentialMovingAverage {
+            period: 3,
+            smoothing: 5.0,
+        };
+        assert_eq!(signal.calculate(&series).await, None);
+    }
+
+    #[async_std::test]
+    async fn test_RelativeStrengthIndex_calculate() {
+        let signal = RelativeStrengthIndex { period: 2 };
+        assert_eq!(signal.calculate(&[]).await, None);
+        assert_eq!(signal.calculate(&[1.0, 2.0]).await, Some(vec![]));
+        // gains 1, 1, then a loss of 2: averages (1, 0), then (0.5, 1)
+        assert_eq!(
+            signal.calculate(&[1.0, 2.0, 3.0, 1.0]).await,
+            Some(vec![100.0, 100.0 / 3.0])
+        );
+        // no change at all is neutral
+        assert_eq!(signal.calculate(&[5.0, 5.0, 5.0]).await, Some(vec![50.0]));
+        assert_eq!(RelativeStrengthIndex::default().period, 14);
+    }
+
+    #[async_std::test]
+    async fn test_RollingVolatility_calculate() {
+        let signal = RollingVolatility {
+            window: 2,
+            periods_per_year: 4.0,
+        };
+        assert_eq!(signal.calculate(&[]).await, None);
+        assert_eq!(signal.calculate(&[1.0, 2.0]).await, Some(vec![]));
+        // steady growth doesn't vary
+        let steady = signal.calculate(&[1.0, 2.0, 4.0, 8.0]).await.unwrap();
+        assert_eq!(steady.len(), 2);
+        assert!(steady.iter().all(|v| v.abs() < 1e-12));
+        // log returns ln 2 and -ln 2: a deviation of ln 2 * sqrt 2, doubled by the scaling
+        let volatility = signal.calculate(&[1.0, 2.0, 1.0]).await.unwrap();
+        assert!((volatility[0] - 2.0f64.ln() * 2.0f64.sqrt() * 2.0).abs() < 1e-12);
+        assert_eq!(RollingVolatility::default().window, 20);
+    }
+
+    #[async_std::test]
+    async fn test_ZScore_calculate() {
+        let signal = ZScore { window_size: 4 };
+        assert_eq!(signal.calculate(&[1.0, 2.0, 3.0, 4.0]).await, None);
+        assert_eq!(
+            signal.calculate(&[5.0, 5.0, 5.0, 5.0, 9.0]).await,
+            Some(0.0)
+        );
+        // mean 2.5, sample standard deviation ~1.29
+        let z = signal
+            .calculate(&[100.0, 1.0, 2.0, 3.0, 4.0, 6.373])
+            .await
+            .unwrap();
+        assert!((z - 3.0).abs() < 1e-3, "{}", z);
+    }
+
+    #[test]
+    fn test_Resolution_parse() {
+        let hour: Resolution = "1h".parse().unwrap();
+        assert_eq!(hour.seconds, 3600);
+        assert_eq!(hour.label, "1h");
+        assert_eq!(
+            Resolution::parse_list(" 5m, 1d").unwrap(),
+            vec![
+                Resolution {
+                    label: "5m".to_string(),
+                    seconds: 300
+                },
+                Resolution {
+                    label: "1d".to_string(),
+                    seconds: 86400
+                }
+            ]
+        );
+        assert!(Resolution::parse_list("").unwrap().is_empty());
+        assert!("0m".parse::<Resolution>().is_err());
+        assert!("h".parse::<Resolution>().is_err());
+        assert!("1y".parse::<Resolution>().is_err());
+    }
+
+    #[test]
+    fn test_SignalSet_includes() {
+        let all = SignalSet::default();
+        assert!(all.includes("range"));
+        let some = SignalSet {
+            custom: Some(vec!["range".to_string()]),
+            ..Default::default()
+        };
+        assert!(some.includes("range"));
+        assert!(!some.includes("double"));
+    }
 }
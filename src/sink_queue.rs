@@ -0,0 +1,187 @@
+//!
+//! Retry queues for the sinks. A sink that can't write (a full disk, a locked database) keeps
+//! the rows in its own queue and writes them once it recovers, so it neither loses them right
+//! away nor holds up the other sinks. Every queue is bounded: when it's full, the oldest rows
+//! are dropped.
+//!
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use xactor::*;
+
+///
+/// Rows a sink keeps at most while it can't write
+///
+pub const DEFAULT_CAPACITY: usize = 10_000;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+///
+/// The state of a sink's queue, published after every write attempt
+///
+#[message]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SinkHealth {
+    pub sink: String,
+    /// Rows waiting to be written
+    pub queued: usize,
+    /// Failed write attempts since the start
+    pub failures: u64,
+    /// Rows dropped from the full queue since the start
+    pub dropped: u64,
+}
+
+///
+/// Rows a sink couldn't write yet, oldest first, retried with a backoff
+///
+#[derive(Debug)]
+pub struct RetryQueue<T> {
+    sink: String,
+    /// Rows the queue holds at most (0 for no limit)
+    capacity: usize,
+    rows: VecDeque<T>,
+    failures: u64,
+    dropped: u64,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl<T> RetryQueue<T> {
+    pub fn new(sink: impl Into<String>, capacity: usize) -> Self {
+        RetryQueue {
+            sink: sink.into(),
+            capacity,
+            rows: VecDeque::new(),
+            failures: 0,
+            dropped: 0,
+            backoff: MIN_BACKOFF,
+            retry_at: None,
+        }
+    }
+
+    ///
+    /// Queues a row, dropping the oldest one if the queue is full
+    ///
+    pub fn push(&mut self, row: T) {
+        if self.capacity > 0 && self.rows.len() >= self.capacity {
+            self.rows.pop_front();
+            self.dropped += 1;
+        }
+        self.rows.push_back(row);
+    }
+
+    pub fn extend(&mut self, rows: impl IntoIterator<Item = T>) {
+        for row in rows {
+            self.push(row);
+        }
+    }
+
+    ///
+    /// There are rows, and the backoff after the last failure has passed
+    ///
+    pub fn is_ready(&self) -> bool {
+        !self.rows.is_empty() && self.retry_at.is_none_or(|at| Instant::now() >= at)
+    }
+
+    ///
+    /// Ignores the backoff for the next attempt, e.g. on shutdown
+    ///
+    pub fn retry_now(&mut self) {
+        self.retry_at = None;
+    }
+
+    pub fn rows(&mut self) -> &[T] {
+        self.rows.make_contiguous()
+    }
+
+    ///
+    /// Removes the oldest `count` rows, which were written
+    ///
+    pub fn delivered(&mut self, count: usize) -> Vec<T> {
+        let count = count.min(self.rows.len());
+        let rows = self.rows.drain(..count).collect();
+        self.backoff = MIN_BACKOFF;
+        self.retry_at = None;
+        rows
+    }
+
+    ///
+    /// Records a failed attempt. Returns how long the queue waits before the next one.
+    ///
+    pub fn failed(&mut self) -> Duration {
+        let backoff = self.backoff;
+        self.failures += 1;
+        self.retry_at = Some(Instant::now() + backoff);
+        self.backoff = (backoff * 2).min(MAX_BACKOFF);
+        backoff
+    }
+
+    /// The last attempt failed
+    pub fn is_failing(&self) -> bool {
+        self.retry_at.is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn health(&self) -> SinkHealth {
+        SinkHealth {
+            sink: self.sink.clone(),
+            queued: self.rows.len(),
+            failures: self.failures,
+            dropped: self.dropped,
+        }
+    }
+
+    ///
+    /// Publishes the health of the sink for the metrics
+    ///
+    pub async fn report(&self) {
+        if let Ok(mut broker) = Broker::from_registry().await {
+            let _ = broker.publish(self.health());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_queue() {
+        let mut queue = RetryQueue::new("sqlite", 3);
+        assert!(!queue.is_ready());
+        queue.extend(vec![1, 2]);
+        assert!(queue.is_ready());
+
+        assert_eq!(queue.failed(), Duration::from_secs(1));
+        assert!(!queue.is_ready());
+        assert!(queue.is_failing());
+        assert_eq!(queue.failed(), Duration::from_secs(2));
+
+        // the oldest rows make room for new ones
+        queue.extend(vec![3, 4, 5]);
+        assert_eq!(queue.rows(), &[3, 4, 5]);
+        queue.retry_now();
+        assert!(queue.is_ready());
+        assert_eq!(queue.delivered(2), vec![3, 4]);
+        assert!(!queue.is_failing());
+        assert_eq!(
+            queue.health(),
+            SinkHealth {
+                sink: "sqlite".to_string(),
+                queued: 1,
+                failures: 2,
+                dropped: 2,
+            }
+        );
+        // the backoff starts over after a success
+        assert_eq!(queue.failed(), Duration::from_secs(1));
+    }
+}
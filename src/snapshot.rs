@@ -0,0 +1,308 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::time::Duration;
+
+use anyhow::{bail, Context as _};
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::alert::{AlertEngine, AlertRestore, AlertStateRequest};
+use crate::buffer::{BufferRestore, BufferSink, BufferSnapshotRequest};
+use crate::checkpoint::Checkpoints;
+use crate::history::{HistoryRestore, HistorySnapshotRequest, SavedHistory};
+use crate::{PerformanceIndicators, Quotes, StockDataProcessor};
+
+///
+/// Bump this whenever `AppState` changes in a way older versions can't read
+///
+pub const STATE_VERSION: u32 = 1;
+
+///
+/// Everything needed to pick up where a previous run left off
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AppState {
+    pub version: u32,
+    pub created: Option<DateTime<Utc>>,
+    /// The contents of the `BufferSink`
+    #[serde(default)]
+    pub buffer: Vec<PerformanceIndicators>,
+    /// The contents of the watchlists' buffers, by watchlist
+    #[serde(default)]
+    pub watchlists: BTreeMap<String, Vec<PerformanceIndicators>>,
+    /// Timestamp of the latest quote seen per symbol (and watchlist), by `Checkpoints::key`
+    #[serde(default)]
+    pub checkpoints: BTreeMap<String, DateTime<Utc>>,
+    /// The quote histories the processor calculates the indicators over
+    #[serde(default)]
+    pub history: SavedHistory,
+    /// The (rule, symbol) pairs whose alert condition is currently true
+    #[serde(default)]
+    pub alerts: BTreeSet<(String, String)>,
+}
+
+impl AppState {
+    ///
+    /// Reads a state file, refusing files written by an incompatible version.
+    ///
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("Could not open '{}'", path))?;
+        let state: AppState = serde_json::from_reader(BufReader::new(file))?;
+        if state.version > STATE_VERSION {
+            bail!(
+                "'{}' has state version {}, but only versions up to {} are supported",
+                path,
+                state.version,
+                STATE_VERSION
+            );
+        }
+        Ok(state)
+    }
+
+    ///
+    /// Writes the state to a temporary file first and then moves it over `path`, so a crash
+    /// never leaves a half-written state file behind.
+    ///
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let tmp = format!("{}.tmp", path);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+///
+/// Take a snapshot right now (e.g. at shutdown)
+///
+#[message(result = "anyhow::Result<()>")]
+#[derive(Clone)]
+pub struct TakeSnapshot;
+
+#[message]
+#[derive(Clone)]
+struct ScheduledSnapshot;
+
+///
+/// Actor that periodically collects the application state and writes it to a file
+///
+pub struct Snapshotter {
+    pub filename: String,
+    pub interval: Duration,
+    pub buffer: Addr<BufferSink>,
+    pub watchlists: BTreeMap<String, Addr<BufferSink>>,
+    pub(crate) processor: Addr<StockDataProcessor>,
+    pub alerts: Addr<AlertEngine>,
+    pub checkpoints: Checkpoints,
+}
+
+impl Snapshotter {
+    async fn snapshot(&self) -> anyhow::Result<()> {
+        let mut watchlists = BTreeMap::new();
+        for (name, buffer) in &self.watchlists {
+            watchlists.insert(name.clone(), buffer.call(BufferSnapshotRequest).await?);
+        }
+        let state = AppState {
+            version: STATE_VERSION,
+            created: Some(Utc::now()),
+            buffer: self.buffer.call(BufferSnapshotRequest).await?,
+            watchlists,
+            checkpoints: self.checkpoints.last_fetch.clone(),
+            history: self.processor.call(HistorySnapshotRequest).await?,
+            alerts: self.alerts.call(AlertStateRequest).await?,
+        };
+        state.save(&self.filename)
+    }
+}
+
+///
+/// Puts the restored state back into the actors that own it.
+///
+pub(crate) fn restore(
+    state: AppState,
+    buffer: &Addr<BufferSink>,
+    watchlists: &BTreeMap<String, Addr<BufferSink>>,
+    processor: &Addr<StockDataProcessor>,
+    alerts: &Addr<AlertEngine>,
+) -> Result<()> {
+    buffer.send(BufferRestore { data: state.buffer })?;
+    for (name, data) in state.watchlists {
+        match watchlists.get(&name) {
+            Some(buffer) => buffer.send(BufferRestore { data })?,
+            None => tracing::warn!(
+                "Dropping {} restored records of the watchlist '{}', which is gone",
+                data.len(),
+                name
+            ),
+        }
+    }
+    processor.send(HistoryRestore {
+        history: state.history,
+    })?;
+    alerts.send(AlertRestore {
+        active: state.alerts,
+    })
+}
+
+#[async_trait::async_trait]
+impl Actor for Snapshotter {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.send_interval(ScheduledSnapshot, self.interval);
+        ctx.subscribe::<Quotes>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Quotes> for Snapshotter {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: Quotes) {
+        self.checkpoints.update(&msg);
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<ScheduledSnapshot> for Snapshotter {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: ScheduledSnapshot) {
+        if let Err(e) = self.snapshot().await {
+            tracing::error!("Could not write snapshot '{}': {}", self.filename, e);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<TakeSnapshot> for Snapshotter {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: TakeSnapshot) -> anyhow::Result<()> {
+        self.snapshot().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::QuoteStore;
+    use crate::signal::TickerQuote;
+    use std::collections::HashMap;
+
+    fn quote(timestamp: u64, close: f64) -> TickerQuote {
+        TickerQuote {
+            timestamp,
+            open: close,
+            high: close,
+            low: close,
+            volume: 1,
+            close,
+            adjclose: close,
+        }
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let path = std::env::temp_dir().join("snapshot_roundtrip.json");
+        let path = path.to_str().unwrap();
+        let mut state = AppState {
+            version: STATE_VERSION,
+            ..Default::default()
+        };
+        let mut checkpoints = Checkpoints::default();
+        checkpoints.update(&Quotes {
+            symbol: "AAPL".to_string(),
+            quotes: vec![quote(86_400, 100.0)],
+            watchlist: Some("tech".to_string()),
+        });
+        state.checkpoints = checkpoints.last_fetch;
+        let mut history = QuoteStore::default();
+        history.append("AAPL", &[quote(0, 99.0), quote(86_400, 100.0)]);
+        state.history = history.save();
+        state
+            .alerts
+            .insert(("high".to_string(), "AAPL".to_string()));
+        state.save(path).unwrap();
+        let loaded = AppState::load(path).unwrap();
+        // the watchlist's checkpoint doesn't move the default pipeline's
+        assert_eq!(loaded.checkpoints.keys().collect::<Vec<_>>(), ["tech:AAPL"]);
+        assert_eq!(loaded.alerts, state.alerts);
+        let mut restored = QuoteStore::default();
+        restored.restore(loaded.history);
+        assert_eq!(restored.quotes("AAPL"), history.quotes("AAPL"));
+        assert_eq!(restored.year_range("AAPL"), history.year_range("AAPL"));
+
+        state.version = STATE_VERSION + 1;
+        state.save(path).unwrap();
+        assert!(AppState::load(path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_watchlist_roundtrip() {
+        let path = std::env::temp_dir().join("snapshot_watchlist.json");
+        let path = path.to_str().unwrap();
+        let row = |symbol: &str, watchlist: Option<&str>| PerformanceIndicators {
+            symbol: symbol.to_string(),
+            watchlist: watchlist.map(|w| w.to_string()),
+            price: 100.0,
+            ..Default::default()
+        };
+        let buffers = || async {
+            let buffer = BufferSink::new(None, 0, Default::default());
+            let tech = BufferSink::new(Some("tech".to_string()), 0, Default::default());
+            (
+                buffer.start().await.unwrap(),
+                BTreeMap::from([("tech".to_string(), tech.start().await.unwrap())]),
+            )
+        };
+        let processor = StockDataProcessor::default().start().await.unwrap();
+        let alerts = AlertEngine::new(vec![], Default::default(), BTreeMap::new(), HashMap::new())
+            .start()
+            .await
+            .unwrap();
+
+        let (buffer, watchlists) = buffers().await;
+        buffer
+            .send(BufferRestore {
+                data: vec![row("AAPL", None)],
+            })
+            .unwrap();
+        watchlists["tech"]
+            .send(BufferRestore {
+                data: vec![row("NVDA", Some("tech"))],
+            })
+            .unwrap();
+        let snapshotter = Snapshotter {
+            filename: path.to_string(),
+            interval: Duration::from_secs(3600),
+            buffer,
+            watchlists,
+            processor: processor.clone(),
+            alerts: alerts.clone(),
+            checkpoints: Checkpoints::default(),
+        }
+        .start()
+        .await
+        .unwrap();
+        snapshotter.call(TakeSnapshot).await.unwrap().unwrap();
+
+        let (buffer, watchlists) = buffers().await;
+        restore(
+            AppState::load(path).unwrap(),
+            &buffer,
+            &watchlists,
+            &processor,
+            &alerts,
+        )
+        .unwrap();
+        let restored = watchlists["tech"]
+            .call(BufferSnapshotRequest)
+            .await
+            .unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].symbol, "NVDA");
+        let restored = buffer.call(BufferSnapshotRequest).await.unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].symbol, "AAPL");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,489 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use rusqlite::{params, Connection, OpenFlags, Row};
+use xactor::*;
+
+use crate::metrics::{self, Observation, Stage};
+use crate::shutdown::Shutdown;
+use crate::sink_queue::RetryQueue;
+use crate::PerformanceIndicators;
+
+///
+/// Rows written in one transaction at most
+///
+const BATCH_SIZE: usize = 100;
+
+///
+/// Rows wait at most this long before they are written
+///
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+///
+/// One row per symbol, bar, watchlist, and resolution. The default pipeline and the fetched
+/// quotes have an empty `watchlist` and `resolution`, since NULLs are never equal in a unique
+/// constraint. `version` grows with every write, so replicas can read what changed.
+///
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS performance (
+    symbol TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    watchlist TEXT NOT NULL DEFAULT '',
+    resolution TEXT NOT NULL DEFAULT '',
+    price REAL NOT NULL,
+    pct_change REAL NOT NULL,
+    period_min REAL NOT NULL,
+    period_max REAL NOT NULL,
+    last_sma REAL NOT NULL,
+    last_ema REAL NOT NULL,
+    rsi REAL,
+    volatility REAL,
+    score REAL,
+    high_52w REAL,
+    low_52w REAL,
+    currency TEXT,
+    custom TEXT,
+    historical INTEGER NOT NULL DEFAULT 0,
+    version INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (symbol, timestamp, watchlist, resolution)
+);
+CREATE INDEX IF NOT EXISTS performance_timestamp ON performance (timestamp);
+";
+
+///
+/// Databases written before there were replicas lack the `version`
+///
+const ADD_VERSION: &str = "ALTER TABLE performance ADD COLUMN version INTEGER NOT NULL DEFAULT 0";
+
+const VERSION_INDEX: &str =
+    "CREATE INDEX IF NOT EXISTS performance_version ON performance (version)";
+
+///
+/// A newer row for the same bar (e.g. while the bar is still forming) replaces the old one
+///
+const INSERT: &str = "
+INSERT INTO performance (
+    symbol, timestamp, watchlist, resolution, price, pct_change, period_min, period_max,
+    last_sma, last_ema, rsi, volatility, score, high_52w, low_52w, currency, custom, historical,
+    version
+) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19)
+ON CONFLICT (symbol, timestamp, watchlist, resolution) DO UPDATE SET
+    price = excluded.price,
+    pct_change = excluded.pct_change,
+    period_min = excluded.period_min,
+    period_max = excluded.period_max,
+    last_sma = excluded.last_sma,
+    last_ema = excluded.last_ema,
+    rsi = excluded.rsi,
+    volatility = excluded.volatility,
+    score = excluded.score,
+    high_52w = excluded.high_52w,
+    low_52w = excluded.low_52w,
+    currency = excluded.currency,
+    custom = excluded.custom,
+    historical = excluded.historical,
+    version = excluded.version
+";
+
+///
+/// The rows written after a version, in the order they were written
+///
+const CHANGES: &str = "
+SELECT version, symbol, timestamp, watchlist, resolution, price, pct_change, period_min,
+    period_max, last_sma, last_ema, rsi, volatility, score, high_52w, low_52w, currency, custom,
+    historical
+FROM performance WHERE version > ?1 ORDER BY version LIMIT ?2
+";
+
+///
+/// The oldest rows before a timestamp, with their versions
+///
+const OLDER: &str = "
+SELECT version, symbol, timestamp, watchlist, resolution, price, pct_change, period_min,
+    period_max, last_sma, last_ema, rsi, volatility, score, high_52w, low_52w, currency, custom,
+    historical
+FROM performance WHERE timestamp < ?1 ORDER BY timestamp LIMIT ?2
+";
+
+///
+/// Deletes a row unless it was replaced since it was read
+///
+const DELETE: &str = "
+DELETE FROM performance
+WHERE symbol = ?1 AND timestamp = ?2 AND watchlist = ?3 AND resolution = ?4 AND version = ?5
+";
+
+///
+/// The rows of a symbol between two timestamps (inclusive), oldest first
+///
+const RANGE: &str = "
+SELECT version, symbol, timestamp, watchlist, resolution, price, pct_change, period_min,
+    period_max, last_sma, last_ema, rsi, volatility, score, high_52w, low_52w, currency, custom,
+    historical
+FROM performance WHERE symbol = ?1 AND timestamp >= ?2 AND timestamp <= ?3 ORDER BY timestamp
+";
+
+///
+/// The `performance` table of a SQLite database
+///
+pub struct SqliteStore {
+    conn: Connection,
+    /// Of the last row written
+    version: i64,
+}
+
+impl SqliteStore {
+    ///
+    /// Opens (or creates) the database and the table
+    ///
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        // readers don't block the writer
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        if conn.prepare("SELECT version FROM performance").is_err() {
+            conn.execute(ADD_VERSION, [])?;
+        }
+        conn.execute(VERSION_INDEX, [])?;
+        let version = conn.query_row(
+            "SELECT COALESCE(MAX(version), 0) FROM performance",
+            [],
+            |r| r.get(0),
+        )?;
+        Ok(SqliteStore { conn, version })
+    }
+
+    ///
+    /// Opens the database of another process to read from. The table is created by the
+    /// writer.
+    ///
+    pub fn open_read_only(path: &str) -> rusqlite::Result<Self> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let conn = Connection::open_with_flags(path, flags)?;
+        Ok(SqliteStore { conn, version: 0 })
+    }
+
+    ///
+    /// Up to `limit` rows written after `version`, each with its version
+    ///
+    pub fn changes(
+        &self,
+        version: i64,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<(i64, PerformanceIndicators)>> {
+        let mut statement = self.conn.prepare_cached(CHANGES)?;
+        let rows = statement.query_map(params![version, limit as i64], |r| {
+            Ok((r.get(0)?, indicators(r)?))
+        })?;
+        rows.collect()
+    }
+
+    ///
+    /// Up to `limit` rows of bars before `before`, oldest first, each with its version
+    ///
+    pub fn older(
+        &self,
+        before: DateTime<Utc>,
+        limit: usize,
+    ) -> rusqlite::Result<Vec<(i64, PerformanceIndicators)>> {
+        let mut statement = self.conn.prepare_cached(OLDER)?;
+        let rows = statement.query_map(params![text(before), limit as i64], |r| {
+            Ok((r.get(0)?, indicators(r)?))
+        })?;
+        rows.collect()
+    }
+
+    ///
+    /// Deletes rows read with `older`, in a single transaction. Rows that were written again
+    /// since are kept. Returns the number of rows deleted.
+    ///
+    pub fn delete(&mut self, rows: &[(i64, PerformanceIndicators)]) -> rusqlite::Result<usize> {
+        let tx = self.conn.transaction()?;
+        let mut deleted = 0;
+        {
+            let mut statement = tx.prepare_cached(DELETE)?;
+            for (version, row) in rows {
+                deleted += statement.execute(params![
+                    row.symbol,
+                    text(row.timestamp),
+                    row.watchlist.as_deref().unwrap_or_default(),
+                    row.resolution.as_deref().unwrap_or_default(),
+                    version,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    ///
+    /// The rows of a symbol from `from` to `to`, oldest first
+    ///
+    pub fn range(
+        &self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> rusqlite::Result<Vec<PerformanceIndicators>> {
+        let mut statement = self.conn.prepare_cached(RANGE)?;
+        let rows = statement.query_map(params![symbol, text(from), text(to)], indicators)?;
+        rows.collect()
+    }
+
+    ///
+    /// Writes the rows in a single transaction
+    ///
+    pub fn insert(&mut self, rows: &[PerformanceIndicators]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut statement = tx.prepare_cached(INSERT)?;
+            for row in rows {
+                self.version += 1;
+                let custom = if row.custom.is_empty() {
+                    None
+                } else {
+                    serde_json::to_string(&row.custom).ok()
+                };
+                statement.execute(params![
+                    row.symbol,
+                    text(row.timestamp),
+                    row.watchlist.as_deref().unwrap_or_default(),
+                    row.resolution.as_deref().unwrap_or_default(),
+                    row.price,
+                    row.pct_change,
+                    row.period_min,
+                    row.period_max,
+                    row.last_sma,
+                    row.last_ema,
+                    row.rsi,
+                    row.volatility,
+                    row.score,
+                    row.high_52w,
+                    row.low_52w,
+                    row.currency,
+                    custom,
+                    row.historical,
+                    self.version,
+                ])?;
+            }
+        }
+        tx.commit()
+    }
+}
+
+///
+/// Timestamps are stored as RFC 3339 text in UTC, which sorts like the timestamps
+///
+fn text(timestamp: DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+///
+/// A row of `CHANGES` without the version
+///
+fn indicators(r: &Row) -> rusqlite::Result<PerformanceIndicators> {
+    let timestamp: String = r.get(2)?;
+    let timestamp = DateTime::parse_from_rfc3339(&timestamp).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(2, rusqlite::types::Type::Text, e.into())
+    })?;
+    let text = |index| -> rusqlite::Result<Option<String>> {
+        let value: String = r.get(index)?;
+        Ok(Some(value).filter(|v| !v.is_empty()))
+    };
+    let custom: Option<String> = r.get(17)?;
+    Ok(PerformanceIndicators {
+        symbol: r.get(1)?,
+        timestamp: timestamp.with_timezone(&Utc),
+        watchlist: text(3)?,
+        resolution: text(4)?,
+        price: r.get(5)?,
+        pct_change: r.get(6)?,
+        period_min: r.get(7)?,
+        period_max: r.get(8)?,
+        last_sma: r.get(9)?,
+        last_ema: r.get(10)?,
+        rsi: r.get(11)?,
+        volatility: r.get(12)?,
+        score: r.get(13)?,
+        high_52w: r.get(14)?,
+        low_52w: r.get(15)?,
+        currency: r.get(16)?,
+        custom: custom
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default(),
+        historical: r.get(18)?,
+        ..Default::default()
+    })
+}
+
+#[message]
+#[derive(Clone)]
+struct Flush;
+
+///
+/// Actor that stores the indicators of all pipelines in a SQLite database, in batches.
+/// Batches that can't be written are kept in a retry queue.
+///
+pub struct SqliteSink {
+    path: String,
+    store: Option<SqliteStore>,
+    pending: Vec<PerformanceIndicators>,
+    queue: RetryQueue<PerformanceIndicators>,
+}
+
+impl SqliteSink {
+    pub fn new(path: String, queue_capacity: usize) -> Self {
+        SqliteSink {
+            path,
+            store: None,
+            pending: Vec::with_capacity(BATCH_SIZE),
+            queue: RetryQueue::new("sqlite", queue_capacity),
+        }
+    }
+
+    async fn flush(&mut self) {
+        let store = match &mut self.store {
+            Some(store) => store,
+            None => return,
+        };
+        self.queue.extend(self.pending.drain(..));
+        if !self.queue.is_ready() {
+            if !self.queue.is_empty() {
+                self.queue.report().await;
+            }
+            return;
+        }
+        let failing = self.queue.is_failing();
+        let count = self.queue.len();
+        let started = Instant::now();
+        if let Err(e) = store.insert(self.queue.rows()) {
+            let backoff = self.queue.failed();
+            tracing::error!(
+                "Could not write {} rows to '{}', retrying in {}s: {}",
+                count,
+                self.path,
+                backoff.as_secs(),
+                e
+            );
+            self.queue.report().await;
+            return;
+        }
+        if failing {
+            tracing::info!("Wrote {} queued rows to '{}'", count, self.path);
+        }
+        let elapsed = started.elapsed() / count as u32;
+        let rows = self.queue.delivered(count);
+        self.queue.report().await;
+        for row in rows {
+            metrics::record(Observation::duration(
+                Stage::SinkWrite("sqlite"),
+                &row.symbol,
+                elapsed,
+            ))
+            .await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for SqliteSink {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        self.store = Some(SqliteStore::open(&self.path)?);
+        ctx.send_interval(Flush, FLUSH_INTERVAL);
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+
+    async fn stopped(&mut self, _ctx: &mut Context<Self>) {
+        self.flush().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for SqliteSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        self.pending.push(msg);
+        if self.pending.len() >= BATCH_SIZE {
+            self.flush().await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Flush> for SqliteSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Flush) {
+        self.flush().await;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Shutdown> for SqliteSink {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {
+        self.queue.retry_now();
+        self.flush().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::prelude::*;
+
+    fn row(ts: i64, price: f64, resolution: Option<&str>) -> PerformanceIndicators {
+        PerformanceIndicators {
+            timestamp: Utc.timestamp_opt(ts, 0).unwrap(),
+            symbol: "AAPL".to_string(),
+            price,
+            resolution: resolution.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut store = SqliteStore::open(":memory:").unwrap();
+        let mut first = row(60, 1.0, None);
+        first.custom.insert("zscore".to_string(), 1.5);
+        store
+            .insert(&[first, row(60, 1.0, Some("1h")), row(120, 2.0, None)])
+            .unwrap();
+        // the bar at 120 is still forming
+        store.insert(&[row(120, 2.5, None)]).unwrap();
+
+        let mut statement = store
+            .conn
+            .prepare(
+                "SELECT timestamp, resolution, price, custom FROM performance \
+                 ORDER BY timestamp, resolution",
+            )
+            .unwrap();
+        let rows: Vec<(String, String, f64, Option<String>)> = statement
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (
+                    "1970-01-01T00:01:00Z".to_string(),
+                    "".to_string(),
+                    1.0,
+                    Some(r#"{"zscore":1.5}"#.to_string())
+                ),
+                (
+                    "1970-01-01T00:01:00Z".to_string(),
+                    "1h".to_string(),
+                    1.0,
+                    None
+                ),
+                (
+                    "1970-01-01T00:02:00Z".to_string(),
+                    "".to_string(),
+                    2.5,
+                    None
+                ),
+            ]
+        );
+    }
+}
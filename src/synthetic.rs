@@ -0,0 +1,223 @@
+//!
+//! Soak testing without external APIs: a provider that makes up random walks for any number of
+//! symbols, and a report of the throughput and memory use over time.
+//!
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+use xactor::*;
+
+use crate::buffer::{BufferLenRequest, BufferSink};
+use crate::provider::DataProvider;
+use crate::quality::CleanQuotes;
+use crate::signal::{DataSourceError, TickerQuote};
+use crate::PerformanceIndicators;
+
+///
+/// Names of the generated symbols
+///
+pub fn symbols(n: usize) -> Vec<String> {
+    (0..n).map(|i| format!("SYN{:04}", i)).collect()
+}
+
+///
+/// A random walk with a deterministic seed per symbol (xorshift)
+///
+#[derive(Debug, Clone)]
+struct Walk {
+    state: u64,
+    price: f64,
+}
+
+impl Walk {
+    fn new(symbol: &str) -> Self {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        symbol.hash(&mut hasher);
+        let mut walk = Walk {
+            // xorshift gets stuck at 0
+            state: hasher.finish() | 1,
+            price: 0.0,
+        };
+        walk.price = 10.0 + walk.random() * 490.0;
+        walk
+    }
+
+    ///
+    /// A uniformly distributed number in [0, 1)
+    ///
+    fn random(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    ///
+    /// The next bar: the price moves by up to 1% either way
+    ///
+    fn next(&mut self, timestamp: u64) -> TickerQuote {
+        let open = self.price;
+        self.price = (open * (1.0 + (self.random() - 0.5) * 0.02)).max(0.01);
+        let spread = self.random() * 0.005;
+        TickerQuote {
+            timestamp,
+            open,
+            high: open.max(self.price) * (1.0 + spread),
+            low: open.min(self.price) * (1.0 - spread),
+            volume: (self.random() * 10_000.0) as u64,
+            close: self.price,
+            adjclose: self.price,
+        }
+    }
+}
+
+///
+/// Answers every request with a single new bar at the end of the requested period, like a live
+/// feed would
+///
+#[derive(Default)]
+pub struct SyntheticProvider {
+    walks: HashMap<String, Walk>,
+}
+
+#[async_trait::async_trait]
+impl DataProvider for SyntheticProvider {
+    fn name(&self) -> &str {
+        "synthetic"
+    }
+
+    async fn fetch_quotes(
+        &mut self,
+        symbol: &str,
+        _from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> std::result::Result<Vec<TickerQuote>, DataSourceError> {
+        let quote = self
+            .walks
+            .entry(symbol.to_string())
+            .or_insert_with(|| Walk::new(symbol))
+            .next(to.timestamp().max(0) as u64);
+        Ok(vec![quote])
+    }
+}
+
+///
+/// Resident memory of the process in bytes, where `/proc` is available
+///
+fn resident_memory() -> Option<u64> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // the page size is 4 KiB on all common Linux platforms
+    Some(pages * 4096)
+}
+
+#[message]
+#[derive(Clone)]
+struct Report;
+
+///
+/// Actor that prints the pipeline throughput, the memory use, and the buffer size at an
+/// interval
+///
+pub struct SoakReport {
+    interval: Duration,
+    buffer: Addr<BufferSink>,
+    started: Instant,
+    last: Instant,
+    quotes: u64,
+    indicators: u64,
+}
+
+impl SoakReport {
+    pub fn new(interval: Duration, buffer: Addr<BufferSink>) -> Self {
+        SoakReport {
+            interval,
+            buffer,
+            started: Instant::now(),
+            last: Instant::now(),
+            quotes: 0,
+            indicators: 0,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for SoakReport {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.send_interval(Report, self.interval);
+        ctx.subscribe::<CleanQuotes>().await?;
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<CleanQuotes> for SoakReport {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: CleanQuotes) {
+        self.quotes += msg.0.quotes.len() as u64;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for SoakReport {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: PerformanceIndicators) {
+        self.indicators += 1;
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Report> for SoakReport {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Report) {
+        let seconds = self.last.elapsed().as_secs_f64().max(1e-3);
+        let buffered = self.buffer.call(BufferLenRequest).await.unwrap_or_default();
+        tracing::info!(
+            "soak {:>6}s: {:>8.1} quotes/s, {:>8.1} indicators/s, {} buffered, {} resident",
+            self.started.elapsed().as_secs(),
+            self.quotes as f64 / seconds,
+            self.indicators as f64 / seconds,
+            buffered,
+            resident_memory()
+                .map(|bytes| format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0)))
+                .unwrap_or_else(|| "?".to_string())
+        );
+        self.last = Instant::now();
+        self.quotes = 0;
+        self.indicators = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_walk() {
+        assert_eq!(symbols(2), vec!["SYN0000", "SYN0001"]);
+        let mut walk = Walk::new("SYN0000");
+        let mut again = Walk::new("SYN0000");
+        for t in 0..1000 {
+            let quote = walk.next(t);
+            assert_eq!(quote, again.next(t));
+            assert!(quote.low <= quote.open.min(quote.close));
+            assert!(quote.high >= quote.open.max(quote.close));
+            assert!(quote.close > 0.0);
+        }
+        assert_ne!(Walk::new("SYN0001").price, Walk::new("SYN0000").price);
+    }
+
+    #[async_std::test]
+    async fn test_fetch_quotes() {
+        let mut provider = SyntheticProvider::default();
+        let to = Utc.timestamp_opt(1_000, 0).unwrap();
+        let quotes = provider
+            .fetch_quotes("SYN0000", Utc.timestamp_opt(0, 0).unwrap(), to)
+            .await
+            .unwrap();
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(quotes[0].timestamp, 1_000);
+        assert_eq!(quotes[0], Walk::new("SYN0000").next(1_000));
+        assert_eq!(provider.name(), "synthetic");
+    }
+}
@@ -0,0 +1,389 @@
+//!
+//! Storage tiers of the indicators. The hot tier is what the buffers and the SQLite database
+//! hold; once a bar is older than `--cold-after`, its rows move to the cold tier, one Parquet
+//! file per month. `/history` reads from both.
+//!
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use xactor::*;
+
+use crate::buffer::{BufferRestore, BufferSink, ChangesRequest, EvictRequest};
+use crate::parquet_file::{self, ParquetWriter};
+use crate::sqlite_sink::SqliteStore;
+use crate::PerformanceIndicators;
+
+///
+/// Rows moved out of SQLite at once
+///
+const BATCH_SIZE: usize = 10_000;
+
+///
+/// Rows are moved every quarter of `--cold-after`, within these bounds
+///
+const MIN_MOVE_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_MOVE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+///
+/// What makes a row unique: its bar, watchlist, and resolution
+///
+type RowKey = (DateTime<Utc>, String, Option<String>, Option<String>);
+
+fn key(row: &PerformanceIndicators) -> RowKey {
+    (
+        row.timestamp,
+        row.symbol.clone(),
+        row.watchlist.clone(),
+        row.resolution.clone(),
+    )
+}
+
+///
+/// A directory with a Parquet file of the indicators of every month, e.g. `2020-07.parquet`
+///
+#[derive(Clone)]
+pub struct ColdStore {
+    dir: PathBuf,
+}
+
+impl ColdStore {
+    pub fn open(dir: &str) -> std::io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(ColdStore { dir: dir.into() })
+    }
+
+    fn path(&self, month: &str) -> PathBuf {
+        self.dir.join(format!("{}.parquet", month))
+    }
+
+    ///
+    /// The months there are files for, oldest first
+    ///
+    fn months(&self) -> std::io::Result<Vec<String>> {
+        let mut months: Vec<String> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                name.strip_suffix(".parquet").map(String::from)
+            })
+            .filter(|month| NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d").is_ok())
+            .collect();
+        months.sort();
+        Ok(months)
+    }
+
+    fn read(&self, month: &str) -> anyhow::Result<Vec<PerformanceIndicators>> {
+        let path = self.path(month);
+        match path.exists() {
+            true => parquet_file::read(&path.to_string_lossy()),
+            false => Ok(vec![]),
+        }
+    }
+
+    ///
+    /// Adds rows to the files of their months. Parquet files can't be appended to, so each
+    /// file is rewritten with the new rows, which replace rows of the same bar.
+    ///
+    pub fn write(&self, rows: Vec<PerformanceIndicators>) -> anyhow::Result<()> {
+        let mut months: BTreeMap<String, Vec<PerformanceIndicators>> = BTreeMap::new();
+        for row in rows {
+            let month = row.timestamp.format("%Y-%m").to_string();
+            months.entry(month).or_default().push(row);
+        }
+        for (month, rows) in months {
+            let mut merged: BTreeMap<RowKey, PerformanceIndicators> = BTreeMap::new();
+            for row in self.read(&month)?.into_iter().chain(rows) {
+                merged.insert(key(&row), row);
+            }
+            let rows: Vec<PerformanceIndicators> = merged.into_values().collect();
+            let path = self.path(&month);
+            let tmp = path.with_extension("parquet.tmp");
+            let mut writer = ParquetWriter::create(&tmp.to_string_lossy())?;
+            writer.write(&rows)?;
+            writer.close()?;
+            fs::rename(&tmp, &path)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// The rows of a symbol from `from` to `to`, oldest first
+    ///
+    pub fn query(
+        &self,
+        symbol: &str,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<PerformanceIndicators>> {
+        let (first, last) = (
+            from.format("%Y-%m").to_string(),
+            to.format("%Y-%m").to_string(),
+        );
+        let mut rows = vec![];
+        for month in self.months()? {
+            if month < first || month > last {
+                continue;
+            }
+            rows.extend(
+                self.read(&month)?
+                    .into_iter()
+                    .filter(|r| r.symbol == symbol && r.timestamp >= from && r.timestamp <= to),
+            );
+        }
+        Ok(rows)
+    }
+}
+
+#[message]
+#[derive(Clone)]
+struct Move;
+
+///
+/// Request the rows of a symbol from both tiers, of one pipeline and resolution
+///
+#[message(result = "anyhow::Result<Vec<PerformanceIndicators>>")]
+pub struct HistoryRequest {
+    pub symbol: String,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// `None` for the default pipeline
+    pub watchlist: Option<String>,
+    /// `None` for the fetched quotes
+    pub resolution: Option<String>,
+}
+
+///
+/// Actor that moves old rows from the hot tier to the cold one, and answers history requests
+/// over both. Without a cold store, nothing is moved.
+///
+pub struct Tiering {
+    cold: Option<ColdStore>,
+    /// Rows of bars older than this move to the cold store
+    age: Duration,
+    buffers: Vec<Addr<BufferSink>>,
+    sqlite: Option<String>,
+    store: Option<SqliteStore>,
+}
+
+impl Tiering {
+    pub fn new(
+        cold: Option<ColdStore>,
+        age: Duration,
+        buffers: Vec<Addr<BufferSink>>,
+        sqlite: Option<String>,
+    ) -> Self {
+        Tiering {
+            cold,
+            age,
+            buffers,
+            sqlite,
+            store: None,
+        }
+    }
+
+    fn cutoff(&self) -> DateTime<Utc> {
+        chrono::Duration::from_std(self.age)
+            .ok()
+            .and_then(|age| Utc::now().checked_sub_signed(age))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+
+    ///
+    /// Moves the rows older than the cutoff. Rows are only removed from the hot tier once
+    /// they are in the cold one. Returns the number of rows moved.
+    ///
+    async fn move_rows(&mut self) -> anyhow::Result<usize> {
+        let cold = match &self.cold {
+            Some(cold) => cold,
+            None => return Ok(0),
+        };
+        let before = self.cutoff();
+        let mut moved = 0;
+        // the buffers last, their rows replace the database's
+        if let Some(store) = &mut self.store {
+            loop {
+                let rows = store.older(before, BATCH_SIZE)?;
+                let done = rows.len() < BATCH_SIZE;
+                cold.write(rows.iter().map(|(_, row)| row.clone()).collect())?;
+                moved += store.delete(&rows)?;
+                if done {
+                    break;
+                }
+            }
+        }
+        for buffer in &self.buffers {
+            let rows = buffer.call(EvictRequest { before }).await?;
+            if rows.is_empty() {
+                continue;
+            }
+            let count = rows.len();
+            if let Err(e) = cold.write(rows.clone()) {
+                buffer.send(BufferRestore { data: rows })?;
+                return Err(e);
+            }
+            moved += count;
+        }
+        Ok(moved)
+    }
+
+    async fn history(&mut self, msg: HistoryRequest) -> anyhow::Result<Vec<PerformanceIndicators>> {
+        // years of four digits, which compare as text like the timestamps in SQLite and the
+        // months of the file names
+        let from = msg
+            .from
+            .unwrap_or_else(|| Utc.with_ymd_and_hms(1, 1, 1, 0, 0, 0).unwrap());
+        let to = msg
+            .to
+            .unwrap_or_else(|| Utc.with_ymd_and_hms(9999, 12, 31, 23, 59, 59).unwrap());
+        // the hot tier wins over the cold one, the buffers over the database
+        let mut rows: BTreeMap<RowKey, PerformanceIndicators> = BTreeMap::new();
+        let mut add = |found: Vec<PerformanceIndicators>| {
+            for row in found {
+                let wanted = row.timestamp >= from
+                    && row.timestamp <= to
+                    && row.watchlist == msg.watchlist
+                    && row.resolution == msg.resolution;
+                if wanted {
+                    rows.insert(key(&row), row);
+                }
+            }
+        };
+        if let Some(cold) = &self.cold {
+            add(cold.query(&msg.symbol, from, to)?);
+        }
+        if let Some(store) = &self.store {
+            add(store.range(&msg.symbol, from, to)?);
+        }
+        for buffer in &self.buffers {
+            let request = ChangesRequest {
+                symbol: msg.symbol.clone(),
+                since: None,
+            };
+            add(buffer.call(request).await?);
+        }
+        Ok(rows.into_values().collect())
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for Tiering {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        if let Some(path) = &self.sqlite {
+            self.store = Some(SqliteStore::open(path)?);
+        }
+        if self.cold.is_some() {
+            let interval = (self.age / 4).clamp(MIN_MOVE_INTERVAL, MAX_MOVE_INTERVAL);
+            ctx.address().send(Move)?;
+            ctx.send_interval(Move, interval);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<Move> for Tiering {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Move) {
+        match self.move_rows().await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!("Moved {} rows to the cold store", count),
+            Err(e) => tracing::error!("Could not move rows to the cold store: {}", e),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<HistoryRequest> for Tiering {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: HistoryRequest,
+    ) -> anyhow::Result<Vec<PerformanceIndicators>> {
+        self.history(msg).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::{BufferSnapshotRequest, Overflow};
+
+    fn row(day: u32, price: f64) -> PerformanceIndicators {
+        PerformanceIndicators {
+            timestamp: Utc.with_ymd_and_hms(2020, 7, day, 12, 0, 0).unwrap(),
+            symbol: "AAPL".to_string(),
+            price,
+            ..Default::default()
+        }
+    }
+
+    #[async_std::test]
+    async fn test_tiering() {
+        let dir = std::env::temp_dir().join("tiering_cold");
+        let _ = fs::remove_dir_all(&dir);
+        let cold = ColdStore::open(dir.to_str().unwrap()).unwrap();
+        // a bar of the previous month, already in the cold store
+        let june = PerformanceIndicators {
+            timestamp: Utc.with_ymd_and_hms(2020, 6, 30, 12, 0, 0).unwrap(),
+            ..row(1, 0.5)
+        };
+        cold.write(vec![june.clone(), row(1, 0.9)]).unwrap();
+
+        let db = dir.join("hot.db");
+        let mut store = SqliteStore::open(db.to_str().unwrap()).unwrap();
+        store.insert(&[row(2, 2.0), row(3, 3.0)]).unwrap();
+        drop(store);
+        let buffer = BufferSink::new(None, 0, Overflow::default())
+            .start()
+            .await
+            .unwrap();
+        buffer.send(row(1, 1.0)).unwrap();
+        buffer.send(row(3, 3.5)).unwrap();
+        let mut tiering = Tiering::new(
+            Some(cold),
+            Duration::from_secs(1),
+            vec![buffer.clone()],
+            Some(db.to_str().unwrap().to_string()),
+        );
+        tiering.store = Some(SqliteStore::open(db.to_str().unwrap()).unwrap());
+
+        let request = || HistoryRequest {
+            symbol: "AAPL".to_string(),
+            from: Some(Utc.with_ymd_and_hms(2020, 6, 1, 0, 0, 0).unwrap()),
+            to: None,
+            watchlist: None,
+            resolution: None,
+        };
+        let prices = |rows: Vec<PerformanceIndicators>| -> Vec<f64> {
+            rows.iter().map(|r| r.price).collect()
+        };
+        // the buffer's row of the 1st wins over the cold one
+        assert_eq!(
+            prices(tiering.history(request()).await.unwrap()),
+            vec![0.5, 1.0, 2.0, 3.5]
+        );
+
+        // everything is older than a second
+        assert_eq!(tiering.move_rows().await.unwrap(), 4);
+        assert!(buffer.call(BufferSnapshotRequest).await.unwrap().is_empty());
+        let store = tiering.store.as_ref().unwrap();
+        assert!(store.older(Utc::now(), 10).unwrap().is_empty());
+        assert_eq!(
+            prices(tiering.history(request()).await.unwrap()),
+            vec![0.5, 1.0, 2.0, 3.5]
+        );
+        let july = HistoryRequest {
+            from: Some(Utc.with_ymd_and_hms(2020, 7, 2, 0, 0, 0).unwrap()),
+            ..request()
+        };
+        assert_eq!(prices(tiering.history(july).await.unwrap()), vec![2.0, 3.5]);
+        assert_eq!(
+            tiering.cold.as_ref().unwrap().months().unwrap(),
+            vec!["2020-06", "2020-07"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
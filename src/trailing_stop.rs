@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use xactor::*;
+
+use crate::alert::Alert;
+use crate::PerformanceIndicators;
+
+///
+/// The rule name of the alerts a trailing stop fires
+///
+pub const RULE: &str = "trailing_stop";
+
+///
+/// A trailing stop of a symbol in the config file, e.g. for a position bought on Jan 2:
+///
+/// ```toml
+/// [trailing_stops.AAPL]
+/// drop = 0.08
+/// since = "2024-01-02T14:30:00Z"
+/// ```
+///
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TrailingStopConfig {
+    /// Fire when the price falls this much below the high, e.g. `0.08` for 8%
+    pub drop: f64,
+    /// Only prices from here on count, e.g. the entry of a position. Defaults to the start.
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    /// Also raise a desktop notification
+    #[serde(default)]
+    pub notify: bool,
+}
+
+///
+/// The state of a trailing stop, served at `/trailing-stops`
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StopState {
+    pub drop: f64,
+    pub since: DateTime<Utc>,
+    /// The highest price since the anchor, and when it was reached
+    pub high: Option<f64>,
+    pub high_at: Option<DateTime<Utc>>,
+    /// The price the stop fires at
+    pub stop: Option<f64>,
+    /// Fired since the last high
+    pub fired: bool,
+}
+
+impl StopState {
+    fn new(config: &TrailingStopConfig, start: DateTime<Utc>) -> Self {
+        StopState {
+            drop: config.drop,
+            since: config.since.unwrap_or(start),
+            high: None,
+            high_at: None,
+            stop: None,
+            fired: false,
+        }
+    }
+
+    ///
+    /// Follows the price, returns true when the stop is hit
+    ///
+    fn update(&mut self, price: f64, timestamp: DateTime<Utc>) -> bool {
+        if timestamp < self.since || !price.is_finite() {
+            return false;
+        }
+        if self.high.is_none_or(|high| price > high) {
+            // a new high re-arms the stop
+            self.high = Some(price);
+            self.high_at = Some(timestamp);
+            self.stop = Some(price * (1.0 - self.drop));
+            self.fired = false;
+            return false;
+        }
+        let hit = self.stop.is_some_and(|stop| price <= stop);
+        if hit && !self.fired {
+            self.fired = true;
+            return true;
+        }
+        false
+    }
+}
+
+#[message(result = "BTreeMap<String, StopState>")]
+pub struct TrailingStopsRequest;
+
+///
+/// Actor that tracks the highest price of every symbol with a trailing stop and fires an
+/// `Alert` once the price falls the configured amount below it. Only the current prices of
+/// the default pipeline count, at the fetched resolution.
+///
+pub struct TrailingStop {
+    stops: BTreeMap<String, StopState>,
+}
+
+impl TrailingStop {
+    pub fn new(config: &BTreeMap<String, TrailingStopConfig>, start: DateTime<Utc>) -> Self {
+        TrailingStop {
+            stops: config
+                .iter()
+                .map(|(symbol, c)| (symbol.clone(), StopState::new(c, start)))
+                .collect(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Actor for TrailingStop {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<PerformanceIndicators> for TrailingStop {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        if msg.watchlist.is_some() || msg.resolution.is_some() {
+            return;
+        }
+        let stop = match self.stops.get_mut(&msg.symbol) {
+            Some(stop) => stop,
+            None => return,
+        };
+        if !stop.update(msg.price, msg.timestamp) {
+            return;
+        }
+        let high = stop.high.unwrap_or_default();
+        let alert = Alert {
+            rule: RULE.to_string(),
+            symbol: msg.symbol.clone(),
+            timestamp: msg.timestamp,
+            message: format!(
+                "{} fell to {}, {:.1}% below its high of {} since {}",
+                msg.symbol,
+                msg.money(msg.price),
+                (1.0 - msg.price / high) * 100.0,
+                msg.money(high),
+                stop.since.format("%Y-%m-%d")
+            ),
+        };
+        tracing::info!("ALERT {}", alert.message);
+        if let Err(e) = Broker::from_registry().await.unwrap().publish(alert) {
+            tracing::error!("{}", e);
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<TrailingStopsRequest> for TrailingStop {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: TrailingStopsRequest,
+    ) -> BTreeMap<String, StopState> {
+        self.stops.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trailing_stop() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 2, 14, 30, 0).unwrap();
+        let config = TrailingStopConfig {
+            drop: 0.1,
+            since: Some(start),
+            notify: false,
+        };
+        let mut stop = StopState::new(&config, start - chrono::Duration::days(30));
+        let at = |hours: i64| start + chrono::Duration::hours(hours);
+
+        // before the anchor
+        assert!(!stop.update(500.0, at(-1)));
+        assert_eq!(stop.high, None);
+        assert!(!stop.update(100.0, at(0)));
+        assert!(!stop.update(120.0, at(1)));
+        assert!(!stop.update(109.0, at(2)));
+        assert_eq!(stop.stop, Some(108.0));
+        assert!(stop.update(108.0, at(3)));
+        // fires once
+        assert!(!stop.update(100.0, at(4)));
+        assert!(stop.fired);
+
+        // a new high re-arms it
+        assert!(!stop.update(130.0, at(5)));
+        assert!(!stop.fired);
+        assert!(stop.update(110.0, at(6)));
+        assert_eq!(stop.high_at, Some(at(5)));
+    }
+}
@@ -0,0 +1,120 @@
+//!
+//! The volatility cone of a symbol: the realized volatility over several lookback windows,
+//! each with the range it moved in over the stored history. Options traders compare the
+//! implied volatility of an expiry with the cone of the matching window.
+//!
+use chrono::prelude::*;
+use serde::Serialize;
+use xactor::*;
+
+use crate::signal::{AsyncStockSignal, RollingVolatility};
+
+///
+/// The lookback windows of the cone, in bars
+///
+pub const WINDOWS: [usize; 4] = [10, 20, 60, 120];
+
+///
+/// Request the volatility cone of a symbol
+///
+#[message(result = "Option<VolatilityCone>")]
+pub struct VolatilityRequest(pub String);
+
+///
+/// The annualized volatility over one window, now and across the history
+///
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ConeWindow {
+    /// Bars the volatility is calculated over
+    pub window: usize,
+    /// Over the latest bars
+    pub current: f64,
+    pub min: f64,
+    pub median: f64,
+    pub max: f64,
+    /// Share of the other windows in the history that were at most as volatile as `current`
+    pub percentile: f64,
+    /// Number of windows in the history
+    pub samples: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct VolatilityCone {
+    pub symbol: String,
+    /// Timestamp of the latest bar
+    pub as_of: DateTime<Utc>,
+    /// Only the windows the history is long enough for
+    pub windows: Vec<ConeWindow>,
+}
+
+fn median(sorted: &[f64]) -> f64 {
+    let middle = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        (sorted[middle - 1] + sorted[middle]) / 2.0
+    } else {
+        sorted[middle]
+    }
+}
+
+///
+/// The cone over the closing prices, one entry per window in `windows` that fits
+///
+pub async fn cone(closes: &[f64], windows: &[usize]) -> Vec<ConeWindow> {
+    let mut cone = vec![];
+    for &window in windows {
+        let signal = RollingVolatility {
+            window,
+            ..Default::default()
+        };
+        let series = match signal.calculate(closes).await {
+            Some(series) if !series.is_empty() => series,
+            _ => continue,
+        };
+        let current = *series.last().unwrap();
+        let mut sorted = series.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let samples = sorted.len();
+        // the current window itself doesn't count
+        let below = sorted.iter().filter(|v| **v <= current).count() - 1;
+        cone.push(ConeWindow {
+            window,
+            current,
+            min: sorted[0],
+            median: median(&sorted),
+            max: sorted[samples - 1],
+            percentile: if samples > 1 {
+                below as f64 / (samples - 1) as f64
+            } else {
+                1.0
+            },
+            samples,
+        });
+    }
+    cone
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn test_cone() {
+        // calm at first, then swinging
+        let mut closes: Vec<f64> = (0..40).map(|i| 100.0 + i as f64 * 0.1).collect();
+        for i in 0..20 {
+            closes.push(if i % 2 == 0 { 110.0 } else { 100.0 });
+        }
+        let cone = cone(&closes, &[10, 20, 60]).await;
+        // 59 returns aren't enough for 60 bars
+        assert_eq!(cone.len(), 2);
+        let short = &cone[0];
+        assert_eq!(short.window, 10);
+        assert_eq!(short.samples, 50);
+        assert_eq!(short.current, short.max);
+        assert_eq!(short.percentile, 1.0);
+        assert!(short.min < 0.01);
+        assert!(short.min <= short.median && short.median <= short.max);
+        assert_eq!(cone[1].samples, 40);
+        assert_eq!(median(&[1.0, 2.0, 3.0, 4.0]), 2.5);
+    }
+}
@@ -0,0 +1,428 @@
+//!
+//! A write-ahead log in front of sinks that are slow or unreliable, e.g. webhooks.
+//!
+//! Every row is appended (and synced) to the log before the sink sees it, and the log records
+//! which rows the sink accepted. Rows that weren't accepted are retried with a backoff, and
+//! replayed after a restart. Delivery is at least once: a row whose delivery succeeded right
+//! before a crash is sent again.
+//!
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use xactor::*;
+
+use crate::shutdown::Shutdown;
+use crate::sink_queue::SinkHealth;
+use crate::PerformanceIndicators;
+
+///
+/// Rows sent to the sink at once
+///
+const BATCH_SIZE: usize = 100;
+
+///
+/// Delivered rows kept in the log before it is rewritten without them
+///
+const COMPACT_AFTER: usize = 10_000;
+
+///
+/// How long the sink gets for the pending rows at a shutdown, the rest is replayed at the
+/// next start
+///
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+///
+/// A line of the log
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Record<T> {
+    /// A row to deliver
+    Entry { seq: u64, row: T },
+    /// Every row up to `seq` was delivered
+    Ack { seq: u64 },
+}
+
+///
+/// An append-only log of rows and acknowledgements in JSON lines
+///
+pub struct WriteAheadLog<T> {
+    path: String,
+    file: File,
+    /// Rows that weren't delivered yet, oldest first
+    pending: VecDeque<(u64, T)>,
+    next_seq: u64,
+    /// Delivered rows still in the file
+    delivered: usize,
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> WriteAheadLog<T> {
+    ///
+    /// Opens the log, creating it if needed. Rows that weren't acknowledged are pending again.
+    ///
+    pub fn open(path: &str) -> io::Result<Self> {
+        let mut entries: Vec<(u64, T)> = vec![];
+        let mut acked = 0;
+        let mut damaged = false;
+        match File::open(path) {
+            Ok(file) => {
+                for (n, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    match serde_json::from_str(&line) {
+                        Ok(Record::Entry { seq, row }) => entries.push((seq, row)),
+                        Ok(Record::Ack { seq }) => acked = acked.max(seq),
+                        // the last line is cut off if the process died while writing it
+                        Err(e) => {
+                            tracing::warn!("Skipping line {} of '{}': {}", n + 1, path, e);
+                            damaged = true;
+                        }
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let next_seq = entries.iter().map(|(seq, _)| seq + 1).max().unwrap_or(1);
+        let delivered = entries.iter().filter(|(seq, _)| *seq <= acked).count();
+        let pending = entries
+            .into_iter()
+            .filter(|(seq, _)| *seq > acked)
+            .collect();
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut log = WriteAheadLog {
+            path: path.to_string(),
+            file,
+            pending,
+            next_seq: next_seq.max(acked + 1),
+            delivered,
+        };
+        // new lines must not continue a cut off one
+        if damaged {
+            log.compact()?;
+        }
+        Ok(log)
+    }
+
+    fn write(&mut self, record: &Record<T>) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()
+    }
+
+    ///
+    /// Appends a row; it is on disk when this returns
+    ///
+    pub fn append(&mut self, row: T) -> io::Result<()> {
+        let seq = self.next_seq;
+        self.write(&Record::Entry {
+            seq,
+            row: row.clone(),
+        })?;
+        self.next_seq += 1;
+        self.pending.push_back((seq, row));
+        Ok(())
+    }
+
+    ///
+    /// The oldest pending rows, with the sequence number of the last one
+    ///
+    pub fn batch(&self, size: usize) -> Option<(u64, Vec<T>)> {
+        let rows: Vec<&(u64, T)> = self.pending.iter().take(size).collect();
+        let last = rows.last()?.0;
+        Some((last, rows.into_iter().map(|(_, row)| row.clone()).collect()))
+    }
+
+    ///
+    /// Marks every row up to `seq` as delivered
+    ///
+    pub fn ack(&mut self, seq: u64) -> io::Result<()> {
+        self.write(&Record::Ack { seq })?;
+        while self.pending.front().is_some_and(|(s, _)| *s <= seq) {
+            self.pending.pop_front();
+            self.delivered += 1;
+        }
+        if self.pending.is_empty() || self.delivered >= COMPACT_AFTER {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Rewrites the log with only the pending rows
+    ///
+    fn compact(&mut self) -> io::Result<()> {
+        let tmp = format!("{}.tmp", self.path);
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        for (seq, row) in &self.pending {
+            let record = Record::Entry {
+                seq: *seq,
+                row: row.clone(),
+            };
+            serde_json::to_writer(&mut writer, &record)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.delivered = 0;
+        Ok(())
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+///
+/// A sink behind a write-ahead log. Failed deliveries are retried with the same rows.
+///
+#[async_trait::async_trait]
+pub trait Delivery: Clone + Send + Sync + 'static {
+    ///
+    /// Name of the sink in messages
+    ///
+    fn name(&self) -> String;
+
+    ///
+    /// Name of the sink in the metrics
+    ///
+    fn kind(&self) -> &'static str;
+
+    async fn deliver(&self, rows: &[PerformanceIndicators]) -> anyhow::Result<()>;
+}
+
+#[message]
+#[derive(Clone)]
+struct Drain;
+
+#[message]
+struct Delivered {
+    seq: u64,
+    result: anyhow::Result<()>,
+}
+
+///
+/// Actor that appends the indicators to a write-ahead log and drains the log into a sink in
+/// the background, so a slow sink never holds up the broker
+///
+pub struct WalSink<D> {
+    path: String,
+    sink: D,
+    log: Option<WriteAheadLog<PerformanceIndicators>>,
+    /// A delivery is running
+    in_flight: bool,
+    backoff: Duration,
+    retry_at: Option<Instant>,
+    /// Failed deliveries since the start
+    failures: u64,
+}
+
+impl<D: Delivery> WalSink<D> {
+    pub fn new(path: String, sink: D) -> Self {
+        WalSink {
+            path,
+            sink,
+            log: None,
+            in_flight: false,
+            backoff: MIN_BACKOFF,
+            retry_at: None,
+            failures: 0,
+        }
+    }
+
+    ///
+    /// Publishes the health of the sink for the metrics. The log isn't bounded, so nothing
+    /// is ever dropped.
+    ///
+    async fn report(&self) {
+        let health = SinkHealth {
+            sink: self.sink.kind().to_string(),
+            queued: self.log.as_ref().map_or(0, |log| log.pending()),
+            failures: self.failures,
+            dropped: 0,
+        };
+        if let Ok(mut broker) = Broker::from_registry().await {
+            let _ = broker.publish(health);
+        }
+    }
+
+    fn drain(&mut self, ctx: &mut Context<Self>) {
+        if self.in_flight || self.retry_at.is_some_and(|at| Instant::now() < at) {
+            return;
+        }
+        let (seq, rows) = match self.log.as_ref().and_then(|log| log.batch(BATCH_SIZE)) {
+            Some(batch) => batch,
+            None => return,
+        };
+        self.in_flight = true;
+        let sink = self.sink.clone();
+        let addr = ctx.address();
+        async_std::task::spawn(async move {
+            let result = sink.deliver(&rows).await;
+            let _ = addr.send(Delivered { seq, result });
+        });
+    }
+}
+
+#[async_trait::async_trait]
+impl<D: Delivery> Actor for WalSink<D> {
+    async fn started(&mut self, ctx: &mut Context<Self>) -> Result<()> {
+        crate::crash::track_start::<Self>(ctx.actor_id());
+        let log = WriteAheadLog::open(&self.path)?;
+        if log.pending() > 0 {
+            tracing::info!(
+                "Replaying {} rows from '{}' to {}",
+                log.pending(),
+                self.path,
+                self.sink.name()
+            );
+        }
+        self.log = Some(log);
+        ctx.send_interval(Drain, MIN_BACKOFF);
+        ctx.subscribe::<PerformanceIndicators>().await
+    }
+}
+
+#[async_trait::async_trait]
+impl<D: Delivery> Handler<PerformanceIndicators> for WalSink<D> {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: PerformanceIndicators) {
+        if msg.resolution.is_some() {
+            return;
+        }
+        if let Some(log) = &mut self.log {
+            if let Err(e) = log.append(msg) {
+                tracing::error!("Could not append to '{}': {}", self.path, e);
+            }
+        }
+        self.drain(ctx);
+    }
+}
+
+#[async_trait::async_trait]
+impl<D: Delivery> Handler<Drain> for WalSink<D> {
+    async fn handle(&mut self, ctx: &mut Context<Self>, _msg: Drain) {
+        self.drain(ctx);
+    }
+}
+
+#[async_trait::async_trait]
+impl<D: Delivery> Handler<Delivered> for WalSink<D> {
+    async fn handle(&mut self, ctx: &mut Context<Self>, msg: Delivered) {
+        self.in_flight = false;
+        match msg.result {
+            Ok(()) => {
+                self.backoff = MIN_BACKOFF;
+                self.retry_at = None;
+                if let Some(log) = &mut self.log {
+                    if let Err(e) = log.ack(msg.seq) {
+                        tracing::error!("Could not update '{}': {}", self.path, e);
+                    }
+                }
+                self.drain(ctx);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    "Delivery to {} failed, retrying in {}s: {}",
+                    self.sink.name(),
+                    self.backoff.as_secs(),
+                    e
+                );
+                self.retry_at = Some(Instant::now() + self.backoff);
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                self.failures += 1;
+            }
+        }
+        self.report().await;
+    }
+}
+
+///
+/// Delivers the pending rows until the sink fails or `SHUTDOWN_TIMEOUT` is up. A delivery in
+/// flight may be sent again, which at least once allows.
+///
+#[async_trait::async_trait]
+impl<D: Delivery> Handler<Shutdown> for WalSink<D> {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, _msg: Shutdown) {
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        while let Some((seq, rows)) = self.log.as_ref().and_then(|log| log.batch(BATCH_SIZE)) {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match async_std::future::timeout(left, self.sink.deliver(&rows)).await {
+                Ok(Ok(())) => {
+                    if let Some(log) = &mut self.log {
+                        if let Err(e) = log.ack(seq) {
+                            tracing::error!("Could not update '{}': {}", self.path, e);
+                            break;
+                        }
+                    }
+                }
+                Ok(Err(e)) => {
+                    tracing::warn!("Delivery to {} failed at shutdown: {}", self.sink.name(), e);
+                    break;
+                }
+                Err(_) => {
+                    tracing::warn!("Delivery to {} timed out at shutdown", self.sink.name());
+                    break;
+                }
+            }
+        }
+        if let Some(log) = &self.log {
+            if log.pending() > 0 {
+                tracing::info!(
+                    "{} rows stay in '{}' for the next start",
+                    log.pending(),
+                    self.path
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> String {
+        let path = std::env::temp_dir().join(name);
+        let _ = fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_replay() {
+        let path = path("wal_replay.jsonl");
+        let mut log: WriteAheadLog<String> = WriteAheadLog::open(&path).unwrap();
+        for row in &["a", "b", "c"] {
+            log.append(row.to_string()).unwrap();
+        }
+        let (seq, rows) = log.batch(2).unwrap();
+        assert_eq!((seq, rows), (2, vec!["a".to_string(), "b".to_string()]));
+        log.ack(seq).unwrap();
+        drop(log);
+
+        // a row cut off by a crash
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"entry\":{\"seq\":4,\"ro").unwrap();
+        drop(file);
+
+        let mut log: WriteAheadLog<String> = WriteAheadLog::open(&path).unwrap();
+        assert_eq!(log.pending(), 1);
+        assert!(fs::read_to_string(&path).unwrap().ends_with("}\n"));
+        assert_eq!(log.batch(10).unwrap(), (3, vec!["c".to_string()]));
+        log.append("d".to_string()).unwrap();
+        assert_eq!(log.batch(10).unwrap().0, 4);
+        log.ack(4).unwrap();
+        // everything was delivered, so the log is empty again
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert!(WriteAheadLog::<String>::open(&path)
+            .unwrap()
+            .batch(10)
+            .is_none());
+    }
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use crate::wal::Delivery;
+use crate::PerformanceIndicators;
+
+///
+/// Posts batches of indicators as a JSON array to a URL. Runs behind a `WalSink`, which
+/// retries failed posts.
+///
+#[derive(Clone)]
+pub struct WebhookSink {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(url: String) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()?;
+        Ok(WebhookSink { url, client })
+    }
+}
+
+#[async_trait::async_trait]
+impl Delivery for WebhookSink {
+    fn name(&self) -> String {
+        format!("webhook '{}'", self.url)
+    }
+
+    fn kind(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn deliver(&self, rows: &[PerformanceIndicators]) -> anyhow::Result<()> {
+        self.client
+            .post(&self.url)
+            .json(rows)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
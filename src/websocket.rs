@@ -0,0 +1,219 @@
+//!
+//! `/ws` pushes new indicators over a WebSocket. A client picks its symbols with JSON text
+//! messages, and can change them at any time:
+//!
+//! ```json
+//! {"subscribe": ["AAPL", "MSFT"]}
+//! {"unsubscribe": ["MSFT"]}
+//! ```
+//!
+//! Each is answered with the symbols of the connection (`{"subscribed": ["AAPL"]}`), and every
+//! new row of them arrives as `{"indicators": {...}}`.
+//!
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use async_executor::Executor;
+use async_std::channel::Receiver;
+use async_tungstenite::tungstenite::handshake::derive_accept_key;
+use async_tungstenite::tungstenite::protocol::Role;
+use async_tungstenite::tungstenite::Message;
+use async_tungstenite::WebSocketStream;
+use futures::{stream, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tide::http::headers::{CONNECTION, UPGRADE};
+use tide::http::upgrade::Connection;
+use tide::{Request, Response, StatusCode};
+
+use crate::number_format::NumberFormat;
+use crate::PerformanceIndicators;
+
+///
+/// A message from the client
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Command {
+    Subscribe(Vec<String>),
+    Unsubscribe(Vec<String>),
+}
+
+///
+/// A message to the client
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum Push {
+    /// The symbols of the connection, after every command
+    Subscribed(Vec<String>),
+    Indicators(Box<PerformanceIndicators>),
+    /// A message that isn't a command
+    Error(String),
+}
+
+///
+/// The symbols a connection receives
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Subscriptions {
+    symbols: BTreeSet<String>,
+}
+
+impl Subscriptions {
+    ///
+    /// From a comma separated list, e.g. `AAPL,MSFT`
+    ///
+    pub fn parse(symbols: &str) -> Self {
+        Subscriptions {
+            symbols: symbols
+                .split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+
+    pub fn wants(&self, row: &PerformanceIndicators) -> bool {
+        self.symbols.contains(&row.symbol)
+    }
+
+    ///
+    /// Handles a text message of the client, returns the answer
+    ///
+    pub fn handle(&mut self, text: &str) -> Push {
+        match serde_json::from_str(text) {
+            Ok(Command::Subscribe(symbols)) => self.symbols.extend(symbols),
+            Ok(Command::Unsubscribe(symbols)) => {
+                for symbol in &symbols {
+                    self.symbols.remove(symbol);
+                }
+            }
+            Err(e) => return Push::Error(format!("Invalid command: {}", e)),
+        }
+        Push::Subscribed(self.symbols.iter().cloned().collect())
+    }
+}
+
+///
+/// A connection, from the upgrade on
+///
+pub struct Client {
+    /// Every new row, from the `Broadcaster`
+    pub rows: Receiver<PerformanceIndicators>,
+    pub numbers: Arc<NumberFormat>,
+    pub subscriptions: Subscriptions,
+}
+
+///
+/// Answers a WebSocket upgrade request. The connection is served on the executor once it's
+/// upgraded.
+///
+pub async fn upgrade<S>(
+    req: &Request<S>,
+    executor: &Executor<'static>,
+    client: Client,
+) -> tide::Result
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let upgrade_requested = req
+        .header(UPGRADE)
+        .is_some_and(|h| h.as_str().eq_ignore_ascii_case("websocket"));
+    let key = match req.header("Sec-WebSocket-Key") {
+        Some(key) if upgrade_requested => derive_accept_key(key.as_str().as_bytes()),
+        _ => return Ok(Response::new(StatusCode::UpgradeRequired)),
+    };
+    let mut response = Response::new(StatusCode::SwitchingProtocols);
+    response.insert_header(UPGRADE, "websocket");
+    response.insert_header(CONNECTION, "Upgrade");
+    response.insert_header("Sec-WebSocket-Accept", key);
+    let upgraded = AsMut::<tide::http::Response>::as_mut(&mut response)
+        .recv_upgrade()
+        .await;
+    executor
+        .spawn(async move {
+            // the connection is only handed over after the response is written
+            if let Some(connection) = upgraded.await {
+                let socket = WebSocketStream::from_raw_socket(connection, Role::Server, None).await;
+                serve(socket, client).await;
+            }
+        })
+        .detach();
+    Ok(response)
+}
+
+enum Event {
+    Received(Result<Message, async_tungstenite::tungstenite::Error>),
+    Published(Box<PerformanceIndicators>),
+}
+
+///
+/// Forwards the rows the client subscribed to until it disconnects
+///
+async fn serve(socket: WebSocketStream<Connection>, client: Client) {
+    let Client {
+        rows,
+        numbers,
+        mut subscriptions,
+    } = client;
+    let (mut sink, received) = socket.split();
+    let mut events = stream::select(
+        received.map(Event::Received),
+        rows.map(|row| Event::Published(Box::new(row))),
+    );
+    while let Some(event) = events.next().await {
+        let push = match event {
+            Event::Received(Ok(Message::Text(text))) => subscriptions.handle(&text),
+            Event::Received(Ok(Message::Close(_))) | Event::Received(Err(_)) => break,
+            // pings are answered by the socket
+            Event::Received(Ok(_)) => continue,
+            Event::Published(mut row) if subscriptions.wants(&row) => {
+                numbers.round(&mut row);
+                Push::Indicators(row)
+            }
+            Event::Published(_) => continue,
+        };
+        let text = match serde_json::to_string(&push) {
+            Ok(text) => text,
+            Err(e) => {
+                tracing::error!("Could not serialize a WebSocket message: {}", e);
+                continue;
+            }
+        };
+        if sink.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscriptions() {
+        let mut subscriptions = Subscriptions::parse("AAPL, ,MSFT");
+        let row = |symbol: &str| PerformanceIndicators {
+            symbol: symbol.to_string(),
+            ..Default::default()
+        };
+        assert!(subscriptions.wants(&row("AAPL")));
+        assert!(!subscriptions.wants(&row("GOOG")));
+
+        let push = subscriptions.handle(r#"{"subscribe": ["GOOG"]}"#);
+        assert_eq!(
+            serde_json::to_string(&push).unwrap(),
+            r#"{"subscribed":["AAPL","GOOG","MSFT"]}"#
+        );
+        let push = subscriptions.handle(r#"{"unsubscribe": ["AAPL", "UNKNOWN"]}"#);
+        assert!(matches!(push, Push::Subscribed(s) if s == ["GOOG", "MSFT"]));
+        assert!(!subscriptions.wants(&row("AAPL")));
+
+        assert!(matches!(
+            subscriptions.handle(r#"{"follow": ["AAPL"]}"#),
+            Push::Error(_)
+        ));
+        assert_eq!(subscriptions, Subscriptions::parse("GOOG,MSFT"));
+    }
+}
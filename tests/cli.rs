@@ -0,0 +1,35 @@
+use std::process::Command;
+
+use chrono::prelude::*;
+
+#[test]
+fn test_once_writes_every_row() -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join("cli_once");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)?;
+    let from = (Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+    let output = Command::new(env!(
+        "CARGO_BIN_EXE_manning-lp-async-rust-project-2-m1-solution"
+    ))
+    .current_dir(&dir)
+    .args(["--synthetic", "20", "--once", "--from", &from])
+    .args(["--metrics-summary", "0", "--log-level", "warn"])
+    .output()?;
+    assert!(output.status.success(), "{:?}", output);
+
+    // the rows printed as they were calculated, after the header
+    let stdout = String::from_utf8(output.stdout)?;
+    let mut printed: Vec<&str> = stdout.lines().skip(1).collect();
+    let csv_file = std::fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .find(|path| path.extension().is_some_and(|e| e == "csv"))
+        .expect("a CSV file");
+    let written = std::fs::read_to_string(csv_file)?;
+    assert!(written.ends_with('\n'));
+    let mut rows: Vec<&str> = written.lines().filter(|l| l.contains(",SYN")).collect();
+    printed.sort_unstable();
+    rows.sort_unstable();
+    assert_eq!(printed.len(), 20);
+    assert_eq!(rows, printed);
+    Ok(())
+}
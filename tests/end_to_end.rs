@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use async_std::io::prelude::BufReadExt;
+use async_tungstenite::tungstenite::Message;
+use chrono::prelude::*;
+use futures::{SinkExt, StreamExt};
+use manning_lp_async_rust_project_2_m1_solution::alias::Aliases;
+use manning_lp_async_rust_project_2_m1_solution::envelope::Envelope;
+use manning_lp_async_rust_project_2_m1_solution::harness::{synthetic, TestPipeline, BAR};
+use manning_lp_async_rust_project_2_m1_solution::registry::SymbolChanges;
+use manning_lp_async_rust_project_2_m1_solution::PerformanceIndicators;
+use tide::http::{Method, Request, Response, Url};
+
+///
+/// The brokers are global, so the pipelines of the tests take turns
+///
+static PIPELINE: async_std::sync::Mutex<()> = async_std::sync::Mutex::new(());
+
+#[async_std::test]
+async fn test_pipeline_end_to_end() {
+    let start = Utc.with_ymd_and_hms(2022, 12, 5, 9, 0, 0).unwrap();
+    let series = BTreeMap::from([
+        ("AAA".to_string(), synthetic(start, 100.0, 10)),
+        ("BBB".to_string(), synthetic(start, 50.0, 10)),
+    ]);
+    let _turn = PIPELINE.lock().await;
+    let mut pipeline = TestPipeline::start("harness_end_to_end", start, series)
+        .await
+        .unwrap();
+
+    let request = Request::new(Method::Get, Url::parse("http://localhost/stream").unwrap());
+    let mut stream: Response = pipeline.respond(request).await.unwrap();
+    assert_eq!(stream.content_type(), Some(tide::http::mime::SSE));
+
+    // five bars are complete after five hours
+    pipeline.tick(Duration::from_secs(5 * BAR)).unwrap();
+    let data = pipeline.buffered(2).await.unwrap();
+    assert_eq!(data.len(), 2);
+    let aaa = data.iter().find(|d| d.symbol == "AAA").unwrap();
+    assert_eq!(aaa.price, 104.0);
+    assert_eq!(aaa.period_min, 100.0);
+    assert_eq!(aaa.timestamp, start + chrono::Duration::hours(4));
+    assert_eq!(aaa.name.as_deref(), Some("AAA Inc."));
+
+    // the rows are streamed as they are published
+    let mut events = stream.take_body();
+    let mut event = String::new();
+    while !event.ends_with("\n\n") {
+        events.read_line(&mut event).await.unwrap();
+    }
+    let json = event
+        .lines()
+        .nth(1)
+        .unwrap()
+        .strip_prefix("data: ")
+        .unwrap();
+    let first: PerformanceIndicators = serde_json::from_str(json).unwrap();
+    assert!(data
+        .iter()
+        .any(|d| d.symbol == first.symbol && d.price == first.price));
+
+    // the next fetch only requests the new bars, the signals still cover everything
+    pipeline.tick(Duration::from_secs(5 * BAR)).unwrap();
+    let data = pipeline.buffered(4).await.unwrap();
+    assert_eq!(data.len(), 4);
+    let bbb = data.iter().rev().find(|d| d.symbol == "BBB").unwrap();
+    assert_eq!(bbb.price, 59.0);
+    assert_eq!((bbb.period_min, bbb.period_max), (50.0, 59.0));
+    assert!((bbb.pct_change - 0.18).abs() < 1e-9);
+
+    let request = Request::new(Method::Get, Url::parse("http://localhost/tail/10").unwrap());
+    let mut response: Response = pipeline.respond(request).await.unwrap();
+    assert_eq!(response.status(), 200);
+    let tail: Envelope<Vec<PerformanceIndicators>> = response.body_json().await.unwrap();
+    assert_eq!(tail.count, Some(4));
+    assert!(tail.version >= 4);
+    // the last fetch was at the current time of the test clock
+    assert_eq!(tail.lag_ms, Some(0));
+    let tail = tail.data;
+    assert_eq!(
+        tail.iter()
+            .map(|d| (d.symbol.as_str(), d.price))
+            .collect::<Vec<_>>(),
+        data.iter()
+            .map(|d| (d.symbol.as_str(), d.price))
+            .collect::<Vec<_>>()
+    );
+    // reading the tail leaves the data for other clients, draining removes it
+    assert_eq!(pipeline.buffered(0).await.unwrap().len(), 4);
+    let request = Request::new(
+        Method::Post,
+        Url::parse("http://localhost/drain/3").unwrap(),
+    );
+    let mut response: Response = pipeline.respond(request).await.unwrap();
+    let drained: Envelope<Vec<PerformanceIndicators>> = response.body_json().await.unwrap();
+    let drained = drained.data;
+    assert_eq!(drained.len(), 3);
+    assert_eq!(drained[0].price, data[0].price);
+    let left = pipeline.buffered(0).await.unwrap();
+    assert_eq!(left.len(), 1);
+    assert_eq!(left[0].price, data[3].price);
+
+    // the latest indicators of a symbol outlive draining
+    let latest = |symbol: &str| {
+        let url = format!("http://localhost/symbols/{}/latest", symbol);
+        Request::new(Method::Get, Url::parse(&url).unwrap())
+    };
+    let mut response: Response = pipeline.respond(latest("BBB")).await.unwrap();
+    let bbb: Envelope<PerformanceIndicators> = response.body_json().await.unwrap();
+    assert_eq!(bbb.data.price, 59.0);
+    assert_eq!(bbb.count, None);
+    let response: Response = pipeline.respond(latest("CCC")).await.unwrap();
+    assert_eq!(response.status(), 404);
+
+    let rows = pipeline.csv_rows().await.unwrap();
+    let mut rows: Vec<(String, f64)> = rows.into_iter().map(|r| (r.symbol, r.price)).collect();
+    rows.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        rows,
+        vec![
+            ("AAA".to_string(), 104.0),
+            ("AAA".to_string(), 109.0),
+            ("BBB".to_string(), 54.0),
+            ("BBB".to_string(), 59.0),
+        ]
+    );
+}
+
+#[async_std::test]
+async fn test_symbol_management() {
+    let start = Utc.with_ymd_and_hms(2022, 12, 5, 9, 0, 0).unwrap();
+    let series = BTreeMap::from([
+        ("AAA".to_string(), synthetic(start, 100.0, 10)),
+        ("BBB".to_string(), synthetic(start, 50.0, 10)),
+    ]);
+    let aliases = Aliases::new([("bravo".to_string(), "BBB".to_string())]).unwrap();
+    let _turn = PIPELINE.lock().await;
+    let pipeline = TestPipeline::start("harness_symbol_management", start, series)
+        .await
+        .unwrap()
+        .with_aliases(aliases);
+
+    // removed by its alias
+    let url = Url::parse("http://localhost/symbols/Bravo").unwrap();
+    let mut response: Response = pipeline
+        .respond(Request::new(Method::Delete, url))
+        .await
+        .unwrap();
+    let changes: Envelope<SymbolChanges> = response.body_json().await.unwrap();
+    assert!(changes.data.removed.contains("BBB"));
+    pipeline.tick(Duration::from_secs(5 * BAR)).unwrap();
+    assert_eq!(pipeline.buffered(1).await.unwrap()[0].symbol, "AAA");
+
+    // added again, BBB is fetched from the start with the next tick
+    let mut request = Request::new(
+        Method::Post,
+        Url::parse("http://localhost/symbols").unwrap(),
+    );
+    request.set_body(r#"{"symbol": "BBB"}"#);
+    let response: Response = pipeline.respond(request).await.unwrap();
+    assert_eq!(response.status(), 200);
+    pipeline.tick(Duration::from_secs(5 * BAR)).unwrap();
+    let data = pipeline.buffered(3).await.unwrap();
+    let bbb: Vec<f64> = data
+        .iter()
+        .filter(|d| d.symbol == "BBB")
+        .map(|d| d.price)
+        .collect();
+    assert_eq!(bbb, vec![59.0]);
+}
+
+#[async_std::test]
+async fn test_websocket() {
+    let start = Utc.with_ymd_and_hms(2022, 12, 5, 9, 0, 0).unwrap();
+    let series = BTreeMap::from([
+        ("AAA".to_string(), synthetic(start, 100.0, 10)),
+        ("BBB".to_string(), synthetic(start, 50.0, 10)),
+    ]);
+    let _turn = PIPELINE.lock().await;
+    let pipeline = TestPipeline::start("harness_websocket", start, series)
+        .await
+        .unwrap();
+
+    // served on a socket, the upgrade needs a connection
+    let (address, runtime) = pipeline.serve().unwrap();
+
+    let stream = async_std::net::TcpStream::connect(&address).await.unwrap();
+    let url = format!("ws://{}/ws?symbols=AAA", address);
+    let (mut socket, _) = async_tungstenite::client_async(url, stream).await.unwrap();
+    let commands = [r#"{"subscribe": ["BBB"]}"#, r#"{"unsubscribe": ["AAA"]}"#];
+    let mut answers = vec![];
+    for command in commands {
+        socket
+            .send(Message::Text(command.to_string()))
+            .await
+            .unwrap();
+        answers.push(socket.next().await.unwrap().unwrap().into_text().unwrap());
+    }
+    assert_eq!(
+        answers,
+        vec![
+            r#"{"subscribed":["AAA","BBB"]}"#,
+            r#"{"subscribed":["BBB"]}"#
+        ]
+    );
+
+    // only the rows of BBB are pushed
+    pipeline.tick(Duration::from_secs(5 * BAR)).unwrap();
+    let push = socket.next().await.unwrap().unwrap().into_text().unwrap();
+    let push: serde_json::Value = serde_json::from_str(&push).unwrap();
+    assert_eq!(push["indicators"]["symbol"], "BBB");
+    assert_eq!(push["indicators"]["price"], 54.0);
+
+    socket.close(None).await.unwrap();
+    async_std::task::spawn_blocking(move || runtime.stop()).await;
+}
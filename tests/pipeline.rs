@@ -0,0 +1,36 @@
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use manning_lp_async_rust_project_2_m1_solution::buffer::BufferDataRequest;
+use manning_lp_async_rust_project_2_m1_solution::synthetic::SyntheticProvider;
+use manning_lp_async_rust_project_2_m1_solution::Pipeline;
+
+#[async_std::test]
+async fn test_embedded_pipeline() -> anyhow::Result<()> {
+    let csv_file = std::env::temp_dir().join("embedded_pipeline.csv");
+    let rows = Arc::new(Mutex::new(vec![]));
+    let received = rows.clone();
+    let pipeline = Pipeline::builder()
+        .symbols(["SYN0000", "SYN0001"])
+        .provider(SyntheticProvider::default)
+        .from(Utc::now() - chrono::Duration::hours(1))
+        .once()
+        .sink(move |indicators| received.lock().unwrap().push(indicators.symbol.clone()))
+        .csv(csv_file.to_str().unwrap())
+        .build()
+        .await?;
+    pipeline.wait().await;
+    let buffer = pipeline.buffer().clone();
+    pipeline.stop().await?;
+    let buffered = buffer.call(BufferDataRequest::Peek { n: 10 }).await?;
+
+    let mut symbols = rows.lock().unwrap().clone();
+    symbols.sort();
+    assert_eq!(symbols, ["SYN0000", "SYN0001"]);
+    assert_eq!(buffered.len(), 2);
+    let written = std::fs::read_to_string(&csv_file)?;
+    assert_eq!(written.lines().filter(|l| l.contains(",SYN")).count(), 2);
+
+    assert!(Pipeline::builder().symbols(["AAPL"]).build().await.is_err());
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::prelude::*;
+use manning_lp_async_rust_project_2_m1_solution::synthetic::SyntheticProvider;
+use manning_lp_async_rust_project_2_m1_solution::{Pipeline, PipelineBuilder};
+
+///
+/// The brokers are global, so the pipelines of the tests take turns
+///
+static PIPELINE: async_std::sync::Mutex<()> = async_std::sync::Mutex::new(());
+
+type Rows = Arc<Mutex<Vec<(String, DateTime<Utc>)>>>;
+
+fn builder(name: &str, symbols: &[&str]) -> (PipelineBuilder, Rows, PathBuf) {
+    let csv_file = std::env::temp_dir().join(format!("shutdown_{}.csv", name));
+    let _ = std::fs::remove_file(&csv_file);
+    let rows = Rows::default();
+    let received = rows.clone();
+    let builder = Pipeline::builder()
+        .symbols(symbols.iter().copied())
+        .provider(SyntheticProvider::default)
+        .from(Utc::now() - chrono::Duration::hours(1))
+        .sink(move |indicators| {
+            let row = (indicators.symbol.clone(), indicators.timestamp);
+            received.lock().unwrap().push(row)
+        })
+        .csv(csv_file.to_str().unwrap());
+    (builder, rows, csv_file)
+}
+
+///
+/// The rows of `symbols` in the CSV file, which has every bar the callbacks got (once)
+///
+fn assert_flushed(rows: &Rows, csv_file: &PathBuf, symbols: &[&str]) -> usize {
+    let mut received = rows.lock().unwrap().clone();
+    received.retain(|(s, _)| symbols.contains(&s.as_str()));
+    received.sort();
+    received.dedup();
+    let written = std::fs::read_to_string(csv_file).unwrap();
+    let written = written
+        .lines()
+        .filter(|l| symbols.iter().any(|s| l.contains(&format!(",{},", s))))
+        .count();
+    assert_eq!(written, received.len());
+    written
+}
+
+#[async_std::test]
+async fn test_flush_on_completion() -> anyhow::Result<()> {
+    let _turn = PIPELINE.lock().await;
+    let symbols = ["SYN0100", "SYN0101", "SYN0102"];
+    let (builder, rows, csv_file) = builder("completion", &symbols);
+    let pipeline = builder.once().build().await?;
+    pipeline.run_until(futures::future::pending()).await?;
+    assert_eq!(assert_flushed(&rows, &csv_file, &symbols), symbols.len());
+    Ok(())
+}
+
+#[async_std::test]
+async fn test_flush_on_signal() -> anyhow::Result<()> {
+    let _turn = PIPELINE.lock().await;
+    let symbols = ["SYN0200", "SYN0201", "SYN0202", "SYN0203"];
+    let (builder, rows, csv_file) = builder("signal", &symbols);
+    let pipeline = builder.interval(Duration::from_secs(1)).build().await?;
+    // interrupted while the next fetches are in flight
+    let received = rows.clone();
+    let signal = async move {
+        while received.lock().unwrap().len() < 2 * symbols.len() {
+            async_std::task::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    pipeline.run_until(signal).await?;
+    assert!(assert_flushed(&rows, &csv_file, &symbols) >= symbols.len());
+    Ok(())
+}